@@ -20,7 +20,9 @@ fn process_configuration_file() -> Result<()> {
         if config.is_empty() {
             return Ok(()); // Assuming tests
         } else {
-            ron::from_str(&config)?
+            Configuration::from_ron(&config).unwrap_or_else(|error| {
+                panic!("\r\n\r\nThe configuration file could not be loaded. {}\r\n\r\n", error)
+            })
         }
     } else {
         panic!(
@@ -33,6 +35,7 @@ fn process_configuration_file() -> Result<()> {
         )
     };
 
+    validate_configuration(&configuration);
     validate_feature_flags_against_configuration(&configuration);
     generate_modules(env!("CARGO_MANIFEST_DIR"), &configuration)?;
     configure_runner(&configuration.port.to_string());
@@ -40,6 +43,17 @@ fn process_configuration_file() -> Result<()> {
     Ok(())
 }
 
+fn validate_configuration(configuration: &Configuration) {
+    let problems = configuration.validate();
+    if !problems.is_empty() {
+        panic!(
+            "\r\n\r\nThe configuration file is invalid. Please address the following \
+            problems:\r\n{}\r\n\r\n",
+            problems.iter().map(|p| format!("* {}", p)).collect::<Vec<_>>().join("\r\n"),
+        );
+    }
+}
+
 fn validate_feature_flags_against_configuration(configuration: &Configuration) {
     let supplied_flags: Vec<_> = std::env::vars()
         .filter_map(|(k, _)| {
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    features::BootMetrics,
+    memory::{ExternalFlashDriver, FlashChip},
+    pins,
+    port::{Family, LinkerArea, LinkerScriptConstants, Port},
+    KB,
+};
+
+/// Largest external flash the generic SFDP driver can address with three byte addresses.
+const MAX_SFDP_FLASH_SIZE: u32 = 1 << 24;
+
+/// Description of a custom board, built around the MCU of one of the supported ports
+/// but with its own memory layout. When a configuration defines one, it takes the
+/// place of everything the port would otherwise predefine: MCU flash, RAM and the
+/// external flash chips on offer. The port still provides the drivers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Board {
+    /// Tag to identify the board.
+    pub name: String,
+    /// Hardware family of the MCU. Must match the family of the port.
+    pub family: Family,
+    /// MCU flash, which holds Loadstone and the bootable bank.
+    pub internal_flash: FlashChip,
+    /// RAM available to Loadstone and the application.
+    pub ram: Ram,
+    /// External flash chips fitted to the board, if any.
+    pub external_flash: Vec<FlashChip>,
+}
+
+/// A contiguous area of RAM.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Ram {
+    /// Start address of RAM.
+    pub origin: u32,
+    /// RAM size in kilobytes.
+    pub size_kb: u32,
+}
+
+impl Board {
+    /// Constants propagated to the linker script. Loadstone may use the entire
+    /// MCU flash and RAM of the board.
+    pub fn linker_script_constants(&self) -> LinkerScriptConstants {
+        LinkerScriptConstants {
+            flash: LinkerArea {
+                origin: self.internal_flash.start,
+                size: (self.internal_flash.end - self.internal_flash.start) as usize,
+            },
+            ram: LinkerArea { origin: self.ram.origin, size: KB!(self.ram.size_kb as usize) },
+        }
+    }
+
+    /// Returns every inconsistency in the board description, or in its pairing
+    /// with the port that provides its drivers.
+    pub fn problems(&self, port: &Port) -> Vec<String> {
+        let mut problems = vec![];
+        if self.family != port.family() {
+            problems.push(format!(
+                "Board \"{}\" is of the {} family, but port {} is of the {} family",
+                self.name,
+                self.family,
+                port,
+                port.family()
+            ));
+        }
+        if !self.internal_flash.internal {
+            problems.push(format!(
+                "MCU flash \"{}\" is not marked as internal",
+                self.internal_flash.name
+            ));
+        }
+        for chip in &self.external_flash {
+            if chip.internal {
+                problems.push(format!("External flash \"{}\" is marked as internal", chip.name));
+            }
+            // Any chip other than the n25q128a is assumed to be a JEDEC NOR chip, and
+            // goes through the generic SFDP driver. Either way, it takes QSPI.
+            if pins::qspi_flash(port).is_none() {
+                problems.push(format!(
+                    "Port {} can't drive external flash \"{}\" over QSPI",
+                    port, chip.name
+                ));
+            }
+            if chip.driver() == ExternalFlashDriver::Sfdp
+                && chip.end.saturating_sub(chip.start) >= MAX_SFDP_FLASH_SIZE
+            {
+                problems.push(format!(
+                    "External flash \"{}\" is larger than the 16MB reachable by the SFDP driver",
+                    chip.name
+                ));
+            }
+        }
+        for chip in Some(&self.internal_flash).into_iter().chain(&self.external_flash) {
+            // External flash chips are described by their last address rather than
+            // the one past it.
+            let size = chip.end.wrapping_sub(chip.start) + if chip.internal { 0 } else { 1 };
+            if chip.end <= chip.start {
+                problems.push(format!("Flash \"{}\" ends before it starts", chip.name));
+            } else if chip.region_size == 0 || size % chip.region_size != 0 {
+                problems.push(format!(
+                    "Flash \"{}\" is not a whole number of {} byte regions",
+                    chip.name, chip.region_size
+                ));
+            }
+        }
+        if self.ram.size_kb == 0 {
+            problems.push(format!("Board \"{}\" has no RAM", self.name));
+        }
+        // Loadstone leaves the boot metrics at a fixed address, so they must fall
+        // within the RAM of every board.
+        let ram_end = self.ram.origin as u64 + KB!(self.ram.size_kb as u64);
+        let metrics = BootMetrics::ram_range();
+        if (metrics.start as u64) < self.ram.origin as u64 || metrics.end as u64 > ram_end {
+            problems.push(format!(
+                "Board \"{}\" has no RAM at {:#010X}..{:#010X}, where Loadstone leaves the boot metrics",
+                self.name, metrics.start, metrics.end
+            ));
+        }
+        problems
+    }
+}
@@ -0,0 +1,291 @@
+//! Machine readable descriptions of the memory map, so applications and host
+//! tools don't have to duplicate bank addresses from the .ron file:
+//!
+//! * `descriptor.json` lists every bank, the boot metrics location and the
+//!   security mode.
+//! * `loadstone_layout.h` holds the same information as C definitions.
+//! * `descriptor.rs` optionally embeds a compact copy in the Loadstone binary,
+//!   so tools can read the layout straight from a device dump. All fields are
+//!   little endian:
+//!
+//! ```text
+//! offset  size  field
+//!      0     8  magic, "LSLAYOUT"
+//!      8     1  format version (1)
+//!      9     1  number of banks
+//!     10     1  security mode (0: CRC, 1: P256 ECDSA)
+//!     11     1  reserved
+//!     12     4  boot metrics address
+//!     16     4  boot metrics size
+//!     20    12  first bank: index (1), flags (1, bit 0: bootable, bit 1: golden,
+//!               bit 2: external), reserved (2), address (4), size (4)
+//!    ...        remaining banks
+//! ```
+use anyhow::Result;
+use quote::quote;
+use serde::Serialize;
+use std::{fmt::Write, fs, path::Path};
+
+use crate::{
+    features::{BootMetrics, EmbeddedLayout},
+    security::SecurityMode,
+    Configuration, KB,
+};
+
+use super::prettify_file;
+
+/// Marks the start of the embedded descriptor, so tools can find it in a dump.
+const MAGIC: &[u8; 8] = b"LSLAYOUT";
+const FORMAT_VERSION: u8 = 1;
+
+#[derive(Serialize)]
+struct Descriptor {
+    port: String,
+    security_mode: &'static str,
+    boot_metrics: Area,
+    banks: Vec<BankDescriptor>,
+}
+
+#[derive(Serialize)]
+struct Area {
+    address: u32,
+    size: u32,
+}
+
+#[derive(Serialize)]
+struct BankDescriptor {
+    /// Index of the bank as Loadstone and its boot manager know it, starting at 1.
+    index: u8,
+    address: u32,
+    size: u32,
+    bootable: bool,
+    golden: bool,
+    external: bool,
+    flash: String,
+}
+
+/// Writes the JSON descriptor, the C header and the module that embeds the
+/// descriptor in Loadstone, if enabled.
+pub fn generate<P: AsRef<Path>>(
+    autogenerated_folder_path: P,
+    configuration: &Configuration,
+) -> Result<()> {
+    let folder = autogenerated_folder_path.as_ref();
+    let descriptor = describe(configuration);
+    fs::write(folder.join("descriptor.json"), serde_json::to_string_pretty(&descriptor)?)?;
+    fs::write(folder.join("loadstone_layout.h"), c_header(&descriptor))?;
+
+    let filename = folder.join("descriptor.rs");
+    let code = match configuration.feature_configuration.embedded_layout {
+        EmbeddedLayout::Enabled => {
+            let bytes = compact(&descriptor);
+            let length = bytes.len();
+            quote! {
+                //! This code is autogenerated! Don't modify it manually, as it will be overwritten
+                //! in the next project build. Generation logic for this module is defined in
+                //! `loadstone_config/src/codegen/descriptor.rs`
+
+                /// Compact copy of the memory map, for host tools to read from a device dump.
+                #[used]
+                pub static LAYOUT_DESCRIPTOR: [u8; #length] = [#(#bytes),*];
+
+                /// Keeps the layout descriptor in the binary, as nothing else reads it.
+                #[inline(never)]
+                pub fn retain_layout_descriptor() {
+                    unsafe { core::ptr::read_volatile(LAYOUT_DESCRIPTOR.as_ptr()) };
+                }
+            }
+        }
+        EmbeddedLayout::Disabled => quote! {
+            //! This code is autogenerated! Don't modify it manually, as it will be overwritten
+            //! in the next project build. Generation logic for this module is defined in
+            //! `loadstone_config/src/codegen/descriptor.rs`
+
+            /// The layout descriptor is not embedded in this configuration.
+            pub fn retain_layout_descriptor() {}
+        },
+    };
+    fs::write(&filename, format!("{}", code))?;
+    prettify_file(filename).ok();
+    Ok(())
+}
+
+fn describe(configuration: &Configuration) -> Descriptor {
+    let memory = &configuration.memory_configuration;
+    let internal = &memory.internal_memory_map;
+    let internal_flash = configuration.internal_flash();
+    let external_flash_name = memory.external_flash.as_ref().map(|c| c.name.clone());
+
+    let internal_banks = internal.banks.iter().map(|b| (b, false, internal_flash.name.clone()));
+    let external_banks = memory
+        .external_memory_map
+        .banks
+        .iter()
+        .map(|b| (b, true, external_flash_name.clone().unwrap_or_default()));
+    let banks = internal_banks
+        .chain(external_banks)
+        .enumerate()
+        .map(|(i, (bank, external, flash))| BankDescriptor {
+            index: (i + 1) as u8,
+            address: bank.start_address,
+            size: KB!(bank.size_kb),
+            bootable: !external && Some(i) == internal.bootable_index,
+            golden: Some(i) == memory.golden_index,
+            external,
+            flash,
+        })
+        .collect();
+
+    let metrics = BootMetrics::ram_range();
+    Descriptor {
+        port: configuration.port.to_string(),
+        security_mode: match configuration.security_configuration.security_mode {
+            SecurityMode::Crc => "crc",
+            SecurityMode::P256ECDSA => "p256_ecdsa",
+        },
+        boot_metrics: Area { address: metrics.start, size: metrics.end - metrics.start },
+        banks,
+    }
+}
+
+fn c_header(descriptor: &Descriptor) -> String {
+    let mut text = String::new();
+    let define = |text: &mut String, name: &str, value: String| {
+        writeln!(text, "#define LOADSTONE_{} {}", name, value).unwrap();
+    };
+    let flag = |value: bool| if value { "1" } else { "0" }.to_owned();
+
+    writeln!(text, "/* This header is autogenerated! Don't modify it manually, as it will be")
+        .unwrap();
+    writeln!(text, " * overwritten in the next project build. Generation logic for this header")
+        .unwrap();
+    writeln!(text, " * is defined in `loadstone_config/src/codegen/descriptor.rs` */").unwrap();
+    writeln!(text, "#ifndef LOADSTONE_LAYOUT_H\n#define LOADSTONE_LAYOUT_H\n").unwrap();
+
+    define(&mut text, "PORT", format!("\"{}\"", descriptor.port));
+    define(&mut text, "SECURITY_MODE_CRC", "0".to_owned());
+    define(&mut text, "SECURITY_MODE_P256_ECDSA", "1".to_owned());
+    define(
+        &mut text,
+        "SECURITY_MODE",
+        format!("LOADSTONE_SECURITY_MODE_{}", descriptor.security_mode.to_uppercase()),
+    );
+    define(
+        &mut text,
+        "BOOT_METRICS_ADDRESS",
+        format!("0x{:08X}u", descriptor.boot_metrics.address),
+    );
+    define(&mut text, "BOOT_METRICS_SIZE", format!("{}u", descriptor.boot_metrics.size));
+    define(&mut text, "NUMBER_OF_BANKS", format!("{}u", descriptor.banks.len()));
+
+    for bank in &descriptor.banks {
+        let name = |field: &str| format!("BANK_{}_{}", bank.index, field);
+        writeln!(text).unwrap();
+        define(&mut text, &name("ADDRESS"), format!("0x{:08X}u", bank.address));
+        define(&mut text, &name("SIZE"), format!("0x{:X}u", bank.size));
+        define(&mut text, &name("BOOTABLE"), flag(bank.bootable));
+        define(&mut text, &name("GOLDEN"), flag(bank.golden));
+        define(&mut text, &name("EXTERNAL"), flag(bank.external));
+        define(&mut text, &name("FLASH"), format!("\"{}\"", c_escape(&bank.flash)));
+    }
+
+    writeln!(text, "\n#endif /* LOADSTONE_LAYOUT_H */").unwrap();
+    text
+}
+
+fn c_escape(text: &str) -> String { text.replace('\\', "\\\\").replace('"', "\\\"") }
+
+/// The binary layout described in the module documentation.
+fn compact(descriptor: &Descriptor) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(FORMAT_VERSION);
+    bytes.push(descriptor.banks.len() as u8);
+    bytes.push(if descriptor.security_mode == "crc" { 0 } else { 1 });
+    bytes.push(0);
+    bytes.extend_from_slice(&descriptor.boot_metrics.address.to_le_bytes());
+    bytes.extend_from_slice(&descriptor.boot_metrics.size.to_le_bytes());
+    for bank in &descriptor.banks {
+        bytes.push(bank.index);
+        bytes.push(bank.bootable as u8 | (bank.golden as u8) << 1 | (bank.external as u8) << 2);
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&bank.address.to_le_bytes());
+        bytes.extend_from_slice(&bank.size.to_le_bytes());
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        memory::{self, Bank},
+        port::Port,
+    };
+
+    /// Two MCU banks, the first bootable, and a golden bank in external flash.
+    fn configuration() -> Configuration {
+        let mut configuration = Configuration { port: Port::Stm32F412, ..Default::default() };
+        configuration.security_configuration.security_mode = SecurityMode::Crc;
+        let memory = &mut configuration.memory_configuration;
+        memory.internal_memory_map.bootloader_location = 0x0800_0000;
+        memory.internal_memory_map.bootloader_length_kb = 64;
+        memory.internal_memory_map.banks =
+            vec![Bank { start_address: 0x0801_0000, size_kb: 128 }, Bank {
+                start_address: 0x0803_0000,
+                size_kb: 128,
+            }];
+        memory.internal_memory_map.bootable_index = Some(0);
+        memory.external_flash = memory::external_flash(&Port::Stm32F412).next();
+        memory.external_memory_map.banks = vec![Bank { start_address: 0x0010_0000, size_kb: 1024 }];
+        memory.golden_index = Some(2);
+        configuration
+    }
+
+    #[test]
+    fn compact_descriptor_follows_the_documented_layout() {
+        let bytes = compact(&describe(&configuration()));
+
+        #[rustfmt::skip]
+        let expected: Vec<u8> = [
+            &b"LSLAYOUT"[..],
+            &[1, 3, 0, 0],
+            &[0xE4, 0xFF, 0x00, 0x20],
+            &[28, 0, 0, 0],
+            &[1, 0b001, 0, 0, 0x00, 0x00, 0x01, 0x08, 0x00, 0x00, 0x02, 0x00],
+            &[2, 0b000, 0, 0, 0x00, 0x00, 0x03, 0x08, 0x00, 0x00, 0x02, 0x00],
+            &[3, 0b110, 0, 0, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x10, 0x00],
+        ]
+        .concat();
+        assert_eq!(expected, bytes);
+        assert_eq!(20 + 3 * 12, bytes.len());
+    }
+
+    #[test]
+    fn c_header_defines_every_bank() {
+        let header = c_header(&describe(&configuration()));
+
+        let expected = [
+            "#define LOADSTONE_PORT \"stm32f412\"",
+            "#define LOADSTONE_SECURITY_MODE LOADSTONE_SECURITY_MODE_CRC",
+            "#define LOADSTONE_BOOT_METRICS_ADDRESS 0x2000FFE4u",
+            "#define LOADSTONE_BOOT_METRICS_SIZE 28u",
+            "#define LOADSTONE_NUMBER_OF_BANKS 3u",
+            "#define LOADSTONE_BANK_1_ADDRESS 0x08010000u",
+            "#define LOADSTONE_BANK_1_SIZE 0x20000u",
+            "#define LOADSTONE_BANK_1_BOOTABLE 1",
+            "#define LOADSTONE_BANK_1_GOLDEN 0",
+            "#define LOADSTONE_BANK_1_EXTERNAL 0",
+            "#define LOADSTONE_BANK_1_FLASH \"STM32F412 MCU Flash\"",
+            "#define LOADSTONE_BANK_2_BOOTABLE 0",
+            "#define LOADSTONE_BANK_3_ADDRESS 0x00100000u",
+            "#define LOADSTONE_BANK_3_SIZE 0x100000u",
+            "#define LOADSTONE_BANK_3_GOLDEN 1",
+            "#define LOADSTONE_BANK_3_EXTERNAL 1",
+            "#define LOADSTONE_BANK_3_FLASH \"Micron n25q128a\"",
+        ];
+        for define in expected.iter() {
+            assert!(header.lines().any(|line| line == *define), "Missing `{}`", define);
+        }
+        assert!(header.trim_end().ends_with("#endif /* LOADSTONE_LAYOUT_H */"));
+    }
+}
@@ -1,8 +1,18 @@
 use anyhow::Result;
 use quote::{format_ident, quote, TokenStreamExt};
 use std::{fs::OpenOptions, io::Write, path::Path};
+use syn::Index;
 
-use crate::{codegen::prettify_file, features::Serial, Configuration};
+use crate::{
+    codegen::prettify_file,
+    features::{
+        FlashProtection, Parity, ReadoutProtection, RecoveryPin, Serial, StopBits, UpdateSignal,
+    },
+    memory::QspiConfiguration,
+    pins,
+    port::Port,
+    Configuration,
+};
 
 /// Generates the `devices.rs` module, which contains type definitions and
 /// initialisation functions for bootloader features such as serial and external
@@ -16,11 +26,17 @@ pub fn generate<P: AsRef<Path>>(
     let mut code = quote! {};
 
     match configuration.port {
-        crate::port::Port::Stm32F412 => {
+        Port::Stm32F407 | Port::Stm32F412 | Port::Stm32F429 | Port::Stm32F469 => {
             generate_serial_stm32(configuration, &mut code)?;
             generate_flash_stm32(configuration, &mut code)?;
+            generate_recovery_pin_stm32(configuration, &mut code)?;
+            generate_flash_protection_stm32(configuration, &mut code)?;
+            generate_update_signal_stm32(configuration, &mut code)?;
+        }
+        Port::Wgm160P => {
+            generate_serial_efm32(configuration, &mut code)?;
+            generate_update_signal_efm32(configuration, &mut code)?;
         }
-        crate::port::Port::Wgm160P => {}
     }
 
     file.write_all(format!("{}", code).as_bytes())?;
@@ -32,14 +48,31 @@ fn generate_flash_stm32(
     configuration: &Configuration,
     code: &mut quote::__private::TokenStream,
 ) -> Result<()> {
-    if configuration.memory_configuration.external_flash.is_some() {
+    if pins::qspi_flash(&configuration.port).is_none() {
+        // Parts without a QSPI peripheral have no external flash to construct.
+        code.append_all(quote!{
+            use blue_hal::hal::time;
+            use super::pin_configuration::*;
+            #[allow(unused)]
+            pub fn construct_flash(qspi_pins: QspiPins) -> Option<ExternalFlash> { None }
+        });
+    } else if let Some(chip) = &configuration.memory_configuration.external_flash {
+        // The QSPI peripheral takes the flash size as the number of address bits.
+        let size = (chip.end - chip.start) as u64 + 1;
+        let flash_size = Index::from((64 - (size - 1).leading_zeros()).min(24) as usize);
+        let QspiConfiguration { mode, clock_prescaler, timeout_ms } =
+            &configuration.memory_configuration.qspi;
+        let mode = format_ident!("{}", format!("{:?}", mode));
         code.append_all(quote!{
             use blue_hal::hal::time;
             use super::pin_configuration::*;
             pub fn construct_flash(qspi_pins: QspiPins, qspi: stm32pac::QUADSPI) -> Option<ExternalFlash> {
-                let qspi_config = qspi::Config::<mode::Single>::default().with_flash_size(24).unwrap();
+                let qspi_config = qspi::Config::<mode::#mode>::default()
+                    .with_flash_size(#flash_size)
+                    .unwrap()
+                    .with_clock_prescaler(#clock_prescaler);
                 let qspi = Qspi::from_config(qspi, qspi_pins, qspi_config).unwrap();
-                let external_flash = ExternalFlash::with_timeout(qspi, time::Milliseconds(5000)).unwrap();
+                let external_flash = ExternalFlash::with_timeout(qspi, time::Milliseconds(#timeout_ms)).unwrap();
                 Some(external_flash)
             }
         })
@@ -58,8 +91,24 @@ fn generate_serial_stm32(
     configuration: &Configuration,
     code: &mut quote::__private::TokenStream,
 ) -> Result<()> {
-    if let Serial::Enabled { tx_pin, .. } = &configuration.feature_configuration.serial {
+    if let Serial::Enabled { tx_pin, link, .. } = &configuration.feature_configuration.serial {
         let peripheral = format_ident!("{}", tx_pin.peripheral.to_lowercase());
+        let baud_rate = link.baud_rate;
+        let parity = format_ident!(
+            "{}",
+            match link.parity {
+                Parity::None => "parity_none",
+                Parity::Even => "parity_even",
+                Parity::Odd => "parity_odd",
+            }
+        );
+        let stop_bits = format_ident!(
+            "{}",
+            match link.stop_bits {
+                StopBits::One => "STOP1",
+                StopBits::Two => "STOP2",
+            }
+        );
         code.append_all(quote! {
             use super::pin_configuration::{UsartPins, Serial};
             use blue_hal::stm32pac;
@@ -73,7 +122,10 @@ fn generate_serial_stm32(
                 usart2: stm32pac::USART2,
                 usart6: stm32pac::USART6
             ) -> Option<Serial> {
-                let serial_config = serial::config::Config::default().baudrate(time::Bps(115200));
+                let serial_config = serial::config::Config::default()
+                    .baudrate(time::Bps(#baud_rate))
+                    .#parity()
+                    .stopbits(serial::config::StopBits::#stop_bits);
                 Some(#peripheral.constrain(serial_pins, serial_config, clocks).unwrap())
             }
         });
@@ -96,3 +148,261 @@ fn generate_serial_stm32(
     }
     Ok(())
 }
+
+fn generate_serial_efm32(
+    configuration: &Configuration,
+    code: &mut quote::__private::TokenStream,
+) -> Result<()> {
+    if let Serial::Enabled { tx_pin, link, .. } = &configuration.feature_configuration.serial {
+        let peripheral = format_ident!("{}", tx_pin.peripheral.to_lowercase());
+        let baud_rate = link.baud_rate;
+        let parity = format_ident!("{}", format!("{:?}", link.parity));
+        let stop_bits = format_ident!("{}", format!("{:?}", link.stop_bits));
+        code.append_all(quote! {
+            use super::pin_configuration::{UsartPins, Serial};
+            use crate::ports::wgm160p::serial::{Parity, StopBits};
+            use blue_hal::efm32pac;
+            use blue_hal::drivers::efm32gg11b::clocks::Clocks;
+            #[allow(unused)]
+            pub fn construct_serial(
+                serial_pins: Option<UsartPins>,
+                clocks: &Clocks,
+                usart0: efm32pac::USART0,
+                usart1: efm32pac::USART1,
+                usart2: efm32pac::USART2
+            ) -> Option<Serial> {
+                Some(Serial::new(#peripheral, serial_pins?, clocks, #baud_rate, Parity::#parity, StopBits::#stop_bits))
+            }
+        });
+    } else {
+        code.append_all(quote! {
+            use super::pin_configuration::{UsartPins, Serial};
+            use blue_hal::efm32pac;
+            use blue_hal::drivers::efm32gg11b::clocks::Clocks;
+            #[allow(unused)]
+            pub fn construct_serial(
+                _serial_pins: Option<UsartPins>,
+                _clocks: &Clocks,
+                _usart0: efm32pac::USART0,
+                _usart1: efm32pac::USART1,
+                _usart2: efm32pac::USART2
+            ) -> Option<Serial> {
+                None
+            }
+        });
+    }
+    Ok(())
+}
+
+fn generate_recovery_pin_stm32(
+    configuration: &Configuration,
+    code: &mut quote::__private::TokenStream,
+) -> Result<()> {
+    if let RecoveryPin::Enabled { active_high, .. } =
+        &configuration.feature_configuration.recovery_pin
+    {
+        code.append_all(quote! {
+            use super::pin_configuration::RecoveryPinInput;
+            pub type RecoveryPin = crate::devices::recovery_pin::GpioRecoveryPin<RecoveryPinInput>;
+            #[allow(unused)]
+            pub fn construct_recovery_pin(pin: RecoveryPinInput) -> Option<RecoveryPin> {
+                Some(RecoveryPin::new(pin, #active_high))
+            }
+        });
+    } else {
+        code.append_all(quote! {
+            use super::pin_configuration::RecoveryPinInput;
+            pub type RecoveryPin = crate::devices::recovery_pin::NullRecoveryPin;
+            #[allow(unused)]
+            pub fn construct_recovery_pin(_pin: RecoveryPinInput) -> Option<RecoveryPin> {
+                None
+            }
+        });
+    }
+    Ok(())
+}
+
+fn generate_flash_protection_stm32(
+    configuration: &Configuration,
+    code: &mut quote::__private::TokenStream,
+) -> Result<()> {
+    let port = format_ident!("{}", configuration.port.to_string());
+    if let FlashProtection::Enabled { golden_bank, readout_protection } =
+        &configuration.feature_configuration.flash_protection
+    {
+        let memory_map = &configuration.memory_configuration.internal_memory_map;
+        let bootloader_start = memory_map.bootloader_location as usize;
+        let bootloader_size = (memory_map.bootloader_length_kb * 1024) as usize;
+
+        // Only golden banks in MCU flash can be protected through option bytes.
+        let golden_bank = configuration
+            .memory_configuration
+            .golden_index
+            .and_then(|index| memory_map.banks.get(index))
+            .filter(|_| *golden_bank);
+        let golden_bank = match golden_bank {
+            Some(bank) => {
+                let start = bank.start_address as usize;
+                let size = (bank.size_kb * 1024) as usize;
+                quote! { Some(Region { start: #start, size: #size }) }
+            }
+            None => quote! { None },
+        };
+
+        let readout_protection = format_ident!(
+            "{}",
+            match readout_protection {
+                ReadoutProtection::Disabled => "Disabled",
+                ReadoutProtection::Level1 => "Level1",
+                ReadoutProtection::Level2 => "Level2",
+            }
+        );
+
+        code.append_all(quote! {
+            use crate::devices::flash_protection::{FlashProtection, ReadoutProtection, Region};
+            pub type ProtectionOptions = crate::ports::#port::flash_protection::OptionBytes;
+            #[allow(unused)]
+            pub fn construct_flash_protection() -> Option<FlashProtection<ProtectionOptions>> {
+                Some(FlashProtection::new(
+                    ProtectionOptions::new(),
+                    Region { start: #bootloader_start, size: #bootloader_size },
+                    #golden_bank,
+                    ReadoutProtection::#readout_protection,
+                ))
+            }
+        });
+    } else {
+        // Only parts that support flash protection have option bytes to drive.
+        code.append_all(quote! {
+            use crate::devices::flash_protection::FlashProtection;
+            pub type ProtectionOptions = crate::devices::flash_protection::NullProtectionOptions;
+            #[allow(unused)]
+            pub fn construct_flash_protection() -> Option<FlashProtection<ProtectionOptions>> {
+                None
+            }
+        });
+    }
+    Ok(())
+}
+
+fn generate_update_signal_stm32(
+    configuration: &Configuration,
+    code: &mut quote::__private::TokenStream,
+) -> Result<()> {
+    let port = format_ident!("{}", configuration.port.to_string());
+    match configuration.feature_configuration.update_signal {
+        UpdateSignal::Flash { start_address, size_kb } => {
+            let size = (size_kb * 1024) as usize;
+            code.append_all(quote! {
+                use blue_hal::drivers::stm32f4::flash::{Address as McuAddress, McuFlash};
+                use crate::devices::shared_flash::SharedFlash;
+                pub type UpdateSignal =
+                    crate::devices::update_signal::flash::FlashUpdateSignal<SharedFlash<McuFlash>>;
+                pub type UpdateSignalWriter = UpdateSignal;
+                #[allow(unused)]
+                pub fn construct_update_signal(
+                    _rtc: stm32pac::RTC,
+                    mcu_flash: SharedFlash<McuFlash>,
+                ) -> Option<UpdateSignal> {
+                    Some(UpdateSignal::new(mcu_flash, McuAddress(#start_address), #size))
+                }
+                #[allow(unused)]
+                pub fn construct_update_signal_writer(
+                    rtc: stm32pac::RTC,
+                    mcu_flash: SharedFlash<McuFlash>,
+                ) -> Option<UpdateSignalWriter> {
+                    construct_update_signal(rtc, mcu_flash)
+                }
+            });
+        }
+        UpdateSignal::Enabled => {
+            code.append_all(quote! {
+                use blue_hal::drivers::stm32f4::flash::McuFlash;
+                use crate::devices::shared_flash::SharedFlash;
+                pub use crate::ports::#port::update_signal::{UpdateSignal, UpdateSignalWriter};
+                #[allow(unused)]
+                pub fn construct_update_signal(
+                    rtc: stm32pac::RTC,
+                    _mcu_flash: SharedFlash<McuFlash>,
+                ) -> Option<UpdateSignal> {
+                    Some(UpdateSignal::new(rtc))
+                }
+                #[allow(unused)]
+                pub fn construct_update_signal_writer(
+                    rtc: stm32pac::RTC,
+                    _mcu_flash: SharedFlash<McuFlash>,
+                ) -> Option<UpdateSignalWriter> {
+                    Some(UpdateSignalWriter::new(rtc))
+                }
+            });
+        }
+        UpdateSignal::Disabled => {
+            code.append_all(quote! {
+                use blue_hal::drivers::stm32f4::flash::McuFlash;
+                use crate::devices::shared_flash::SharedFlash;
+                pub use crate::ports::#port::update_signal::{UpdateSignal, UpdateSignalWriter};
+                #[allow(unused)]
+                pub fn construct_update_signal(
+                    _rtc: stm32pac::RTC,
+                    _mcu_flash: SharedFlash<McuFlash>,
+                ) -> Option<UpdateSignal> {
+                    None
+                }
+                #[allow(unused)]
+                pub fn construct_update_signal_writer(
+                    _rtc: stm32pac::RTC,
+                    _mcu_flash: SharedFlash<McuFlash>,
+                ) -> Option<UpdateSignalWriter> {
+                    None
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
+fn generate_update_signal_efm32(
+    configuration: &Configuration,
+    code: &mut quote::__private::TokenStream,
+) -> Result<()> {
+    if let UpdateSignal::Flash { start_address, size_kb } =
+        configuration.feature_configuration.update_signal
+    {
+        let size = (size_kb * 1024) as usize;
+        code.append_all(quote! {
+            use blue_hal::drivers::efm32gg11b::flash::{Address as McuAddress, Flash};
+            use crate::devices::shared_flash::SharedFlash;
+            pub type UpdateSignal =
+                crate::devices::update_signal::flash::FlashUpdateSignal<SharedFlash<Flash>>;
+            pub type UpdateSignalWriter = UpdateSignal;
+            #[allow(unused)]
+            pub fn construct_update_signal(mcu_flash: SharedFlash<Flash>) -> Option<UpdateSignal> {
+                Some(UpdateSignal::new(mcu_flash, McuAddress(#start_address), #size))
+            }
+            #[allow(unused)]
+            pub fn construct_update_signal_writer(
+                mcu_flash: SharedFlash<Flash>,
+            ) -> Option<UpdateSignalWriter> {
+                construct_update_signal(mcu_flash)
+            }
+        });
+    } else {
+        code.append_all(quote! {
+            use blue_hal::drivers::efm32gg11b::flash::Flash;
+            use crate::devices::shared_flash::SharedFlash;
+            pub type UpdateSignal = crate::ports::wgm160p::update_signal::NullUpdateSignal;
+            pub type UpdateSignalWriter = UpdateSignal;
+            #[allow(unused)]
+            pub fn construct_update_signal(_mcu_flash: SharedFlash<Flash>) -> Option<UpdateSignal> {
+                None
+            }
+            #[allow(unused)]
+            pub fn construct_update_signal_writer(
+                _mcu_flash: SharedFlash<Flash>,
+            ) -> Option<UpdateSignalWriter> {
+                None
+            }
+        });
+    }
+    Ok(())
+}
@@ -1,49 +1,156 @@
-use std::{fs::OpenOptions, io::Write};
+use std::{fs, path::Path};
 
-use crate::{port::LinkerScriptConstants, Configuration};
+use crate::{
+    features::BootMetrics, port::LinkerScriptConstants, security::SecurityMode, Configuration, KB,
+};
 use anyhow::{anyhow, Result};
 
+/// Bytes appended to an image by the signing tools: the golden string, the magic
+/// string and the CRC or signature. Mirrors the constants in `src/devices/image`.
+fn image_footer_size(security_mode: SecurityMode) -> u32 {
+    const GOLDEN_STRING_SIZE: u32 = 10;
+    const MAGIC_STRING_SIZE: u32 = 32;
+    GOLDEN_STRING_SIZE
+        + MAGIC_STRING_SIZE
+        + match security_mode {
+            SecurityMode::Crc => 4,
+            SecurityMode::P256ECDSA => 64,
+        }
+}
+
 /// Generates the linker script `memory.x`, which describes the amount and location
 /// of flash and RAM memory available to a particular Loadstone instance.
-pub fn generate_linker_script(configuration: &Configuration) -> Result<()> {
-    let mut file = OpenOptions::new().write(true).create(true).truncate(true).open("memory.x")?;
+pub fn generate_linker_script<P: AsRef<Path>>(
+    loadstone_path: P,
+    configuration: &Configuration,
+) -> Result<()> {
+    let constants = if std::env::var("CARGO_FEATURE_RELOCATE_TO_BOOTABLE_BANK").is_ok() {
+        let bootable_index = configuration.memory_configuration.internal_memory_map.bootable_index;
+        let bootable_index = bootable_index.ok_or(anyhow!(
+            "Impossible to relocate: bootable bank is undefined in configuration file."
+        ))?;
+        application_constants(configuration, bootable_index)?
+    } else {
+        configuration
+            .linker_script_constants()
+            .ok_or(anyhow!("Current board doesn't have linker script constants defined."))?
+    };
+
+    fs::write(loadstone_path.as_ref().join("memory.x"), memory_x(&constants))?;
+    Ok(())
+}
+
+/// Generates a `memory.x` and an `application.x` linker script for applications
+/// booted by Loadstone from the given MCU bank, so they don't have to maintain
+/// their own. The application links `application.x` after `cortex-m-rt`'s
+/// `link.x`, to be told at link time when its image outgrows the bank.
+pub fn generate_application_linker_scripts<P: AsRef<Path>>(
+    output_path: P,
+    configuration: &Configuration,
+    bank_index: usize,
+) -> Result<()> {
+    let constants = application_constants(configuration, bank_index)?;
+    let footer_size = image_footer_size(configuration.security_configuration.security_mode);
+    let metrics = BootMetrics::ram_range();
+    let flash_end = constants.flash.origin + constants.flash.size as u32;
+
+    let output_path = output_path.as_ref();
+    fs::create_dir_all(output_path)?;
+    fs::write(output_path.join("memory.x"), memory_x(&constants))?;
+    fs::write(
+        output_path.join("application.x"),
+        format!(
+            "/* This script is autogenerated! Don't modify it manually. Generation logic for\n \
+             * it is defined in `loadstone_config/src/codegen/linker_script.rs` */\n\
+             \n\
+             /* Bank {bank} ({bank_size}K), where Loadstone boots this application from. */\n\
+             _loadstone_bank_start = 0x{origin:08X};\n\
+             _loadstone_bank_end = 0x{end:08X};\n\
+             \n\
+             /* Boot metrics Loadstone leaves for the application, outside its RAM. */\n\
+             _loadstone_boot_metrics_start = 0x{metrics_start:08X};\n\
+             _loadstone_boot_metrics_end = 0x{metrics_end:08X};\n\
+             \n\
+             /* The image is followed by a {footer_size} byte footer added when signing it. */\n\
+             ASSERT(__sidata + (__edata - __sdata) + {footer_size} <= _loadstone_bank_end,\n  \
+             \"The application doesn't fit in Bank {bank} ({bank_size}K) once signed.\");\n",
+            bank = bank_index + 1,
+            bank_size = constants.flash.size / 1024,
+            origin = constants.flash.origin,
+            end = flash_end,
+            metrics_start = metrics.start,
+            metrics_end = metrics.end,
+            footer_size = footer_size,
+        ),
+    )?;
+    Ok(())
+}
 
-    #[allow(unused_mut)]
+/// Flash and RAM available to an application booted from the given MCU bank:
+/// the whole bank, and as much RAM as possible without the boot metrics.
+fn application_constants(
+    configuration: &Configuration,
+    bank_index: usize,
+) -> Result<LinkerScriptConstants> {
     let mut constants = configuration
-        .port
         .linker_script_constants()
         .ok_or(anyhow!("Current board doesn't have linker script constants defined."))?;
+    let bank =
+        configuration.memory_configuration.internal_memory_map.banks.get(bank_index).ok_or(
+            anyhow!("Bank {} is not in MCU flash, so it can't be booted.", bank_index + 1),
+        )?;
 
-    if std::env::var("CARGO_FEATURE_RELOCATE_TO_BOOTABLE_BANK").is_ok() {
-        relocate_to_bootable_bank(&mut constants, configuration)?;
+    let bank_size = KB!(bank.size_kb);
+    let flash_end = constants.flash.origin as u64 + constants.flash.size as u64;
+    if bank.start_address < constants.flash.origin
+        || bank.start_address as u64 + bank_size as u64 > flash_end
+    {
+        return Err(anyhow!("Bank {} is outside the linkable flash area.", bank_index + 1));
+    }
+    let footer_size = image_footer_size(configuration.security_configuration.security_mode);
+    if bank_size <= footer_size {
+        return Err(anyhow!(
+            "Bank {} ({}K) has no room left for an application.",
+            bank_index + 1,
+            bank.size_kb
+        ));
     }
+    constants.flash.origin = bank.start_address;
+    constants.flash.size = bank_size as usize;
 
-    write!(
-        file,
+    // The boot metrics split RAM in two, of which the application gets the largest.
+    let metrics = BootMetrics::ram_range();
+    let ram_end = constants.ram.origin as u64 + constants.ram.size as u64;
+    if constants.ram.origin < metrics.end && (metrics.start as u64) < ram_end {
+        let below = metrics.start.saturating_sub(constants.ram.origin) as u64;
+        let above = ram_end.saturating_sub(metrics.end as u64);
+        if below >= above {
+            constants.ram.size = below as usize;
+        } else {
+            constants.ram.origin = metrics.end;
+            constants.ram.size = above as usize;
+        }
+    }
+    Ok(constants)
+}
+
+fn memory_x(constants: &LinkerScriptConstants) -> String {
+    let length = |size: usize| {
+        if size % 1024 == 0 {
+            format!("{}K", size / 1024)
+        } else {
+            format!("0x{:X}", size)
+        }
+    };
+    format!(
         "MEMORY\n\
          {{\n\
-             FLASH : ORIGIN = 0x{:08X}, LENGTH = {}K\n\
-             RAM : ORIGIN = 0x{:08X}, LENGTH = {}K\n\
+             FLASH : ORIGIN = 0x{:08X}, LENGTH = {}\n\
+             RAM : ORIGIN = 0x{:08X}, LENGTH = {}\n\
          }}\n",
         constants.flash.origin,
-        constants.flash.size / 1024,
+        length(constants.flash.size),
         constants.ram.origin,
-        constants.ram.size / 1024,
-    )?;
-
-    Ok(())
-}
-
-#[allow(unused)]
-fn relocate_to_bootable_bank(
-    constants: &mut LinkerScriptConstants,
-    configuration: &Configuration,
-) -> Result<()> {
-    let bootable_address = configuration.memory_configuration.bootable_address().ok_or(anyhow!(
-        "Impossible to relocate: bootable bank is undefined in configuration file."
-    ))?;
-    let offset = bootable_address - constants.flash.origin;
-    constants.flash.size = constants.flash.size.saturating_sub(offset as usize);
-    constants.flash.origin = bootable_address;
-    Ok(())
+        length(constants.ram.size),
+    )
 }
@@ -1,10 +1,10 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use quote::{format_ident, quote};
 use std::{fs::OpenOptions, io::Write, path::Path};
 
 use crate::{
-    memory::{ExternalMemoryMap, InternalMemoryMap, MemoryConfiguration},
-    port::{Port, Subfamily},
+    memory::{ExternalFlashDriver, ExternalMemoryMap, InternalMemoryMap, MemoryConfiguration},
+    port::{Family, Port, Subfamily},
 };
 
 use super::prettify_file;
@@ -17,6 +17,7 @@ pub fn generate<P: AsRef<Path>>(
     memory_configuration: &MemoryConfiguration,
     port: &Port,
 ) -> Result<()> {
+    verify_bank_layout(memory_configuration)?;
     let filename = autogenerated_folder_path.as_ref().join("memory_map.rs");
     let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&filename)?;
     let base_index = 1usize;
@@ -35,19 +36,58 @@ pub fn generate<P: AsRef<Path>>(
     file.write_all(imports.as_bytes())?;
     file.write_all(mcu_banks.as_bytes())?;
     file.write_all(external_banks.as_bytes())?;
+    file.write_all(generate_layout_assertion(memory_configuration).as_bytes())?;
     prettify_file(filename).ok();
     Ok(())
 }
 
+/// Refuses to describe a bank layout Loadstone would reject at boot. The generated
+/// module checks the same at build time, but a bad map is best caught here.
+fn verify_bank_layout(memory_configuration: &MemoryConfiguration) -> Result<()> {
+    let internal = &memory_configuration.internal_memory_map;
+    let total_banks = internal.banks.len() + memory_configuration.external_memory_map.banks.len();
+    match internal.bootable_index {
+        Some(index) if index < internal.banks.len() => {}
+        _ => return Err(anyhow!("There must be exactly one bootable bank, in MCU flash.")),
+    }
+    if matches!(memory_configuration.golden_index, Some(index) if index >= total_banks) {
+        return Err(anyhow!("The golden bank doesn't exist."));
+    }
+    if total_banks > u8::MAX as usize {
+        return Err(anyhow!("Too many banks to index."));
+    }
+    if memory_configuration.external_flash.is_none()
+        && !memory_configuration.external_memory_map.banks.is_empty()
+    {
+        return Err(anyhow!("There are external flash banks, but no external flash chip."));
+    }
+    Ok(())
+}
+
+/// Checks the generated bank tables at build time, like a manual port would.
+fn generate_layout_assertion(memory_configuration: &MemoryConfiguration) -> String {
+    let external_flash = memory_configuration.external_flash.is_some();
+    let code = quote! {
+        crate::assert_bank_layout!(MCU_BANK_TABLE, EXTERNAL_BANK_TABLE, #external_flash);
+    };
+    format!("{}", code)
+}
+
 fn generate_imports(memory_configuration: &MemoryConfiguration, port: &Port) -> Result<String> {
     let external_address: Vec<_> = match &memory_configuration.external_flash {
-        Some(external_flash) if external_flash.name.to_lowercase().contains("n25q128a") => {
-            ["blue_hal", "drivers", "micron", "n25q128a_flash", "Address"]
+        Some(external_flash) => match external_flash.driver() {
+            ExternalFlashDriver::MicronN25q128a => {
+                ["blue_hal", "drivers", "micron", "n25q128a_flash", "Address"]
+                    .iter()
+                    .map(|f| format_ident!("{}", f))
+                    .collect()
+            }
+            ExternalFlashDriver::Sfdp => ["crate", "devices", "sfdp_flash", "Address"]
                 .iter()
                 .map(|f| format_ident!("{}", f))
-                .collect()
-        }
-        None if *port == Port::Stm32F412 => ["blue_hal", "hal", "null", "NullAddress"]
+                .collect(),
+        },
+        None if port.family() == Family::Stm32 => ["blue_hal", "hal", "null", "NullAddress"]
             .iter()
             .map(|f| format_ident!("{}", f))
             .collect(),
@@ -94,7 +134,7 @@ fn generate_external_banks(
 
     let code = quote! {
         const NUMBER_OF_EXTERNAL_BANKS: usize = #number_of_external_banks;
-        pub static EXTERNAL_BANKS: [image::Bank<ExternalAddress>; NUMBER_OF_EXTERNAL_BANKS] = [
+        const EXTERNAL_BANK_TABLE: [image::Bank<ExternalAddress>; NUMBER_OF_EXTERNAL_BANKS] = [
             #(image::Bank {
                 index: #index,
                 bootable: #bootable,
@@ -103,6 +143,7 @@ fn generate_external_banks(
                 is_golden: #golden,
             }),*
         ];
+        pub static EXTERNAL_BANKS: [image::Bank<ExternalAddress>; NUMBER_OF_EXTERNAL_BANKS] = EXTERNAL_BANK_TABLE;
     };
     Ok(format!("{}", code))
 }
@@ -123,7 +164,7 @@ fn generate_mcu_banks(
 
     let code = quote! {
         const NUMBER_OF_MCU_BANKS: usize = #number_of_mcu_banks;
-        pub static MCU_BANKS: [image::Bank<McuAddress>; NUMBER_OF_MCU_BANKS] = [
+        const MCU_BANK_TABLE: [image::Bank<McuAddress>; NUMBER_OF_MCU_BANKS] = [
             #(image::Bank {
                 index: #index,
                 bootable: #bootable,
@@ -132,6 +173,7 @@ fn generate_mcu_banks(
                 is_golden: #golden,
             }),*
         ];
+        pub static MCU_BANKS: [image::Bank<McuAddress>; NUMBER_OF_MCU_BANKS] = MCU_BANK_TABLE;
     };
     Ok(format!("{}", code))
 }
@@ -12,18 +12,25 @@ use std::{
 };
 use syn::LitStr;
 
-use crate::{Configuration, features::{BootMetrics, Greetings, Serial, UpdateSignal}, security::SecurityMode};
-use anyhow::Result;
+use crate::{
+    features::{BootMetrics, Greetings, RecoveryPin, Serial},
+    report,
+    security::SecurityMode,
+    Configuration,
+};
+use anyhow::{anyhow, Result};
 
 use self::linker_script::generate_linker_script;
+pub use self::linker_script::generate_application_linker_scripts;
 mod memory_map;
 mod linker_script;
 mod pins;
 mod devices;
+mod descriptor;
 
 /// Transforms a `Configuration` struct into a set of source code files
 /// that will be compiled into `Loadstone`. The resulting source is written
-/// to src/ports/<port>/autogenerated.
+/// to src/ports/<port>/autogenerated, under the root of the Loadstone repository.
 pub fn generate_modules<P: AsRef<Path>>(
     loadstone_path: P,
     configuration: &Configuration,
@@ -31,11 +38,18 @@ pub fn generate_modules<P: AsRef<Path>>(
     let autogenerated_folder_path = loadstone_path.as_ref().join(
         format!("src/ports/{}/autogenerated", configuration.port)
     );
+    let problems = configuration.validate();
+    if !problems.is_empty() {
+        return Err(anyhow!(
+            "Invalid configuration:\n{}",
+            problems.iter().map(|p| format!("* {}", p)).collect::<Vec<_>>().join("\n")
+        ));
+    }
     fs::create_dir(&autogenerated_folder_path).ok();
-    generate_linker_script(&configuration)?;
+    generate_linker_script(&loadstone_path, &configuration)?;
     generate_top_level_module(&autogenerated_folder_path, configuration)?;
 
-    if std::env::var("CARGO_FEATURE_ECDSA_VERIFY").is_ok() {
+    if configuration.security_configuration.security_mode == SecurityMode::P256ECDSA {
         generate_key(loadstone_path, configuration)?;
     }
     memory_map::generate(
@@ -45,6 +59,26 @@ pub fn generate_modules<P: AsRef<Path>>(
     )?;
     pins::generate(&autogenerated_folder_path, &configuration)?;
     devices::generate(&autogenerated_folder_path, &configuration)?;
+    descriptor::generate(&autogenerated_folder_path, configuration)?;
+    generate_report(&autogenerated_folder_path, configuration)?;
+
+    // Applications for the bootable bank can link against these instead of their own.
+    let bootable_index = configuration.memory_configuration.internal_memory_map.bootable_index;
+    if let Some(index) = bootable_index {
+        let application_path = autogenerated_folder_path.join("application");
+        generate_application_linker_scripts(application_path, configuration, index)?;
+    }
+    Ok(())
+}
+
+/// Writes the memory map report next to the generated sources, as Markdown and SVG.
+fn generate_report<P: AsRef<Path>>(
+    autogenerated_folder_path: P,
+    configuration: &Configuration,
+) -> Result<()> {
+    let folder = autogenerated_folder_path.as_ref();
+    fs::write(folder.join("memory_map.md"), report::markdown(configuration))?;
+    fs::write(folder.join("memory_map.svg"), report::svg(configuration))?;
     Ok(())
 }
 
@@ -75,33 +109,19 @@ fn generate_top_level_module<P: AsRef<Path>>(
     let filename = autogenerated_folder_path.as_ref().join("mod.rs");
     let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&filename)?;
 
+    // The configuration has been validated, so every enabled feature is supported.
     let (serial_enabled, recovery_enabled) = if let Serial::Enabled { recovery_enabled, .. } =
         configuration.feature_configuration.serial
     {
-        if !Serial::supported(&configuration.port) {
-            panic!(
-                "Serial features enabled for a port that doesn't support them: {:?}",
-                configuration.port
-            );
-        }
         (true, recovery_enabled)
     } else {
         (false, false)
     };
 
-    let boot_time_metrics_enabled = if let BootMetrics::Enabled { timing: true } =
-        &configuration.feature_configuration.boot_metrics
-    {
-        if !BootMetrics::timing_supported(&configuration.port) {
-            panic!(
-                "Timing features enabled for a port that doesn't support them: {:?}",
-                configuration.port
-            );
-        }
-        true
-    } else {
-        false
-    };
+    let boot_time_metrics_enabled = matches!(
+        configuration.feature_configuration.boot_metrics,
+        BootMetrics::Enabled { timing: true }
+    );
 
     let loadstone_greeting = match &configuration.feature_configuration.greetings {
         Greetings::Default => LitStr::new("-- Loadstone --", Span::call_site()),
@@ -112,8 +132,25 @@ fn generate_top_level_module<P: AsRef<Path>>(
         Greetings::Custom { demo,..} => LitStr::new(&demo, Span::call_site()),
     };
 
-    let update_signal = configuration.feature_configuration.update_signal;
-    let update_signal_enabled = matches!(update_signal, UpdateSignal::Enabled);
+    let update_signal_enabled = configuration.feature_configuration.update_signal.enabled();
+
+    let recovery_menu_enabled = if let RecoveryPin::Enabled { menu, .. } =
+        &configuration.feature_configuration.recovery_pin
+    {
+        *menu
+    } else {
+        false
+    };
+
+    let recovery_policy = &configuration.feature_configuration.recovery_policy;
+    let recovery_timeout_ms = match recovery_policy.timeout_s {
+        Some(seconds) => {
+            let milliseconds = seconds.saturating_mul(1000);
+            quote! { Some(#milliseconds) }
+        }
+        None => quote! { None },
+    };
+    let recovery_max_attempts = recovery_policy.max_attempts.max(1);
 
     let code = quote! {
         //! This entire module is autogenerated. Don't modify it manually!
@@ -121,6 +158,7 @@ fn generate_top_level_module<P: AsRef<Path>>(
         pub mod memory_map;
         pub mod pin_configuration;
         pub mod devices;
+        pub mod descriptor;
 
         #[allow(unused)]
         pub const SERIAL_ENABLED: bool = #serial_enabled;
@@ -134,6 +172,12 @@ fn generate_top_level_module<P: AsRef<Path>>(
         pub const DEMO_APP_GREETING: &str = #demo_app_greeting;
         #[allow(unused)]
         pub const UPDATE_SIGNAL_ENABLED: bool = #update_signal_enabled;
+        #[allow(unused)]
+        pub const RECOVERY_MENU_ENABLED: bool = #recovery_menu_enabled;
+        #[allow(unused)]
+        pub const RECOVERY_TIMEOUT_MS: Option<u32> = #recovery_timeout_ms;
+        #[allow(unused)]
+        pub const RECOVERY_MAX_ATTEMPTS: u32 = #recovery_max_attempts;
     };
 
     file.write_all(format!("{}", code).as_bytes())?;
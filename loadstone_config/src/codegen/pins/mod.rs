@@ -1,6 +1,6 @@
-use crate::{port, Configuration};
+use crate::{features::Serial, pins::PeripheralPin, port, Configuration};
 use anyhow::Result;
-use quote::quote;
+use quote::{format_ident, quote, TokenStreamExt};
 use std::{
     fs::{File, OpenOptions},
     io::Write,
@@ -27,10 +27,45 @@ pub fn generate<P: AsRef<Path>>(
     Ok(())
 }
 
-fn generate_efm32gg(_configuration: &Configuration, file: &mut File) -> Result<()> {
-    let code = quote! {
+fn generate_efm32gg(configuration: &Configuration, file: &mut File) -> Result<()> {
+    let mut code = quote! {
+        use blue_hal::efm32pac;
         pub use blue_hal::hal::null::NullFlash as ExternalFlash;
+        pub use crate::ports::wgm160p::serial::{Usart as Serial, UsartPins};
     };
+
+    if let Serial::Enabled { tx_pin, rx_pin, .. } = &configuration.feature_configuration.serial {
+        let mode_register = |pin: &PeripheralPin| {
+            let half = if pin.index < 8 { "l" } else { "h" };
+            format_ident!("p{}_mode{}", pin.bank, half)
+        };
+        let (tx_register, rx_register) = (mode_register(tx_pin), mode_register(rx_pin));
+        let tx_dout = format_ident!("p{}_dout", tx_pin.bank);
+        let tx_mode = format_ident!("mode{}", tx_pin.index);
+        let rx_mode = format_ident!("mode{}", rx_pin.index);
+        let tx_mask = 1u32 << tx_pin.index;
+        let (tx_location, rx_location) = (tx_pin.af_index as u8, rx_pin.af_index as u8);
+        code.append_all(quote! {
+            /// Configures the serial pins, and returns the locations they are routed through.
+            #[allow(unused)]
+            pub fn pins(gpio: &mut efm32pac::GPIO) -> Option<UsartPins> {
+                // NOTE(Safety): Only the GPIO clock enable bit is touched.
+                let cmu = unsafe { &*efm32pac::CMU::ptr() };
+                cmu.hfbusclken0.modify(|_, w| w.gpio().set_bit());
+                // The transmit line idles high.
+                gpio.#tx_dout.modify(|r, w| unsafe { w.bits(r.bits() | #tx_mask) });
+                gpio.#tx_register.modify(|_, w| w.#tx_mode().pushpull());
+                gpio.#rx_register.modify(|_, w| w.#rx_mode().input());
+                Some(UsartPins { tx_location: #tx_location, rx_location: #rx_location })
+            }
+        });
+    } else {
+        code.append_all(quote! {
+            #[allow(unused)]
+            pub fn pins(_gpio: &mut efm32pac::GPIO) -> Option<UsartPins> { None }
+        });
+    }
+
     file.write_all(format!("{}", code).as_bytes())?;
     Ok(())
 }
@@ -4,7 +4,12 @@ use quote::{format_ident, quote, TokenStreamExt};
 use std::{array::IntoIter, fs::File, io::Write};
 use syn::{Ident, Index};
 
-use crate::{features::Serial, Configuration};
+use crate::{
+    features::{RecoveryPin, Serial},
+    memory::ExternalFlashDriver,
+    pins::{self, PeripheralPin},
+    Configuration,
+};
 
 struct InputPinTokens {
     bank: char,
@@ -69,43 +74,38 @@ fn generate_pin_constructor(
             Box::new(None.into_iter())
         };
 
-    // TODO expose in configuration file
-    let qspi_pin_structs: Box<dyn Iterator<Item = Ident>> =
-        if configuration.memory_configuration.external_flash.is_some() {
-            Box::new(IntoIter::new([
-                format_ident!("gpiob"),
-                format_ident!("gpiog"),
-                format_ident!("gpiof"),
-                format_ident!("gpiof"),
-                format_ident!("gpiof"),
-                format_ident!("gpiof"),
-            ]))
+    let qspi_pin_structs = qspi_flash_pins(configuration)
+        .into_iter()
+        .map(|(pin, _)| format_ident!("gpio{}", pin.bank));
+    let qspi_pin_fields = qspi_flash_pins(configuration)
+        .into_iter()
+        .map(|(pin, _)| format_ident!("p{}{}", pin.bank, pin.index));
+
+    let recovery_pin_struct =
+        if let RecoveryPin::Enabled { pin, .. } = &configuration.feature_configuration.recovery_pin {
+            Some(format_ident!("gpio{}", pin.bank))
         } else {
-            Box::new(None.into_iter())
-        };
+            None
+        }
+        .into_iter();
 
-    let qspi_pin_fields: Box<dyn Iterator<Item = Ident>> =
-        if configuration.memory_configuration.external_flash.is_some() {
-            Box::new(IntoIter::new([
-                format_ident!("pb2"),
-                format_ident!("pg6"),
-                format_ident!("pf8"),
-                format_ident!("pf9"),
-                format_ident!("pf7"),
-                format_ident!("pf6"),
-            ]))
+    let recovery_pin_field =
+        if let RecoveryPin::Enabled { pin, .. } = &configuration.feature_configuration.recovery_pin {
+            Some(format_ident!("p{}{}", pin.bank, pin.index))
         } else {
-            Box::new(None.into_iter())
-        };
+            None
+        }
+        .into_iter();
 
     code.append_all(quote! {
         #[allow(unused)]
-        pub fn pins(#(#gpio_fields: stm32pac::#pac_gpio_fields),*, rcc: &mut stm32pac::RCC) -> (UsartPins, QspiPins) {
+        pub fn pins(#(#gpio_fields: stm32pac::#pac_gpio_fields),*, rcc: &mut stm32pac::RCC) -> (UsartPins, QspiPins, RecoveryPinInput) {
 
             #(let #gpio_fields = #gpio_fields.split(rcc);)*
             (
                 (#(#serial_pin_structs.#serial_pin_fields),*),
-                (#(#qspi_pin_structs.#qspi_pin_fields),*)
+                (#(#qspi_pin_structs.#qspi_pin_fields),*),
+                (#(#recovery_pin_struct.#recovery_pin_field)*)
             )
 
         }
@@ -139,13 +139,26 @@ fn generate_imports_and_types(
             pub type Serial = blue_hal::hal::null::NullSerial;
         });
     }
-    if let Some(_) = &configuration.memory_configuration.external_flash {
+    let qspi_flash_pins = qspi_flash_pins(configuration);
+    if !qspi_flash_pins.is_empty() {
+        let qspi_pin_types = qspi_flash_pins.iter().map(|(pin, _)| {
+            let pin_type = format_ident!("P{}{}", pin.bank, pin.index);
+            let af = format_ident!("AF{}", pin.af_index);
+            quote! { #pin_type<#af> }
+        });
+        let external_flash_driver = match configuration.memory_configuration.external_flash {
+            Some(ref chip) if chip.driver() == ExternalFlashDriver::Sfdp => {
+                quote! { crate::devices::sfdp_flash::SfdpFlash }
+            }
+            _ => quote! { blue_hal::drivers::micron::n25q128a_flash::MicronN25q128a },
+        };
+        let qspi_mode =
+            format_ident!("{}", format!("{:?}", configuration.memory_configuration.qspi.mode));
         code.append_all(quote! {
-            use blue_hal::drivers::micron::n25q128a_flash::MicronN25q128a;
             use blue_hal::drivers::stm32f4::systick::SysTick;
-            pub type QspiPins = (Pb2<AF9>, Pg6<AF10>, Pf8<AF10>, Pf9<AF10>, Pf7<AF9>, Pf6<AF9>);
-            pub type Qspi = QuadSpi<QspiPins, mode::Single>;
-            pub type ExternalFlash = MicronN25q128a<Qspi, SysTick>;
+            pub type QspiPins = (#(#qspi_pin_types),*);
+            pub type Qspi = QuadSpi<QspiPins, mode::#qspi_mode>;
+            pub type ExternalFlash = #external_flash_driver<Qspi, SysTick>;
             #[allow(unused_imports)]
             pub use blue_hal::drivers::stm32f4::qspi::{
                 self, mode, QuadSpi,
@@ -165,6 +178,29 @@ fn generate_imports_and_types(
             enable_gpio!();
         });
     }
+    if let RecoveryPin::Enabled { pin, active_high, .. } =
+        &configuration.feature_configuration.recovery_pin
+    {
+        let recovery_pin = format_ident!("P{}{}", pin.bank, pin.index);
+        let mode = recovery_pin_mode(*active_high);
+        code.append_all(quote! {
+            pub type RecoveryPinInput = #recovery_pin<Input<#mode>>;
+        });
+    } else {
+        code.append_all(quote! {
+            pub type RecoveryPinInput = ();
+        });
+    }
+}
+
+/// Active high recovery pins are pulled down so they read as deasserted
+/// when left floating, and vice versa.
+fn recovery_pin_mode(active_high: bool) -> Ident {
+    if active_high {
+        format_ident!("PullDown")
+    } else {
+        format_ident!("PullUp")
+    }
 }
 
 fn generate_gpio_macros(configuration: &Configuration, code: &mut quote::__private::TokenStream) {
@@ -198,11 +234,29 @@ fn generate_gpio_macros(configuration: &Configuration, code: &mut quote::__priva
     }
 }
 
-fn input_tokens(_configuration: &Configuration) -> Box<dyn Iterator<Item = InputPinTokens>> {
-    Box::new(IntoIter::new([
+fn input_tokens(configuration: &Configuration) -> Box<dyn Iterator<Item = InputPinTokens>> {
+    let default_inputs = IntoIter::new([
         InputPinTokens { bank: 'a', index: 0.into(), mode: format_ident!("Floating") },
         InputPinTokens { bank: 'a', index: 1.into(), mode: format_ident!("Floating") },
-    ]))
+    ]);
+
+    if let RecoveryPin::Enabled { pin, active_high, .. } =
+        &configuration.feature_configuration.recovery_pin
+    {
+        let recovery_pin = InputPinTokens {
+            bank: pin.bank.chars().nth(0).unwrap(),
+            index: (pin.index as usize).into(),
+            mode: recovery_pin_mode(*active_high),
+        };
+        let (bank, index) = (recovery_pin.bank, recovery_pin.index.clone());
+        Box::new(
+            default_inputs
+                .filter(move |t| t.bank != bank || t.index != index)
+                .chain(Some(recovery_pin)),
+        )
+    } else {
+        Box::new(default_inputs)
+    }
 }
 
 fn serial_tokens(configuration: &Configuration) -> Box<dyn Iterator<Item = SerialPinTokens>> {
@@ -231,47 +285,32 @@ fn serial_tokens(configuration: &Configuration) -> Box<dyn Iterator<Item = Seria
 fn qspi_flash_pin_tokens(
     configuration: &Configuration,
 ) -> Box<dyn Iterator<Item = QspiFlashPinTokens>> {
-    // TODO parse these from config file. They're currently hardcoded here
-    if let Some(_) = &configuration.memory_configuration.external_flash {
-        Box::new(IntoIter::new([
-            QspiFlashPinTokens {
-                bank: 'b',
-                index: 2.into(),
-                mode: format_ident!("AF9"),
-                earmark: format_ident!("QspiClk"),
-            },
-            QspiFlashPinTokens {
-                bank: 'f',
-                index: 6.into(),
-                mode: format_ident!("AF9"),
-                earmark: format_ident!("QspiSecondaryInput"),
-            },
-            QspiFlashPinTokens {
-                bank: 'f',
-                index: 7.into(),
-                mode: format_ident!("AF9"),
-                earmark: format_ident!("QspiSecondaryOutput"),
-            },
-            QspiFlashPinTokens {
-                bank: 'f',
-                index: 8.into(),
-                mode: format_ident!("AF10"),
-                earmark: format_ident!("QspiOutput"),
-            },
-            QspiFlashPinTokens {
-                bank: 'f',
-                index: 9.into(),
-                mode: format_ident!("AF10"),
-                earmark: format_ident!("QspiInput"),
-            },
-            QspiFlashPinTokens {
-                bank: 'g',
-                index: 6.into(),
-                mode: format_ident!("AF10"),
-                earmark: format_ident!("QspiChipSelect"),
-            },
-        ]))
-    } else {
-        Box::new(None.into_iter())
-    }
+    Box::new(qspi_flash_pins(configuration).into_iter().map(|(pin, earmark)| {
+        QspiFlashPinTokens {
+            bank: pin.bank.chars().nth(0).unwrap(),
+            index: (pin.index as usize).into(),
+            mode: format_ident!("AF{}", pin.af_index),
+            earmark,
+        }
+    }))
+}
+
+/// External flash pins for this port, in the order the QSPI driver takes them, along
+/// with their role. Empty if the configuration doesn't use external flash.
+fn qspi_flash_pins(configuration: &Configuration) -> Vec<(PeripheralPin, Ident)> {
+    let pins = match (
+        &configuration.memory_configuration.external_flash,
+        pins::qspi_flash(&configuration.port),
+    ) {
+        (Some(_), Some(pins)) => pins,
+        _ => return vec![],
+    };
+    vec![
+        (pins.clk, format_ident!("QspiClk")),
+        (pins.chip_select, format_ident!("QspiChipSelect")),
+        (pins.io0, format_ident!("QspiOutput")),
+        (pins.io1, format_ident!("QspiInput")),
+        (pins.io2, format_ident!("QspiSecondaryOutput")),
+        (pins.io3, format_ident!("QspiSecondaryInput")),
+    ]
 }
@@ -1,8 +1,15 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    ops::{Range, RangeInclusive},
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{pins::PeripheralPin, port::Port};
+use crate::{
+    memory::Bank,
+    pins::{InputPin, PeripheralPin},
+    port::Port,
+};
 
 /// Collection of Loadstone features that are optional or
 /// somehow configurable.
@@ -12,6 +19,12 @@ pub struct FeatureConfiguration {
     pub boot_metrics: BootMetrics,
     pub update_signal: UpdateSignal,
     pub greetings: Greetings,
+    #[serde(default)]
+    pub recovery_pin: RecoveryPin,
+    #[serde(default = "RecoveryPolicy::unbounded")]
+    pub recovery_policy: RecoveryPolicy,
+    pub flash_protection: FlashProtection,
+    pub embedded_layout: EmbeddedLayout,
 }
 
 /// Feature that governs whether loadstone will relay boot information
@@ -43,11 +56,21 @@ impl Default for BootMetrics {
 }
 
 impl BootMetrics {
+    /// Loadstone leaves boot metrics for the application right below this RAM address,
+    /// whether the feature is enabled or not. Must match `src/devices/boot_metrics.rs`,
+    /// and fall within the RAM of every port and board.
+    pub const RAM_END: u32 = 0x2001_0000;
+    /// Size of the boot metrics struct, as laid out in RAM by Loadstone.
+    pub const SIZE: u32 = 28;
+
+    /// RAM that the application must not use before it has read the boot metrics.
+    pub fn ram_range() -> Range<u32> { Self::RAM_END - Self::SIZE..Self::RAM_END }
+
     /// Whether a given port is capable of recording boot timing information.
     pub fn timing_supported(port: &Port) -> bool {
         match port {
-            Port::Stm32F412 => true,
-            Port::Wgm160P => false,
+            Port::Stm32F407 | Port::Stm32F412 | Port::Stm32F429 | Port::Stm32F469 => true,
+            Port::Wgm160P => true,
         }
     }
 }
@@ -63,11 +86,70 @@ pub enum Serial {
         /// Hardware pin for serial transmission (from loadstone's perspective).
         tx_pin: PeripheralPin,
         /// Hardware pin for serial reception (from loadstone's perspective).
-        rx_pin: PeripheralPin
+        rx_pin: PeripheralPin,
+        /// Baud rate and framing of the serial link.
+        link: SerialLink,
     },
     Disabled,
 }
 
+/// Baud rate and framing of a serial link. Data is always eight bits wide.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SerialLink {
+    pub baud_rate: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for SerialLink {
+    fn default() -> Self {
+        Self { baud_rate: 115200, parity: Parity::None, stop_bits: StopBits::One }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+impl SerialLink {
+    /// Baud rates a port can generate reliably from its peripheral clocks.
+    pub fn baud_rate_range(port: &Port) -> RangeInclusive<u32> {
+        match port {
+            // Limited by USART2, which is clocked from the slower APB1 bus.
+            Port::Stm32F407 | Port::Stm32F412 | Port::Stm32F429 | Port::Stm32F469 => {
+                1200..=2_000_000
+            }
+            // The USART runs from the HFRCO at 19MHz, with 16x oversampling.
+            Port::Wgm160P => 1200..=1_000_000,
+        }
+    }
+
+    /// Returns every setting of the link the port can't honour.
+    pub fn problems(&self, port: &Port) -> Vec<String> {
+        let range = Self::baud_rate_range(port);
+        if range.contains(&self.baud_rate) {
+            vec![]
+        } else {
+            vec![format!(
+                "Serial baud rate {} is out of the {}-{} range supported by port {}",
+                self.baud_rate,
+                range.start(),
+                range.end(),
+                port
+            )]
+        }
+    }
+}
+
 impl Default for Serial {
     fn default() -> Self { Self::Disabled }
 }
@@ -76,20 +158,165 @@ impl Serial {
     /// Whether a port is capable of supporting serial communications.
     pub fn supported(port: &Port) -> bool {
         match port {
-            Port::Stm32F412 => true,
-            Port::Wgm160P => false,
+            Port::Stm32F407 | Port::Stm32F412 | Port::Stm32F429 | Port::Stm32F469 => true,
+            Port::Wgm160P => true,
         }
     }
 
     pub fn enabled(&self) -> bool { matches!(self, Serial::Enabled { .. }) }
 }
 
+/// Update signal feature. If enabled, the application can control when and how
+/// Loadstone updates, and read back the outcome.
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum UpdateSignal {
     Disabled,
+    /// The signal is kept in backup registers, which survive resets but not
+    /// full power loss.
     Enabled,
+    /// The signal is kept in a reserved region of MCU flash, which survives
+    /// power loss. The region must span whole flash sectors, and can't overlap
+    /// Loadstone or any bank.
+    Flash { start_address: u32, size_kb: u32 },
 }
 
 impl Default for UpdateSignal {
     fn default() -> Self { UpdateSignal::Disabled }
 }
+
+impl UpdateSignal {
+    /// Whether a port has backup registers to keep the update signal in.
+    pub fn registers_supported(port: &Port) -> bool {
+        match port {
+            Port::Stm32F407 | Port::Stm32F412 | Port::Stm32F429 | Port::Stm32F469 => true,
+            Port::Wgm160P => false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool { !matches!(self, UpdateSignal::Disabled) }
+
+    /// Flash region reserved for the update signal, if it's kept in flash.
+    pub fn flash_region(&self) -> Option<Bank> {
+        match self {
+            UpdateSignal::Flash { start_address, size_kb } => {
+                Some(Bank { start_address: *start_address, size_kb: *size_kb })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Limits on a serial recovery session, so a device with no host attached
+/// doesn't remain in recovery mode indefinitely. Only relevant when serial
+/// recovery is enabled.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RecoveryPolicy {
+    /// Seconds of silence from the sender after which recovery mode is
+    /// abandoned. `None` waits indefinitely.
+    pub timeout_s: Option<u32>,
+    /// Failed transfers tolerated before leaving recovery mode.
+    pub max_attempts: u32,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self { Self { timeout_s: Some(60), max_attempts: 3 } }
+}
+
+impl RecoveryPolicy {
+    /// Recovery as it behaved before policies existed: a single attempt, waiting
+    /// indefinitely. Configuration files that predate them keep this behaviour.
+    pub fn unbounded() -> Self { Self { timeout_s: None, max_attempts: 1 } }
+}
+
+/// Recovery pin feature. If enabled, Loadstone samples a GPIO pin at the start of the
+/// boot process and, when asserted, enters serial recovery mode even if a valid image
+/// is available.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RecoveryPin {
+    Enabled {
+        /// Hardware pin sampled at boot.
+        pin: InputPin,
+        /// Whether the pin is asserted when high (pulled down internally) or when
+        /// low (pulled up internally).
+        active_high: bool,
+        /// If enabled, an asserted pin leads to a serial menu offering recovery,
+        /// normal boot or reboot, rather than going straight into recovery.
+        menu: bool,
+    },
+    Disabled,
+}
+
+impl Default for RecoveryPin {
+    fn default() -> Self { Self::Disabled }
+}
+
+impl RecoveryPin {
+    /// Whether a port is capable of sampling a recovery pin.
+    pub fn supported(port: &Port) -> bool {
+        match port {
+            Port::Stm32F407 | Port::Stm32F412 | Port::Stm32F429 | Port::Stm32F469 => true,
+            Port::Wgm160P => false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool { matches!(self, RecoveryPin::Enabled { .. }) }
+}
+
+/// Flash protection feature. If enabled, Loadstone write-protects its own flash region
+/// (and optionally the golden bank) through the MCU option bytes on first boot, so a
+/// faulty application can't overwrite it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum FlashProtection {
+    Enabled {
+        /// Also write-protect the golden bank, if it resides in MCU flash. Recovery
+        /// mode lifts the protection temporarily to store a new golden image.
+        golden_bank: bool,
+        /// Read-out protection level programmed alongside write protection.
+        readout_protection: ReadoutProtection,
+    },
+    Disabled,
+}
+
+impl Default for FlashProtection {
+    fn default() -> Self { Self::Disabled }
+}
+
+impl FlashProtection {
+    /// Whether a port is capable of protecting its MCU flash. Dual bank parts
+    /// aren't supported yet, as their second bank has its own option bytes.
+    pub fn supported(port: &Port) -> bool {
+        match port {
+            Port::Stm32F407 | Port::Stm32F412 => true,
+            Port::Stm32F429 | Port::Stm32F469 => false,
+            Port::Wgm160P => false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool { matches!(self, FlashProtection::Enabled { .. }) }
+}
+
+/// Read-out protection levels, blocking access to flash contents through the debug interface.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ReadoutProtection {
+    Disabled,
+    /// Reversible, at the cost of a full flash erase.
+    Level1,
+    /// Permanently disables debugging and locks the option bytes. Irreversible.
+    Level2,
+}
+
+impl Default for ReadoutProtection {
+    fn default() -> Self { Self::Disabled }
+}
+
+/// Compact copy of the memory map embedded in the Loadstone binary, so host tools
+/// can read the layout straight from a device dump.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum EmbeddedLayout {
+    Enabled,
+    Disabled,
+}
+
+impl Default for EmbeddedLayout {
+    fn default() -> Self { Self::Disabled }
+}
@@ -11,18 +11,27 @@
 
 use std::{array::IntoIter, fmt::Display};
 
-use features::{BootMetrics, FeatureConfiguration, Serial};
-use memory::{external_flash, MemoryConfiguration};
-use port::Port;
+use board::Board;
+use features::{
+    BootMetrics, FeatureConfiguration, FlashProtection, RecoveryPin, Serial, UpdateSignal,
+};
+use memory::{FlashChip, MemoryConfiguration};
+use port::{LinkerScriptConstants, Port};
+use schema::{LoadError, SchemaVersion};
 use security::{SecurityConfiguration, SecurityMode};
 use serde::{Deserialize, Serialize};
+use validation::{Problem, Section};
 
 pub mod port;
+pub mod board;
 pub mod pins;
 pub mod memory;
 pub mod features;
 pub mod security;
 pub mod codegen;
+pub mod validation;
+pub mod schema;
+pub mod report;
 
 #[derive(Serialize, Deserialize, Default, Debug)]
 /// Defines all configuration for a "codegen" loadstone port. This struct
@@ -30,8 +39,14 @@ pub mod codegen;
 /// into a .ron file, which will be read by the loadstone `build.rs` script
 /// and turned into the port source.
 pub struct Configuration {
+    /// Version of the file format, so files written by older versions of Loadstone
+    /// can be upgraded when loaded.
+    pub schema_version: SchemaVersion,
     /// The target chip, usually defined at the chip subfamily level (e.g stm32f412).
     pub port: Port,
+    /// Optional description of a custom board built around the port's MCU. If present,
+    /// its memory layout is used instead of the one predefined for the port.
+    pub board: Option<Board>,
     /// Internal and external flash configuration, including firmware image
     /// banks and bank sizes.
     pub memory_configuration: MemoryConfiguration,
@@ -42,15 +57,53 @@ pub struct Configuration {
 }
 
 impl Configuration {
-    /// True if the configuration is comprehensive enough to generate a loadstone binary.
-    pub fn complete(&self) -> bool { self.required_configuration_steps().count() == 0 }
+    /// Reads a configuration from the contents of a .ron file, upgrading it from an
+    /// older schema if necessary.
+    pub fn from_ron(source: &str) -> Result<Self, LoadError> { schema::from_ron(source) }
+
+    /// True if the configuration is comprehensive and consistent enough to generate a
+    /// loadstone binary.
+    pub fn complete(&self) -> bool { self.validate().is_empty() }
+
+    /// Returns every problem that prevents generating a loadstone binary from this
+    /// configuration, from missing steps to inconsistent memory maps or pins.
+    pub fn validate(&self) -> Vec<Problem> { validation::problems(self) }
+
+    /// MCU flash of the target, as described by the custom board if there is one.
+    pub fn internal_flash(&self) -> FlashChip {
+        match &self.board {
+            Some(board) => board.internal_flash.clone(),
+            None => memory::internal_flash(&self.port),
+        }
+    }
+
+    /// External flash chips available to the target, as described by the custom
+    /// board if there is one.
+    pub fn external_flash(&self) -> Vec<FlashChip> {
+        match &self.board {
+            Some(board) => board.external_flash.clone(),
+            None => memory::external_flash(&self.port).collect(),
+        }
+    }
+
+    /// Flash and RAM areas to propagate to the linker script, as described by the
+    /// custom board if there is one.
+    pub fn linker_script_constants(&self) -> Option<LinkerScriptConstants> {
+        match &self.board {
+            Some(board) => Some(board.linker_script_constants()),
+            None => self.port.linker_script_constants(),
+        }
+    }
 
     /// Returns an iterator over the feature flags that will be necessary to compile loadstone
     /// when using this configuration struct.
     pub fn required_feature_flags(&self) -> impl Iterator<Item = &'static str> {
         let mut flags = vec![];
         match self.port {
+            Port::Stm32F407 => flags.push("stm32f407"),
             Port::Stm32F412 => flags.push("stm32f412"),
+            Port::Stm32F429 => flags.push("stm32f429"),
+            Port::Stm32F469 => flags.push("stm32f469"),
             Port::Wgm160P => flags.push("wgm160p"),
         };
 
@@ -90,7 +143,36 @@ impl Configuration {
             }
         }
 
-        if !external_flash(&self.port).any(|f| Some(f) == self.memory_configuration.external_flash)
+        if let Serial::Enabled { link, .. } = &mut self.feature_configuration.serial {
+            let range = features::SerialLink::baud_rate_range(&self.port);
+            link.baud_rate = link.baud_rate.max(*range.start()).min(*range.end());
+        }
+
+        let serial_recovery_enabled = matches!(
+            self.feature_configuration.serial,
+            Serial::Enabled { recovery_enabled: true, .. }
+        );
+        if !features::RecoveryPin::supported(&self.port) || !serial_recovery_enabled {
+            self.feature_configuration.recovery_pin = RecoveryPin::Disabled;
+        }
+
+        let recovery_policy = &mut self.feature_configuration.recovery_policy;
+        recovery_policy.max_attempts = recovery_policy.max_attempts.max(1);
+
+        if matches!(self.feature_configuration.update_signal, UpdateSignal::Enabled)
+            && !features::UpdateSignal::registers_supported(&self.port)
+        {
+            self.feature_configuration.update_signal = UpdateSignal::Disabled;
+        }
+
+        if !features::FlashProtection::supported(&self.port) {
+            self.feature_configuration.flash_protection = FlashProtection::Disabled;
+        }
+
+        let available_external_flash = self.external_flash();
+        if !available_external_flash
+            .iter()
+            .any(|f| Some(f) == self.memory_configuration.external_flash.as_ref())
         {
             self.memory_configuration.external_flash = None;
         }
@@ -109,15 +191,32 @@ pub enum RequiredConfigurationStep {
     BootableBank,
 }
 
-impl Display for RequiredConfigurationStep {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(match self {
+impl RequiredConfigurationStep {
+    /// Area of the configuration the step belongs to.
+    pub fn section(&self) -> Section {
+        match self {
+            RequiredConfigurationStep::PublicKey => Section::Security,
+            RequiredConfigurationStep::SerialTxPin | RequiredConfigurationStep::SerialRxPin => {
+                Section::Features
+            }
+            RequiredConfigurationStep::BootableBank => Section::MemoryMap,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
             RequiredConfigurationStep::PublicKey => {
-                "[Security] Provide P256 ECDSA public key or enable CRC32 mode"
+                "Provide P256 ECDSA public key or enable CRC32 mode"
             }
-            RequiredConfigurationStep::SerialTxPin => "[Features] Define Serial Tx pin",
-            RequiredConfigurationStep::SerialRxPin => "[Features] Define Serial Rx pin",
-            RequiredConfigurationStep::BootableBank => "[Memory Map] Define a bootable bank",
-        })
+            RequiredConfigurationStep::SerialTxPin => "Define Serial Tx pin",
+            RequiredConfigurationStep::SerialRxPin => "Define Serial Rx pin",
+            RequiredConfigurationStep::BootableBank => "Define a bootable bank",
+        }
+    }
+}
+
+impl Display for RequiredConfigurationStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.section(), self.description())
     }
 }
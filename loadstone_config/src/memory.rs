@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::port::Port;
+use crate::{pins, port::Port};
 
 /// Helper macro for kilobytes in any type (simply multiplies by 1024).
 #[macro_export(local_inner_macros)]
@@ -23,6 +23,11 @@ pub struct Bank {
 impl Bank {
     /// Address immediately after the end of this bank.
     pub fn end_address(&self) -> u32 { self.start_address + self.size_kb * 1024 }
+
+    /// Whether this bank shares any address with another.
+    pub fn overlaps(&self, other: &Bank) -> bool {
+        self.start_address < other.end_address() && other.start_address < self.end_address()
+    }
 }
 
 /// Memory map for an internal (MCU) flash. This must contain the loadstone bootloader itself
@@ -62,6 +67,85 @@ pub struct MemoryConfiguration {
     pub external_memory_map: ExternalMemoryMap,
     pub external_flash: Option<FlashChip>,
     pub golden_index: Option<usize>,
+    /// Settings of the QSPI link to the external flash chip, if there is one.
+    pub qspi: QspiConfiguration,
+}
+
+/// Settings of the QSPI link to an external flash chip.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QspiConfiguration {
+    /// Number of lines used to transfer data. The chip must accept every
+    /// instruction the driver issues over that many lines. The current drivers
+    /// only support single line transfers.
+    pub mode: QspiMode,
+    /// The QSPI clock runs at the AHB clock divided by `clock_prescaler + 1`.
+    pub clock_prescaler: u8,
+    /// Milliseconds after which any flash operation fails, bar full chip erases.
+    pub timeout_ms: u32,
+}
+
+impl Default for QspiConfiguration {
+    fn default() -> Self { Self { mode: QspiMode::Single, clock_prescaler: 1, timeout_ms: 5000 } }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum QspiMode {
+    Single,
+    Dual,
+    Quad,
+}
+
+impl QspiConfiguration {
+    /// Returns every setting of the link the port can't honour. The link is
+    /// only checked if there is an external flash chip to drive.
+    pub fn problems(&self, port: &Port, external_flash: Option<&FlashChip>) -> Vec<String> {
+        let mut problems = vec![];
+        let chip = match external_flash {
+            Some(chip) => chip,
+            None => return problems,
+        };
+        if pins::qspi_flash(port).is_none() {
+            problems.push(format!(
+                "Port {} has no QSPI peripheral to drive external flash \"{}\"",
+                port, chip.name
+            ));
+        }
+        if self.mode != QspiMode::Single {
+            problems.push(format!(
+                "QSPI {:?} mode is not supported, the flash drivers only transfer over a single line",
+                self.mode
+            ));
+        }
+        if let Some(ahb_clock_hz) = Self::ahb_clock_hz(port) {
+            let clock_hz = ahb_clock_hz / (self.clock_prescaler as u32 + 1);
+            let max_clock_hz = chip.driver().max_read_clock_hz();
+            if clock_hz > max_clock_hz {
+                problems.push(format!(
+                    "QSPI clock prescaler {} runs external flash \"{}\" at {}MHz, above the {}MHz \
+                    its driver can read at",
+                    self.clock_prescaler,
+                    chip.name,
+                    clock_hz / 1_000_000,
+                    max_clock_hz / 1_000_000
+                ));
+            }
+        }
+        if self.timeout_ms == 0 {
+            problems.push("QSPI flash timeout must be at least one millisecond".to_owned());
+        }
+        problems
+    }
+
+    /// Clock the QSPI peripheral divides down, if the port has one.
+    fn ahb_clock_hz(port: &Port) -> Option<u32> {
+        match port {
+            // Every stm32f4 port runs from the same hardcoded 100MHz clock tree.
+            Port::Stm32F407 | Port::Stm32F412 | Port::Stm32F429 | Port::Stm32F469 => {
+                Some(100_000_000)
+            }
+            Port::Wgm160P => None,
+        }
+    }
 }
 
 impl MemoryConfiguration {
@@ -96,6 +180,13 @@ pub struct FlashChip {
 /// main MCU flash for Loadstone to correctly function.
 pub fn internal_flash(port: &Port) -> FlashChip {
     match port {
+        Port::Stm32F407 => FlashChip {
+            name: "STM32F407 MCU Flash".to_owned(),
+            internal: true,
+            start: 0x0800_0000,
+            end: 0x0810_0000,
+            region_size: KB!(16),
+        },
         Port::Stm32F412 => FlashChip {
             name: "STM32F412 MCU Flash".to_owned(),
             internal: true,
@@ -103,6 +194,20 @@ pub fn internal_flash(port: &Port) -> FlashChip {
             end: 0x0810_0000,
             region_size: KB!(16),
         },
+        Port::Stm32F429 => FlashChip {
+            name: "STM32F429 MCU Flash".to_owned(),
+            internal: true,
+            start: 0x0800_0000,
+            end: 0x0820_0000,
+            region_size: KB!(16),
+        },
+        Port::Stm32F469 => FlashChip {
+            name: "STM32F469 MCU Flash".to_owned(),
+            internal: true,
+            start: 0x0800_0000,
+            end: 0x0820_0000,
+            region_size: KB!(16),
+        },
         Port::Wgm160P => FlashChip {
             name: "EFM32GG11 MCU Flash".to_owned(),
             internal: true,
@@ -113,18 +218,75 @@ pub fn internal_flash(port: &Port) -> FlashChip {
     }
 }
 
+/// Drivers for external flash chips.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ExternalFlashDriver {
+    /// Dedicated driver for the Micron n25q128a.
+    MicronN25q128a,
+    /// Generic JEDEC NOR driver, which learns the chip geometry from its SFDP table.
+    Sfdp,
+}
+
+impl ExternalFlashDriver {
+    /// Fastest clock at which the driver can read. Both drivers read with the
+    /// plain READ (0x03) instruction, which is slower than the chips' fast reads.
+    pub fn max_read_clock_hz(&self) -> u32 {
+        match self {
+            ExternalFlashDriver::MicronN25q128a => 54_000_000,
+            // The lowest READ limit among the chips in the catalog.
+            ExternalFlashDriver::Sfdp => 50_000_000,
+        }
+    }
+}
+
+impl FlashChip {
+    /// Driver that handles this chip when used as external flash. Every chip
+    /// other than the Micron n25q128a goes through the generic SFDP driver.
+    pub fn driver(&self) -> ExternalFlashDriver {
+        if self.name.to_lowercase().contains("n25q128a") {
+            ExternalFlashDriver::MicronN25q128a
+        } else {
+            ExternalFlashDriver::Sfdp
+        }
+    }
+}
+
+/// Common JEDEC NOR chips, handled by the generic SFDP driver. All of them have
+/// 4KB sectors, and fit within three byte addressing.
+const SFDP_CATALOG: &[(&str, u32)] = &[
+    ("Winbond W25Q32JV", 4),
+    ("Winbond W25Q64JV", 8),
+    ("Winbond W25Q128JV", 16),
+    ("Macronix MX25L3233F", 4),
+    ("Macronix MX25L6433F", 8),
+    ("Macronix MX25L12835F", 16),
+    ("ISSI IS25LP032D", 4),
+    ("ISSI IS25LP064A", 8),
+    ("ISSI IS25LP128F", 16),
+];
+
 /// Returns an iterator over all the flash chips compatible with the current
 /// port (a driver exists for them).
 pub fn external_flash(port: &Port) -> impl Iterator<Item = FlashChip> {
-    match port {
-        Port::Stm32F412 => Some(FlashChip {
+    let chips: Vec<FlashChip> = match port {
+        Port::Stm32F412 | Port::Stm32F469 => Some(FlashChip {
             name: "Micron n25q128a".to_owned(),
             internal: false,
             start: 0x0000_0000,
             end: 0x00FF_FFFF,
             region_size: KB!(4),
         })
-        .into_iter(),
-        Port::Wgm160P => None.into_iter(),
-    }
+        .into_iter()
+        .chain(SFDP_CATALOG.iter().map(|(name, size_mb)| FlashChip {
+            name: (*name).to_owned(),
+            internal: false,
+            start: 0x0000_0000,
+            end: KB!(KB!(size_mb)) - 1,
+            region_size: KB!(4),
+        }))
+        .collect(),
+        // No QSPI peripheral, or no driver for it.
+        Port::Stm32F407 | Port::Stm32F429 | Port::Wgm160P => vec![],
+    };
+    chips.into_iter()
 }
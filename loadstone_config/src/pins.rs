@@ -30,12 +30,41 @@ impl PeripheralPin {
     }
 }
 
+impl PeripheralPin {
+    /// Whether this pin is the same physical pin as another, whatever their functions.
+    pub fn collides_with(&self, pin: &PeripheralPin) -> bool {
+        self.bank == pin.bank && self.index == pin.index
+    }
+}
+
 impl Display for PeripheralPin {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "P{}{}", self.bank, self.index)
     }
 }
 
+/// A pin configured as a raw digital input.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct InputPin {
+    /// Pin bank (the "B" in PB1).
+    pub bank: Bank,
+    /// Pin index (the "1" in PB1).
+    pub index: u32,
+}
+
+impl InputPin {
+    /// Whether this input pin is the same physical pin as a peripheral pin.
+    pub fn collides_with(&self, pin: &PeripheralPin) -> bool {
+        self.bank == pin.bank && self.index == pin.index
+    }
+}
+
+impl Display for InputPin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "P{}{}", self.bank, self.index)
+    }
+}
+
 /// Returns an iterator over the possible serial transmission pins for this port.
 pub fn serial_tx(port: &Port) -> Box<dyn Iterator<Item = PeripheralPin>> {
     match port {
@@ -49,7 +78,21 @@ pub fn serial_tx(port: &Port) -> Box<dyn Iterator<Item = PeripheralPin>> {
             PeripheralPin::new(Cow::from("USART6"), Cow::from("a"), 11, 8),
             PeripheralPin::new(Cow::from("USART6"), Cow::from("g"), 14, 8),
         ])),
-        Port::Wgm160P => Box::new(None.into_iter()),
+        Port::Stm32F407 | Port::Stm32F429 | Port::Stm32F469 => Box::new(IntoIter::new([
+            PeripheralPin::new(Cow::from("USART1"), Cow::from("a"), 9, 7),
+            PeripheralPin::new(Cow::from("USART1"), Cow::from("b"), 6, 7),
+            PeripheralPin::new(Cow::from("USART2"), Cow::from("a"), 2, 7),
+            PeripheralPin::new(Cow::from("USART2"), Cow::from("d"), 5, 7),
+            PeripheralPin::new(Cow::from("USART6"), Cow::from("c"), 6, 8),
+            PeripheralPin::new(Cow::from("USART6"), Cow::from("g"), 14, 8),
+        ])),
+        Port::Wgm160P => Box::new(IntoIter::new([
+            PeripheralPin::new(Cow::from("USART0"), Cow::from("e"), 10, 0),
+            PeripheralPin::new(Cow::from("USART0"), Cow::from("c"), 11, 2),
+            PeripheralPin::new(Cow::from("USART1"), Cow::from("c"), 0, 0),
+            PeripheralPin::new(Cow::from("USART1"), Cow::from("d"), 0, 1),
+            PeripheralPin::new(Cow::from("USART2"), Cow::from("c"), 2, 0),
+        ])),
     }
 }
 
@@ -66,6 +109,75 @@ pub fn serial_rx(port: &Port) -> Box<dyn Iterator<Item = PeripheralPin>> {
             PeripheralPin::new(Cow::from("USART6"), Cow::from("a"), 12, 8),
             PeripheralPin::new(Cow::from("USART6"), Cow::from("g"), 9, 8),
         ])),
+        Port::Stm32F407 | Port::Stm32F429 | Port::Stm32F469 => Box::new(IntoIter::new([
+            PeripheralPin::new(Cow::from("USART1"), Cow::from("a"), 10, 7),
+            PeripheralPin::new(Cow::from("USART1"), Cow::from("b"), 7, 7),
+            PeripheralPin::new(Cow::from("USART2"), Cow::from("a"), 3, 7),
+            PeripheralPin::new(Cow::from("USART2"), Cow::from("d"), 6, 7),
+            PeripheralPin::new(Cow::from("USART6"), Cow::from("c"), 7, 8),
+            PeripheralPin::new(Cow::from("USART6"), Cow::from("g"), 9, 8),
+        ])),
+        Port::Wgm160P => Box::new(IntoIter::new([
+            PeripheralPin::new(Cow::from("USART0"), Cow::from("e"), 11, 0),
+            PeripheralPin::new(Cow::from("USART0"), Cow::from("c"), 10, 2),
+            PeripheralPin::new(Cow::from("USART1"), Cow::from("c"), 1, 0),
+            PeripheralPin::new(Cow::from("USART1"), Cow::from("d"), 1, 1),
+            PeripheralPin::new(Cow::from("USART2"), Cow::from("c"), 3, 0),
+        ])),
+    }
+}
+
+/// Returns an iterator over the pins that may be sampled to force recovery mode in this port.
+pub fn recovery_pins(port: &Port) -> Box<dyn Iterator<Item = InputPin>> {
+    match port {
+        Port::Stm32F407 | Port::Stm32F412 | Port::Stm32F429 | Port::Stm32F469 => {
+            Box::new(('a'..='h').flat_map(|bank| {
+                (0..16).map(move |index| InputPin { bank: Cow::from(bank.to_string()), index })
+            }))
+        }
         Port::Wgm160P => Box::new(None.into_iter()),
     }
 }
+
+/// Pins wired to an external QSPI flash chip, by function. Only the first
+/// QSPI bank is used.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QspiFlashPins {
+    pub clk: PeripheralPin,
+    pub chip_select: PeripheralPin,
+    pub io0: PeripheralPin,
+    pub io1: PeripheralPin,
+    pub io2: PeripheralPin,
+    pub io3: PeripheralPin,
+}
+
+impl QspiFlashPins {
+    /// Every pin wired to the flash chip.
+    pub fn iter(&self) -> impl Iterator<Item = &PeripheralPin> {
+        IntoIter::new([&self.clk, &self.chip_select, &self.io0, &self.io1, &self.io2, &self.io3])
+    }
+}
+
+/// Returns the pins used to drive external flash in this port, if it supports any.
+pub fn qspi_flash(port: &Port) -> Option<QspiFlashPins> {
+    const QSPI: Cow<'static, str> = Cow::Borrowed("QUADSPI");
+    match port {
+        Port::Stm32F412 => Some(QspiFlashPins {
+            clk: PeripheralPin::new(QSPI, Cow::Borrowed("b"), 2, 9),
+            chip_select: PeripheralPin::new(QSPI, Cow::Borrowed("g"), 6, 10),
+            io0: PeripheralPin::new(QSPI, Cow::Borrowed("f"), 8, 10),
+            io1: PeripheralPin::new(QSPI, Cow::Borrowed("f"), 9, 10),
+            io2: PeripheralPin::new(QSPI, Cow::Borrowed("f"), 7, 9),
+            io3: PeripheralPin::new(QSPI, Cow::Borrowed("f"), 6, 9),
+        }),
+        Port::Stm32F469 => Some(QspiFlashPins {
+            clk: PeripheralPin::new(QSPI, Cow::Borrowed("f"), 10, 9),
+            chip_select: PeripheralPin::new(QSPI, Cow::Borrowed("b"), 6, 10),
+            io0: PeripheralPin::new(QSPI, Cow::Borrowed("f"), 8, 10),
+            io1: PeripheralPin::new(QSPI, Cow::Borrowed("f"), 9, 10),
+            io2: PeripheralPin::new(QSPI, Cow::Borrowed("f"), 7, 9),
+            io3: PeripheralPin::new(QSPI, Cow::Borrowed("f"), 6, 9),
+        }),
+        Port::Stm32F407 | Port::Stm32F429 | Port::Wgm160P => None,
+    }
+}
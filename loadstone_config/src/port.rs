@@ -8,7 +8,10 @@ use serde::{Deserialize, Serialize};
 /// may be more or less concrete depending on the available drivers.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, IntoEnumIterator)]
 pub enum Port {
+    Stm32F407,
     Stm32F412,
+    Stm32F429,
+    Stm32F469,
     Wgm160P,
 }
 
@@ -35,7 +38,10 @@ pub enum Subfamily {
 impl Display for Port {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
+            Port::Stm32F407 => "stm32f407",
             Port::Stm32F412 => "stm32f412",
+            Port::Stm32F429 => "stm32f429",
+            Port::Stm32F469 => "stm32f469",
             Port::Wgm160P => "wgm160p",
         })
     }
@@ -63,7 +69,7 @@ impl Port {
     /// Hardware family of this port.
     pub fn family(&self) -> Family {
         match self {
-            Port::Stm32F412 => Family::Stm32,
+            Port::Stm32F407 | Port::Stm32F412 | Port::Stm32F429 | Port::Stm32F469 => Family::Stm32,
             Port::Wgm160P => Family::Efm32,
         }
     }
@@ -71,7 +77,9 @@ impl Port {
     /// Hardware subfamily of this port.
     pub fn subfamily(&self) -> Subfamily {
         match self {
-            Port::Stm32F412 => Subfamily::Stm32f4,
+            Port::Stm32F407 | Port::Stm32F412 | Port::Stm32F429 | Port::Stm32F469 => {
+                Subfamily::Stm32f4
+            }
             Port::Wgm160P => Subfamily::Efm32Gg11,
         }
     }
@@ -81,10 +89,22 @@ impl Port {
     // We might consider making these configurable later, but the need hasn't come up yet.
     pub fn linker_script_constants(&self) -> Option<LinkerScriptConstants> {
         match self {
+            Port::Stm32F407 => Some(LinkerScriptConstants {
+                flash: LinkerArea { origin: 0x08000000, size: KB!(896) },
+                ram: LinkerArea { origin: 0x20000000, size: KB!(128) },
+            }),
             Port::Stm32F412 => Some(LinkerScriptConstants {
                 flash: LinkerArea { origin: 0x08000000, size: KB!(896) },
                 ram: LinkerArea { origin: 0x20000000, size: KB!(256) },
             }),
+            Port::Stm32F429 => Some(LinkerScriptConstants {
+                flash: LinkerArea { origin: 0x08000000, size: KB!(1920) },
+                ram: LinkerArea { origin: 0x20000000, size: KB!(192) },
+            }),
+            Port::Stm32F469 => Some(LinkerScriptConstants {
+                flash: LinkerArea { origin: 0x08000000, size: KB!(1920) },
+                ram: LinkerArea { origin: 0x20000000, size: KB!(320) },
+            }),
             Port::Wgm160P => Some(LinkerScriptConstants {
                 flash: LinkerArea { origin: 0x00000000, size: KB!(1024) },
                 ram: LinkerArea { origin: 0x20000000, size: KB!(128) },
@@ -0,0 +1,405 @@
+//! Human readable report of the memory map described by a configuration, for
+//! release documentation and for anyone who'd rather not read .ron. The same
+//! layout can be rendered as Markdown, as an ASCII diagram or as an SVG image.
+use std::fmt::Write;
+
+use crate::{features::BootMetrics, memory::Bank, Configuration, KB};
+
+/// Purpose of an area of memory.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Role {
+    Loadstone,
+    Bank { bootable: bool, golden: bool },
+    UpdateSignal,
+    BootMetrics,
+    Free,
+}
+
+/// A contiguous area of memory. `end` is the first address past it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Area {
+    pub name: String,
+    pub start: u64,
+    pub end: u64,
+    pub role: Role,
+}
+
+/// A flash chip or RAM, covered end to end by its areas in address order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Memory {
+    pub name: String,
+    pub start: u64,
+    pub end: u64,
+    pub areas: Vec<Area>,
+}
+
+impl Area {
+    fn new(name: String, start: u64, end: u64, role: Role) -> Self {
+        Self { name, start, end, role }
+    }
+
+    fn from_bank(name: String, bank: &Bank, role: Role) -> Self {
+        let start = bank.start_address as u64;
+        Self::new(name, start, start + KB!(bank.size_kb as u64), role)
+    }
+
+    fn notes(&self) -> &'static str {
+        match self.role {
+            Role::Bank { bootable: true, .. } => "bootable",
+            Role::Bank { golden: true, .. } => "golden",
+            _ => "",
+        }
+    }
+
+    /// Name of the area, followed by its notes if it has any.
+    fn label(&self) -> String {
+        match self.notes() {
+            "" => self.name.clone(),
+            notes => format!("{} ({})", self.name, notes),
+        }
+    }
+}
+
+impl Memory {
+    /// Sorts the areas and fills any gap between them with free space.
+    fn new(name: String, start: u64, end: u64, mut areas: Vec<Area>) -> Self {
+        areas.sort_by_key(|a| a.start);
+        let mut covered = vec![];
+        let mut cursor = start;
+        for area in areas {
+            if area.start > cursor {
+                covered.push(Area::new("Free".to_owned(), cursor, area.start, Role::Free));
+            }
+            cursor = cursor.max(area.end);
+            covered.push(area);
+        }
+        if end > cursor {
+            covered.push(Area::new("Free".to_owned(), cursor, end, Role::Free));
+        }
+        Self { name, start, end, areas: covered }
+    }
+}
+
+/// Every memory the configuration lays something out in: MCU flash, external
+/// flash if there is one, and the RAM holding the boot metrics.
+pub fn layout(configuration: &Configuration) -> Vec<Memory> {
+    let memory = &configuration.memory_configuration;
+    let internal = &memory.internal_memory_map;
+    let role = |index: usize| Role::Bank {
+        bootable: Some(index) == internal.bootable_index,
+        golden: Some(index) == memory.golden_index,
+    };
+
+    let internal_flash = configuration.internal_flash();
+    let mut areas = vec![Area::from_bank(
+        "Loadstone".to_owned(),
+        &Bank {
+            start_address: internal.bootloader_location,
+            size_kb: internal.bootloader_length_kb,
+        },
+        Role::Loadstone,
+    )];
+    areas.extend(
+        internal
+            .banks
+            .iter()
+            .enumerate()
+            .map(|(i, bank)| Area::from_bank(format!("Bank {}", i + 1), bank, role(i))),
+    );
+    if let Some(region) = configuration.feature_configuration.update_signal.flash_region() {
+        areas.push(Area::from_bank("Update signal".to_owned(), &region, Role::UpdateSignal));
+    }
+    let mut memories = vec![Memory::new(
+        internal_flash.name.clone(),
+        internal_flash.start as u64,
+        internal_flash.end as u64,
+        areas,
+    )];
+
+    if let Some(chip) = &memory.external_flash {
+        let offset = internal.banks.len();
+        let areas = memory
+            .external_memory_map
+            .banks
+            .iter()
+            .enumerate()
+            .map(|(i, bank)| {
+                Area::from_bank(format!("Bank {}", i + offset + 1), bank, role(i + offset))
+            })
+            .collect();
+        // External flash chips are described by their last address.
+        memories.push(Memory::new(
+            chip.name.clone(),
+            chip.start as u64,
+            chip.end as u64 + 1,
+            areas,
+        ));
+    }
+
+    if let Some(constants) = configuration.linker_script_constants() {
+        let metrics = BootMetrics::ram_range();
+        let origin = constants.ram.origin as u64;
+        memories.push(Memory::new(
+            "RAM".to_owned(),
+            origin,
+            origin + constants.ram.size as u64,
+            vec![Area::new(
+                "Boot metrics".to_owned(),
+                metrics.start as u64,
+                metrics.end as u64,
+                Role::BootMetrics,
+            )],
+        ));
+    }
+    memories
+}
+
+fn size(bytes: u64) -> String {
+    if bytes % KB!(1) == 0 {
+        format!("{}KB", bytes / KB!(1))
+    } else {
+        format!("{}B", bytes)
+    }
+}
+
+/// Markdown document with a table per memory, followed by the ASCII diagram.
+pub fn markdown(configuration: &Configuration) -> String {
+    let mut text = String::new();
+    let target = match &configuration.board {
+        Some(board) => format!("{} ({})", board.name, configuration.port),
+        None => configuration.port.to_string(),
+    };
+    writeln!(text, "# Loadstone memory map: {}", target).unwrap();
+    for memory in layout(configuration) {
+        writeln!(text, "\n## {} (0x{:08x} - 0x{:08x})\n", memory.name, memory.start, memory.end)
+            .unwrap();
+        writeln!(text, "| Area | Start | End | Size | Notes |").unwrap();
+        writeln!(text, "|------|-------|-----|------|-------|").unwrap();
+        for area in &memory.areas {
+            writeln!(
+                text,
+                "| {} | 0x{:08x} | 0x{:08x} | {} | {} |",
+                area.name,
+                area.start,
+                area.end,
+                size(area.end - area.start),
+                area.notes()
+            )
+            .unwrap();
+        }
+    }
+    writeln!(text, "\n```\n{}```", diagram(configuration)).unwrap();
+    text
+}
+
+/// Plain text diagram with a column of boxes per memory, one box per area.
+/// Boxes are not to scale.
+pub fn diagram(configuration: &Configuration) -> String {
+    const WIDTH: usize = 32;
+    let border = format!("+{}+", "-".repeat(WIDTH));
+    let mut text = String::new();
+    for memory in layout(configuration) {
+        writeln!(text, "{}", memory.name).unwrap();
+        for area in &memory.areas {
+            let label = area.label();
+            let size = size(area.end - area.start);
+            writeln!(text, "0x{:08x} {}", area.start, border).unwrap();
+            writeln!(
+                text,
+                "{:10} | {:<width$}{} |",
+                "",
+                label,
+                size,
+                width = WIDTH.saturating_sub(size.len() + 2)
+            )
+            .unwrap();
+        }
+        writeln!(text, "0x{:08x} {}\n", memory.end, border).unwrap();
+    }
+    text
+}
+
+/// SVG image with a horizontal bar per memory. Areas are to scale, except
+/// that tiny ones are widened enough to be seen.
+pub fn svg(configuration: &Configuration) -> String {
+    const BAR_WIDTH: f64 = 800.0;
+    const MIN_AREA_WIDTH: f64 = 6.0;
+    const ROW_HEIGHT: usize = 90;
+
+    let memories = layout(configuration);
+    let mut text = String::new();
+    writeln!(
+        text,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+        font-family=\"monospace\" font-size=\"11\">",
+        BAR_WIDTH as usize + 40,
+        memories.len() * ROW_HEIGHT + 10
+    )
+    .unwrap();
+    for (row, memory) in memories.iter().enumerate() {
+        let y = row * ROW_HEIGHT + 20;
+        writeln!(
+            text,
+            "  <text x=\"20\" y=\"{}\" font-weight=\"bold\">{} (0x{:08x} - 0x{:08x})</text>",
+            y,
+            escape(&memory.name),
+            memory.start,
+            memory.end
+        )
+        .unwrap();
+
+        let widths: Vec<f64> = memory
+            .areas
+            .iter()
+            .map(|a| {
+                let share = (a.end - a.start) as f64 / (memory.end - memory.start).max(1) as f64;
+                (share * BAR_WIDTH).max(MIN_AREA_WIDTH)
+            })
+            .collect();
+        let scale = BAR_WIDTH / widths.iter().sum::<f64>();
+        let mut x = 20.0;
+        for (area, width) in memory.areas.iter().zip(widths.iter().map(|w| w * scale)) {
+            let label = area.label();
+            writeln!(
+                text,
+                "  <rect x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"40\" fill=\"{}\" \
+                stroke=\"#333333\"><title>{}: 0x{:08x} - 0x{:08x}, {}</title></rect>",
+                x,
+                y + 8,
+                width,
+                color(area.role),
+                escape(&label),
+                area.start,
+                area.end,
+                size(area.end - area.start)
+            )
+            .unwrap();
+            // Only label areas wide enough to hold the text.
+            if width > label.len() as f64 * 7.0 + 4.0 {
+                writeln!(
+                    text,
+                    "  <text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+                    x + width / 2.0,
+                    y + 32,
+                    escape(&label)
+                )
+                .unwrap();
+            }
+            x += width;
+        }
+        writeln!(text, "  <text x=\"20\" y=\"{}\">0x{:08x}</text>", y + 62, memory.start).unwrap();
+        writeln!(
+            text,
+            "  <text x=\"{}\" y=\"{}\" text-anchor=\"end\">0x{:08x}</text>",
+            BAR_WIDTH as usize + 20,
+            y + 62,
+            memory.end
+        )
+        .unwrap();
+    }
+    writeln!(text, "</svg>").unwrap();
+    text
+}
+
+fn color(role: Role) -> &'static str {
+    match role {
+        Role::Loadstone => "#4e79a7",
+        Role::Bank { bootable: true, .. } => "#59a14f",
+        Role::Bank { golden: true, .. } => "#edc948",
+        Role::Bank { .. } => "#76b7b2",
+        Role::UpdateSignal => "#e15759",
+        Role::BootMetrics => "#b07aa1",
+        Role::Free => "#eeeeee",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{memory, port::Port};
+
+    fn configuration(banks: Vec<Bank>) -> Configuration {
+        let mut configuration = Configuration { port: Port::Stm32F412, ..Default::default() };
+        let map = &mut configuration.memory_configuration.internal_memory_map;
+        map.bootloader_location = 0x0800_0000;
+        map.bootloader_length_kb = 64;
+        map.banks = banks;
+        map.bootable_index = Some(0);
+        configuration
+    }
+
+    fn summary(memory: &Memory) -> Vec<(&str, u64, u64)> {
+        memory.areas.iter().map(|a| (a.name.as_str(), a.start, a.end)).collect()
+    }
+
+    #[test]
+    fn gaps_between_areas_are_free() {
+        let configuration = configuration(vec![Bank { start_address: 0x0802_0000, size_kb: 64 }]);
+        let memories = layout(&configuration);
+
+        assert_eq!(
+            vec![
+                ("Loadstone", 0x0800_0000, 0x0801_0000),
+                ("Free", 0x0801_0000, 0x0802_0000),
+                ("Bank 1", 0x0802_0000, 0x0803_0000),
+                ("Free", 0x0803_0000, 0x0810_0000),
+            ],
+            summary(&memories[0])
+        );
+        assert_eq!(Role::Free, memories[0].areas[1].role);
+        assert_eq!(Role::Bank { bootable: true, golden: false }, memories[0].areas[2].role);
+    }
+
+    #[test]
+    fn overlapping_banks_leave_no_free_space_between_them() {
+        let configuration =
+            configuration(vec![Bank { start_address: 0x0801_0000, size_kb: 128 }, Bank {
+                start_address: 0x0801_4000,
+                size_kb: 16,
+            }]);
+        let memories = layout(&configuration);
+
+        assert_eq!(
+            vec![
+                ("Loadstone", 0x0800_0000, 0x0801_0000),
+                ("Bank 1", 0x0801_0000, 0x0803_0000),
+                ("Bank 2", 0x0801_4000, 0x0801_8000),
+                ("Free", 0x0803_0000, 0x0810_0000),
+            ],
+            summary(&memories[0])
+        );
+    }
+
+    #[test]
+    fn external_flash_ends_past_its_last_address() {
+        let mut configuration =
+            configuration(vec![Bank { start_address: 0x0801_0000, size_kb: 128 }]);
+        let memory = &mut configuration.memory_configuration;
+        memory.external_flash = memory::external_flash(&Port::Stm32F412).next();
+        memory.external_memory_map.banks = vec![Bank { start_address: 0, size_kb: 1024 }];
+        memory.golden_index = Some(1);
+        let memories = layout(&configuration);
+
+        assert_eq!("Micron n25q128a", memories[1].name);
+        assert_eq!((0, 0x0100_0000), (memories[1].start, memories[1].end));
+        assert_eq!(
+            vec![("Bank 2", 0, 0x0010_0000), ("Free", 0x0010_0000, 0x0100_0000)],
+            summary(&memories[1])
+        );
+        assert_eq!(Role::Bank { bootable: false, golden: true }, memories[1].areas[0].role);
+    }
+
+    #[test]
+    fn markdown_tables_show_sizes_in_kilobytes_or_bytes() {
+        let configuration = configuration(vec![Bank { start_address: 0x0801_0000, size_kb: 128 }]);
+        let markdown = markdown(&configuration);
+
+        assert!(markdown.contains("| Loadstone | 0x08000000 | 0x08010000 | 64KB |  |\n"));
+        assert!(markdown.contains("| Bank 1 | 0x08010000 | 0x08030000 | 128KB | bootable |\n"));
+        assert!(markdown.contains("| Boot metrics | 0x2000ffe4 | 0x20010000 | 28B |  |\n"));
+    }
+}
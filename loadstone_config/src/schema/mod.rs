@@ -0,0 +1,273 @@
+//! Versioning of the configuration file format. Every file written by
+//! `loadstone_front` records the schema it follows, and files following an
+//! older schema are upgraded on load, one version at a time, so they keep
+//! building after the configuration grows new fields or changes an enum.
+//!
+//! When changing the layout of `Configuration` in a way that older files
+//! would no longer deserialize into:
+//!
+//! 1) Copy the types that are about to change into a new `vN` module, where
+//! `N` is the current `SCHEMA_VERSION`, and give it a migration to the types
+//! that replace them.
+//! 2) Bump `SCHEMA_VERSION` and make the change.
+//! 3) Add the new step to `from_ron`.
+use std::fmt::Display;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::Configuration;
+
+mod v1;
+mod v2;
+
+/// Schema followed by the configuration files this version of Loadstone writes.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Schema assumed for files that don't record one, as they predate versioning.
+const UNVERSIONED: u32 = 1;
+
+/// Version of the schema a configuration follows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SchemaVersion(pub u32);
+
+impl Default for SchemaVersion {
+    fn default() -> Self { Self(SCHEMA_VERSION) }
+}
+
+impl Display for SchemaVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { self.0.fmt(f) }
+}
+
+/// Reason why a configuration file couldn't be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file follows a schema newer than this version of Loadstone knows about.
+    UnsupportedVersion(u32),
+    /// The file isn't valid for the schema it follows, or isn't valid .ron at
+    /// all, in which case there's no version to speak of. `path` leads to the
+    /// offending field, e.g. `feature_configuration.serial`.
+    InvalidField { version: Option<u32>, path: String, cause: String },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "Configuration schema version {} is not supported. This version of \
+                    Loadstone reads schema versions {} to {}",
+                    version, UNVERSIONED, SCHEMA_VERSION
+                )?;
+                if *version > SCHEMA_VERSION {
+                    f.write_str(", so the file was likely written by a newer one")?;
+                }
+                Ok(())
+            }
+            LoadError::InvalidField { version, path, cause } => {
+                f.write_str("Invalid configuration")?;
+                if let Some(version) = version {
+                    write!(f, " (schema version {})", version)?;
+                }
+                if path != "." {
+                    write!(f, " at `{}`", path)?;
+                }
+                write!(f, ": {}", cause)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Just enough of a configuration file to tell which schema it follows.
+#[derive(Deserialize)]
+struct Header {
+    #[serde(default = "unversioned")]
+    schema_version: u32,
+}
+
+fn unversioned() -> u32 { UNVERSIONED }
+
+/// Reads a configuration from the contents of a .ron file, upgrading it to
+/// the current schema if it follows an older one.
+pub fn from_ron(source: &str) -> Result<Configuration, LoadError> {
+    let version = deserialize::<Header>(source, None)?.schema_version;
+    let configuration = match version {
+        1 => deserialize::<v1::Configuration>(source, Some(version))?.migrate().migrate(),
+        2 => deserialize::<v2::Configuration>(source, Some(version))?.migrate(),
+        SCHEMA_VERSION => deserialize::<Configuration>(source, Some(version))?,
+        _ => return Err(LoadError::UnsupportedVersion(version)),
+    };
+    Ok(Configuration { schema_version: SchemaVersion::default(), ..configuration })
+}
+
+/// Deserializes a .ron source, keeping track of the field that caused an error.
+fn deserialize<T: DeserializeOwned>(source: &str, version: Option<u32>) -> Result<T, LoadError> {
+    let error = |path: String, cause: ron::Error| LoadError::InvalidField {
+        version,
+        path,
+        cause: cause.to_string(),
+    };
+    let mut deserializer =
+        ron::de::Deserializer::from_str(source).map_err(|e| error(".".to_owned(), e))?;
+    let value = serde_path_to_error::deserialize(&mut deserializer)
+        .map_err(|e| error(e.path().to_string(), e.into_inner()))?;
+    deserializer.end().map_err(|e| error(".".to_owned(), e))?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        features::{EmbeddedLayout, FlashProtection, RecoveryPin, RecoveryPolicy, Serial},
+        memory::{QspiConfiguration, QspiMode},
+        port::Port,
+    };
+
+    /// Configuration as written before the schema was versioned, with none of the
+    /// fields added since.
+    const UNVERSIONED_FILE: &str = r#"(
+        port: Stm32F412,
+        memory_configuration: (
+            internal_memory_map: (
+                bootloader_location: 134217728,
+                bootloader_length_kb: 64,
+                banks: [(start_address: 134283264, size_kb: 128)],
+                bootable_index: Some(0),
+            ),
+            external_memory_map: (banks: [(start_address: 0, size_kb: 1024)]),
+            external_flash: Some((
+                name: "Micron n25q128a",
+                internal: false,
+                start: 0,
+                end: 16777215,
+                region_size: 4096,
+            )),
+            golden_index: Some(1),
+        ),
+        feature_configuration: (
+            serial: Enabled(
+                recovery_enabled: true,
+                tx_pin: (peripheral: "USART6", bank: "g", index: 14, af_index: 8),
+                rx_pin: (peripheral: "USART6", bank: "g", index: 9, af_index: 8),
+            ),
+            boot_metrics: Enabled(timing: true),
+            update_signal: Enabled,
+            greetings: Default,
+        ),
+        security_configuration: (security_mode: Crc, verifying_key_raw: ""),
+    )"#;
+
+    /// Configuration following schema version 2, with every field explicit.
+    const V2_FILE: &str = r#"(
+        schema_version: 2,
+        port: Stm32F412,
+        board: None,
+        memory_configuration: (
+            internal_memory_map: (
+                bootloader_location: 134217728,
+                bootloader_length_kb: 64,
+                banks: [(start_address: 134283264, size_kb: 128)],
+                bootable_index: Some(0),
+            ),
+            external_memory_map: (banks: []),
+            external_flash: None,
+            golden_index: None,
+            qspi: (mode: Single, clock_prescaler: 3, timeout_ms: 1000),
+        ),
+        feature_configuration: (
+            serial: Enabled(
+                recovery_enabled: true,
+                tx_pin: (peripheral: "USART6", bank: "g", index: 14, af_index: 8),
+                rx_pin: (peripheral: "USART6", bank: "g", index: 9, af_index: 8),
+                link: (baud_rate: 9600, parity: Even, stop_bits: Two),
+            ),
+            boot_metrics: Disabled,
+            update_signal: Disabled,
+            greetings: Default,
+            recovery_pin: Enabled(pin: (bank: "a", index: 0), active_high: true, menu: false),
+            recovery_policy: (timeout_s: Some(30), max_attempts: 2),
+            flash_protection: Enabled(golden_bank: false, readout_protection: Level1),
+        ),
+        security_configuration: (security_mode: Crc, verifying_key_raw: ""),
+    )"#;
+
+    #[test]
+    fn unversioned_files_are_upgraded_with_previous_behaviour() {
+        let configuration = from_ron(UNVERSIONED_FILE).unwrap();
+
+        assert_eq!(SchemaVersion(SCHEMA_VERSION), configuration.schema_version);
+        assert_eq!(Port::Stm32F412, configuration.port);
+        assert!(configuration.board.is_none());
+        let memory = &configuration.memory_configuration;
+        assert_eq!(134283264, memory.internal_memory_map.banks[0].start_address);
+        assert_eq!(Some(1), memory.golden_index);
+        assert_eq!(QspiConfiguration::default(), memory.qspi);
+        let features = &configuration.feature_configuration;
+        assert!(
+            matches!(&features.serial, Serial::Enabled { link, .. } if *link == Default::default())
+        );
+        assert!(matches!(features.recovery_pin, RecoveryPin::Disabled));
+        assert_eq!(RecoveryPolicy::unbounded(), features.recovery_policy);
+        assert!(matches!(features.flash_protection, FlashProtection::Disabled));
+        assert_eq!(EmbeddedLayout::Disabled, features.embedded_layout);
+    }
+
+    #[test]
+    fn version_2_files_keep_every_setting() {
+        let configuration = from_ron(V2_FILE).unwrap();
+
+        assert_eq!(SchemaVersion(SCHEMA_VERSION), configuration.schema_version);
+        let qspi = &configuration.memory_configuration.qspi;
+        assert_eq!(
+            QspiConfiguration { mode: QspiMode::Single, clock_prescaler: 3, timeout_ms: 1000 },
+            *qspi
+        );
+        let features = &configuration.feature_configuration;
+        assert!(matches!(&features.serial, Serial::Enabled { link, .. } if link.baud_rate == 9600));
+        assert!(matches!(features.recovery_pin, RecoveryPin::Enabled { active_high: true, .. }));
+        assert_eq!(
+            RecoveryPolicy { timeout_s: Some(30), max_attempts: 2 },
+            features.recovery_policy
+        );
+        assert!(matches!(features.flash_protection, FlashProtection::Enabled {
+            golden_bank: false,
+            ..
+        }));
+        assert_eq!(EmbeddedLayout::Disabled, features.embedded_layout);
+    }
+
+    #[test]
+    fn files_from_newer_versions_are_rejected() {
+        let source = V2_FILE.replace("schema_version: 2", "schema_version: 4");
+        let error = from_ron(&source).unwrap_err();
+
+        assert!(matches!(error, LoadError::UnsupportedVersion(4)));
+        assert!(error.to_string().ends_with("so the file was likely written by a newer one"));
+    }
+
+    #[test]
+    fn invalid_fields_are_reported_with_their_path() {
+        let source = V2_FILE.replace("timeout_ms: 1000", "timeout_ms: \"long\"");
+        match from_ron(&source).unwrap_err() {
+            LoadError::InvalidField { version, path, .. } => {
+                assert_eq!(Some(2), version);
+                assert_eq!("memory_configuration.qspi.timeout_ms", path);
+            }
+            error => panic!("Unexpected error: {}", error),
+        }
+
+        let source =
+            UNVERSIONED_FILE.replace("bootloader_length_kb: 64", "bootloader_length_kb: -1");
+        match from_ron(&source).unwrap_err() {
+            LoadError::InvalidField { version, path, .. } => {
+                assert_eq!(Some(1), version);
+                assert_eq!("memory_configuration.internal_memory_map.bootloader_length_kb", path);
+            }
+            error => panic!("Unexpected error: {}", error),
+        }
+    }
+}
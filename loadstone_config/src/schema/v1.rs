@@ -0,0 +1,103 @@
+//! Schema version 1, followed by every configuration file written before the
+//! schema was versioned. Fields were added to it over time with defaults
+//! standing in for them when absent, so any of those may be missing. Only the
+//! types that differ from the current schema are reproduced here.
+use serde::Deserialize;
+
+use crate::{
+    board::Board,
+    features::{
+        self, BootMetrics, FlashProtection, Greetings, RecoveryPin, RecoveryPolicy, SerialLink,
+        UpdateSignal,
+    },
+    memory::{self, ExternalMemoryMap, FlashChip, InternalMemoryMap, QspiConfiguration},
+    pins::PeripheralPin,
+    port::Port,
+    schema::v2,
+    security::SecurityConfiguration,
+};
+
+#[derive(Deserialize)]
+pub struct Configuration {
+    port: Port,
+    #[serde(default)]
+    board: Option<Board>,
+    memory_configuration: MemoryConfiguration,
+    feature_configuration: FeatureConfiguration,
+    security_configuration: SecurityConfiguration,
+}
+
+#[derive(Deserialize)]
+struct MemoryConfiguration {
+    internal_memory_map: InternalMemoryMap,
+    external_memory_map: ExternalMemoryMap,
+    external_flash: Option<FlashChip>,
+    golden_index: Option<usize>,
+    #[serde(default)]
+    qspi: QspiConfiguration,
+}
+
+#[derive(Deserialize)]
+struct FeatureConfiguration {
+    serial: Serial,
+    boot_metrics: BootMetrics,
+    update_signal: UpdateSignal,
+    greetings: Greetings,
+    #[serde(default)]
+    recovery_pin: RecoveryPin,
+    #[serde(default = "RecoveryPolicy::unbounded")]
+    recovery_policy: RecoveryPolicy,
+    #[serde(default)]
+    flash_protection: FlashProtection,
+}
+
+#[derive(Deserialize)]
+enum Serial {
+    Enabled {
+        recovery_enabled: bool,
+        tx_pin: PeripheralPin,
+        rx_pin: PeripheralPin,
+        #[serde(default)]
+        link: SerialLink,
+    },
+    Disabled,
+}
+
+impl Configuration {
+    /// Upgrades to schema version 2, which only makes the defaulted fields explicit.
+    pub fn migrate(self) -> v2::Configuration {
+        let Configuration {
+            port,
+            board,
+            memory_configuration: memory,
+            feature_configuration: features,
+            security_configuration,
+        } = self;
+        v2::Configuration {
+            port,
+            board,
+            memory_configuration: memory::MemoryConfiguration {
+                internal_memory_map: memory.internal_memory_map,
+                external_memory_map: memory.external_memory_map,
+                external_flash: memory.external_flash,
+                golden_index: memory.golden_index,
+                qspi: memory.qspi,
+            },
+            feature_configuration: v2::FeatureConfiguration {
+                serial: match features.serial {
+                    Serial::Enabled { recovery_enabled, tx_pin, rx_pin, link } => {
+                        features::Serial::Enabled { recovery_enabled, tx_pin, rx_pin, link }
+                    }
+                    Serial::Disabled => features::Serial::Disabled,
+                },
+                boot_metrics: features.boot_metrics,
+                update_signal: features.update_signal,
+                greetings: features.greetings,
+                recovery_pin: features.recovery_pin,
+                recovery_policy: features.recovery_policy,
+                flash_protection: features.flash_protection,
+            },
+            security_configuration,
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Schema version 2, which made every field of version 1 explicit. Only the
+//! types that differ from the current schema are reproduced here.
+use serde::Deserialize;
+
+use crate::{
+    board::Board,
+    features::{
+        self, BootMetrics, EmbeddedLayout, FlashProtection, Greetings, RecoveryPin, RecoveryPolicy,
+        Serial, UpdateSignal,
+    },
+    memory::MemoryConfiguration,
+    port::Port,
+    schema::SchemaVersion,
+    security::SecurityConfiguration,
+};
+
+#[derive(Deserialize)]
+pub struct Configuration {
+    pub(super) port: Port,
+    pub(super) board: Option<Board>,
+    pub(super) memory_configuration: MemoryConfiguration,
+    pub(super) feature_configuration: FeatureConfiguration,
+    pub(super) security_configuration: SecurityConfiguration,
+}
+
+#[derive(Deserialize)]
+pub(super) struct FeatureConfiguration {
+    pub(super) serial: Serial,
+    pub(super) boot_metrics: BootMetrics,
+    pub(super) update_signal: UpdateSignal,
+    pub(super) greetings: Greetings,
+    pub(super) recovery_pin: RecoveryPin,
+    pub(super) recovery_policy: RecoveryPolicy,
+    pub(super) flash_protection: FlashProtection,
+}
+
+impl Configuration {
+    /// Upgrades to schema version 3, which can embed the memory map in the
+    /// Loadstone binary. It is left out, as it always was before.
+    pub fn migrate(self) -> crate::Configuration {
+        let Configuration {
+            port,
+            board,
+            memory_configuration,
+            feature_configuration: features,
+            security_configuration,
+        } = self;
+        crate::Configuration {
+            schema_version: SchemaVersion(3),
+            port,
+            board,
+            memory_configuration,
+            feature_configuration: features::FeatureConfiguration {
+                serial: features.serial,
+                boot_metrics: features.boot_metrics,
+                update_signal: features.update_signal,
+                greetings: features.greetings,
+                recovery_pin: features.recovery_pin,
+                recovery_policy: features.recovery_policy,
+                flash_protection: features.flash_protection,
+                embedded_layout: EmbeddedLayout::Disabled,
+            },
+            security_configuration,
+        }
+    }
+}
@@ -0,0 +1,546 @@
+//! Consistency checks over a whole configuration. Every problem is reported at
+//! once, so the GUI, the command line tool and the build script can present a
+//! complete list instead of failing on the first one.
+use p256::ecdsa::VerifyingKey;
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    features::{BootMetrics, FlashProtection, RecoveryPin, Serial, UpdateSignal},
+    memory::{Bank, FlashChip},
+    pins::{self, PeripheralPin},
+    security::SecurityMode,
+    Configuration,
+};
+
+/// Area of the configuration a problem belongs to, matching the GUI menus.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Section {
+    Board,
+    MemoryMap,
+    Features,
+    Security,
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Section::Board => "Board",
+            Section::MemoryMap => "Memory Map",
+            Section::Features => "Features",
+            Section::Security => "Security",
+        })
+    }
+}
+
+/// A single reason why a configuration can't produce a Loadstone binary.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Problem {
+    pub section: Section,
+    pub description: String,
+}
+
+impl Problem {
+    fn new<S: Into<String>>(section: Section, description: S) -> Self {
+        Self { section, description: description.into() }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.section, self.description)
+    }
+}
+
+/// Returns every problem with a configuration, including the steps missing to
+/// complete it.
+pub fn problems(configuration: &Configuration) -> Vec<Problem> {
+    let mut problems: Vec<_> = configuration
+        .required_configuration_steps()
+        .map(|step| Problem::new(step.section(), step.description()))
+        .collect();
+    if let Some(board) = &configuration.board {
+        problems.extend(
+            board
+                .problems(&configuration.port)
+                .into_iter()
+                .map(|p| Problem::new(Section::Board, p)),
+        );
+    }
+    check_internal_memory_map(configuration, &mut problems);
+    check_external_memory_map(configuration, &mut problems);
+    check_golden_bank(configuration, &mut problems);
+    check_serial(configuration, &mut problems);
+    check_recovery_pin(configuration, &mut problems);
+    check_features(configuration, &mut problems);
+    check_update_signal(configuration, &mut problems);
+    check_security(configuration, &mut problems);
+    problems
+}
+
+/// First address past a bank, in a type that can't overflow.
+fn end(bank: &Bank) -> u64 { bank.start_address as u64 + bank.size_kb as u64 * 1024 }
+
+fn overlap(a: &Bank, b: &Bank) -> bool {
+    (a.start_address as u64) < end(b) && (b.start_address as u64) < end(a)
+}
+
+fn describe(bank: &Bank) -> String { format!("(0x{:x} - 0x{:x})", bank.start_address, end(bank)) }
+
+/// Checks that an area sits within a flash chip, returning whether it does.
+/// `chip_end` is the first address past the chip.
+fn check_within(
+    section: Section,
+    name: &str,
+    area: &Bank,
+    chip: &FlashChip,
+    chip_end: u64,
+    problems: &mut Vec<Problem>,
+) -> bool {
+    if area.size_kb == 0 {
+        problems.push(Problem::new(section, format!("{} is empty", name)));
+        false
+    } else if area.start_address < chip.start || end(area) > chip_end {
+        problems.push(Problem::new(
+            section,
+            format!(
+                "{} {} exceeds flash \"{}\" (0x{:x} - 0x{:x})",
+                name,
+                describe(area),
+                chip.name,
+                chip.start,
+                chip_end
+            ),
+        ));
+        false
+    } else {
+        true
+    }
+}
+
+/// Checks that an area sits within a flash chip and spans whole sectors of it,
+/// so erasing it never affects anything else.
+fn check_area(
+    section: Section,
+    name: &str,
+    area: &Bank,
+    chip: &FlashChip,
+    chip_end: u64,
+    problems: &mut Vec<Problem>,
+) {
+    if check_within(section, name, area, chip, chip_end, problems)
+        && chip.region_size > 0
+        && ((area.start_address - chip.start) % chip.region_size != 0
+            || (area.size_kb * 1024) % chip.region_size != 0)
+    {
+        problems.push(Problem::new(
+            section,
+            format!(
+                "{} {} is not aligned to the {}KB sectors of flash \"{}\"",
+                name,
+                describe(area),
+                chip.region_size / 1024,
+                chip.name
+            ),
+        ));
+    }
+}
+
+fn check_overlaps(areas: &[(String, &Bank)], problems: &mut Vec<Problem>) {
+    for (i, (name, area)) in areas.iter().enumerate() {
+        for (other_name, other) in &areas[i + 1..] {
+            if area.size_kb > 0 && other.size_kb > 0 && overlap(area, other) {
+                problems.push(Problem::new(
+                    Section::MemoryMap,
+                    format!(
+                        "{} {} overlaps {} {}",
+                        name,
+                        describe(area),
+                        other_name,
+                        describe(other)
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+fn check_internal_memory_map(configuration: &Configuration, problems: &mut Vec<Problem>) {
+    let flash = configuration.internal_flash();
+    let map = &configuration.memory_configuration.internal_memory_map;
+    let bootloader =
+        Bank { start_address: map.bootloader_location, size_kb: map.bootloader_length_kb };
+
+    // Loadstone itself needn't end on a sector boundary, as long as the banks after it
+    // start on one.
+    check_within(Section::MemoryMap, "Loadstone", &bootloader, &flash, flash.end as u64, problems);
+    let mut areas = vec![("Loadstone".to_owned(), &bootloader)];
+    areas.extend(map.banks.iter().enumerate().map(|(i, bank)| (format!("Bank {}", i + 1), bank)));
+    for (name, area) in &areas[1..] {
+        check_area(Section::MemoryMap, name, area, &flash, flash.end as u64, problems);
+    }
+    check_overlaps(&areas, problems);
+
+    if let Some(index) = map.bootable_index {
+        if index >= map.banks.len() {
+            problems.push(Problem::new(
+                Section::MemoryMap,
+                format!("Bootable bank {} doesn't exist in MCU flash", index + 1),
+            ));
+        }
+    }
+}
+
+fn check_external_memory_map(configuration: &Configuration, problems: &mut Vec<Problem>) {
+    let memory = &configuration.memory_configuration;
+    let chip = match &memory.external_flash {
+        Some(chip) => chip,
+        None if memory.external_memory_map.banks.is_empty() => return,
+        None => {
+            problems.push(Problem::new(
+                Section::MemoryMap,
+                "There are external flash banks, but no external flash chip",
+            ));
+            return;
+        }
+    };
+
+    if !configuration.external_flash().contains(chip) {
+        problems.push(Problem::new(
+            Section::MemoryMap,
+            format!("External flash \"{}\" is not available for this target", chip.name),
+        ));
+    }
+    problems.extend(
+        memory
+            .qspi
+            .problems(&configuration.port, Some(chip))
+            .into_iter()
+            .map(|p| Problem::new(Section::MemoryMap, p)),
+    );
+
+    // External chips are described by their last address rather than the one past it.
+    let chip_end = chip.end as u64 + 1;
+    let base_index = memory.internal_memory_map.banks.len();
+    let areas: Vec<_> = memory
+        .external_memory_map
+        .banks
+        .iter()
+        .enumerate()
+        .map(|(i, bank)| (format!("Bank {}", base_index + i + 1), bank))
+        .collect();
+    for (name, area) in &areas {
+        check_area(Section::MemoryMap, name, area, chip, chip_end, problems);
+    }
+    check_overlaps(&areas, problems);
+}
+
+fn check_golden_bank(configuration: &Configuration, problems: &mut Vec<Problem>) {
+    let memory = &configuration.memory_configuration;
+    let golden_index = match memory.golden_index {
+        Some(index) => index,
+        None => return,
+    };
+    let total_banks =
+        memory.internal_memory_map.banks.len() + memory.external_memory_map.banks.len();
+    if golden_index >= total_banks {
+        problems.push(Problem::new(
+            Section::MemoryMap,
+            format!("Golden bank {} doesn't exist", golden_index + 1),
+        ));
+    } else if memory.internal_memory_map.bootable_index == Some(golden_index) {
+        problems.push(Problem::new(
+            Section::MemoryMap,
+            format!("Bank {} can't be both bootable and golden", golden_index + 1),
+        ));
+    }
+}
+
+/// Pins taken by the QSPI link to external flash, if there is an external chip.
+fn qspi_pins(configuration: &Configuration) -> Vec<PeripheralPin> {
+    match (
+        &configuration.memory_configuration.external_flash,
+        pins::qspi_flash(&configuration.port),
+    ) {
+        (Some(_), Some(qspi_pins)) => qspi_pins.iter().cloned().collect(),
+        _ => vec![],
+    }
+}
+
+fn check_serial(configuration: &Configuration, problems: &mut Vec<Problem>) {
+    let port = &configuration.port;
+    let (tx_pin, rx_pin, link) = match &configuration.feature_configuration.serial {
+        Serial::Enabled { tx_pin, rx_pin, link, .. } => (tx_pin, rx_pin, link),
+        Serial::Disabled => return,
+    };
+    if !Serial::supported(port) {
+        problems.push(Problem::new(
+            Section::Features,
+            format!("Port {} doesn't support serial communication", port),
+        ));
+        return;
+    }
+    if !pins::serial_tx(port).any(|pin| &pin == tx_pin) {
+        problems.push(Problem::new(
+            Section::Features,
+            format!(
+                "Serial TX pin {} (AF{}) can't be used as {} output on port {}",
+                tx_pin, tx_pin.af_index, tx_pin.peripheral, port
+            ),
+        ));
+    }
+    if !pins::serial_rx(port).any(|pin| &pin == rx_pin) {
+        problems.push(Problem::new(
+            Section::Features,
+            format!(
+                "Serial RX pin {} (AF{}) can't be used as {} input on port {}",
+                rx_pin, rx_pin.af_index, rx_pin.peripheral, port
+            ),
+        ));
+    }
+    if tx_pin.peripheral != rx_pin.peripheral {
+        problems.push(Problem::new(
+            Section::Features,
+            format!(
+                "Serial TX and RX pins belong to different peripherals ({} and {})",
+                tx_pin.peripheral, rx_pin.peripheral
+            ),
+        ));
+    }
+    for (name, pin) in [("TX", tx_pin), ("RX", rx_pin)].iter() {
+        for qspi_pin in qspi_pins(configuration).iter().filter(|q| pin.collides_with(q)) {
+            problems.push(Problem::new(
+                Section::Features,
+                format!("Serial {} pin {} is already in use by {}", name, pin, qspi_pin.peripheral),
+            ));
+        }
+    }
+    problems.extend(link.problems(port).into_iter().map(|p| Problem::new(Section::Features, p)));
+}
+
+fn check_recovery_pin(configuration: &Configuration, problems: &mut Vec<Problem>) {
+    let port = &configuration.port;
+    let features = &configuration.feature_configuration;
+    let pin = match &features.recovery_pin {
+        RecoveryPin::Enabled { pin, .. } => pin,
+        RecoveryPin::Disabled => return,
+    };
+    if !RecoveryPin::supported(port) {
+        problems.push(Problem::new(
+            Section::Features,
+            format!("Port {} doesn't support a recovery pin", port),
+        ));
+        return;
+    }
+    if !matches!(features.serial, Serial::Enabled { recovery_enabled: true, .. }) {
+        problems.push(Problem::new(Section::Features, "The recovery pin requires serial recovery"));
+    }
+    if !pins::recovery_pins(port).any(|candidate| &candidate == pin) {
+        problems.push(Problem::new(
+            Section::Features,
+            format!("Pin {} can't be used as a recovery pin on port {}", pin, port),
+        ));
+    }
+    let mut taken = qspi_pins(configuration);
+    if let Serial::Enabled { tx_pin, rx_pin, .. } = &features.serial {
+        taken.extend(vec![tx_pin.clone(), rx_pin.clone()]);
+    }
+    for taken_pin in taken.iter().filter(|p| pin.collides_with(p)) {
+        problems.push(Problem::new(
+            Section::Features,
+            format!("Recovery pin {} is already in use by {}", pin, taken_pin.peripheral),
+        ));
+    }
+}
+
+fn check_features(configuration: &Configuration, problems: &mut Vec<Problem>) {
+    let port = &configuration.port;
+    let features = &configuration.feature_configuration;
+    if matches!(features.boot_metrics, BootMetrics::Enabled { timing: true })
+        && !BootMetrics::timing_supported(port)
+    {
+        problems.push(Problem::new(
+            Section::Features,
+            format!("Port {} can't time the boot process", port),
+        ));
+    }
+    if let FlashProtection::Enabled { golden_bank, .. } = features.flash_protection {
+        if !FlashProtection::supported(port) {
+            problems.push(Problem::new(
+                Section::Features,
+                format!("Port {} doesn't support flash protection", port),
+            ));
+        }
+        let memory = &configuration.memory_configuration;
+        let golden_in_mcu_flash = memory
+            .golden_index
+            .map_or(false, |index| index < memory.internal_memory_map.banks.len());
+        if golden_bank && !golden_in_mcu_flash {
+            problems.push(Problem::new(
+                Section::Features,
+                "Only a golden bank in MCU flash can be write protected",
+            ));
+        }
+    }
+}
+
+fn check_update_signal(configuration: &Configuration, problems: &mut Vec<Problem>) {
+    let port = &configuration.port;
+    let update_signal = configuration.feature_configuration.update_signal;
+    if matches!(update_signal, UpdateSignal::Enabled) && !UpdateSignal::registers_supported(port) {
+        problems.push(Problem::new(
+            Section::Features,
+            format!("Port {} has no backup registers to keep the update signal in", port),
+        ));
+    }
+    let region = match update_signal.flash_region() {
+        Some(region) => region,
+        None => return,
+    };
+    let flash = configuration.internal_flash();
+    let map = &configuration.memory_configuration.internal_memory_map;
+    let bootloader =
+        Bank { start_address: map.bootloader_location, size_kb: map.bootloader_length_kb };
+
+    let name = "Update signal region";
+    check_area(Section::Features, name, &region, &flash, flash.end as u64, problems);
+    let mut others = vec![("Loadstone".to_owned(), &bootloader)];
+    others.extend(map.banks.iter().enumerate().map(|(i, bank)| (format!("Bank {}", i + 1), bank)));
+    for (other_name, other) in others.iter().filter(|(_, other)| overlap(&region, other)) {
+        problems.push(Problem::new(
+            Section::Features,
+            format!("{} {} overlaps {} {}", name, describe(&region), other_name, describe(other)),
+        ));
+    }
+}
+
+fn check_security(configuration: &Configuration, problems: &mut Vec<Problem>) {
+    let security = &configuration.security_configuration;
+    if security.security_mode == SecurityMode::P256ECDSA
+        && !security.verifying_key_raw.is_empty()
+        && VerifyingKey::from_str(&security.verifying_key_raw).is_err()
+    {
+        problems.push(Problem::new(Section::Security, "The P256 ECDSA public key is not valid"));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{features::SerialLink, memory, pins::InputPin, port::Port, security::SecurityMode};
+    use std::borrow::Cow;
+
+    /// MCU flash configuration with Loadstone and two banks, free of problems.
+    fn configuration(port: Port) -> Configuration {
+        let mut configuration = Configuration { port, ..Default::default() };
+        configuration.security_configuration.security_mode = SecurityMode::Crc;
+        let map = &mut configuration.memory_configuration.internal_memory_map;
+        map.bootloader_location = 0x0800_0000;
+        map.bootloader_length_kb = 64;
+        map.banks = vec![Bank { start_address: 0x0801_0000, size_kb: 128 }, Bank {
+            start_address: 0x0803_0000,
+            size_kb: 128,
+        }];
+        map.bootable_index = Some(0);
+        configuration
+    }
+
+    fn with_external_flash(mut configuration: Configuration) -> Configuration {
+        configuration.memory_configuration.external_flash =
+            memory::external_flash(&configuration.port).next();
+        configuration
+    }
+
+    fn usart1_pin(bank: &'static str, index: u32) -> PeripheralPin {
+        PeripheralPin {
+            peripheral: Cow::Borrowed("USART1"),
+            bank: Cow::Borrowed(bank),
+            index,
+            af_index: 7,
+        }
+    }
+
+    fn descriptions(configuration: &Configuration) -> Vec<String> {
+        configuration.validate().into_iter().map(|problem| problem.description).collect()
+    }
+
+    #[test]
+    fn consistent_configurations_have_no_problems() {
+        assert_eq!(Vec::<String>::new(), descriptions(&configuration(Port::Stm32F412)));
+        assert_eq!(
+            Vec::<String>::new(),
+            descriptions(&with_external_flash(configuration(Port::Stm32F469)))
+        );
+    }
+
+    #[test]
+    fn overlapping_banks_are_reported() {
+        let mut configuration = configuration(Port::Stm32F412);
+        configuration.memory_configuration.internal_memory_map.banks[1].start_address = 0x0802_0000;
+
+        assert_eq!(
+            vec!["Bank 1 (0x8010000 - 0x8030000) overlaps Bank 2 (0x8020000 - 0x8040000)"],
+            descriptions(&configuration)
+        );
+    }
+
+    #[test]
+    fn banks_must_span_whole_sectors() {
+        let mut configuration = configuration(Port::Stm32F412);
+        configuration.memory_configuration.internal_memory_map.banks[1].start_address = 0x0803_1000;
+
+        assert_eq!(
+            vec![
+                "Bank 2 (0x8031000 - 0x8051000) is not aligned to the 16KB sectors of flash \
+                  \"STM32F412 MCU Flash\""
+            ],
+            descriptions(&configuration)
+        );
+    }
+
+    #[test]
+    fn serial_pins_must_not_collide_with_qspi_pins() {
+        let mut configuration = configuration(Port::Stm32F469);
+        configuration.feature_configuration.serial = Serial::Enabled {
+            recovery_enabled: true,
+            tx_pin: usart1_pin("b", 6),
+            rx_pin: usart1_pin("b", 7),
+            link: SerialLink::default(),
+        };
+        assert_eq!(Vec::<String>::new(), descriptions(&configuration));
+
+        let configuration = with_external_flash(configuration);
+        assert_eq!(
+            vec!["Serial TX pin Pb6 is already in use by QUADSPI"],
+            descriptions(&configuration)
+        );
+    }
+
+    #[test]
+    fn recovery_pin_must_not_collide_with_serial_or_qspi_pins() {
+        let mut configuration = configuration(Port::Stm32F412);
+        configuration.feature_configuration.serial = Serial::Enabled {
+            recovery_enabled: true,
+            tx_pin: usart1_pin("a", 9),
+            rx_pin: usart1_pin("a", 10),
+            link: SerialLink::default(),
+        };
+        let recovery_pin = |bank, index| RecoveryPin::Enabled {
+            pin: InputPin { bank: Cow::Borrowed(bank), index },
+            active_high: true,
+            menu: false,
+        };
+
+        configuration.feature_configuration.recovery_pin = recovery_pin("a", 10);
+        assert_eq!(
+            vec!["Recovery pin Pa10 is already in use by USART1"],
+            descriptions(&configuration)
+        );
+
+        configuration.feature_configuration.recovery_pin = recovery_pin("g", 6);
+        assert_eq!(Vec::<String>::new(), descriptions(&configuration));
+        assert_eq!(
+            vec!["Recovery pin Pg6 is already in use by QUADSPI"],
+            descriptions(&with_external_flash(configuration))
+        );
+    }
+}
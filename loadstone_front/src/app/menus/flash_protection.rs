@@ -0,0 +1,60 @@
+use eframe::egui;
+use loadstone_config::features::{FlashProtection, ReadoutProtection};
+
+/// Renders the menu to configure flash protection, which write-protects the bootloader
+/// (and optionally the golden bank) and sets the read-out protection level at first boot.
+pub fn configure_flash_protection(ui: &mut egui::Ui, flash_protection: &mut FlashProtection) {
+    let mut flash_protection_box = flash_protection.enabled();
+    ui.horizontal_wrapped(|ui| {
+        ui.checkbox(&mut flash_protection_box, "Flash Protection");
+        match (flash_protection_box, &flash_protection) {
+            (true, FlashProtection::Disabled) => {
+                *flash_protection = FlashProtection::Enabled {
+                    golden_bank: true,
+                    readout_protection: ReadoutProtection::Disabled,
+                }
+            }
+            (false, FlashProtection::Enabled { .. }) => {
+                *flash_protection = FlashProtection::Disabled
+            }
+            _ => {}
+        }
+        ui.label("Write-protect the bootloader through option bytes at first boot.");
+    });
+
+    if let FlashProtection::Enabled { golden_bank, readout_protection } = flash_protection {
+        ui.vertical(|ui| {
+            ui.horizontal_wrapped(|ui| {
+                ui.separator();
+                ui.checkbox(golden_bank, "Protect Golden Bank");
+                ui.label("Also write-protect the golden bank, if it's in MCU flash. It is only unlocked during recovery.");
+            });
+            select_readout_protection(ui, readout_protection);
+        });
+    }
+}
+
+fn select_readout_protection(ui: &mut egui::Ui, readout_protection: &mut ReadoutProtection) {
+    ui.horizontal_wrapped(|ui| {
+        ui.separator();
+        egui::ComboBox::from_label("Read-out protection")
+            .selected_text(format!("{:?}", readout_protection))
+            .show_ui(ui, |ui| {
+                for choice in
+                    [ReadoutProtection::Disabled, ReadoutProtection::Level1, ReadoutProtection::Level2]
+                {
+                    ui.selectable_value(readout_protection, choice, format!("{:?}", choice));
+                }
+            });
+    });
+    if *readout_protection == ReadoutProtection::Level2 {
+        ui.horizontal_wrapped(|ui| {
+            ui.separator();
+            ui.colored_label(
+                egui::Color32::RED,
+                "Warning: Level 2 permanently disables debugging and locks the option bytes. \
+                 This cannot be undone.",
+            );
+        });
+    }
+}
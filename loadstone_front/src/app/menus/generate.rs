@@ -42,7 +42,8 @@ pub fn generate<'a>(
     last_request_response: &mut Arc<Mutex<Option<Result<Response, reqwest_wasm::Error>>>>,
     configuration: &Configuration,
 ) {
-    if configuration.complete() {
+    let problems = configuration.validate();
+    if problems.is_empty() {
         if frame.is_web() {
             ui.group(|ui| {
                 generate_in_ci(
@@ -61,9 +62,9 @@ pub fn generate<'a>(
             generate_native(ui, configuration);
         }
     } else {
-        ui.label("Provide the missing configuration to generate the loadstone binary:");
-        for step in configuration.required_configuration_steps() {
-            ui.colored_label(Color32::RED, format!("\u{27A1} {}.", step));
+        ui.label("Address the following problems to generate the loadstone binary:");
+        for problem in problems {
+            ui.colored_label(Color32::RED, format!("\u{27A1} {}.", problem));
         }
     }
 }
@@ -1,15 +1,20 @@
-use std::cmp::{self, max};
+use std::{
+    cmp::{self, max},
+    fs,
+};
 
-use crate::app::menus::memory_map::normalize::normalize;
+use crate::app::{menus::memory_map::normalize::normalize, utilities::download_file};
 
 use eframe::egui::{self, Button, Color32, Label, Slider};
 use loadstone_config::{
-    memory::{self, Bank, ExternalMemoryMap, FlashChip, InternalMemoryMap},
-    port::Port,
-    KB,
+    memory::{
+        self, Bank, ExternalMemoryMap, FlashChip, InternalMemoryMap, QspiConfiguration, QspiMode,
+    },
+    report, Configuration, KB,
 };
 
 static BOOTLOADER_MAX_LENGTH_KB: u32 = 128;
+static REPORT_FILENAME: &'static str = "memory_map.md";
 static GOLDEN_TOOLTIP: &'static str =
     "Mark this bank as golden (used as a fallback in case of corruption)\r\n \
     Only one non-bootable bank may be golden, and only golden banks can store golden images.";
@@ -18,24 +23,24 @@ mod normalize;
 
 /// Renders the menu to configure the entire memory map, consisting of a mandatory internal
 /// flash (and its bank distribution, which must contain a bootable bank) and an optional
-/// external flash.
+/// external flash, chosen among the available chips.
 pub fn configure_memory_map(
     ui: &mut egui::Ui,
     internal_memory_map: &mut InternalMemoryMap,
     external_memory_map: &mut ExternalMemoryMap,
     external_flash: &mut Option<FlashChip>,
     golden_index: &mut Option<usize>,
-    port: &Port,
+    qspi: &mut QspiConfiguration,
+    internal_flash: &FlashChip,
+    available_external_flash: &[FlashChip],
 ) {
-    let internal_flash = memory::internal_flash(port);
-
     normalize(
         internal_memory_map,
         external_memory_map,
-        &internal_flash,
+        internal_flash,
         external_flash,
         golden_index,
-        port,
+        available_external_flash,
     );
 
     ui.group(|ui| {
@@ -48,18 +53,18 @@ pub fn configure_memory_map(
 
         ui.separator();
         ui.label("Bootloader:");
-        select_bootloader_location(ui, internal_memory_map, &internal_flash);
-        select_bootloader_length(ui, internal_memory_map, &internal_flash);
+        select_bootloader_location(ui, internal_memory_map, internal_flash);
+        select_bootloader_length(ui, internal_memory_map, internal_flash);
         ui.label("Banks:");
         ui.separator();
-        configure_internal_banks(ui, internal_memory_map, &internal_flash, golden_index);
+        configure_internal_banks(ui, internal_memory_map, internal_flash, golden_index);
     });
 
     ui.separator();
 
     ui.group(|ui| {
         ui.horizontal_wrapped(|ui| {
-            ui.set_enabled(memory::external_flash(port).count() > 0);
+            ui.set_enabled(!available_external_flash.is_empty());
             ui.add(Label::new("External flash chip:").heading());
             egui::ComboBox::from_id_source("external_flash_chip")
                 .selected_text(match external_flash {
@@ -68,14 +73,17 @@ pub fn configure_memory_map(
                 })
                 .show_ui(ui, |ui| {
                     ui.selectable_value(external_flash, None, "None");
-                    for chip in memory::external_flash(port) {
-                        ui.selectable_value(external_flash, Some(chip.clone()), chip.name);
+                    for chip in available_external_flash {
+                        ui.selectable_value(external_flash, Some(chip.clone()), &chip.name);
                     }
                 });
         });
         ui.separator();
 
         if let Some(external_flash) = external_flash {
+            ui.label("QSPI link:");
+            configure_qspi(ui, qspi);
+            ui.separator();
             ui.label("Banks:");
             ui.separator();
             configure_external_banks(
@@ -89,6 +97,44 @@ pub fn configure_memory_map(
     });
 }
 
+/// Renders the memory map report, as it appears in release documentation, and
+/// offers it as a Markdown file.
+pub fn show_report(ui: &mut egui::Ui, configuration: &Configuration, web: bool) {
+    ui.add(Label::new(report::diagram(configuration)).monospace());
+    ui.horizontal_wrapped(|ui| {
+        if ui.button(if web { "Download" } else { "Save" }).clicked() {
+            let markdown = report::markdown(configuration);
+            if web {
+                download_file(REPORT_FILENAME, &markdown).unwrap();
+            } else {
+                // TODO clean up unwraps
+                fs::write(REPORT_FILENAME, markdown).unwrap();
+            }
+        }
+        ui.label("The report as a Markdown file, with a table for each memory.");
+    });
+}
+
+fn configure_qspi(ui: &mut egui::Ui, qspi: &mut QspiConfiguration) {
+    ui.horizontal_wrapped(|ui| {
+        ui.separator();
+        for mode in &[QspiMode::Single, QspiMode::Dual, QspiMode::Quad] {
+            ui.radio_value(&mut qspi.mode, *mode, format!("{:?}", mode));
+        }
+        ui.label("Data lines. The chip must accept every instruction over them.");
+    });
+    ui.horizontal_wrapped(|ui| {
+        ui.separator();
+        ui.add(Slider::new(&mut qspi.clock_prescaler, 0..=255).clamp_to_range(true));
+        ui.label("Clock prescaler (the QSPI clock is the AHB clock divided by this plus one).");
+    });
+    ui.horizontal_wrapped(|ui| {
+        ui.separator();
+        ui.add(Slider::new(&mut qspi.timeout_ms, 100..=60_000).clamp_to_range(true).suffix("ms"));
+        ui.label("Timeout for flash operations.");
+    });
+}
+
 fn configure_internal_banks(
     ui: &mut egui::Ui,
     internal_memory_map: &mut InternalMemoryMap,
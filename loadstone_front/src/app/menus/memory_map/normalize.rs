@@ -1,8 +1,8 @@
 use loadstone_config::{
-    memory::{self, ExternalMemoryMap, FlashChip, InternalMemoryMap},
-    port::Port,
+    memory::{self, Bank, ExternalMemoryMap, FlashChip, InternalMemoryMap},
     KB,
 };
+use std::cmp::max;
 
 /// Ensures internal consistency of the memory maps is maintained. Rules like banks
 /// staying contiguous, single boot banks, etc are enforced here. This is called
@@ -15,15 +15,17 @@ pub fn normalize(
     internal_flash: &memory::FlashChip,
     external_flash: &mut Option<memory::FlashChip>,
     golden_index: &mut Option<usize>,
-    port: &Port,
+    available_external_flash: &[FlashChip],
 ) {
     enforce_bootable_bank_not_golden(golden_index, internal_memory_map);
+    enforce_banks_span_whole_sectors(&mut internal_memory_map.banks, internal_flash);
     enforce_internal_banks_follow_bootloader(internal_memory_map, internal_flash);
     enforce_internal_banks_are_contiguous(internal_memory_map);
     enforce_internal_bank_ranges_are_maintained(internal_memory_map, internal_flash);
 
     if let Some(chip) = external_flash {
-        if memory::external_flash(port).any(|c| c.name == chip.name) {
+        if available_external_flash.iter().any(|c| c.name == chip.name) {
+            enforce_banks_span_whole_sectors(&mut external_memory_map.banks, chip);
             enforce_external_banks_are_contiguous(external_memory_map, chip);
         } else {
             *external_flash = None;
@@ -33,6 +35,15 @@ pub fn normalize(
     }
 }
 
+/// Rounds bank sizes down to a whole number of sectors (at least one), so erasing
+/// a bank never affects its neighbours.
+fn enforce_banks_span_whole_sectors(banks: &mut [Bank], chip: &FlashChip) {
+    let sector_kb = max(chip.region_size / KB!(1), 1);
+    for bank in banks {
+        bank.size_kb = max(bank.size_kb - bank.size_kb % sector_kb, sector_kb);
+    }
+}
+
 fn enforce_external_banks_are_contiguous(
     external_memory_map: &mut ExternalMemoryMap,
     chip: &mut FlashChip,
@@ -1,7 +1,7 @@
 use eframe::egui;
 use enum_iterator::IntoEnumIterator;
 use loadstone_config::{
-    features::{BootMetrics, Greetings},
+    features::{BootMetrics, EmbeddedLayout, Greetings},
     port::Port,
 };
 
@@ -10,6 +10,8 @@ pub mod security;
 pub mod generate;
 pub mod update_signal;
 pub mod serial;
+pub mod recovery_pin;
+pub mod flash_protection;
 
 /// Renders the dropdown menu to select one of the supported
 /// hardware ports.
@@ -53,6 +55,17 @@ pub fn configure_boot_metrics(ui: &mut egui::Ui, boot_metrics: &mut BootMetrics,
     });
 }
 
+/// Renders the menu to embed a compact copy of the memory map in the Loadstone binary.
+pub fn configure_embedded_layout(ui: &mut egui::Ui, embedded_layout: &mut EmbeddedLayout) {
+    let mut layout_box = matches!(embedded_layout, EmbeddedLayout::Enabled);
+    ui.horizontal_wrapped(|ui| {
+        ui.checkbox(&mut layout_box, "Embedded Layout");
+        *embedded_layout =
+            if layout_box { EmbeddedLayout::Enabled } else { EmbeddedLayout::Disabled };
+        ui.label("Embed the memory map in Loadstone, so tools can read it from a device dump.");
+    });
+}
+
 /// Configures the custom greetings feature; optional strings that will be printed via
 /// serial by both Loadstone and the companion demo app. When enabled, they default to
 /// a version string containing Git and Cargo information.
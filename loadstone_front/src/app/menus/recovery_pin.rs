@@ -0,0 +1,88 @@
+use eframe::egui;
+use loadstone_config::{
+    features::{RecoveryPin, RecoveryPolicy, Serial},
+    memory::FlashChip,
+    pins::{self, InputPin},
+    port::Port,
+};
+
+/// Seconds the recovery menu waits for a choice when recovery has no timeout of its
+/// own, matching the bootloader's fallback.
+const MENU_TIMEOUT_S: u32 = 30;
+
+/// Renders the menu to configure the recovery pin, a GPIO input that forces Loadstone into
+/// serial recovery mode (or a recovery menu) when asserted at boot.
+pub fn configure_recovery_pin(
+    ui: &mut egui::Ui,
+    recovery_pin: &mut RecoveryPin,
+    serial: &Serial,
+    recovery_policy: &RecoveryPolicy,
+    external_flash: &Option<FlashChip>,
+    port: &Port,
+) {
+    // External flash is driven through QSPI, whose pins are then taken.
+    let qspi_pins = external_flash.as_ref().and_then(|_| pins::qspi_flash(port));
+    let available_pins = || {
+        let qspi_pins = qspi_pins.clone();
+        pins::recovery_pins(port)
+            .filter(move |pin| match serial {
+                Serial::Enabled { tx_pin, rx_pin, .. } => {
+                    !pin.collides_with(tx_pin) && !pin.collides_with(rx_pin)
+                }
+                Serial::Disabled => true,
+            })
+            .filter(move |pin| {
+                qspi_pins.as_ref().map_or(true, |q| !q.iter().any(|p| pin.collides_with(p)))
+            })
+    };
+
+    let mut recovery_pin_box = recovery_pin.enabled();
+    ui.horizontal_wrapped(|ui| {
+        ui.checkbox(&mut recovery_pin_box, "Recovery Pin");
+        match (recovery_pin_box, &recovery_pin) {
+            (true, RecoveryPin::Disabled) => {
+                if let Some(pin) = available_pins().next() {
+                    *recovery_pin = RecoveryPin::Enabled { pin, active_high: false, menu: false }
+                }
+            }
+            (false, RecoveryPin::Enabled { .. }) => *recovery_pin = RecoveryPin::Disabled,
+            _ => {}
+        }
+        ui.label("Enter serial recovery mode at boot when a pin is asserted (requires serial recovery).");
+    });
+
+    if let RecoveryPin::Enabled { pin, active_high, menu } = recovery_pin {
+        ui.vertical(|ui| {
+            select_recovery_pin(ui, pin, available_pins());
+            ui.horizontal_wrapped(|ui| {
+                ui.separator();
+                ui.checkbox(active_high, "Active High");
+                ui.label("Recovery is requested when the pin reads high (otherwise, when it reads low).");
+            });
+            ui.horizontal_wrapped(|ui| {
+                ui.separator();
+                ui.checkbox(menu, "Recovery Menu");
+                let menu_timeout_s = recovery_policy.timeout_s.unwrap_or(MENU_TIMEOUT_S);
+                ui.label(format!("Offer a serial menu to recover, boot or reboot instead of recovering directly. Booting continues if nothing is chosen within {} seconds.", menu_timeout_s));
+            });
+        });
+    }
+}
+
+fn select_recovery_pin(
+    ui: &mut egui::Ui,
+    pin: &mut InputPin,
+    available_pins: impl Iterator<Item = InputPin>,
+) {
+    ui.horizontal_wrapped(|ui| {
+        ui.separator();
+        egui::ComboBox::from_label("Recovery pin").selected_text(pin.to_string()).show_ui(
+            ui,
+            |ui| {
+                for choice in available_pins {
+                    ui.selectable_value(pin, choice.clone(), choice);
+                }
+            },
+        );
+    });
+}
@@ -1,15 +1,24 @@
-use eframe::egui;
+use eframe::egui::{self, Slider};
 use itertools::Itertools;
 use loadstone_config::{
-    features::{self, Serial},
+    features::{self, Parity, RecoveryPolicy, Serial, SerialLink, StopBits},
     pins::{self, Peripheral, PeripheralPin},
     port::Port,
 };
 
+/// Baud rates offered in the GUI, where the port supports them.
+const STANDARD_BAUD_RATES: [u32; 10] =
+    [1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800];
+
 /// Renders the menu that configures serial communication features, including
 /// whether serial communication is available at all, whether it allows for image
 /// recovery, and what pins and peripherals it uses in a particular port.
-pub fn configure_serial(ui: &mut egui::Ui, serial: &mut Serial, port: &Port) {
+pub fn configure_serial(
+    ui: &mut egui::Ui,
+    serial: &mut Serial,
+    recovery_policy: &mut RecoveryPolicy,
+    port: &Port,
+) {
     let mut available_peripherals =
         pins::serial_tx(port).chain(pins::serial_rx(port)).map(|p| p.peripheral).collect_vec();
     available_peripherals.sort();
@@ -36,6 +45,7 @@ pub fn configure_serial(ui: &mut egui::Ui, serial: &mut Serial, port: &Port) {
                     recovery_enabled: false,
                     tx_pin: first_valid_tx_pin(),
                     rx_pin: first_valid_rx_pin(),
+                    link: SerialLink::default(),
                 }
             }
             (false, Serial::Enabled { .. }) => *serial = Serial::Disabled,
@@ -44,15 +54,17 @@ pub fn configure_serial(ui: &mut egui::Ui, serial: &mut Serial, port: &Port) {
 
         ui.label("Enable serial communications to retrieve information about the boot process.");
     });
-    if let Serial::Enabled { recovery_enabled, tx_pin, rx_pin } = serial {
+    if let Serial::Enabled { recovery_enabled, tx_pin, rx_pin, link } = serial {
         define_serial_options(
             ui,
             port,
             recovery_enabled,
+            recovery_policy,
             tx_pin,
             rx_pin,
             available_peripherals.iter().cloned(),
         );
+        configure_link(ui, link, port);
     }
 }
 
@@ -60,6 +72,7 @@ fn define_serial_options(
     ui: &mut egui::Ui,
     port: &Port,
     recovery_enabled: &mut bool,
+    recovery_policy: &mut RecoveryPolicy,
     tx_pin: &mut PeripheralPin,
     rx_pin: &mut PeripheralPin,
     available_peripherals: impl Iterator<Item = Peripheral>,
@@ -69,6 +82,9 @@ fn define_serial_options(
         select_tx_pins(ui, tx_pin, port);
         select_rx_pins(ui, rx_pin, port);
         select_recovery_mode(ui, recovery_enabled, port);
+        if *recovery_enabled {
+            configure_recovery_policy(ui, recovery_policy);
+        }
     });
 }
 
@@ -136,6 +152,30 @@ fn select_tx_pins(ui: &mut egui::Ui, tx_pin: &mut PeripheralPin, port: &Port) {
     });
 }
 
+fn configure_link(ui: &mut egui::Ui, link: &mut SerialLink, port: &Port) {
+    let range = SerialLink::baud_rate_range(port);
+    ui.horizontal_wrapped(|ui| {
+        ui.separator();
+        egui::ComboBox::from_label("Baud rate").selected_text(link.baud_rate.to_string()).show_ui(
+            ui,
+            |ui| {
+                for baud_rate in STANDARD_BAUD_RATES.iter().filter(|b| range.contains(b)) {
+                    ui.selectable_value(&mut link.baud_rate, *baud_rate, baud_rate.to_string());
+                }
+            },
+        );
+    });
+    ui.horizontal_wrapped(|ui| {
+        ui.separator();
+        for parity in &[Parity::None, Parity::Even, Parity::Odd] {
+            ui.radio_value(&mut link.parity, *parity, format!("{:?} parity", parity));
+        }
+        ui.separator();
+        ui.radio_value(&mut link.stop_bits, StopBits::One, "One stop bit");
+        ui.radio_value(&mut link.stop_bits, StopBits::Two, "Two stop bits");
+    });
+}
+
 fn select_recovery_mode(ui: &mut egui::Ui, recovery_enabled: &mut bool, port: &Port) {
     ui.horizontal_wrapped(|ui| {
         ui.set_enabled(features::Serial::supported(port));
@@ -144,3 +184,25 @@ fn select_recovery_mode(ui: &mut egui::Ui, recovery_enabled: &mut bool, port: &P
         ui.label("Allow recovering a device by sending a new image via XModem.");
     });
 }
+
+fn configure_recovery_policy(ui: &mut egui::Ui, recovery_policy: &mut RecoveryPolicy) {
+    let mut timeout_box = recovery_policy.timeout_s.is_some();
+    ui.horizontal_wrapped(|ui| {
+        ui.separator();
+        ui.checkbox(&mut timeout_box, "Recovery Timeout");
+        match (timeout_box, recovery_policy.timeout_s) {
+            (true, None) => recovery_policy.timeout_s = RecoveryPolicy::default().timeout_s,
+            (false, Some(_)) => recovery_policy.timeout_s = None,
+            _ => {}
+        }
+        if let Some(timeout_s) = &mut recovery_policy.timeout_s {
+            ui.add(Slider::new(timeout_s, 1..=600).clamp_to_range(true).suffix("s"));
+        }
+        ui.label("Leave recovery mode if no image is received in time.");
+    });
+    ui.horizontal_wrapped(|ui| {
+        ui.separator();
+        ui.add(Slider::new(&mut recovery_policy.max_attempts, 1..=10).clamp_to_range(true));
+        ui.label("Failed transfers tolerated before leaving recovery mode.");
+    });
+}
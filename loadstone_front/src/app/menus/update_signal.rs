@@ -1,16 +1,76 @@
-use eframe::egui;
-use loadstone_config::features::UpdateSignal;
+use eframe::egui::{self, Color32, Label, Slider};
+use loadstone_config::{features::UpdateSignal, memory::FlashChip, port::Port, KB};
 
-pub fn configure_update_signal(ui: &mut egui::Ui, update_signal: &mut UpdateSignal) {
-    let mut enabled = matches!(update_signal, UpdateSignal::Enabled);
+/// Most flash sectors that can be reserved for the update signal from the GUI.
+const MAX_FLASH_SECTORS: u32 = 4;
+
+/// Renders the menu to configure the update signal, kept either in backup registers
+/// (where the port has them) or in a reserved region at the end of MCU flash.
+pub fn configure_update_signal(
+    ui: &mut egui::Ui,
+    update_signal: &mut UpdateSignal,
+    port: &Port,
+    internal_flash: &FlashChip,
+) {
+    let registers_supported = UpdateSignal::registers_supported(port);
+    let mut enabled = update_signal.enabled();
 
     ui.horizontal_wrapped(|ui| {
         ui.checkbox(&mut enabled, "Update Signal");
         ui.label("Enable update signal to control when image updates happen.");
-        if enabled {
-            *update_signal = UpdateSignal::Enabled;
-        } else {
-            *update_signal = UpdateSignal::Disabled;
+        match (enabled, &update_signal) {
+            (true, UpdateSignal::Disabled) if registers_supported => {
+                *update_signal = UpdateSignal::Enabled
+            }
+            (true, UpdateSignal::Disabled) => *update_signal = flash_signal(internal_flash, 1),
+            (false, _) => *update_signal = UpdateSignal::Disabled,
+            _ => {}
+        }
+    });
+
+    if !enabled {
+        return;
+    }
+    ui.horizontal_wrapped(|ui| {
+        ui.separator();
+        ui.scope(|ui| {
+            ui.set_enabled(registers_supported);
+            if ui
+                .radio(matches!(update_signal, UpdateSignal::Enabled), "Backup registers")
+                .clicked()
+            {
+                *update_signal = UpdateSignal::Enabled;
+            }
+        });
+        let flash_selected = update_signal.flash_region().is_some();
+        if ui.radio(flash_selected, "MCU flash").clicked() && !flash_selected {
+            *update_signal = flash_signal(internal_flash, 1);
         }
+        ui.label("Flash survives power loss, at the cost of reserving whole sectors.");
     });
+
+    if let Some(region) = update_signal.flash_region() {
+        let sector_kb = internal_flash.region_size / KB!(1);
+        let mut sectors = (region.size_kb / sector_kb).max(1);
+        ui.horizontal_wrapped(|ui| {
+            ui.separator();
+            ui.add(Slider::new(&mut sectors, 1..=MAX_FLASH_SECTORS).clamp_to_range(true));
+            ui.label("Reserved sectors");
+            ui.add(
+                Label::new(format!(
+                    "(0x{:x} - 0x{:x})",
+                    region.start_address,
+                    region.end_address()
+                ))
+                .text_color(Color32::LIGHT_BLUE),
+            );
+        });
+        *update_signal = flash_signal(internal_flash, sectors);
+    }
+}
+
+/// Update signal kept in the last `sectors` sectors of MCU flash.
+fn flash_signal(flash: &FlashChip, sectors: u32) -> UpdateSignal {
+    let size = sectors * flash.region_size;
+    UpdateSignal::Flash { start_address: flash.end - size, size_kb: size / KB!(1) }
 }
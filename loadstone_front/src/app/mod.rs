@@ -1,13 +1,16 @@
 use std::sync::Arc;
 
 use self::menus::{
-    configure_boot_metrics, memory_map::configure_memory_map, security::configure_security,
+    configure_boot_metrics, configure_embedded_layout,
+    memory_map::{configure_memory_map, show_report},
+    security::configure_security,
     select_port,
 };
 
 use crate::app::menus::{
     generate, update_signal::configure_update_signal,
-    serial::configure_serial, configure_custom_greetings
+    serial::configure_serial, configure_custom_greetings,
+    recovery_pin::configure_recovery_pin, flash_protection::configure_flash_protection,
 };
 
 use eframe::{
@@ -16,7 +19,10 @@ use eframe::{
 };
 const GIT_VERSION: &str = git_version::git_version!();
 
-use loadstone_config::{features::Serial, pins, Configuration};
+use loadstone_config::{
+    features::{FlashProtection, RecoveryPin, Serial},
+    pins, Configuration,
+};
 use reqwest_wasm::Response;
 
 mod menus;
@@ -89,6 +95,12 @@ impl epi::App for LoadstoneApp {
                 ));
                 ui.separator();
                 select_port(ui, &mut configuration.port);
+                if let Some(board) = &configuration.board {
+                    ui.label(format!(
+                        "Custom board [{}], as described in the loaded configuration.",
+                        board.name
+                    ));
+                }
                 ui.separator();
                 ui.collapsing("Features", |ui| {
                     ui.label("Greyed out features are unsupported in the current configuration.");
@@ -101,9 +113,34 @@ impl epi::App for LoadstoneApp {
                         configure_serial(
                             ui,
                             &mut &mut configuration.feature_configuration.serial,
+                            &mut configuration.feature_configuration.recovery_policy,
                             &mut configuration.port,
                         );
                     });
+                    ui.group(|ui| {
+                        ui.set_enabled(
+                            RecoveryPin::supported(&configuration.port)
+                                && matches!(
+                                    configuration.feature_configuration.serial,
+                                    Serial::Enabled { recovery_enabled: true, .. }
+                                ),
+                        );
+                        configure_recovery_pin(
+                            ui,
+                            &mut configuration.feature_configuration.recovery_pin,
+                            &configuration.feature_configuration.serial,
+                            &configuration.feature_configuration.recovery_policy,
+                            &configuration.memory_configuration.external_flash,
+                            &configuration.port,
+                        );
+                    });
+                    ui.group(|ui| {
+                        ui.set_enabled(FlashProtection::supported(&configuration.port));
+                        configure_flash_protection(
+                            ui,
+                            &mut configuration.feature_configuration.flash_protection,
+                        );
+                    });
                     ui.group(|ui| {
                         configure_boot_metrics(
                             ui,
@@ -111,6 +148,12 @@ impl epi::App for LoadstoneApp {
                             &mut configuration.port,
                         );
                     });
+                    ui.group(|ui| {
+                        configure_embedded_layout(
+                            ui,
+                            &mut configuration.feature_configuration.embedded_layout,
+                        );
+                    });
                     ui.group(|ui| {
                         configure_custom_greetings(
                             ui,
@@ -118,22 +161,33 @@ impl epi::App for LoadstoneApp {
                         );
                     });
                     ui.group(|ui| {
+                        let internal_flash = configuration.internal_flash();
                         configure_update_signal(
                             ui,
                             &mut configuration.feature_configuration.update_signal,
+                            &configuration.port,
+                            &internal_flash,
                         );
                     });
                 });
                 ui.separator();
                 ui.collapsing("Memory Map", |ui| {
+                    let internal_flash = configuration.internal_flash();
+                    let available_external_flash = configuration.external_flash();
                     configure_memory_map(
                         ui,
                         &mut configuration.memory_configuration.internal_memory_map,
                         &mut configuration.memory_configuration.external_memory_map,
                         &mut configuration.memory_configuration.external_flash,
                         &mut configuration.memory_configuration.golden_index,
-                        &configuration.port,
+                        &mut configuration.memory_configuration.qspi,
+                        &internal_flash,
+                        &available_external_flash,
                     );
+                    ui.separator();
+                    ui.collapsing("Report", |ui| {
+                        show_report(ui, configuration, frame.is_web());
+                    });
                 });
                 ui.separator();
                 ui.collapsing("Security", |ui| {
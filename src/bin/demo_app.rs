@@ -6,7 +6,7 @@
 use cortex_m_rt::{entry, exception};
 pub const HEAP_SIZE_BYTES: usize = 8192;
 
-#[cfg(all(target_arch = "arm", feature = "stm32f412"))]
+#[cfg(target_arch = "arm")]
 #[entry]
 fn main() -> ! {
     let heap_start = cortex_m_rt::heap_start() as usize;
@@ -17,12 +17,5 @@ fn main() -> ! {
     app.run();
 }
 
-#[cfg(all(target_arch = "arm", feature = "wgm160p"))]
-#[entry]
-fn main() -> ! {
-    use loadstone_lib as _;
-    loop {}
-}
-
 #[cfg(not(target_arch = "arm"))]
 fn main() {}
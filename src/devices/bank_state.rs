@@ -0,0 +1,229 @@
+//! Persistent per-bank state.
+//!
+//! Loadstone otherwise derives everything from scanning banks on every boot,
+//! so it can't tell a bank that failed verification on the last boot, or that
+//! holds an image the application already rejected, from one that was never
+//! looked at. The state table remembers the outcome for each bank, so banks
+//! known to be unusable are skipped without being scanned again.
+//!
+//! A bank that was never recorded has no state, and is treated exactly as it
+//! was before the table existed. The table is sealed with a magic value and a
+//! CRC, so a table that was never written, or was corrupted, holds no states.
+use crate::{devices::cli::file_transfer::crc16, error::Error};
+
+/// Lifecycle of the image stored in a bank.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BankState {
+    /// Holds no image.
+    Empty,
+    /// Holds an image written by the application or a host tool, not yet
+    /// considered by Loadstone.
+    Staged,
+    /// Holds an image that was just installed in the bootable bank, and is
+    /// waiting for the application to confirm or reject it.
+    Pending,
+    /// Holds an image the application confirmed to work.
+    Confirmed,
+    /// Holds an image that failed verification or was rejected by the
+    /// application.
+    Bad,
+}
+
+/// Number of bits used by a single bank state.
+const BITS_PER_STATE: u32 = 4;
+/// Number of bank states that fit in a 32 bit word.
+pub const STATES_PER_WORD: u8 = (32 / BITS_PER_STATE) as u8;
+/// Number of words in a packed state table, enough for 16 banks.
+pub const TABLE_WORDS: usize = 2;
+/// Upper half of a valid seal.
+const SEAL_MAGIC: u32 = 0xB5A7;
+
+impl BankState {
+    /// Whether a bank in this state may supply images to boot, update or restore from.
+    pub fn is_usable(&self) -> bool { !matches!(self, BankState::Empty | BankState::Bad) }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BankState::Empty => "Empty",
+            BankState::Staged => "Staged",
+            BankState::Pending => "Pending",
+            BankState::Confirmed => "Confirmed",
+            BankState::Bad => "Bad",
+        }
+    }
+
+    /// State implied by the result of verifying the image in a bank. Errors
+    /// unrelated to the contents of the bank don't imply any state.
+    pub fn after_verification<T>(result: &Result<T, Error>) -> Option<Self> {
+        match result {
+            Ok(_) => None,
+            Err(Error::BankEmpty) => Some(BankState::Empty),
+            Err(Error::CrcInvalid) | Err(Error::SignatureInvalid) => Some(BankState::Bad),
+            Err(_) => None,
+        }
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            BankState::Empty => 1,
+            BankState::Staged => 2,
+            BankState::Pending => 3,
+            BankState::Confirmed => 4,
+            BankState::Bad => 5,
+        }
+    }
+
+    fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            1 => Some(BankState::Empty),
+            2 => Some(BankState::Staged),
+            3 => Some(BankState::Pending),
+            4 => Some(BankState::Confirmed),
+            5 => Some(BankState::Bad),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the state of a bank from a word of a packed state table, where word
+/// `bank / STATES_PER_WORD` holds the state for `bank`. A zeroed table holds
+/// no states.
+pub fn unpack(word: u32, bank: u8) -> Option<BankState> {
+    BankState::from_bits((word >> shift(bank)) & mask())
+}
+
+/// Returns the word of a packed state table with the state of a bank replaced.
+pub fn pack(word: u32, bank: u8, state: BankState) -> u32 {
+    (word & !(mask() << shift(bank))) | (state.bits() << shift(bank))
+}
+
+/// Seal stored next to a packed state table: a magic value in the upper half,
+/// and the CRC-16 of the table in the lower half.
+pub fn seal(table: &[u32; TABLE_WORDS]) -> u32 {
+    let mut bytes = [0u8; TABLE_WORDS * 4];
+    for (chunk, word) in bytes.chunks_mut(4).zip(table.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    (SEAL_MAGIC << 16) | crc16(&bytes) as u32
+}
+
+/// Reads the state of a bank from a sealed table. A table with a broken seal
+/// holds no states.
+pub fn read(table: &[u32; TABLE_WORDS], table_seal: u32, bank: u8) -> Option<BankState> {
+    if table_seal != seal(table) {
+        return None;
+    }
+    table.get((bank / STATES_PER_WORD) as usize).and_then(|word| unpack(*word, bank))
+}
+
+/// Records the state of a bank in a sealed table, returning its new seal. A table
+/// with a broken seal is cleared first, so none of its other states are trusted.
+pub fn write(table: &mut [u32; TABLE_WORDS], table_seal: u32, bank: u8, state: BankState) -> u32 {
+    if table_seal != seal(table) {
+        *table = [0; TABLE_WORDS];
+    }
+    if let Some(word) = table.get_mut((bank / STATES_PER_WORD) as usize) {
+        *word = pack(*word, bank, state);
+    }
+    seal(table)
+}
+
+fn shift(bank: u8) -> u32 { (bank % STATES_PER_WORD) as u32 * BITS_PER_STATE }
+fn mask() -> u32 { (1 << BITS_PER_STATE) - 1 }
+
+/// Persistent storage for the state of each bank, shared between Loadstone
+/// and the application.
+pub trait BankStateStore {
+    /// Last state recorded for a bank, if any.
+    fn bank_state(&self, bank: u8) -> Option<BankState>;
+    fn set_bank_state(&mut self, bank: u8, state: BankState);
+}
+
+/// State store for ports that can't persist bank states. Never holds a state.
+pub struct NullBankStateStore;
+
+impl BankStateStore for NullBankStateStore {
+    fn bank_state(&self, _bank: u8) -> Option<BankState> { None }
+    fn set_bank_state(&mut self, _bank: u8, _state: BankState) {}
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+pub mod doubles {
+    use super::*;
+
+    /// Sealed state table in RAM, large enough for 16 banks.
+    #[derive(Default)]
+    pub struct FakeBankStateStore {
+        pub words: [u32; TABLE_WORDS],
+        pub seal: u32,
+    }
+
+    impl BankStateStore for FakeBankStateStore {
+        fn bank_state(&self, bank: u8) -> Option<BankState> { read(&self.words, self.seal, bank) }
+
+        fn set_bank_state(&mut self, bank: u8, state: BankState) {
+            self.seal = write(&mut self.words, self.seal, bank, state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{doubles::*, *};
+
+    #[test]
+    fn banks_without_a_recorded_state_have_none() {
+        let store = FakeBankStateStore::default();
+        assert_eq!(None, store.bank_state(0));
+        assert_eq!(None, store.bank_state(15));
+        assert_eq!(None, store.bank_state(16));
+    }
+
+    #[test]
+    fn recording_a_state_leaves_other_banks_untouched() {
+        let mut store = FakeBankStateStore::default();
+        store.set_bank_state(3, BankState::Bad);
+        store.set_bank_state(4, BankState::Pending);
+        store.set_bank_state(9, BankState::Staged);
+        store.set_bank_state(3, BankState::Confirmed);
+
+        assert_eq!(Some(BankState::Confirmed), store.bank_state(3));
+        assert_eq!(Some(BankState::Pending), store.bank_state(4));
+        assert_eq!(Some(BankState::Staged), store.bank_state(9));
+        assert_eq!(None, store.bank_state(2));
+        assert_eq!(None, store.bank_state(8));
+    }
+
+    #[test]
+    fn tables_with_a_broken_seal_hold_no_states() {
+        let mut store = FakeBankStateStore::default();
+        store.words = [pack(0, 1, BankState::Confirmed), 0];
+        assert_eq!(None, store.bank_state(1));
+
+        store.set_bank_state(1, BankState::Confirmed);
+        store.set_bank_state(9, BankState::Staged);
+        store.words[1] ^= 1 << 8;
+        assert_eq!(None, store.bank_state(1));
+        assert_eq!(None, store.bank_state(9));
+
+        store.set_bank_state(2, BankState::Bad);
+        assert_eq!(Some(BankState::Bad), store.bank_state(2));
+        assert_eq!(None, store.bank_state(1));
+        assert_eq!(None, store.bank_state(9));
+    }
+
+    #[test]
+    fn only_errors_about_bank_contents_imply_a_state() {
+        assert_eq!(
+            Some(BankState::Empty),
+            BankState::after_verification::<()>(&Err(Error::BankEmpty))
+        );
+        assert_eq!(
+            Some(BankState::Bad),
+            BankState::after_verification::<()>(&Err(Error::CrcInvalid))
+        );
+        assert_eq!(None, BankState::after_verification::<()>(&Err(Error::NoExternalFlash)));
+        assert_eq!(None, BankState::after_verification(&Ok(())));
+    }
+}
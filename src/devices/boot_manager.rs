@@ -17,11 +17,13 @@
 use core::marker::PhantomData;
 
 use super::{
-    boot_metrics::{boot_metrics, BootMetrics},
+    bank_state::{BankState, BankStateStore},
+    boot_metrics::{boot_metrics, BootMetrics, BootPath},
     cli::{Cli, DEFAULT_GREETING},
-    image,
+    image::{self, WriteSession},
+    management::{self, BankInfo, ImageInfo, Role, Target},
     traits::{Flash, Serial},
-    update_signal::{UpdatePlan, WriteUpdateSignal},
+    update_signal::{UpdatePlan, UpdateResult, WriteUpdateSignal},
 };
 use crate::error::Error;
 use blue_hal::hal::flash;
@@ -36,6 +38,7 @@ pub struct BootManager<
     SRL: Serial,
     R: image::Reader,
     WUS: WriteUpdateSignal,
+    BS: BankStateStore,
 > {
     pub(crate) external_banks: &'static [image::Bank<<EXTF as flash::ReadWrite>::Address>],
     pub(crate) mcu_banks: &'static [image::Bank<<MCUF as flash::ReadWrite>::Address>],
@@ -46,10 +49,17 @@ pub struct BootManager<
     pub(crate) greeting: Option<&'static str>,
     pub(crate) _marker: PhantomData<R>,
     pub(crate) update_signal: Option<WUS>,
+    pub(crate) bank_states: Option<BS>,
 }
 
-impl<MCUF: Flash, EXTF: Flash, SRL: Serial, R: image::Reader, WUS: WriteUpdateSignal>
-    BootManager<MCUF, EXTF, SRL, R, WUS>
+impl<
+        MCUF: Flash,
+        EXTF: Flash,
+        SRL: Serial,
+        R: image::Reader,
+        WUS: WriteUpdateSignal,
+        BS: BankStateStore,
+    > BootManager<MCUF, EXTF, SRL, R, WUS, BS>
 {
     /// Provides an iterator over all external flash banks.
     pub fn external_banks(&self) -> impl Iterator<Item = image::Bank<EXTF::Address>> {
@@ -67,31 +77,46 @@ impl<MCUF: Flash, EXTF: Flash, SRL: Serial, R: image::Reader, WUS: WriteUpdateSi
 
     /// Writes a firmware image to an external flash bank. Takes an iterator over byte
     /// blocks, to easily interface with serial or network protocols like XMODEM or TCP/IP
-    /// where information is received in chunks.
+    /// where information is received in chunks. If the image size is known in advance,
+    /// oversized images are rejected before anything is written.
     pub fn store_image_external<I: Iterator<Item = [u8; N]>, const N: usize>(
         &mut self,
         blocks: I,
+        size: Option<usize>,
         bank: image::Bank<EXTF::Address>,
     ) -> Result<(), Error> {
         let external_flash = self.external_flash.as_mut().ok_or(Error::NoExternalFlash)?;
-        external_flash.write_from_blocks(bank.location, blocks)?;
+        let mut session = WriteSession::new(external_flash, bank);
+        if let Some(size) = size {
+            session.reserve(size)?;
+        }
+        session.write(blocks)?;
+        session.finish::<R>()?;
+        self.record_bank_state(bank.index, BankState::Staged);
         Ok(())
     }
 
     /// Writes a firmware image to a MCU flash bank that is not bootable. Takes an iterator over byte
     /// blocks, to easily interface with serial or network protocols like XMODEM or TCP/IP
-    /// where information is received in chunks.
+    /// where information is received in chunks. If the image size is known in advance,
+    /// oversized images are rejected before anything is written.
     pub fn store_image_mcu<I: Iterator<Item = [u8; N]>, const N: usize>(
         &mut self,
         blocks: I,
+        size: Option<usize>,
         bank: image::Bank<MCUF::Address>,
     ) -> Result<(), Error> {
         if bank.bootable {
-            Err(Error::BankInvalid)
-        } else {
-            self.mcu_flash.write_from_blocks(bank.location, blocks)?;
-            Ok(())
+            return Err(Error::BankInvalid);
+        }
+        let mut session = WriteSession::new(&mut self.mcu_flash, bank);
+        if let Some(size) = size {
+            session.reserve(size)?;
         }
+        session.write(blocks)?;
+        session.finish::<R>()?;
+        self.record_bank_state(bank.index, BankState::Staged);
+        Ok(())
     }
 
     /// Fully erases the external flash bank, ensuring there are no leftover images
@@ -99,15 +124,32 @@ impl<MCUF: Flash, EXTF: Flash, SRL: Serial, R: image::Reader, WUS: WriteUpdateSi
     pub fn format_external(&mut self) -> Result<(), Error> {
         let external_flash = self.external_flash.as_mut().ok_or(Error::NoExternalFlash)?;
         nb::block!(external_flash.erase())?;
+        for bank in self.external_banks() {
+            self.record_bank_state(bank.index, BankState::Empty);
+        }
         Ok(())
     }
 
     /// Triggers a soft system reset.
     pub fn reset(&mut self) -> ! { SCB::sys_reset(); }
 
-    pub fn set_update_signal(&mut self, plan: UpdatePlan) -> Result<(), Error> {
+    /// Sets the update plan, either until it's changed again or (if `one_shot`) only for
+    /// the next boot. Targeting a specific bank gives it another chance if it was
+    /// previously marked as bad. Golden restores and rollbacks are refused unless
+    /// they are one-shot.
+    pub fn set_update_signal(&mut self, plan: UpdatePlan, one_shot: bool) -> Result<(), Error> {
+        if plan.is_one_shot_only() && !one_shot {
+            return Err(Error::DeviceError(
+                "Golden restores and rollbacks can only apply to the next boot.",
+            ));
+        }
         if let Some(us) = self.update_signal.as_mut() {
-            us.write_update_plan(plan);
+            us.write_update_plan(plan, one_shot);
+            if let UpdatePlan::Index(bank) = plan {
+                if self.bank_state(bank) == Some(BankState::Bad) {
+                    self.record_bank_state(bank, BankState::Staged);
+                }
+            }
             Ok(())
         } else {
             Err(Error::DeviceError(
@@ -117,6 +159,72 @@ impl<MCUF: Flash, EXTF: Flash, SRL: Serial, R: image::Reader, WUS: WriteUpdateSi
         }
     }
 
+    /// Outcome of the update process during the last boot, if Loadstone reported one.
+    pub fn update_result(&self) -> Result<Option<UpdateResult>, Error> {
+        self.update_signal.as_ref().map(|us| us.read_update_result()).ok_or(Error::DeviceError(
+            "Update signal commands are not supported without the update \
+            signal feature enabled.",
+        ))
+    }
+
+    /// Last recorded state of a bank, if the port keeps track of bank states.
+    pub fn bank_state(&self, bank: u8) -> Option<BankState> {
+        self.bank_states.as_ref().and_then(|s| s.bank_state(bank))
+    }
+
+    fn record_bank_state(&mut self, bank: u8, state: BankState) {
+        if let Some(states) = self.bank_states.as_mut() {
+            states.set_bank_state(bank, state);
+        }
+    }
+
+    fn storage(&mut self) -> management::Storage<MCUF, EXTF> {
+        management::Storage {
+            mcu_flash: &mut self.mcu_flash,
+            mcu_banks: self.mcu_banks,
+            external_flash: self.external_flash.as_mut(),
+            external_banks: self.external_banks,
+        }
+    }
+
+    /// Bank that host tools may write to or erase, which excludes the bootable bank
+    /// the boot manager itself runs from.
+    fn writable_bank(&self, bank: u8) -> Result<BankInfo, Error> {
+        match management::find_bank(self.mcu_banks, self.external_banks, bank) {
+            Some(info) if !info.bootable => Ok(info),
+            _ => Err(Error::BankInvalid),
+        }
+    }
+
+    /// Marks the running image, and the bank Loadstone installed it from, as confirmed
+    /// (if the image was pending) or bad. Rejected images are replaced on the next boot.
+    /// Golden banks are never marked, so they remain available as a last resort.
+    pub fn resolve_pending_image(&mut self, accepted: bool) -> Result<(), Error> {
+        if self.bank_states.is_none() {
+            return Err(Error::DeviceError(
+                "Image confirmation is not supported without bank state tracking.",
+            ));
+        }
+        let state = if accepted { BankState::Confirmed } else { BankState::Bad };
+        let source = match self.boot_metrics.as_ref().map(|m| &m.boot_path) {
+            Some(BootPath::Updated { bank }) | Some(BootPath::Restored { bank }) => Some(*bank),
+            _ => None,
+        };
+        let source_is_golden = self
+            .mcu_banks()
+            .map(|b| (b.index, b.is_golden))
+            .chain(self.external_banks().map(|b| (b.index, b.is_golden)))
+            .any(|(index, golden)| golden && Some(index) == source);
+
+        let boot_bank = self.boot_bank().index;
+        self.record_bank_state(boot_bank, state);
+        match source {
+            Some(bank) if !source_is_golden => self.record_bank_state(bank, state),
+            _ => {}
+        }
+        Ok(())
+    }
+
     /// Gathers metrics left over in memory by Loadstone, if available, and launches
     /// the command line interface.
     pub fn run(mut self) -> ! {
@@ -135,3 +243,121 @@ impl<MCUF: Flash, EXTF: Flash, SRL: Serial, R: image::Reader, WUS: WriteUpdateSi
         }
     }
 }
+
+/// Exposes the boot manager to host tools through the
+/// [management protocol](`crate::devices::management`).
+impl<
+        MCUF: Flash,
+        EXTF: Flash,
+        SRL: Serial,
+        R: image::Reader,
+        WUS: WriteUpdateSignal,
+        BS: BankStateStore,
+    > Target for BootManager<MCUF, EXTF, SRL, R, WUS, BS>
+{
+    fn role(&self) -> Role { Role::BootManager }
+
+    fn bank(&self, position: usize) -> Option<BankInfo> {
+        management::bank_at(self.mcu_banks, self.external_banks, position)
+    }
+
+    fn image_info(&mut self, bank: u8) -> Result<ImageInfo, Error> {
+        self.storage().image_info::<R>(bank)
+    }
+
+    fn write_bank(&mut self, bank: u8, offset: usize, data: &[u8]) -> Result<(), Error> {
+        let info = self.writable_bank(bank)?;
+        self.storage().write(bank, offset, data)?;
+        if let Some(state) = management::state_after_write(&info, offset) {
+            self.record_bank_state(bank, state);
+        }
+        Ok(())
+    }
+
+    fn erase_bank(&mut self, bank: u8) -> Result<(), Error> {
+        self.writable_bank(bank)?;
+        self.storage().erase(bank)?;
+        self.record_bank_state(bank, BankState::Empty);
+        Ok(())
+    }
+
+    fn verify_bank(&mut self, bank: u8) -> Result<(), Error> {
+        let info = management::find_bank(self.mcu_banks, self.external_banks, bank)
+            .ok_or(Error::BankInvalid)?;
+        let image = self.storage().image_info::<R>(bank);
+        let (result, state) = management::check_verified(&info, self.bank_state(bank), image);
+        if let Some(state) = state {
+            self.record_bank_state(bank, state);
+        }
+        result
+    }
+
+    fn set_update_plan(&mut self, plan: UpdatePlan, one_shot: bool) -> Result<(), Error> {
+        self.set_update_signal(plan, one_shot)
+    }
+
+    fn reboot(&mut self) -> ! { self.reset() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::devices::{
+        bank_state::NullBankStateStore, bootloader::doubles::FakeReader,
+        update_signal::UpdateResult,
+    };
+    use blue_hal::hal::doubles::{
+        flash::{Address, FakeFlash},
+        serial::SerialStub,
+    };
+
+    #[derive(Default)]
+    struct FakeUpdateSignal {
+        plan: Option<(UpdatePlan, bool)>,
+    }
+
+    impl WriteUpdateSignal for FakeUpdateSignal {
+        fn write_update_plan(&mut self, plan: UpdatePlan, one_shot: bool) {
+            self.plan = Some((plan, one_shot));
+        }
+
+        fn read_update_result(&self) -> Option<UpdateResult> { None }
+    }
+
+    type BootManagerDouble = BootManager<
+        FakeFlash,
+        FakeFlash,
+        SerialStub,
+        FakeReader,
+        FakeUpdateSignal,
+        NullBankStateStore,
+    >;
+
+    fn boot_manager() -> BootManagerDouble {
+        BootManager {
+            external_banks: &[],
+            mcu_banks: &[],
+            mcu_flash: FakeFlash::new(Address(0)),
+            external_flash: None,
+            cli: None,
+            boot_metrics: None,
+            greeting: None,
+            _marker: PhantomData,
+            update_signal: Some(FakeUpdateSignal::default()),
+            bank_states: None,
+        }
+    }
+
+    #[test]
+    fn golden_restores_and_rollbacks_only_apply_to_the_next_boot() {
+        let mut boot_manager = boot_manager();
+        for plan in [UpdatePlan::RestoreGolden, UpdatePlan::Rollback].iter().cloned() {
+            assert!(boot_manager.set_update_signal(plan, false).is_err());
+            assert_eq!(None, boot_manager.update_signal.as_ref().unwrap().plan);
+            assert_eq!(Ok(()), boot_manager.set_update_signal(plan, true));
+            assert_eq!(Some((plan, true)), boot_manager.update_signal.as_ref().unwrap().plan);
+            boot_manager.update_signal = Some(FakeUpdateSignal::default());
+        }
+        assert_eq!(Ok(()), boot_manager.set_update_signal(UpdatePlan::Any, false));
+    }
+}
@@ -20,6 +20,8 @@ pub struct BootMetrics {
     /// Time from construction of Loadstone's driver suite to the target image
     /// being booted.
     pub boot_time_ms: Option<u32>,
+    /// What Loadstone found in the update signal.
+    pub update_signal: UpdateSignalState,
     /// Magic string to ensure the boot metrics' integrity when read. Must
     /// be equal to [`BOOT_MAGIC_END`] when read to guarantee validity.
     pub boot_magic_end: u32,
@@ -43,12 +45,26 @@ pub enum BootPath {
     Updated { bank: u8 },
 }
 
+/// State of the update signal, as read by Loadstone during boot.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UpdateSignalState {
+    /// The update signal isn't supported, or wasn't read.
+    Unavailable,
+    /// The update signal held a valid plan.
+    Valid,
+    /// The update signal didn't hold a valid plan (e.g. the backup domain lost
+    /// power), so it was ignored.
+    NoSignal,
+}
+
 impl Default for BootMetrics {
     fn default() -> Self {
         Self {
             boot_magic_start: BOOT_MAGIC_START,
             boot_path: BootPath::Direct,
             boot_time_ms: None,
+            update_signal: UpdateSignalState::Unavailable,
             boot_magic_end: BOOT_MAGIC_END,
         }
     }
@@ -73,6 +89,7 @@ impl BootMetrics {
 /// This *will* clobber data so it must only be called immediately before jumping into the target
 /// application.
 pub unsafe fn boot_metrics_mut() -> &'static mut BootMetrics {
+    // Mirrored by `BootMetrics::RAM_END` in `loadstone_config`.
     let ram_end = 0x20010000;
     let boot_metrics_raw: *mut BootMetrics = core::mem::transmute::<usize, *mut BootMetrics>(
         ram_end - core::mem::size_of::<BootMetrics>(),
@@ -8,7 +8,10 @@ impl<
         T: time::Now,
         R: image::Reader,
         RUS: ReadUpdateSignal,
-    > Bootloader<EXTF, MCUF, SRL, T, R, RUS>
+        RP: RecoveryPin,
+        FP: ProtectionOptions,
+        BS: BankStateStore,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, RP, FP, BS>
 {
     pub fn copy_image_single_flash<F: Flash>(
         serial: &mut Option<SRL>,
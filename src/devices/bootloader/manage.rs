@@ -0,0 +1,126 @@
+use crate::devices::{
+    cli::file_transfer::Timeout,
+    management::{self, BankInfo, ImageInfo, Role, Target},
+    update_signal::{ReadUpdateSignal, UpdatePlan},
+};
+use blue_hal::{hal::serial::TimeoutRead, utilities::xmodem};
+
+use super::*;
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        RP: RecoveryPin,
+        FP: ProtectionOptions,
+        BS: BankStateStore,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, RP, FP, BS>
+{
+    /// Briefly listens for the first byte from the host. Host tools send a management
+    /// frame delimiter to request the management protocol instead of an XMODEM transfer,
+    /// while anything else is typed by an operator and belongs to the next reader.
+    pub(super) fn listen_for_management(&mut self) -> Option<u8> {
+        self.serial.as_mut().and_then(|s| TimeoutRead::read(s, xmodem::DEFAULT_TIMEOUT).ok())
+    }
+
+    /// Serves management requests until the host goes silent for longer than the
+    /// recovery timeout, or requests a reboot.
+    pub(super) fn serve_management(&mut self) -> Result<(), Error> {
+        let start = T::now();
+        let clock = || T::now() - start;
+        let timeout = self.recovery_policy.timeout.map(|limit| Timeout { clock: &clock, limit });
+        let mut serial = self.serial.take().ok_or(Error::NoRecoverySupport)?;
+        let result = management::serve(&mut serial, self, timeout);
+        self.serial = Some(serial);
+        result
+    }
+
+    fn storage(&mut self) -> management::Storage<MCUF, EXTF> {
+        management::Storage {
+            mcu_flash: &mut self.mcu_flash,
+            mcu_banks: self.mcu_banks,
+            external_flash: self.external_flash.as_mut(),
+            external_banks: self.external_banks,
+        }
+    }
+
+    /// Runs a flash operation on a bank, lifting the golden bank write protection
+    /// for its duration if the bank is golden and in MCU flash.
+    fn with_golden_unlocked<F>(&mut self, bank: &BankInfo, operation: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Error>,
+    {
+        let protected = bank.golden && !bank.external;
+        if protected {
+            self.unlock_golden_bank()?;
+        }
+        let result = operation(self);
+        if protected {
+            self.enforce_flash_protection();
+        }
+        result
+    }
+}
+
+/// Exposes recovery mode to host tools through the
+/// [management protocol](`crate::devices::management`).
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        RP: RecoveryPin,
+        FP: ProtectionOptions,
+        BS: BankStateStore,
+    > Target for Bootloader<EXTF, MCUF, SRL, T, R, RUS, RP, FP, BS>
+{
+    fn role(&self) -> Role { Role::Recovery }
+
+    fn bank(&self, position: usize) -> Option<BankInfo> {
+        management::bank_at(self.mcu_banks, self.external_banks, position)
+    }
+
+    fn image_info(&mut self, bank: u8) -> Result<ImageInfo, Error> {
+        self.storage().image_info::<R>(bank)
+    }
+
+    fn write_bank(&mut self, bank: u8, offset: usize, data: &[u8]) -> Result<(), Error> {
+        let info = management::find_bank(self.mcu_banks, self.external_banks, bank)
+            .ok_or(Error::BankInvalid)?;
+        self.with_golden_unlocked(&info, |s| s.storage().write(bank, offset, data))?;
+        if let Some(state) = management::state_after_write(&info, offset) {
+            self.set_bank_state(bank, state);
+        }
+        Ok(())
+    }
+
+    fn erase_bank(&mut self, bank: u8) -> Result<(), Error> {
+        let info = management::find_bank(self.mcu_banks, self.external_banks, bank)
+            .ok_or(Error::BankInvalid)?;
+        self.with_golden_unlocked(&info, |s| s.storage().erase(bank))?;
+        self.set_bank_state(bank, BankState::Empty);
+        Ok(())
+    }
+
+    fn verify_bank(&mut self, bank: u8) -> Result<(), Error> {
+        let info = management::find_bank(self.mcu_banks, self.external_banks, bank)
+            .ok_or(Error::BankInvalid)?;
+        let image = self.storage().image_info::<R>(bank);
+        let (result, state) = management::check_verified(&info, self.bank_state(bank), image);
+        if let Some(state) = state {
+            self.set_bank_state(bank, state);
+        }
+        result
+    }
+
+    fn set_update_plan(&mut self, _plan: UpdatePlan, _one_shot: bool) -> Result<(), Error> {
+        Err(Error::DeviceError("Update plans can only be set by the application."))
+    }
+
+    fn reboot(&mut self) -> ! { Bootloader::reboot(self) }
+}
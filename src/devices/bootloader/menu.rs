@@ -0,0 +1,68 @@
+use crate::devices::update_signal::ReadUpdateSignal;
+use blue_hal::{hal::serial::TimeoutRead, utilities::xmodem};
+
+use super::*;
+
+/// Time the operator has to choose an option before booting continues, unless the
+/// recovery policy sets its own timeout, so an asserted recovery pin doesn't hang the
+/// device when nobody is on the serial line.
+const MENU_TIMEOUT: time::Milliseconds = time::Milliseconds(30_000);
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        RP: RecoveryPin,
+        FP: ProtectionOptions,
+        BS: BankStateStore,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, RP, FP, BS>
+{
+    /// Lets the operator choose between entering recovery mode, booting as normal or
+    /// rebooting. Returns only if the operator chooses to continue booting, or doesn't
+    /// choose anything in time.
+    pub fn offer_recovery_menu(&mut self) {
+        if self.serial.is_none() {
+            return;
+        }
+
+        loop {
+            duprintln!(self.serial, "-- Loadstone Recovery Menu --");
+            duprintln!(self.serial, "[1] Enter recovery mode");
+            duprintln!(self.serial, "[2] Continue booting");
+            duprintln!(self.serial, "[3] Reboot");
+
+            match self.read_menu_selection() {
+                Some(b'1') => {
+                    if self.recovery_session() {
+                        self.reboot();
+                    }
+                }
+                Some(b'2') => return,
+                Some(b'3') => self.reboot(),
+                Some(_) => duprintln!(self.serial, "Invalid selection."),
+                None => {
+                    duprintln!(self.serial, "No selection received, continuing boot.");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Waits for the operator to type a character other than white space. Returns
+    /// `None` if nothing is typed before the menu times out.
+    fn read_menu_selection(&mut self) -> Option<u8> {
+        let timeout = self.recovery_policy.timeout.unwrap_or(MENU_TIMEOUT);
+        let serial = self.serial.as_mut()?;
+        let start = T::now();
+        while T::now() - start < timeout {
+            match TimeoutRead::read(serial, xmodem::DEFAULT_TIMEOUT) {
+                Ok(byte) if !byte.is_ascii_whitespace() => return Some(byte),
+                _ => {}
+            }
+        }
+        None
+    }
+}
@@ -5,8 +5,11 @@
 //! handled by the `port` module as it depends on board
 //! specific information.
 use super::{
-    boot_metrics::{boot_metrics_mut, BootMetrics, BootPath},
+    bank_state::{BankState, BankStateStore},
+    boot_metrics::{boot_metrics_mut, BootMetrics, BootPath, UpdateSignalState},
+    flash_protection::{FlashProtection, ProtectionOptions},
     image::{self, Bank, Image},
+    recovery_pin::RecoveryPin,
     traits::{Flash, Serial},
 };
 use crate::{devices::update_signal::ReadUpdateSignal, error::Error};
@@ -16,20 +19,48 @@ use blue_hal::{
     KB,
 };
 use core::{cmp::min, marker::PhantomData, mem::size_of};
-use cortex_m::peripheral::SCB;
+use cortex_m::peripheral::{SCB, SYST};
 use defmt::{info, warn};
 use nb::block;
 use ufmt::uwriteln;
 
 /// Operations related to copying images between flash chips.
 mod copy;
+/// Operations related to serving the management protocol during recovery.
+mod manage;
+/// Operations related to write and read-out protection of MCU flash.
+mod protect;
+/// Operations related to the menu offered when the recovery pin is asserted.
+mod menu;
 /// Operations related to serial recovery when there's no fallback to restore to.
 mod recover;
 /// Operations related to restoring an image when there's no current one to boot.
 mod restore;
+/// Operations related to the persistent state of each bank.
+mod state;
 /// Operations related to updating images with newer ones.
 mod update;
 
+/// SysTick control and status register bits that run the timer and its interrupt.
+const SYST_CSR_ENABLE: u32 = 1 << 0;
+const SYST_CSR_TICKINT: u32 = 1 << 1;
+/// Interrupt control and state register bit that clears a pending SysTick exception.
+const SCB_ICSR_PENDSTCLR: u32 = 1 << 25;
+
+/// Limits on how long Loadstone remains in serial recovery mode.
+#[derive(Copy, Clone)]
+pub struct RecoveryPolicy {
+    /// Silence from the sender after which a recovery transfer is abandoned.
+    /// `None` waits indefinitely.
+    pub timeout: Option<time::Milliseconds>,
+    /// Failed transfers tolerated before leaving recovery mode.
+    pub max_attempts: u32,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self { Self { timeout: None, max_attempts: 1 } }
+}
+
 /// Main bootloader struct.
 // Members are public for the `ports` layer to be able to construct them freely and easily.
 pub struct Bootloader<
@@ -39,6 +70,9 @@ pub struct Bootloader<
     T: time::Now,
     R: image::Reader,
     RUS: ReadUpdateSignal,
+    RP: RecoveryPin,
+    FP: ProtectionOptions,
+    BS: BankStateStore,
 > {
     pub(crate) mcu_flash: MCUF,
     pub(crate) external_banks: &'static [image::Bank<<EXTF as flash::ReadWrite>::Address>],
@@ -48,7 +82,12 @@ pub struct Bootloader<
     pub(crate) boot_metrics: BootMetrics,
     pub(crate) start_time: Option<T::I>,
     pub(crate) recovery_enabled: bool,
+    pub(crate) recovery_policy: RecoveryPolicy,
     pub(crate) update_signal: Option<RUS>,
+    pub(crate) recovery_pin: Option<RP>,
+    pub(crate) recovery_menu: bool,
+    pub(crate) flash_protection: Option<FlashProtection<FP>>,
+    pub(crate) bank_states: Option<BS>,
     pub(crate) greeting: &'static str,
     pub(crate) _marker: PhantomData<R>,
 }
@@ -60,7 +99,10 @@ impl<
         T: time::Now,
         R: image::Reader,
         RUS: ReadUpdateSignal,
-    > Bootloader<EXTF, MCUF, SRL, T, R, RUS>
+        RP: RecoveryPin,
+        FP: ProtectionOptions,
+        BS: BankStateStore,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, RP, FP, BS>
 {
     /// Main bootloader routine.
     ///
@@ -77,10 +119,25 @@ impl<
     /// image, copy it to bootable MCU flash bank and attempt to boot it.
     /// * Verify golden image. If valid, copy to bootable MCU flash bank and attempt to boot.
     /// * If golden image not available or invalid, proceed to recovery mode.
+    ///
+    /// If a recovery pin is configured and asserted at the start of the process, recovery
+    /// mode (or the recovery menu) is entered first. The steps above only run if the
+    /// recovery session ends without a new image.
+    ///
+    /// If the port keeps track of bank states, banks known to be empty or bad are skipped
+    /// throughout, and newly installed images are left pending confirmation from the
+    /// application.
+    ///
+    /// If flash protection is configured, it is enforced before anything else happens.
     pub fn run(mut self) -> ! {
+        let recovery_requested = self.recovery_pin.as_ref().map_or(false, |p| p.is_asserted());
         self.verify_bank_correctness();
         duprintln!(self.serial, "");
         duprintln!(self.serial, "{}", self.greeting);
+        self.enforce_flash_protection();
+        if recovery_requested {
+            self.force_recovery();
+        }
         if let Some(image) = self.latest_bootable_image() {
             duprintln!(self.serial, "Attempting to boot from default bank.");
             match self.boot(image).unwrap_err() {
@@ -110,7 +167,25 @@ impl<
             }
         }
     }
-    /// Makes several sanity checks on the flash bank configuration.
+
+    /// Handles an asserted recovery pin. Returns only if the boot process should continue
+    /// as normal.
+    fn force_recovery(&mut self) {
+        if !self.recovery_enabled {
+            warn!("Recovery pin asserted, but serial recovery is not supported.");
+            return;
+        }
+
+        duprintln!(self.serial, "Recovery pin asserted.");
+        if self.recovery_menu {
+            self.offer_recovery_menu();
+        } else if self.recovery_session() {
+            self.reboot();
+        }
+    }
+
+    /// Makes several sanity checks on the flash bank configuration. Generated ports
+    /// also make them at build time, through [`crate::assert_bank_layout`].
     pub fn verify_bank_correctness(&self) {
         // There is at most one golden bank between internal and external flash
         let total_golden = self.external_banks.iter().filter(|b| b.is_golden).count()
@@ -147,6 +222,9 @@ impl<
         // entirely different firmware image! We have to assume everything is at the right place,
         // or literally anything could happen here. No turning back after entering this unsafe block.
         unsafe {
+            // The tick keeps firing otherwise, into a handler the application may not have.
+            (*SYST::ptr()).csr.modify(|csr| csr & !(SYST_CSR_TICKINT | SYST_CSR_ENABLE));
+            (*SCB::ptr()).icsr.write(SCB_ICSR_PENDSTCLR);
             let initial_stack_pointer = *(image_location_raw as *const u32);
             let reset_handler_pointer =
                 *((image_location_raw + size_of::<u32>()) as *const u32) as *const ();
@@ -177,7 +255,12 @@ impl<
 #[cfg(test)]
 #[doc(hidden)]
 pub mod doubles {
-    use crate::devices::update_signal::{ReadUpdateSignal, UpdatePlan};
+    use crate::devices::{
+        bank_state::NullBankStateStore,
+        flash_protection::NullProtectionOptions,
+        recovery_pin::NullRecoveryPin,
+        update_signal::{ReadUpdateSignal, UpdatePlan, UpdateResult},
+    };
     use blue_hal::{
         hal::{
             doubles::{
@@ -206,7 +289,10 @@ pub mod doubles {
 
     pub struct FakeUpdateSignal;
     impl ReadUpdateSignal for FakeUpdateSignal {
-        fn read_update_plan(&self) -> UpdatePlan { UpdatePlan::Any }
+        fn read_update_plan(&self) -> Option<UpdatePlan> { Some(UpdatePlan::Any) }
+        fn is_one_shot(&self) -> bool { false }
+        fn clear_one_shot(&mut self) {}
+        fn write_update_result(&mut self, _result: UpdateResult) {}
     }
 
     pub type BootloaderDouble = super::Bootloader<
@@ -216,6 +302,9 @@ pub mod doubles {
         MockSysTick,
         FakeReader,
         FakeUpdateSignal,
+        NullRecoveryPin,
+        NullProtectionOptions,
+        NullBankStateStore,
     >;
 
     impl BootloaderDouble {
@@ -229,9 +318,14 @@ pub mod doubles {
                 boot_metrics: BootMetrics::default(),
                 start_time: None,
                 recovery_enabled: false,
+                recovery_policy: Default::default(),
                 greeting: "I'm a fake bootloader!",
                 _marker: Default::default(),
                 update_signal: None,
+                recovery_pin: None,
+                recovery_menu: false,
+                flash_protection: None,
+                bank_states: None,
             }
         }
 
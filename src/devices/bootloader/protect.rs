@@ -0,0 +1,46 @@
+use super::*;
+use crate::devices::update_signal::ReadUpdateSignal;
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        RP: RecoveryPin,
+        FP: ProtectionOptions,
+        BS: BankStateStore,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, RP, FP, BS>
+{
+    /// Ensures the configured flash protection is in place. Failing to do so is
+    /// reported, but doesn't prevent booting.
+    pub(super) fn enforce_flash_protection(&mut self) {
+        let result = match self.flash_protection.as_mut() {
+            Some(protection) => protection.enforce(),
+            None => return,
+        };
+
+        match result {
+            Ok(true) => {
+                duprintln!(self.serial, "Flash protection applied.");
+            }
+            Ok(false) => (),
+            Err(e) => {
+                warn!("Failed to apply flash protection.");
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
+                }
+            }
+        }
+    }
+
+    /// Lifts write protection from the golden bank ahead of storing a new golden
+    /// image. It is restored by [`enforce_flash_protection`](Self::enforce_flash_protection).
+    pub(super) fn unlock_golden_bank(&mut self) -> Result<(), Error> {
+        match self.flash_protection.as_mut() {
+            Some(protection) => protection.unlock_golden_bank(),
+            None => Ok(()),
+        }
+    }
+}
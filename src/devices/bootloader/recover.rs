@@ -1,7 +1,62 @@
-use crate::devices::{cli::file_transfer::FileTransfer, update_signal::ReadUpdateSignal};
+use crate::devices::{
+    cli::file_transfer::{BlockIterator, FileTransfer, Status, Timeout},
+    image::WriteSession,
+    management,
+    update_signal::ReadUpdateSignal,
+};
+use blue_hal::{
+    hal::serial::TimeoutRead,
+    utilities::{memory::Address, xmodem},
+};
 
 use super::*;
 
+/// Consecutive read timeouts after which the operator is assumed to accept the
+/// default recovery bank.
+const BANK_SELECTION_PERIODS: u32 = 5;
+
+/// How a recovery session ended.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum SessionEnd {
+    Flashed,
+    TimedOut,
+    AttemptsExhausted,
+}
+
+/// Bank an image is recovered into.
+#[derive(Copy, Clone)]
+enum RecoveryTarget<M: Address, E: Address> {
+    Mcu(Bank<M>),
+    External(Bank<E>),
+}
+
+impl<M: Address, E: Address> RecoveryTarget<M, E> {
+    fn index(&self) -> u8 {
+        match self {
+            RecoveryTarget::Mcu(bank) => bank.index,
+            RecoveryTarget::External(bank) => bank.index,
+        }
+    }
+
+    /// Bank recovered into unless the operator chooses otherwise: the golden bank if there
+    /// is one, or the bootable bank otherwise.
+    fn default_for(mcu_banks: &[Bank<M>], external_banks: &[Bank<E>]) -> Option<Self> {
+        let golden_mcu = mcu_banks.iter().find(|b| b.is_golden).map(|b| RecoveryTarget::Mcu(*b));
+        let golden_external =
+            external_banks.iter().find(|b| b.is_golden).map(|b| RecoveryTarget::External(*b));
+        let bootable = mcu_banks.iter().find(|b| b.bootable).map(|b| RecoveryTarget::Mcu(*b));
+        golden_mcu.or(golden_external).or(bootable)
+    }
+
+    /// Bank with the index chosen by the operator, if there is one.
+    fn with_index(mcu_banks: &[Bank<M>], external_banks: &[Bank<E>], index: u8) -> Option<Self> {
+        let mcu = mcu_banks.iter().find(|b| b.index == index).map(|b| RecoveryTarget::Mcu(*b));
+        let external =
+            external_banks.iter().find(|b| b.index == index).map(|b| RecoveryTarget::External(*b));
+        mcu.or(external)
+    }
+}
+
 impl<
         EXTF: Flash,
         MCUF: Flash,
@@ -9,146 +64,432 @@ impl<
         T: time::Now,
         R: image::Reader,
         RUS: ReadUpdateSignal,
-    > Bootloader<EXTF, MCUF, SRL, T, R, RUS>
+        RP: RecoveryPin,
+        FP: ProtectionOptions,
+        BS: BankStateStore,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, RP, FP, BS>
 {
     /// Enters recovery mode, which requests a golden image to be transferred via serial through
     /// the XMODEM protocol, then reboot. If Loadstone has no golden image support, recovery
-    /// mode will allow flashing the bootable bank directly.
+    /// mode will allow flashing the bootable bank directly. The operator may also choose any
+    /// other bank to recover into; golden banks only accept golden images.
+    ///
+    /// If the recovery session ends without a new image (because the sender timed out, or
+    /// because too many transfers failed) the restore process is attempted once more before
+    /// rebooting.
     pub fn recover(&mut self) -> ! {
-        duprintln!(self.serial, "-- Loadstone Recovery Mode --");
+        if self.recovery_session() {
+            self.reboot();
+        }
 
-        let mcu_golden_bank_exists = self.mcu_banks().any(|b| b.is_golden);
-        let external_golden_bank_exists = self.external_banks().any(|b| b.is_golden);
-        let no_golden_bank_support = !mcu_golden_bank_exists && !external_golden_bank_exists;
+        duprintln!(self.serial, "Attempting to restore an image...");
+        let error = match self.restore() {
+            Ok(image) => self.boot(image).unwrap_err(),
+            Err(e) => e,
+        };
+        if let Some(serial) = self.serial.as_mut() {
+            error.report(serial);
+        }
+        self.reboot();
+    }
 
-        if mcu_golden_bank_exists {
-            duprintln!(self.serial, "Attempting golden image recovery to MCU flash...");
-            match self.recover_internal(true) {
-                Ok(_) => {
-                    duprintln!(self.serial, "Finished flashing golden image.");
-                    self.reboot();
-                }
-                Err(e) => {
-                    duprintln!(self.serial, "FATAL: Image did not flash correctly.");
-                    if let Some(serial) = self.serial.as_mut() {
-                        e.report(serial);
-                    }
-                    self.reboot();
+    /// Runs a recovery session bounded by the recovery policy. Returns whether
+    /// a new image was successfully flashed. Host tools may take over the session
+    /// with the management protocol instead, in which case they are responsible
+    /// for rebooting the device.
+    pub(super) fn recovery_session(&mut self) -> bool {
+        duprintln!(self.serial, "-- Loadstone Recovery Mode --");
+        let mut first_byte = self.listen_for_management();
+        if first_byte == Some(management::FRAME_DELIMITER) {
+            duprintln!(self.serial, "Serving management requests.");
+            if let Err(e) = self.serve_management() {
+                if let Some(serial) = self.serial.as_mut() {
+                    e.report(serial);
                 }
             }
+            self.enforce_flash_protection();
+            return false;
         }
+        let max_attempts = self.recovery_policy.max_attempts.max(1);
 
-        if self.external_flash.is_some() && external_golden_bank_exists {
-            duprintln!(self.serial, "Attempting golden image recovery to external flash...");
-            match self.recover_external(true) {
-                Ok(_) => {
-                    duprintln!(self.serial, "Finished flashing golden image.");
-                    self.reboot();
-                }
+        let end = run_attempts(max_attempts, |attempt| {
+            if max_attempts > 1 {
+                duprintln!(self.serial, "Recovery attempt {} of {}.", attempt, max_attempts);
+            }
+            let result = self.recovery_attempt(first_byte.take());
+            match result {
+                Ok(()) | Err(Error::TransferTimedOut) => {}
                 Err(e) => {
-                    duprintln!(self.serial, "FATAL: Image did not flash correctly.");
+                    duprintln!(self.serial, "Image did not flash correctly.");
                     if let Some(serial) = self.serial.as_mut() {
                         e.report(serial);
                     }
-                    self.reboot();
                 }
             }
-        }
+            result
+        });
 
-        if no_golden_bank_support {
-            duprintln!(self.serial, "Attempting image recovery to MCU flash...");
-            match self.recover_internal(false) {
-                Ok(_) => {
-                    duprintln!(self.serial, "Finished flashing image.");
-                    self.reboot();
-                }
-                Err(e) => {
-                    duprintln!(self.serial, "FATAL: Image did not flash correctly.");
-                    if let Some(serial) = self.serial.as_mut() {
-                        e.report(serial);
-                    }
-                    self.reboot();
-                }
+        match end {
+            SessionEnd::Flashed => duprintln!(self.serial, "Finished flashing image."),
+            SessionEnd::TimedOut => {
+                duprintln!(self.serial, "No image received, leaving recovery mode.")
+            }
+            SessionEnd::AttemptsExhausted => {
+                duprintln!(self.serial, "Too many failed attempts, leaving recovery mode.")
             }
         }
-
-        self.reboot();
+        end == SessionEnd::Flashed
     }
 
-    fn reboot(&mut self) -> ! {
+    pub(super) fn reboot(&mut self) -> ! {
         duprintln!(self.serial, "Rebooting...");
         SCB::sys_reset();
     }
 
-    fn recover_internal(&mut self, golden: bool) -> Result<(), Error> {
-        if self.serial.is_none() {
-            return Err(Error::NoRecoverySupport);
+    /// Runs a single recovery attempt. A byte the operator already typed, if any,
+    /// starts their bank selection.
+    fn recovery_attempt(&mut self, first_byte: Option<u8>) -> Result<(), Error> {
+        match self.select_recovery_target(first_byte)? {
+            RecoveryTarget::Mcu(bank) => {
+                duprintln!(
+                    self.serial,
+                    "Attempting {}image recovery to MCU flash bank {}...",
+                    if bank.is_golden { "golden " } else { "" },
+                    bank.index
+                );
+                self.recover_internal(bank)
+            }
+            RecoveryTarget::External(bank) => {
+                duprintln!(
+                    self.serial,
+                    "Attempting {}image recovery to external flash bank {}...",
+                    if bank.is_golden { "golden " } else { "" },
+                    bank.index
+                );
+                self.recover_external(bank)
+            }
+        }
+    }
+
+    /// Bank recovered into unless the operator chooses otherwise.
+    fn default_recovery_target(&self) -> RecoveryTarget<MCUF::Address, EXTF::Address> {
+        RecoveryTarget::default_for(self.mcu_banks, self.external_banks)
+            .unwrap_or_else(|| RecoveryTarget::Mcu(self.boot_bank()))
+    }
+
+    /// Lists all banks and lets the operator choose which one to recover into. Falls back
+    /// to the default target if the operator doesn't answer in time.
+    fn select_recovery_target(
+        &mut self,
+        first_byte: Option<u8>,
+    ) -> Result<RecoveryTarget<MCUF::Address, EXTF::Address>, Error> {
+        let default = self.default_recovery_target();
+        if self.mcu_banks.len() + self.external_banks.len() < 2 {
+            return Ok(default);
         }
 
-        if let Some(bank) = self.mcu_banks.iter().find(|b| b.is_golden == golden) {
+        duprintln!(self.serial, "Available banks:");
+        for bank in self.mcu_banks() {
             duprintln!(
                 self.serial,
-                "Please send{} firmware image via XMODEM.",
-                if golden { " golden" } else { "" }
+                "   - [{}] {} - Size: {}b{}{}",
+                bank.index,
+                MCUF::label(),
+                bank.size,
+                if bank.bootable { " - Bootable" } else { "" },
+                if bank.is_golden { " - GOLDEN" } else { "" }
             );
-            let blocks = self.serial.as_mut().unwrap().blocks(None);
-            if self.mcu_flash.write_from_blocks(bank.location, blocks).is_err() {
-                duprintln!(
-                    self.serial,
-                    "FATAL: Failed to flash{} image during recovery mode.",
-                    if golden { " golden" } else { "" },
-                );
-                panic!();
-            }
-            match R::image_at(&mut self.mcu_flash, *bank) {
-                Ok(image) if golden && !image.is_golden() => {
-                    duprintln!(self.serial, "FATAL: Flashed image is not a golden image.");
-                    Err(Error::ImageIsNotGolden)
+        }
+        for bank in self.external_banks() {
+            duprintln!(
+                self.serial,
+                "   - [{}] {} - Size: {}b{}",
+                bank.index,
+                EXTF::label(),
+                bank.size,
+                if bank.is_golden { " - GOLDEN" } else { "" }
+            );
+        }
+        duprintln!(self.serial, "Select a bank to recover into [default: {}]:", default.index());
+
+        let serial = self.serial.as_mut().ok_or(Error::NoRecoverySupport)?;
+        match read_bank_selection(serial, first_byte)? {
+            None => Ok(default),
+            Some(index) => {
+                match RecoveryTarget::with_index(self.mcu_banks, self.external_banks, index) {
+                    Some(target) => Ok(target),
+                    None => {
+                        duprintln!(self.serial, "Index supplied does not correspond to any bank.");
+                        Err(Error::BankInvalid)
+                    }
                 }
+            }
+        }
+    }
+
+    fn recover_internal(&mut self, bank: Bank<MCUF::Address>) -> Result<(), Error> {
+        if self.serial.is_none() {
+            return Err(Error::NoRecoverySupport);
+        }
+
+        duprintln!(
+            self.serial,
+            "Please send{} firmware image via XMODEM (1K, CRC) or YMODEM.",
+            if bank.is_golden { " golden" } else { "" }
+        );
+        if bank.is_golden {
+            self.unlock_golden_bank()?;
+        }
+        let start = T::now();
+        let clock = || T::now() - start;
+        let timeout = self.recovery_policy.timeout.map(|limit| Timeout { clock: &clock, limit });
+        let result = {
+            let mut blocks = self.serial.as_mut().unwrap().timed_blocks(None, timeout);
+            let session = WriteSession::new(&mut self.mcu_flash, bank);
+            match Self::receive_image(session, &mut blocks) {
+                Ok(image) if bank.is_golden && !image.is_golden() => Err(Error::ImageIsNotGolden),
                 Err(e) => Err(e),
                 _ => Ok(()),
             }
-        } else {
-            Err(Error::NoGoldenBankSupport)
+        };
+        if bank.is_golden {
+            self.enforce_flash_protection();
         }
+        self.record_new_image(bank, &result);
+        result
     }
 
-    fn recover_external(&mut self, golden: bool) -> Result<(), Error> {
+    fn recover_external(&mut self, bank: Bank<EXTF::Address>) -> Result<(), Error> {
         if self.serial.is_none() {
             return Err(Error::NoRecoverySupport);
         }
 
-        if let Some(bank) = self.external_banks.iter().find(|b| b.is_golden == golden) {
-            duprintln!(
-                self.serial,
-                "Please send{} firmware image via XMODEM.",
-                if golden { " golden" } else { "" }
-            );
-            let blocks = self.serial.as_mut().unwrap().blocks(None);
-            if self
-                .external_flash
-                .as_mut()
-                .unwrap()
-                .write_from_blocks(bank.location, blocks)
-                .is_err()
-            {
-                duprintln!(
-                    self.serial,
-                    "FATAL: Failed to flash{} image during recovery mode.",
-                    if golden { " golden" } else { "" },
-                );
-                panic!();
-            }
-            match R::image_at(self.external_flash.as_mut().unwrap(), *bank) {
-                Ok(image) if golden && !image.is_golden() => {
-                    duprintln!(self.serial, "FATAL: Flashed image is not a golden image.");
-                    Err(Error::ImageIsNotGolden)
-                }
+        let start = T::now();
+        let clock = || T::now() - start;
+        let timeout = self.recovery_policy.timeout.map(|limit| Timeout { clock: &clock, limit });
+        let external_flash = self.external_flash.as_mut().ok_or(Error::NoExternalFlash)?;
+        duprintln!(
+            self.serial,
+            "Please send{} firmware image via XMODEM (1K, CRC) or YMODEM.",
+            if bank.is_golden { " golden" } else { "" }
+        );
+        let result = {
+            let mut blocks = self.serial.as_mut().unwrap().timed_blocks(None, timeout);
+            let session = WriteSession::new(external_flash, bank);
+            match Self::receive_image(session, &mut blocks) {
+                Ok(image) if bank.is_golden && !image.is_golden() => Err(Error::ImageIsNotGolden),
                 Err(e) => Err(e),
                 _ => Ok(()),
             }
-        } else {
-            Err(Error::NoGoldenBankSupport)
+        };
+        self.record_new_image(bank, &result);
+        result
+    }
+
+    /// Streams an XMODEM transfer into a write session, cancelling the transfer as
+    /// soon as the image is known not to fit, and verifies the result if the transfer
+    /// completed.
+    fn receive_image<A, F, S>(
+        mut session: WriteSession<A, F>,
+        blocks: &mut BlockIterator<S>,
+    ) -> Result<Image<A>, Error>
+    where
+        A: Address,
+        F: flash::ReadWrite<Address = A>,
+        Error: From<F::Error>,
+        S: FileTransfer + ?Sized,
+    {
+        let result = match blocks.header().and_then(|h| h.size()) {
+            Some(size) => session.reserve(size),
+            None => Ok(()),
+        }
+        .and_then(|_| session.write(&mut *blocks));
+
+        if let Err(e) = result {
+            blocks.cancel();
+            return Err(e);
+        }
+
+        match blocks.status() {
+            Status::TimedOut => Err(Error::TransferTimedOut),
+            Status::Cancelled => Err(Error::TransferCancelled),
+            _ => session.finish::<R>(),
+        }
+    }
+}
+
+/// Runs numbered recovery attempts until one flashes an image, one times out waiting
+/// for the sender, or `max_attempts` of them fail. At least one attempt is made.
+fn run_attempts<F>(max_attempts: u32, mut attempt: F) -> SessionEnd
+where
+    F: FnMut(u32) -> Result<(), Error>,
+{
+    for number in 1..=max_attempts.max(1) {
+        match attempt(number) {
+            Ok(()) => return SessionEnd::Flashed,
+            Err(Error::TransferTimedOut) => return SessionEnd::TimedOut,
+            Err(_) => {}
+        }
+    }
+    SessionEnd::AttemptsExhausted
+}
+
+/// Reads a bank index typed by the operator, terminated by a new line and starting
+/// with `first_byte` if the operator typed one already. Anything other than digits
+/// is ignored, so line noise doesn't count as a failed attempt. Returns `None` if no
+/// digits are typed before the selection window closes.
+fn read_bank_selection<S: TimeoutRead + ?Sized>(
+    serial: &mut S,
+    mut first_byte: Option<u8>,
+) -> Result<Option<u8>, Error> {
+    let mut selection: Option<u8> = None;
+    let mut idle_periods = 0;
+
+    while idle_periods < BANK_SELECTION_PERIODS {
+        let byte = match first_byte.take() {
+            Some(byte) => Ok(byte),
+            None => TimeoutRead::read(serial, xmodem::DEFAULT_TIMEOUT),
+        };
+        match byte {
+            Ok(b'\r') | Ok(b'\n') => break,
+            Ok(digit @ b'0'..=b'9') => {
+                selection = selection
+                    .unwrap_or(0)
+                    .checked_mul(10)
+                    .and_then(|s| s.checked_add(digit - b'0'))
+                    .map(Some)
+                    .ok_or(Error::BankInvalid)?;
+            }
+            Ok(_) => {}
+            Err(_) => idle_periods += 1,
         }
     }
+    Ok(selection)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use blue_hal::hal::{doubles::flash::Address, time::Milliseconds};
+    use std::{collections::VecDeque, vec::Vec};
+
+    type Target = RecoveryTarget<Address, Address>;
+
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    struct NoData;
+
+    /// Serial double that plays back what the operator types, then stays silent.
+    struct FakeOperator(VecDeque<u8>);
+
+    impl FakeOperator {
+        fn typing(input: &[u8]) -> Self { Self(input.iter().copied().collect()) }
+    }
+
+    impl TimeoutRead for FakeOperator {
+        type Error = NoData;
+
+        fn read<T: Copy + Into<Milliseconds>>(&mut self, _: T) -> Result<u8, Self::Error> {
+            self.0.pop_front().ok_or(NoData)
+        }
+    }
+
+    fn select(input: &[u8], first_byte: Option<u8>) -> Result<Option<u8>, Error> {
+        read_bank_selection(&mut FakeOperator::typing(input), first_byte)
+    }
+
+    fn is_mcu(target: Option<Target>, index: u8) -> bool {
+        matches!(target, Some(RecoveryTarget::Mcu(bank)) if bank.index == index)
+    }
+
+    fn is_external(target: Option<Target>, index: u8) -> bool {
+        matches!(target, Some(RecoveryTarget::External(bank)) if bank.index == index)
+    }
+
+    #[test]
+    fn recovery_defaults_to_the_golden_bank() {
+        let bootable = Bank::bootable(1, 64, Address(0));
+        let mcu_golden = [bootable, Bank::golden(2, 64, Address(64))];
+        assert!(is_mcu(Target::default_for(&mcu_golden, &[]), 2));
+
+        let external_golden = [Bank::regular(2, 64, Address(0)), Bank::golden(3, 64, Address(64))];
+        assert!(is_external(Target::default_for(&[bootable], &external_golden), 3));
+    }
+
+    #[test]
+    fn recovery_defaults_to_the_bootable_bank_without_a_golden_bank() {
+        let mcu = [Bank::regular(1, 64, Address(0)), Bank::bootable(2, 64, Address(64))];
+        let external = [Bank::regular(3, 64, Address(0))];
+        assert!(is_mcu(Target::default_for(&mcu, &external), 2));
+        assert!(Target::default_for(&mcu[..1], &[]).is_none());
+    }
+
+    #[test]
+    fn chosen_recovery_banks_must_exist() {
+        let mcu = [Bank::bootable(1, 64, Address(0)), Bank::regular(2, 64, Address(64))];
+        let external = [Bank::golden(3, 64, Address(0))];
+        assert!(is_mcu(Target::with_index(&mcu, &external, 2), 2));
+        assert!(is_external(Target::with_index(&mcu, &external, 3), 3));
+        assert!(Target::with_index(&mcu, &external, 0).is_none());
+        assert!(Target::with_index(&mcu, &external, 4).is_none());
+    }
+
+    #[test]
+    fn bank_selections_end_with_a_new_line() {
+        assert_eq!(Ok(Some(12)), select(b"12\r\n3", None));
+        assert_eq!(Ok(Some(12)), select(b"2\n", Some(b'1')));
+    }
+
+    #[test]
+    fn bank_selections_ignore_anything_but_digits() {
+        assert_eq!(Ok(Some(3)), select(b" \x1b[3\0\n", None));
+        assert_eq!(Ok(None), select(b"x\n", None));
+    }
+
+    #[test]
+    fn bank_selections_fall_back_to_the_default_after_silence() {
+        assert_eq!(Ok(None), select(b"", None));
+        assert_eq!(Ok(Some(2)), select(b"2", None));
+    }
+
+    #[test]
+    fn oversized_bank_selections_are_invalid() {
+        assert_eq!(Err(Error::BankInvalid), select(b"256\n", None));
+    }
+
+    #[test]
+    fn failed_attempts_are_retried_up_to_the_limit() {
+        let mut attempts = Vec::new();
+        let end = run_attempts(3, |attempt| {
+            attempts.push(attempt);
+            Err(Error::BankInvalid)
+        });
+        assert_eq!(SessionEnd::AttemptsExhausted, end);
+        assert_eq!([1, 2, 3], attempts[..]);
+
+        let end =
+            run_attempts(3, |attempt| if attempt < 2 { Err(Error::BankInvalid) } else { Ok(()) });
+        assert_eq!(SessionEnd::Flashed, end);
+    }
+
+    #[test]
+    fn recovery_timeouts_end_the_session_at_once() {
+        let mut attempts = 0;
+        let end = run_attempts(3, |_| {
+            attempts += 1;
+            Err(Error::TransferTimedOut)
+        });
+        assert_eq!(SessionEnd::TimedOut, end);
+        assert_eq!(1, attempts);
+    }
+
+    #[test]
+    fn at_least_one_recovery_attempt_is_made() {
+        let mut attempts = 0;
+        let end = run_attempts(0, |_| {
+            attempts += 1;
+            Err(Error::BankInvalid)
+        });
+        assert_eq!(SessionEnd::AttemptsExhausted, end);
+        assert_eq!(1, attempts);
+    }
 }
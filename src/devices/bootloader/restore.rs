@@ -8,10 +8,14 @@ impl<
         T: time::Now,
         R: image::Reader,
         RUS: ReadUpdateSignal,
-    > Bootloader<EXTF, MCUF, SRL, T, R, RUS>
+        RP: RecoveryPin,
+        FP: ProtectionOptions,
+        BS: BankStateStore,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, RP, FP, BS>
 {
     /// Restores the first image available in all banks, attempting to restore
-    /// from the golden image as a last resort.
+    /// from the golden image as a last resort. Banks marked as empty or bad are
+    /// skipped, and a restored image is pending confirmation from the application.
     pub fn restore(&mut self) -> Result<Image<MCUF::Address>, Error> {
         self.restore_internal(false)
             .or_else(|| self.restore_external(false))
@@ -20,25 +24,35 @@ impl<
             .ok_or(Error::NoImageToRestoreFrom)
     }
 
+    /// Replaces the current image with the golden image, if available.
+    pub fn restore_golden(&mut self) -> Result<Image<MCUF::Address>, Error> {
+        self.restore_internal(true)
+            .or_else(|| self.restore_external(true))
+            .ok_or(Error::NoImageToRestoreFrom)
+    }
+
     fn restore_external(&mut self, golden: bool) -> Option<Image<MCUF::Address>> {
         let output = self.boot_bank();
-        for input_bank in self.external_banks.iter().filter(|b| b.is_golden == golden) {
+        for input_bank in self.external_banks().filter(|b| b.is_golden == golden) {
+            if self.known_unusable(input_bank.index) {
+                continue;
+            }
             duprintln!(
                 self.serial,
                 "Attempting to restore from{} bank {:?}.",
                 if golden { " golden" } else { "" },
                 input_bank.index
             );
-            if Self::copy_image(
+            let result = Self::copy_image(
                 &mut self.serial,
                 self.external_flash.as_mut().unwrap(),
                 &mut self.mcu_flash,
-                *input_bank,
+                input_bank,
                 output,
                 golden,
-            )
-            .is_err()
-            {
+            );
+            self.record_verification(input_bank.index, &result);
+            if result.is_err() {
                 continue;
             }
 
@@ -50,6 +64,7 @@ impl<
             );
             duprintln!(self.serial, "Verifying the image again in the boot bank...");
             self.boot_metrics.boot_path = BootPath::Restored { bank: input_bank.index };
+            self.mark_installed(input_bank);
             return R::image_at(&mut self.mcu_flash, output).ok();
         }
         None
@@ -58,23 +73,26 @@ impl<
     fn restore_internal(&mut self, golden: bool) -> Option<Image<MCUF::Address>> {
         let output = self.boot_bank();
         for input_bank in
-            self.mcu_banks.iter().filter(|b| b.is_golden == golden && b.index != output.index)
+            self.mcu_banks().filter(|b| b.is_golden == golden && b.index != output.index)
         {
+            if self.known_unusable(input_bank.index) {
+                continue;
+            }
             duprintln!(
                 self.serial,
                 "Attempting to restore from{} bank {:?}.",
                 if golden { " golden" } else { "" },
                 input_bank.index
             );
-            if Self::copy_image_single_flash(
+            let result = Self::copy_image_single_flash(
                 &mut self.serial,
                 &mut self.mcu_flash,
-                *input_bank,
+                input_bank,
                 output,
                 golden,
-            )
-            .is_err()
-            {
+            );
+            self.record_verification(input_bank.index, &result);
+            if result.is_err() {
                 continue;
             }
 
@@ -86,6 +104,7 @@ impl<
             );
             duprintln!(self.serial, "Verifying the image again in the boot bank...");
             self.boot_metrics.boot_path = BootPath::Restored { bank: input_bank.index };
+            self.mark_installed(input_bank);
             return R::image_at(&mut self.mcu_flash, output).ok();
         }
         None
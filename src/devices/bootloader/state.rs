@@ -0,0 +1,100 @@
+use super::*;
+use crate::devices::update_signal::ReadUpdateSignal;
+use blue_hal::utilities::memory::Address;
+
+impl<
+        EXTF: Flash,
+        MCUF: Flash,
+        SRL: Serial,
+        T: time::Now,
+        R: image::Reader,
+        RUS: ReadUpdateSignal,
+        RP: RecoveryPin,
+        FP: ProtectionOptions,
+        BS: BankStateStore,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, RP, FP, BS>
+{
+    /// Last recorded state of a bank, if the port keeps track of bank states.
+    pub fn bank_state(&self, bank: u8) -> Option<BankState> {
+        self.bank_states.as_ref().and_then(|s| s.bank_state(bank))
+    }
+
+    pub(super) fn set_bank_state(&mut self, bank: u8, state: BankState) {
+        if let Some(states) = self.bank_states.as_mut() {
+            states.set_bank_state(bank, state);
+        }
+    }
+
+    /// Records the state implied by the result of verifying the image in a bank.
+    pub(super) fn record_verification<I>(&mut self, bank: u8, result: &Result<I, Error>) {
+        if let Some(state) = BankState::after_verification(result) {
+            self.set_bank_state(bank, state);
+        }
+    }
+
+    /// Whether a bank is known not to hold a usable image, in which case it's
+    /// skipped without scanning it again. Golden banks are never skipped, as they
+    /// are the last resort.
+    pub(super) fn known_unusable(&mut self, bank: u8) -> bool {
+        let golden = self
+            .mcu_banks()
+            .map(|b| (b.index, b.is_golden))
+            .chain(self.external_banks().map(|b| (b.index, b.is_golden)))
+            .any(|(index, golden)| golden && index == bank);
+        match self.bank_state(bank) {
+            Some(state) if !golden && !state.is_usable() => {
+                duprintln!(self.serial, "Skipping bank {:?} (Marked as {})...", bank, state.name());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Marks an image just copied into the bootable bank, and the bank it came from,
+    /// as pending confirmation from the application. Golden banks are left alone, as
+    /// they must remain available as a last resort.
+    pub(super) fn mark_installed<A: Address>(&mut self, source: Bank<A>) {
+        if !source.is_golden {
+            self.set_bank_state(source.index, BankState::Pending);
+        }
+        self.set_bank_state(self.boot_bank().index, BankState::Pending);
+    }
+
+    /// Records the outcome of writing a new image to a bank. Images written to the
+    /// bootable bank are pending confirmation, and any others are staged.
+    pub(super) fn record_new_image<A: Address>(
+        &mut self,
+        bank: Bank<A>,
+        result: &Result<(), Error>,
+    ) {
+        match result {
+            Ok(()) if bank.bootable => self.set_bank_state(bank.index, BankState::Pending),
+            Ok(()) => self.set_bank_state(bank.index, BankState::Staged),
+            Err(_) => self.record_verification(bank.index, result),
+        }
+    }
+
+    /// Marks an image as bad, both in the bootable bank and in any other non-golden
+    /// bank holding it, so the restore process replaces it with a different one.
+    pub(super) fn reject_image(&mut self, image: Image<MCUF::Address>) {
+        let boot_bank = self.boot_bank();
+        self.set_bank_state(boot_bank.index, BankState::Bad);
+        for bank in self.mcu_banks().filter(|b| !b.is_golden && b.index != boot_bank.index) {
+            let holds_image = matches!(
+                R::image_at(&mut self.mcu_flash, bank),
+                Ok(i) if i.identifier() == image.identifier()
+            );
+            if holds_image {
+                self.set_bank_state(bank.index, BankState::Bad);
+            }
+        }
+        for bank in self.external_banks().filter(|b| !b.is_golden) {
+            let holds_image = self.external_flash.as_mut().map_or(false, |flash| {
+                matches!(R::image_at(flash, bank), Ok(i) if i.identifier() == image.identifier())
+            });
+            if holds_image {
+                self.set_bank_state(bank.index, BankState::Bad);
+            }
+        }
+    }
+}
@@ -1,10 +1,11 @@
 use super::*;
-use crate::devices::update_signal::{ReadUpdateSignal, UpdatePlan};
+use crate::devices::update_signal::{ReadUpdateSignal, UpdateError, UpdatePlan, UpdateResult};
 
-enum UpdateResult<MCUF: Flash> {
+/// Outcome of scanning a flash chip for a newer image.
+enum ScanResult<MCUF: Flash> {
     AlreadyUpToDate(Image<MCUF::Address>),
     NotUpdated(Image<MCUF::Address>),
-    UpdatedTo(Image<MCUF::Address>),
+    UpdatedTo(Image<MCUF::Address>, u8),
     UpdateError,
 }
 
@@ -15,30 +16,51 @@ impl<
         T: time::Now,
         R: image::Reader,
         RUS: ReadUpdateSignal,
-    > Bootloader<EXTF, MCUF, SRL, T, R, RUS>
+        RP: RecoveryPin,
+        FP: ProtectionOptions,
+        BS: BankStateStore,
+    > Bootloader<EXTF, MCUF, SRL, T, R, RUS, RP, FP, BS>
 {
     /// If the current bootable (MCU flash) image is different from the top
     /// non-golden image, attempts to replace it. On failure, this process
     /// is repeated for all non-golden banks. Returns the current
     /// bootable image after the process, if available.
+    ///
+    /// Banks marked as empty or bad are skipped, and the outcome of each scan
+    /// is recorded. A bootable image marked as bad is never returned.
+    ///
+    /// The update signal may also request restoring the golden image, entering
+    /// recovery mode, or rolling back (rejecting the current image, so that the
+    /// restore process replaces it). The outcome is reported back to the
+    /// application through the update signal.
     pub fn latest_bootable_image(&mut self) -> Option<Image<MCUF::Address>> {
+        let (image, result) = self.apply_update_plan();
+        if let Some(signal) = self.update_signal.as_mut() {
+            signal.write_update_result(result);
+        }
+        image
+    }
+
+    fn apply_update_plan(&mut self) -> (Option<Image<MCUF::Address>>, UpdateResult) {
         let boot_bank = self.boot_bank();
-        let current_image = if let Ok(image) = R::image_at(&mut self.mcu_flash, boot_bank) {
+        if self.bank_state(boot_bank.index) == Some(BankState::Bad) {
+            duprintln!(self.serial, "Current image was marked as bad.");
+            return (None, UpdateResult::UpdateError(UpdateError::CurrentImageRejected));
+        }
+        let current_image = R::image_at(&mut self.mcu_flash, boot_bank);
+        self.record_verification(boot_bank.index, &current_image);
+        let current_image = if let Ok(image) = current_image {
             image
         } else {
             duprintln!(self.serial, "No current image.");
-            return None;
+            return (None, UpdateResult::UpdateError(UpdateError::NoCurrentImage));
         };
 
-        let bank: Option<u8> = match self
-            .update_signal
-            .as_ref()
-            .map(ReadUpdateSignal::read_update_plan)
-        {
+        let bank: Option<u8> = match self.take_update_plan() {
             None => None,
             Some(UpdatePlan::None) => {
                 duprintln!(self.serial, "Update signal set to None, refusing to update.");
-                return Some(current_image);
+                return (Some(current_image), UpdateResult::NotUpdated);
             }
             Some(UpdatePlan::Any) => {
                 duprintln!(self.serial, "Update signal set to Any, checking for image updates.");
@@ -53,21 +75,106 @@ impl<
                 );
                 Some(i)
             }
+            Some(UpdatePlan::RestoreGolden) => {
+                duprintln!(
+                    self.serial,
+                    "Update signal set to RestoreGolden, restoring golden image."
+                );
+                return match self.restore_golden() {
+                    Ok(image) => {
+                        let result = match self.boot_metrics.boot_path {
+                            BootPath::Restored { bank } => UpdateResult::UpdatedTo { bank },
+                            _ => UpdateResult::NotUpdated,
+                        };
+                        (Some(image), result)
+                    }
+                    Err(_) => {
+                        duprintln!(
+                            self.serial,
+                            "No golden image to restore, keeping current image."
+                        );
+                        (Some(current_image), UpdateResult::UpdateError(UpdateError::NoGoldenImage))
+                    }
+                };
+            }
+            Some(UpdatePlan::EnterRecovery) => {
+                if !self.recovery_enabled {
+                    duprintln!(
+                        self.serial,
+                        "Update signal set to EnterRecovery, but serial recovery is not supported."
+                    );
+                    return (
+                        Some(current_image),
+                        UpdateResult::UpdateError(UpdateError::NoRecoverySupport),
+                    );
+                }
+                duprintln!(self.serial, "Update signal set to EnterRecovery.");
+                if self.recovery_session() {
+                    self.reboot();
+                }
+                // The session may have partially overwritten the bootable bank.
+                return (
+                    R::image_at(&mut self.mcu_flash, boot_bank).ok(),
+                    UpdateResult::NotUpdated,
+                );
+            }
+            Some(UpdatePlan::Rollback) => {
+                if self.bank_states.is_none() {
+                    duprintln!(
+                        self.serial,
+                        "Update signal set to Rollback, but bank states aren't tracked."
+                    );
+                    return (
+                        Some(current_image),
+                        UpdateResult::UpdateError(UpdateError::NoBankStates),
+                    );
+                }
+                duprintln!(self.serial, "Update signal set to Rollback, rejecting current image.");
+                self.reject_image(current_image);
+                return (None, UpdateResult::NotUpdated);
+            }
         };
 
         let current_image = match self.update_internal(boot_bank, current_image, bank) {
-            UpdateResult::NotUpdated(current_image) => current_image,
-            UpdateResult::AlreadyUpToDate(current_image) => return Some(current_image),
-            UpdateResult::UpdatedTo(new_image) => return Some(new_image),
-            UpdateResult::UpdateError => return None,
+            ScanResult::NotUpdated(current_image) => current_image,
+            result => return Self::scan_outcome(result),
         };
+        Self::scan_outcome(self.update_external(boot_bank, current_image, bank))
+    }
+
+    fn scan_outcome(result: ScanResult<MCUF>) -> (Option<Image<MCUF::Address>>, UpdateResult) {
+        match result {
+            ScanResult::NotUpdated(image) => (Some(image), UpdateResult::NotUpdated),
+            ScanResult::AlreadyUpToDate(image) => (Some(image), UpdateResult::AlreadyUpToDate),
+            ScanResult::UpdatedTo(image, bank) => (Some(image), UpdateResult::UpdatedTo { bank }),
+            ScanResult::UpdateError => (None, UpdateResult::UpdateError(UpdateError::CopyFailed)),
+        }
+    }
 
-        match self.update_external(boot_bank, current_image, bank) {
-            UpdateResult::NotUpdated(current_image) => Some(current_image),
-            UpdateResult::AlreadyUpToDate(current_image) => Some(current_image),
-            UpdateResult::UpdatedTo(new_image) => Some(new_image),
-            UpdateResult::UpdateError => None,
+    /// Reads the update plan, clearing it if it only applies to this boot. One-shot
+    /// plans are cleared before acting on them, so they can't apply more than once
+    /// even if the boot process is interrupted.
+    ///
+    /// A signal that doesn't hold a valid plan is ignored, as if the application had
+    /// never set one, and reported through the boot metrics.
+    fn take_update_plan(&mut self) -> Option<UpdatePlan> {
+        let signal = self.update_signal.as_mut()?;
+        let plan = signal.read_update_plan();
+        let one_shot = plan.is_some() && signal.is_one_shot();
+        if one_shot {
+            signal.clear_one_shot();
         }
+
+        self.boot_metrics.update_signal = match plan {
+            Some(_) => UpdateSignalState::Valid,
+            None => UpdateSignalState::NoSignal,
+        };
+        if plan.is_none() {
+            duprintln!(self.serial, "Update signal holds no valid plan, ignoring it.");
+        } else if one_shot {
+            duprintln!(self.serial, "Consumed one-shot update signal.");
+        }
+        plan
     }
 
     fn update_internal(
@@ -75,7 +182,7 @@ impl<
         boot_bank: Bank<MCUF::Address>,
         current_image: Image<MCUF::Address>,
         target_bank: Option<u8>,
-    ) -> UpdateResult<MCUF> {
+    ) -> ScanResult<MCUF> {
         for bank in self.mcu_banks().filter(|b| b.index != boot_bank.index) {
             if bank.is_golden {
                 duprintln!(
@@ -98,26 +205,33 @@ impl<
                 continue;
             }
 
+            if self.known_unusable(bank.index) {
+                continue;
+            }
+
             duprintln!(
                 self.serial,
                 "[{}] Scanning bank {:?} for a newer image...",
                 MCUF::label(),
                 bank.index
             );
-            match R::image_at(&mut self.mcu_flash, bank) {
+            let image = R::image_at(&mut self.mcu_flash, bank);
+            self.record_verification(bank.index, &image);
+            match image {
                 Ok(image) if image.identifier() != current_image.identifier() => {
                     if let Some(updated_image) = self.replace_image_internal(bank, boot_bank) {
                         self.boot_metrics.boot_path = BootPath::Updated { bank: bank.index };
-                        return UpdateResult::UpdatedTo(updated_image);
+                        self.mark_installed(bank);
+                        return ScanResult::UpdatedTo(updated_image, bank.index);
                     } else {
-                        return UpdateResult::UpdateError;
+                        return ScanResult::UpdateError;
                     }
                 }
-                Ok(_image) => return UpdateResult::AlreadyUpToDate(current_image),
+                Ok(_image) => return ScanResult::AlreadyUpToDate(current_image),
                 _ => (),
             }
         }
-        return UpdateResult::NotUpdated(current_image);
+        return ScanResult::NotUpdated(current_image);
     }
 
     fn update_external(
@@ -125,7 +239,7 @@ impl<
         boot_bank: Bank<MCUF::Address>,
         current_image: Image<MCUF::Address>,
         target_bank: Option<u8>,
-    ) -> UpdateResult<MCUF> {
+    ) -> ScanResult<MCUF> {
         if self.external_flash.is_some() {
             for bank in self.external_banks() {
                 if bank.is_golden {
@@ -149,27 +263,34 @@ impl<
                     continue;
                 }
 
+                if self.known_unusable(bank.index) {
+                    continue;
+                }
+
                 duprintln!(
                     self.serial,
                     "[{}] Scanning bank {:?} for a newer image...",
                     EXTF::label(),
                     bank.index
                 );
-                match R::image_at(self.external_flash.as_mut().unwrap(), bank) {
+                let image = R::image_at(self.external_flash.as_mut().unwrap(), bank);
+                self.record_verification(bank.index, &image);
+                match image {
                     Ok(image) if image.identifier() != current_image.identifier() => {
                         if let Some(updated_image) = self.replace_image_external(bank, boot_bank) {
                             self.boot_metrics.boot_path = BootPath::Updated { bank: bank.index };
-                            return UpdateResult::UpdatedTo(updated_image);
+                            self.mark_installed(bank);
+                            return ScanResult::UpdatedTo(updated_image, bank.index);
                         } else {
-                            return UpdateResult::UpdateError;
+                            return ScanResult::UpdateError;
                         }
                     }
-                    Ok(_image) => return UpdateResult::AlreadyUpToDate(current_image),
+                    Ok(_image) => return ScanResult::AlreadyUpToDate(current_image),
                     _ => (),
                 }
             }
         }
-        return UpdateResult::NotUpdated(current_image);
+        return ScanResult::NotUpdated(current_image);
     }
 
     fn replace_image_internal(
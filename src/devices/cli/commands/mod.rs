@@ -1,11 +1,13 @@
 use crate::{
     devices::{
+        bank_state::BankStateStore,
         boot_manager::BootManager,
-        boot_metrics::BootPath,
+        boot_metrics::{BootPath, UpdateSignalState},
         cli::{file_transfer::FileTransfer, ArgumentIterator, Cli, Error, Name, RetrieveArgument},
         image::{self, MAGIC_STRING},
+        management,
         traits::{Flash, Serial},
-        update_signal::{UpdatePlan, WriteUpdateSignal},
+        update_signal::{UpdatePlan, UpdateResult, WriteUpdateSignal},
     },
     error::Error as ApplicationError,
 };
@@ -21,22 +23,26 @@ commands!( cli, boot_manager, names, helpstrings [
     banks ["Displays bank information"] (){
         uprintln!(cli.serial, "[{}] Banks:", MCUF::label());
         for bank in boot_manager.mcu_banks() {
-            uwriteln!(cli.serial, "   - [{}] {} - Size: {}b{}",
+            uwriteln!(cli.serial, "   - [{}] {} - Size: {}b{}{}{}",
                 bank.index,
                 if bank.bootable { "Bootable" } else { "Non-Bootable" },
                 bank.size,
-                if bank.is_golden { " - GOLDEN" } else { "" }).ok().unwrap();
+                if bank.is_golden { " - GOLDEN" } else { "" },
+                if boot_manager.bank_state(bank.index).is_some() { " - State: " } else { "" },
+                boot_manager.bank_state(bank.index).map_or("", |s| s.name())).ok().unwrap();
         }
 
         if boot_manager.external_banks().count() > 0 {
             uprintln!(cli.serial, "[{}] Banks:", EXTF::label());
         }
         for bank in boot_manager.external_banks() {
-            uwriteln!(cli.serial, "   - [{}] {} - Size: {}b{}",
+            uwriteln!(cli.serial, "   - [{}] {} - Size: {}b{}{}{}",
                 bank.index,
                 if bank.bootable { "Bootable" } else { "Non-Bootable" },
                 bank.size,
-                if bank.is_golden { " - GOLDEN" } else { "" }).ok().unwrap();
+                if bank.is_golden { " - GOLDEN" } else { "" },
+                if boot_manager.bank_state(bank.index).is_some() { " - State: " } else { "" },
+                boot_manager.bank_state(bank.index).map_or("", |s| s.name())).ok().unwrap();
         }
     },
 
@@ -68,8 +74,15 @@ commands!( cli, boot_manager, names, helpstrings [
         )
     {
         if let Some(bank) = boot_manager.external_banks().find(|b| b.index == bank) {
-            uprintln!(cli.serial, "Starting XMODEM mode! Send file with your XMODEM client.");
-            boot_manager.store_image_external(cli.serial.blocks(None), bank)?;
+            uprintln!(cli.serial, "Starting XMODEM mode! Send file with your XMODEM (1K, CRC) or YMODEM client.");
+            let mut blocks = cli.serial.blocks(None);
+            let size = blocks.header().and_then(|h| h.size());
+            let result = boot_manager.store_image_external(&mut blocks, size, bank);
+            if result.is_err() {
+                blocks.cancel();
+            }
+            drop(blocks);
+            result?;
             uprintln!(cli.serial, "Image transfer complete!");
         } else if let Some(bank) = boot_manager.mcu_banks().find(|b| b.index == bank) {
             if bank.bootable {
@@ -78,8 +91,15 @@ commands!( cli, boot_manager, names, helpstrings [
                 uprintln!(cli.serial, "to force it to be invalid.");
                 return Err(Error::ApplicationError(ApplicationError::BankInvalid));
             }
-            uprintln!(cli.serial, "Starting XMODEM mode! Send file with your XMODEM client.");
-            boot_manager.store_image_mcu(cli.serial.blocks(None), bank)?;
+            uprintln!(cli.serial, "Starting XMODEM mode! Send file with your XMODEM (1K, CRC) or YMODEM client.");
+            let mut blocks = cli.serial.blocks(None);
+            let size = blocks.header().and_then(|h| h.size());
+            let result = boot_manager.store_image_mcu(&mut blocks, size, bank);
+            if result.is_err() {
+                blocks.cancel();
+            }
+            drop(blocks);
+            result?;
             uprintln!(cli.serial, "Image transfer complete!");
         } else {
             uprintln!(cli.serial, "Index supplied does not correspond to any bank.");
@@ -165,21 +185,79 @@ commands!( cli, boot_manager, names, helpstrings [
 
     update_signal_bank ["Only allow loadstone to update from a specific bank."] (
         bank: u8 ["Updatable bank index."],
+        once: bool ["Only apply to the next boot."],
+    ) {
+        return boot_manager.set_update_signal(UpdatePlan::Index(bank), once)
+            .map_err(|e| Error::ApplicationError(e));
+    },
+
+    update_signal_none ["Disallow loadstone from updating."] (
+        once: bool ["Only apply to the next boot."],
     ) {
-        return boot_manager.set_update_signal(UpdatePlan::Index(bank))
+        return boot_manager.set_update_signal(UpdatePlan::None, once)
             .map_err(|e| Error::ApplicationError(e));
     },
 
-    update_signal_none ["Disallow loadstone from updating."] ( ) {
-        return boot_manager.set_update_signal(UpdatePlan::None)
+    update_signal_any ["Allow loadstone to update from any bank."] (
+        once: bool ["Only apply to the next boot."],
+    ) {
+        return boot_manager.set_update_signal(UpdatePlan::Any, once)
             .map_err(|e| Error::ApplicationError(e));
     },
 
-    update_signal_any ["Allow loadstone to update from any bank."] ( ) {
-        return boot_manager.set_update_signal(UpdatePlan::Any)
+    update_signal_golden ["Make loadstone replace the current image with the golden image on the next boot."] ( ) {
+        return boot_manager.set_update_signal(UpdatePlan::RestoreGolden, true)
             .map_err(|e| Error::ApplicationError(e));
     },
 
+    update_signal_recovery ["Make loadstone enter serial recovery mode instead of booting."] (
+        once: bool ["Only apply to the next boot."],
+    ) {
+        return boot_manager.set_update_signal(UpdatePlan::EnterRecovery, once)
+            .map_err(|e| Error::ApplicationError(e));
+    },
+
+    update_signal_rollback ["Make loadstone reject the current image and go back to a previous one on the next boot."] ( ) {
+        return boot_manager.set_update_signal(UpdatePlan::Rollback, true)
+            .map_err(|e| Error::ApplicationError(e));
+    },
+
+    update_result ["Displays the outcome of the update process during the last boot."] ( ) {
+        match boot_manager.update_result()? {
+            Some(UpdateResult::UpdatedTo { bank }) => {
+                uprintln!(cli.serial, "Loadstone updated from bank {}.", bank);
+            }
+            Some(UpdateResult::AlreadyUpToDate) => {
+                uprintln!(cli.serial, "Already up to date.");
+            }
+            Some(UpdateResult::NotUpdated) => {
+                uprintln!(cli.serial, "Not updated.");
+            }
+            Some(UpdateResult::UpdateError(error)) => {
+                uprintln!(cli.serial, "Update failed: {}.", error.description());
+            }
+            None => {
+                uprintln!(cli.serial, "Loadstone did not report an update result.");
+            }
+        }
+    },
+
+    confirm ["Confirms the running image works, along with the bank it was installed from."] ( ) {
+        boot_manager.resolve_pending_image(true)?;
+        uprintln!(cli.serial, "Image confirmed.");
+    },
+
+    reject ["Marks the running image and the bank it was installed from as bad. Takes effect on reboot."] ( ) {
+        boot_manager.resolve_pending_image(false)?;
+        uprintln!(cli.serial, "Image rejected. It will be replaced on the next boot.");
+    },
+
+    management ["Switches to the binary management protocol until reboot."] ( )
+    {
+        uprintln!(cli.serial, "Entering management mode. Reboot the device to return to the CLI.");
+        management::serve(&mut cli.serial, boot_manager, None)?;
+    },
+
     metrics ["Displays boot process metrics relayed by Loadstone."] ( )
     {
         if let Some(metrics) = &boot_manager.boot_metrics {
@@ -228,6 +306,9 @@ commands!( cli, boot_manager, names, helpstrings [
             if let Some(boot_time_ms) = metrics.boot_time_ms {
                 uprintln!(cli.serial, "* Boot process took {} milliseconds.", boot_time_ms);
             }
+            if metrics.update_signal == UpdateSignalState::NoSignal {
+                uprintln!(cli.serial, "* Update signal held no valid plan, so it was ignored.");
+            }
         } else {
             uprintln!(cli.serial, "Loadstone did not relay any boot metrics, or the boot metrics were corrupted.");
         }
@@ -1,126 +1,470 @@
 //! XMODEM file transfer implementation.
 //!
 //! Provides methods to receive arbitrary byte streams through serial
-//! via the XMODEM protocol.
+//! via the XMODEM family of protocols. The receiver negotiates CRC-16
+//! error detection and accepts both 128 byte (classic XMODEM) and 1024
+//! byte (XMODEM-1K) packets. YMODEM batch headers are also understood,
+//! which allows the file name and size to be known before any data is
+//! written.
 
 use blue_hal::{
-    hal::serial::{TimeoutRead, Write},
+    hal::{
+        serial::{TimeoutRead, Write},
+        time::Milliseconds,
+    },
     utilities::xmodem,
 };
+use core::str::from_utf8;
 
 /// The size of a single byte block retrieved from an XMODEM stream.
+///
+/// XMODEM-1K packets are split in several blocks of this size, so consumers
+/// see the same item type regardless of the negotiated packet length.
 pub const BLOCK_SIZE: usize = xmodem::PAYLOAD_SIZE;
 
+/// Payload size of an XMODEM-1K (STX) packet.
+const LONG_PAYLOAD_SIZE: usize = 1024;
+
+/// Maximum length of a file name retained from a YMODEM header.
+pub const MAX_FILE_NAME_LENGTH: usize = 64;
+
+/// Number of timed out 'C' requests before the receiver also offers the
+/// classic additive checksum by sending NAK.
+const CRC_NEGOTIATION_ATTEMPTS: u32 = 3;
+
+/// Retries allowed when closing a YMODEM batch, as the sender has already
+/// transmitted the whole file at that point.
+const BATCH_END_RETRIES: u32 = 3;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const ETB: u8 = 0x17;
+const CAN: u8 = 0x18;
+const CRC_REQUEST: u8 = b'C';
+
+/// Limit on how long the sender may go without making progress, measured against a
+/// clock rather than in read timeouts, so line noise and corrupted packets can't
+/// stretch it.
+pub struct Timeout<'a> {
+    /// Time elapsed since any fixed point, e.g. the start of the transfer.
+    pub clock: &'a dyn Fn() -> Milliseconds,
+    /// Time without a valid packet after which the transfer is abandoned.
+    pub limit: Milliseconds,
+}
+
 /// Generic file transfer iterator trait, returning an iterator over byte blocks.
 pub trait FileTransfer: TimeoutRead + Write {
     fn blocks(&mut self, max_retries: Option<u32>) -> BlockIterator<Self> {
+        self.timed_blocks(max_retries, None)
+    }
+
+    /// Like [`FileTransfer::blocks`], also abandoning the transfer once the sender has
+    /// gone longer than the given timeout without sending a valid packet.
+    fn timed_blocks<'a>(
+        &'a mut self,
+        max_retries: Option<u32>,
+        timeout: Option<Timeout<'a>>,
+    ) -> BlockIterator<'a, Self> {
+        let last_progress = timeout.as_ref().map_or(0, |t| (t.clock)().0);
         BlockIterator {
             serial: self,
-            received_block: false,
-            finished: false,
+            timeout,
+            last_progress,
+            status: Status::Negotiating,
+            checksum: Checksum::Crc16,
+            reply: Reply::Start,
+            negotiation_attempts: 0,
             block_number: 0,
             max_retries,
+            buffer: [0u8; LONG_PAYLOAD_SIZE],
+            buffer_length: 0,
+            buffer_offset: 0,
+            bytes_delivered: 0,
+            header: None,
         }
     }
 }
 
 impl<T: TimeoutRead + Write> FileTransfer for T {}
 
+/// State of a file transfer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Status {
+    /// Waiting for the sender to start the transfer.
+    Negotiating,
+    /// Data packets are being received.
+    Transferring,
+    /// The sender finished the transfer cleanly.
+    Complete,
+    /// Either side cancelled the transfer.
+    Cancelled,
+    /// The sender stopped responding, or the retry limit was exhausted.
+    TimedOut,
+}
+
+/// File information carried by a YMODEM batch header.
+#[derive(Copy, Clone, Debug)]
+pub struct FileHeader {
+    name: [u8; MAX_FILE_NAME_LENGTH],
+    name_length: usize,
+    size: Option<usize>,
+}
+
+impl FileHeader {
+    /// Name of the file being transferred, if it is valid UTF-8. Names longer
+    /// than [`MAX_FILE_NAME_LENGTH`] are truncated.
+    pub fn name(&self) -> Option<&str> { from_utf8(&self.name[..self.name_length]).ok() }
+
+    /// Exact size of the file in bytes, if the sender supplied it.
+    pub fn size(&self) -> Option<usize> { self.size }
+
+    fn parse(payload: &[u8]) -> Self {
+        let mut fields = payload.split(|b| *b == 0);
+        let name = fields.next().unwrap_or(&[]);
+        let name_length = name.len().min(MAX_FILE_NAME_LENGTH);
+        let mut header = FileHeader { name: [0u8; MAX_FILE_NAME_LENGTH], name_length, size: None };
+        header.name[..name_length].copy_from_slice(&name[..name_length]);
+
+        // The size is the first space separated field after the name, in decimal.
+        header.size = fields
+            .next()
+            .and_then(|f| f.split(|b| *b == b' ').next())
+            .and_then(|f| from_utf8(f).ok())
+            .and_then(|f| f.parse().ok());
+        header
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Checksum {
+    Additive,
+    Crc16,
+}
+
+impl Checksum {
+    fn length(&self) -> usize {
+        match self {
+            Checksum::Additive => 1,
+            Checksum::Crc16 => 2,
+        }
+    }
+
+    fn start_request(&self) -> u8 {
+        match self {
+            Checksum::Additive => NAK,
+            Checksum::Crc16 => CRC_REQUEST,
+        }
+    }
+
+    fn verify(&self, payload: &[u8], checksum: &[u8]) -> bool {
+        match self {
+            Checksum::Additive => {
+                payload.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == checksum[0]
+            }
+            Checksum::Crc16 => crc16(payload).to_be_bytes() == checksum[..2],
+        }
+    }
+}
+
+/// What the receiver transmits before waiting for the next packet.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Reply {
+    /// Request the start of a transfer ('C' or NAK, depending on the checksum).
+    Start,
+    Ack,
+    Nak,
+}
+
+enum Packet {
+    Data { block_number: u8, length: usize },
+    EndOfTransmission,
+    Cancel,
+}
+
 /// Generic iterator over byte blocks.
 pub struct BlockIterator<'a, S: TimeoutRead + Write + ?Sized> {
     serial: &'a mut S,
-    received_block: bool,
-    finished: bool,
+    timeout: Option<Timeout<'a>>,
+    last_progress: u32,
+    status: Status,
+    checksum: Checksum,
+    reply: Reply,
+    negotiation_attempts: u32,
     block_number: u8,
     max_retries: Option<u32>,
+    buffer: [u8; LONG_PAYLOAD_SIZE],
+    buffer_length: usize,
+    buffer_offset: usize,
+    bytes_delivered: usize,
+    header: Option<FileHeader>,
 }
 
 impl<'a, S: TimeoutRead + Write + ?Sized> Iterator for BlockIterator<'a, S> {
     type Item = [u8; BLOCK_SIZE];
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.finished {
+        self.start();
+        loop {
+            if let Some(block) = self.buffered_block() {
+                return Some(block);
+            }
+
+            if self.status != Status::Transferring {
+                return None;
+            }
+
+            match self.receive_packet() {
+                None => self.status = Status::TimedOut,
+                Some(Packet::Cancel) => self.status = Status::Cancelled,
+                Some(Packet::EndOfTransmission) => self.end_transmission(),
+                Some(Packet::Data { block_number, length }) => {
+                    self.process_data(block_number, length)
+                }
+            }
+        }
+    }
+}
+
+impl<'a, S: TimeoutRead + Write + ?Sized> BlockIterator<'a, S> {
+    /// Current state of the transfer.
+    pub fn status(&self) -> Status { self.status }
+
+    /// YMODEM file header, if the sender supplied one. This waits for the
+    /// transfer to start, but doesn't consume any data blocks, so it can be
+    /// used to size the transfer before writing anything.
+    pub fn header(&mut self) -> Option<&FileHeader> {
+        self.start();
+        self.header.as_ref()
+    }
+
+    /// Aborts the transfer, notifying the sender.
+    pub fn cancel(&mut self) {
+        if matches!(self.status, Status::Negotiating | Status::Transferring) {
+            // Sending CAN several times is the conventional way to ensure the
+            // sender notices, as a single one may be taken for line noise.
+            for _ in 0..3 {
+                let _ = self.serial.write_char(CAN as char);
+            }
+            self.status = Status::Cancelled;
+        }
+    }
+
+    /// Waits for the first packet, processing the YMODEM header if there is one.
+    fn start(&mut self) {
+        while self.status == Status::Negotiating {
+            match self.receive_packet() {
+                None => self.status = Status::TimedOut,
+                Some(Packet::Cancel) => self.status = Status::Cancelled,
+                // Sender gave up before sending anything, nothing to acknowledge.
+                Some(Packet::EndOfTransmission) => {
+                    let _ = self.serial.write_char(ACK as char);
+                    self.status = Status::Complete;
+                }
+                Some(Packet::Data { block_number: 0, length }) => self.process_header(length),
+                Some(Packet::Data { block_number, length }) => {
+                    self.status = Status::Transferring;
+                    self.process_data(block_number, length);
+                }
+            }
+        }
+    }
+
+    fn process_header(&mut self, length: usize) {
+        let header = FileHeader::parse(&self.buffer[..length]);
+        let _ = self.serial.write_char(ACK as char);
+        if header.name_length == 0 {
+            // An empty YMODEM header marks the end of an (empty) batch.
+            self.status = Status::Complete;
+        } else {
+            self.header = Some(header);
+            self.reply = Reply::Start;
+        }
+    }
+
+    fn process_data(&mut self, block_number: u8, length: usize) {
+        if block_number == self.block_number {
+            // Our last acknowledgement was lost, so the sender repeated the packet.
+            self.reply = Reply::Ack;
+        } else if block_number == self.block_number.wrapping_add(1) {
+            self.block_number = block_number;
+            self.buffer_length = length;
+            self.buffer_offset = 0;
+            self.reply = Reply::Ack;
+        } else {
+            // Sequence is lost beyond repair.
+            self.cancel();
+        }
+    }
+
+    /// Returns the next block from the last received packet, if any. Blocks that
+    /// are entirely beyond the file size declared in a YMODEM header are dropped.
+    fn buffered_block(&mut self) -> Option<[u8; BLOCK_SIZE]> {
+        if let Some(size) = self.header.and_then(|h| h.size) {
+            if self.bytes_delivered >= size {
+                self.buffer_offset = self.buffer_length;
+            }
+        }
+
+        if self.buffer_offset >= self.buffer_length {
             return None;
         }
 
-        let mut retries = 0;
-        let mut buffer = [0u8; xmodem::MAX_PACKET_SIZE];
+        let mut block = [0u8; BLOCK_SIZE];
+        block.copy_from_slice(&self.buffer[self.buffer_offset..self.buffer_offset + BLOCK_SIZE]);
+        self.buffer_offset += BLOCK_SIZE;
+        self.bytes_delivered += BLOCK_SIZE;
+        Some(block)
+    }
 
-        'block_loop: while self.max_retries.is_none() || retries < self.max_retries.unwrap() {
-            let mut buffer_index = 0usize;
+    fn reply_byte(&mut self) -> u8 {
+        match self.reply {
+            Reply::Start => {
+                // Offer CRC first, then alternate with the classic checksum so
+                // senders that only support the latter eventually start too.
+                self.checksum = if self.negotiation_attempts < CRC_NEGOTIATION_ATTEMPTS
+                    || self.negotiation_attempts % 2 == 0
+                {
+                    Checksum::Crc16
+                } else {
+                    Checksum::Additive
+                };
+                self.checksum.start_request()
+            }
+            Reply::Ack => ACK,
+            Reply::Nak => NAK,
+        }
+    }
+
+    fn receive_packet(&mut self) -> Option<Packet> {
+        let mut retries = 0;
 
-            let message = if self.received_block { xmodem::ACK } else { xmodem::NAK };
-            if self.serial.write_char(message as char).is_err() {
+        'packet_loop: while self.max_retries.map(|max| retries < max).unwrap_or(true)
+            && !self.timed_out()
+        {
+            let reply = self.reply_byte();
+            if self.serial.write_char(reply as char).is_err() {
                 retries += 1;
-                continue 'block_loop;
+                continue 'packet_loop;
             }
-            self.received_block = false;
 
-            loop {
-                buffer[buffer_index] = match self.serial.read(xmodem::DEFAULT_TIMEOUT) {
-                    Ok(byte) => byte,
-                    Err(_) => {
-                        retries += 1;
-                        continue 'block_loop;
-                    }
-                };
+            // After a packet is acknowledged, a timeout means it must be resent.
+            if self.reply == Reply::Ack {
+                self.reply = Reply::Nak;
+            }
 
-                if buffer_index == 0 || buffer_index == (xmodem::MAX_PACKET_SIZE - 1) {
-                    if let Some(block) = self.process_message(&buffer) {
-                        self.received_block = true;
-                        return Some(block);
+            let length = match self.serial.read(xmodem::DEFAULT_TIMEOUT) {
+                Ok(SOH) => BLOCK_SIZE,
+                Ok(STX) => LONG_PAYLOAD_SIZE,
+                Ok(EOT) => return Some(self.progress(Packet::EndOfTransmission)),
+                Ok(CAN) => match self.serial.read(xmodem::DEFAULT_TIMEOUT) {
+                    Ok(CAN) => return Some(self.progress(Packet::Cancel)),
+                    _ => {
+                        retries += 1;
+                        continue 'packet_loop;
                     }
-
-                    if self.finished {
-                        return None;
+                },
+                Ok(_) => {
+                    self.purge();
+                    retries += 1;
+                    continue 'packet_loop;
+                }
+                Err(_) => {
+                    if self.reply == Reply::Start {
+                        self.negotiation_attempts += 1;
                     }
+                    retries += 1;
+                    continue 'packet_loop;
                 }
-                buffer_index += 1;
-                if buffer_index == xmodem::MAX_PACKET_SIZE {
-                    continue 'block_loop;
+            };
+
+            let mut block_number = [0u8; 2];
+            let mut checksum = [0u8; 2];
+            let checksum_length = self.checksum.length();
+            let received = self.read_exact(&mut block_number)
+                && self.read_exact_payload(length)
+                && self.read_exact(&mut checksum[..checksum_length]);
+
+            let valid = received
+                && block_number[0] == !block_number[1]
+                && self.checksum.verify(&self.buffer[..length], &checksum[..checksum_length]);
+
+            if !valid {
+                self.purge();
+                if self.reply != Reply::Start {
+                    self.reply = Reply::Nak;
                 }
+                retries += 1;
+                continue 'packet_loop;
             }
+
+            return Some(self.progress(Packet::Data { block_number: block_number[0], length }));
         }
 
-        // Fully timed out
-        self.finished = true;
         None
     }
-}
 
-impl<'a, S: TimeoutRead + Write + ?Sized> BlockIterator<'a, S> {
-    fn process_message(&mut self, buffer: &[u8]) -> Option<[u8; BLOCK_SIZE]> {
-        match xmodem::parse_message(&buffer) {
-            Ok((_, xmodem::Message::EndOfTransmission)) => {
-                self.end_transmission();
-                None
-            }
-            Ok((_, xmodem::Message::Chunk(chunk))) => {
-                if let Some(block) = self.process_chunk(chunk) {
-                    self.block_number = self.block_number.wrapping_add(1);
-                    Some(block)
-                } else {
-                    None
-                }
+    /// Whether the sender has gone too long without a valid packet.
+    fn timed_out(&self) -> bool {
+        self.timeout
+            .as_ref()
+            .map_or(false, |t| (t.clock)().0.wrapping_sub(self.last_progress) > t.limit.0)
+    }
+
+    /// Restarts the timeout, as a valid packet was received.
+    fn progress(&mut self, packet: Packet) -> Packet {
+        if let Some(timeout) = &self.timeout {
+            self.last_progress = (timeout.clock)().0;
+        }
+        packet
+    }
+
+    fn read_exact(&mut self, bytes: &mut [u8]) -> bool {
+        for byte in bytes.iter_mut() {
+            match self.serial.read(xmodem::DEFAULT_TIMEOUT) {
+                Ok(b) => *byte = b,
+                Err(_) => return false,
             }
-            _ => None,
         }
+        true
     }
 
-    fn process_chunk(&self, chunk: xmodem::Chunk) -> Option<[u8; BLOCK_SIZE]> {
-        let next_block = self.block_number.wrapping_add(1);
-        (chunk.block_number == next_block).then_some(chunk.payload)
+    fn read_exact_payload(&mut self, length: usize) -> bool {
+        for index in 0..length {
+            match self.serial.read(xmodem::DEFAULT_TIMEOUT) {
+                Ok(b) => self.buffer[index] = b,
+                Err(_) => return false,
+            }
+        }
+        true
     }
 
+    /// Discards incoming bytes until the line goes quiet, so the next
+    /// packet is read from its start.
+    fn purge(&mut self) { while self.serial.read(xmodem::DEFAULT_TIMEOUT).is_ok() {} }
+
     fn end_transmission(&mut self) {
-        self.finished = true;
-        if self.serial.write_char(xmodem::ACK as char).is_err() {
+        self.status = Status::Complete;
+        if self.serial.write_char(ACK as char).is_err() {
             return;
         }
-        if let Ok(xmodem::ETB) = self.serial.read(xmodem::DEFAULT_TIMEOUT) {
+
+        if self.header.is_some() {
+            // YMODEM batches are closed by an empty header, which has to be
+            // requested and acknowledged like any other.
+            self.reply = Reply::Start;
+            self.negotiation_attempts = 0;
+            let max_retries = self.max_retries.replace(BATCH_END_RETRIES);
+            if let Some(Packet::Data { block_number: 0, .. }) = self.receive_packet() {
+                let _ = self.serial.write_char(ACK as char);
+            }
+            self.max_retries = max_retries;
+        } else if let Ok(ETB) = self.serial.read(xmodem::DEFAULT_TIMEOUT) {
             // We don't care about this being received, as there's no
             // recovering from a failure here.
-            let _ = self.serial.write_char(xmodem::ACK as char);
+            let _ = self.serial.write_char(ACK as char);
         }
     }
 }
@@ -130,3 +474,298 @@ impl<'a, S: TimeoutRead + Write + ?Sized> Drop for BlockIterator<'a, S> {
     // to close the xmodem communication cleanly
     fn drop(&mut self) { self.for_each(drop); }
 }
+
+/// CRC-16/XMODEM (polynomial 0x1021, no reflection, zero initial value).
+pub(crate) fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use blue_hal::hal::time::Milliseconds;
+    use std::{collections::VecDeque, vec::Vec};
+
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    struct SimulatedTimeout;
+
+    enum Item {
+        Packet { number: u8, payload: Vec<u8> },
+        EndOfTransmission,
+        Cancel,
+    }
+
+    /// Simulated XMODEM/YMODEM sender, which reacts to the bytes the receiver
+    /// writes the way a host side client would.
+    struct SimulatedSender {
+        items: Vec<Item>,
+        current: usize,
+        crc: Option<bool>,
+        supports_crc: bool,
+        ymodem: bool,
+        awaiting_request: bool,
+        corrupt_once: Option<usize>,
+        outgoing: VecDeque<u8>,
+        receiver_output: Vec<u8>,
+    }
+
+    impl SimulatedSender {
+        fn xmodem(payloads: &[Vec<u8>]) -> Self {
+            let mut items: Vec<_> = payloads
+                .iter()
+                .enumerate()
+                .map(|(i, p)| Item::Packet { number: (i + 1) as u8, payload: p.clone() })
+                .collect();
+            items.push(Item::EndOfTransmission);
+            Self {
+                items,
+                current: 0,
+                crc: None,
+                supports_crc: true,
+                ymodem: false,
+                awaiting_request: false,
+                corrupt_once: None,
+                outgoing: VecDeque::new(),
+                receiver_output: Vec::new(),
+            }
+        }
+
+        fn ymodem(name: &str, size: usize, payloads: &[Vec<u8>]) -> Self {
+            let mut header = vec![0u8; BLOCK_SIZE];
+            let info = format!("{}\0{} 0", name, size);
+            header[..info.len()].copy_from_slice(info.as_bytes());
+            let mut sender = Self::xmodem(payloads);
+            sender.items.insert(0, Item::Packet { number: 0, payload: header });
+            sender.items.push(Item::Packet { number: 0, payload: vec![0u8; BLOCK_SIZE] });
+            sender.ymodem = true;
+            sender
+        }
+
+        fn without_crc(self) -> Self { Self { supports_crc: false, ..self } }
+
+        fn corrupting(self, item: usize) -> Self { Self { corrupt_once: Some(item), ..self } }
+
+        fn send_current(&mut self) {
+            let crc = self.crc.unwrap_or(false);
+            match self.items.get(self.current) {
+                Some(Item::Packet { number, payload }) => {
+                    let start = self.outgoing.len();
+                    self.outgoing.push_back(if payload.len() == BLOCK_SIZE { SOH } else { STX });
+                    self.outgoing.push_back(*number);
+                    self.outgoing.push_back(!*number);
+                    self.outgoing.extend(payload.iter());
+                    if crc {
+                        self.outgoing.extend(crc16(payload).to_be_bytes().iter());
+                    } else {
+                        self.outgoing
+                            .push_back(payload.iter().fold(0u8, |s, b| s.wrapping_add(*b)));
+                    }
+                    if self.corrupt_once == Some(self.current) {
+                        self.corrupt_once = None;
+                        self.outgoing[start + 3] = !self.outgoing[start + 3];
+                    }
+                }
+                Some(Item::EndOfTransmission) => self.outgoing.push_back(EOT),
+                Some(Item::Cancel) => self.outgoing.extend([CAN, CAN].iter()),
+                None => (),
+            }
+        }
+
+        fn react(&mut self, byte: u8) {
+            self.receiver_output.push(byte);
+            match byte {
+                CRC_REQUEST if self.crc.is_none() && self.supports_crc => {
+                    self.crc = Some(true);
+                    self.send_current();
+                }
+                NAK if self.crc.is_none() => {
+                    self.crc = Some(false);
+                    self.send_current();
+                }
+                CRC_REQUEST if self.awaiting_request => {
+                    self.awaiting_request = false;
+                    self.send_current();
+                }
+                NAK if self.crc.is_some() => self.send_current(),
+                ACK if self.crc.is_some() => {
+                    let acknowledged_header_or_eot = matches!(
+                        self.items.get(self.current),
+                        Some(Item::Packet { number: 0, .. }) | Some(Item::EndOfTransmission)
+                    );
+                    self.current += 1;
+                    if self.ymodem && acknowledged_header_or_eot {
+                        self.awaiting_request = true;
+                    } else {
+                        self.send_current();
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    impl Write for SimulatedSender {
+        type Error = SimulatedTimeout;
+
+        fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+            s.bytes().for_each(|b| self.react(b));
+            Ok(())
+        }
+
+        fn write_char(&mut self, c: char) -> Result<(), Self::Error> {
+            self.react(c as u8);
+            Ok(())
+        }
+    }
+
+    impl TimeoutRead for SimulatedSender {
+        type Error = SimulatedTimeout;
+
+        fn read<T: Copy + Into<Milliseconds>>(&mut self, _: T) -> Result<u8, Self::Error> {
+            self.outgoing.pop_front().ok_or(SimulatedTimeout)
+        }
+    }
+
+    fn payload(length: usize, seed: u8) -> Vec<u8> {
+        (0..length).map(|i| (i as u8).wrapping_mul(7).wrapping_add(seed)).collect()
+    }
+
+    fn receive(sender: &mut SimulatedSender, max_retries: Option<u32>) -> (Vec<u8>, Status) {
+        let mut blocks = sender.blocks(max_retries);
+        let received: Vec<u8> = blocks.by_ref().flat_map(|b| b.to_vec()).collect();
+        let status = blocks.status();
+        (received, status)
+    }
+
+    #[test]
+    fn classic_xmodem_transfer_falls_back_to_checksum() {
+        let payloads = [payload(BLOCK_SIZE, 1), payload(BLOCK_SIZE, 2)];
+        let mut sender = SimulatedSender::xmodem(&payloads).without_crc();
+
+        let (received, status) = receive(&mut sender, Some(10));
+
+        assert_eq!(Status::Complete, status);
+        assert_eq!(payloads.concat(), received);
+        assert_eq!(sender.crc, Some(false));
+    }
+
+    #[test]
+    fn xmodem_1k_transfer_with_crc_is_split_into_blocks() {
+        let payloads =
+            [payload(LONG_PAYLOAD_SIZE, 1), payload(BLOCK_SIZE, 2), payload(LONG_PAYLOAD_SIZE, 3)];
+        let mut sender = SimulatedSender::xmodem(&payloads);
+
+        let mut blocks = sender.blocks(Some(10));
+        assert_eq!(8 + 1 + 8, blocks.by_ref().count());
+        assert_eq!(Status::Complete, blocks.status());
+        drop(blocks);
+
+        let mut sender = SimulatedSender::xmodem(&payloads);
+        let (received, _) = receive(&mut sender, Some(10));
+        assert_eq!(payloads.concat(), received);
+        assert_eq!(sender.crc, Some(true));
+    }
+
+    #[test]
+    fn ymodem_header_is_available_before_any_data() {
+        let payloads = [payload(LONG_PAYLOAD_SIZE, 1), payload(LONG_PAYLOAD_SIZE, 2)];
+        let size = LONG_PAYLOAD_SIZE + 200;
+        let mut sender = SimulatedSender::ymodem("golden.bin", size, &payloads);
+
+        let mut blocks = sender.blocks(Some(10));
+        let header = *blocks.header().unwrap();
+        assert_eq!(Some("golden.bin"), header.name());
+        assert_eq!(Some(size), header.size());
+
+        // Blocks past the declared size are padding, so they're dropped.
+        let received: Vec<u8> = blocks.by_ref().flat_map(|b| b.to_vec()).collect();
+        assert_eq!(Status::Complete, blocks.status());
+        drop(blocks);
+        assert_eq!(received.len(), LONG_PAYLOAD_SIZE + 2 * BLOCK_SIZE);
+        assert_eq!(&payloads.concat()[..received.len()], &received[..]);
+
+        // The closing empty header was requested and acknowledged.
+        assert_eq!(Some(&ACK), sender.receiver_output.last());
+        assert!(sender.outgoing.is_empty());
+    }
+
+    #[test]
+    fn corrupted_packets_are_retransmitted() {
+        let payloads = [payload(LONG_PAYLOAD_SIZE, 1), payload(LONG_PAYLOAD_SIZE, 2)];
+        let mut sender = SimulatedSender::xmodem(&payloads).corrupting(1);
+
+        let (received, status) = receive(&mut sender, Some(10));
+
+        assert_eq!(Status::Complete, status);
+        assert_eq!(payloads.concat(), received);
+        assert!(sender.receiver_output.contains(&NAK));
+    }
+
+    #[test]
+    fn sender_cancellation_ends_the_transfer() {
+        let payloads = [payload(BLOCK_SIZE, 1), payload(BLOCK_SIZE, 2)];
+        let mut sender = SimulatedSender::xmodem(&payloads);
+        sender.items.insert(1, Item::Cancel);
+
+        let (received, status) = receive(&mut sender, Some(10));
+
+        assert_eq!(Status::Cancelled, status);
+        assert_eq!(payloads[0], received);
+    }
+
+    #[test]
+    fn silent_sender_times_out() {
+        let mut sender = SimulatedSender::xmodem(&[]);
+        sender.supports_crc = false;
+        sender.items.clear();
+
+        let (received, status) = receive(&mut sender, Some(5));
+
+        assert_eq!(Status::TimedOut, status);
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn timeout_is_measured_in_time_rather_than_retries() {
+        use core::cell::Cell;
+
+        // Every corrupted packet costs the receiver a second.
+        let payloads = [payload(BLOCK_SIZE, 1), payload(BLOCK_SIZE, 2)];
+        let mut sender = SimulatedSender::xmodem(&payloads).corrupting(1);
+        let now = Cell::new(0u32);
+        let clock = || {
+            now.set(now.get() + 1000);
+            Milliseconds(now.get())
+        };
+        let timeout = Timeout { clock: &clock, limit: Milliseconds(60_000) };
+        let mut blocks = sender.timed_blocks(None, Some(timeout));
+        assert_eq!(2, blocks.by_ref().count());
+        assert_eq!(Status::Complete, blocks.status());
+        drop(blocks);
+
+        // A silent sender is given up on once the limit has elapsed, however
+        // many read timeouts that takes.
+        let mut sender = SimulatedSender::xmodem(&[]);
+        sender.items.clear();
+        now.set(0);
+        let timeout = Timeout { clock: &clock, limit: Milliseconds(5_000) };
+        let mut blocks = sender.timed_blocks(None, Some(timeout));
+        assert_eq!(0, blocks.by_ref().count());
+        assert_eq!(Status::TimedOut, blocks.status());
+        drop(blocks);
+        assert!(now.get() > 5_000 && now.get() <= 8_000);
+    }
+
+    #[test]
+    fn crc16_matches_reference_value() {
+        assert_eq!(0x31C3, crc16(b"123456789"));
+    }
+}
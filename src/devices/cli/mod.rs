@@ -16,6 +16,7 @@ use nb::block;
 use ufmt::{uwrite, uwriteln};
 
 use super::{
+    bank_state::BankStateStore,
     boot_manager::BootManager,
     image,
     traits::{Flash, Serial},
@@ -175,9 +176,15 @@ const LINE_TERMINATOR: char = '\n';
 
 impl<SRL: Serial> Cli<SRL> {
     /// Reads a line, parses it as a command and attempts to execute it.
-    pub fn run<MCUF: Flash, EXTF: Flash, R: image::Reader, WUS: WriteUpdateSignal>(
+    pub fn run<
+        MCUF: Flash,
+        EXTF: Flash,
+        R: image::Reader,
+        WUS: WriteUpdateSignal,
+        BS: BankStateStore,
+    >(
         &mut self,
-        boot_manager: &mut BootManager<MCUF, EXTF, SRL, R, WUS>,
+        boot_manager: &mut BootManager<MCUF, EXTF, SRL, R, WUS, BS>,
         greeting: &'static str,
     ) {
         if !self.greeted {
@@ -350,9 +357,9 @@ macro_rules! commands {
         ];
 
         #[allow(unreachable_code)]
-        pub(super) fn run<MCUF: Flash, EXTF: Flash, SRL: Serial, R: image::Reader, WUS: WriteUpdateSignal>(
+        pub(super) fn run<MCUF: Flash, EXTF: Flash, SRL: Serial, R: image::Reader, WUS: WriteUpdateSignal, BS: BankStateStore>(
             $cli: &mut Cli<SRL>,
-            $boot_manager: &mut BootManager<MCUF, EXTF, SRL, R, WUS>,
+            $boot_manager: &mut BootManager<MCUF, EXTF, SRL, R, WUS, BS>,
             name: Name, arguments: ArgumentIterator) -> Result<(), Error>
         {
             match name {
@@ -0,0 +1,209 @@
+//! Write and read-out protection of the MCU flash regions Loadstone relies on.
+//!
+//! Nothing stops a faulty application flash driver from overwriting Loadstone
+//! itself or the golden image. Most MCUs can prevent this in hardware through
+//! option bytes, which persist across resets. Loadstone only reprograms them
+//! when they don't match the configuration, so in practice this happens once,
+//! on first boot.
+//!
+//! Read-out protection is never lowered, as on most parts that causes a mass
+//! erase of the flash.
+use crate::error::Error;
+use core::cmp::max;
+
+/// Read-out protection levels, in increasing order of restriction.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum ReadoutProtection {
+    /// Flash can be freely read through the debug interface.
+    Disabled,
+    /// Flash can't be read through the debug interface. Can be reverted at the
+    /// cost of a mass erase.
+    Level1,
+    /// Debug interface permanently disabled, and option bytes permanently
+    /// locked. Irreversible.
+    Level2,
+}
+
+/// A range of MCU flash, by start address and size in bytes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Region {
+    pub start: usize,
+    pub size: usize,
+}
+
+/// Port-level access to the option bytes that govern flash protection.
+/// Write protection is expressed as a bit mask of flash sectors, with bit
+/// `n` standing for sector `n`.
+pub trait ProtectionOptions {
+    /// Mask of all sectors overlapping a region.
+    fn sectors(&self, region: Region) -> u32;
+    /// Mask of the sectors currently write-protected.
+    fn write_protected_sectors(&self) -> u32;
+    fn readout_protection(&self) -> ReadoutProtection;
+    /// Programs and applies new protection settings.
+    fn program(&mut self, sectors: u32, readout_protection: ReadoutProtection)
+        -> Result<(), Error>;
+}
+
+/// Protection options for ports that don't support flash protection.
+pub struct NullProtectionOptions;
+
+impl ProtectionOptions for NullProtectionOptions {
+    fn sectors(&self, _region: Region) -> u32 { 0 }
+    fn write_protected_sectors(&self) -> u32 { 0 }
+    fn readout_protection(&self) -> ReadoutProtection { ReadoutProtection::Disabled }
+    fn program(&mut self, _: u32, _: ReadoutProtection) -> Result<(), Error> {
+        Err(Error::DeviceError("Flash protection is not supported on this port"))
+    }
+}
+
+/// Keeps the bootloader region, and optionally the golden bank, write-protected.
+pub struct FlashProtection<O: ProtectionOptions> {
+    options: O,
+    bootloader: Region,
+    golden_bank: Option<Region>,
+    readout_protection: ReadoutProtection,
+}
+
+impl<O: ProtectionOptions> FlashProtection<O> {
+    pub fn new(
+        options: O,
+        bootloader: Region,
+        golden_bank: Option<Region>,
+        readout_protection: ReadoutProtection,
+    ) -> Self {
+        Self { options, bootloader, golden_bank, readout_protection }
+    }
+
+    /// Brings the option bytes in line with the configuration. Returns whether
+    /// they had to be reprogrammed.
+    pub fn enforce(&mut self) -> Result<bool, Error> {
+        let current_sectors = self.options.write_protected_sectors();
+        let current_level = self.options.readout_protection();
+        let sectors = current_sectors
+            | self.options.sectors(self.bootloader)
+            | self.golden_bank.map_or(0, |r| self.options.sectors(r));
+        let level = max(current_level, self.readout_protection);
+
+        if sectors == current_sectors && level == current_level {
+            return Ok(false);
+        }
+        self.options.program(sectors, level)?;
+        Ok(true)
+    }
+
+    /// Lifts write protection from the golden bank, so a new golden image can be
+    /// stored during recovery. Sectors shared with the bootloader stay protected.
+    /// Protection is restored by the next call to [`enforce`](Self::enforce).
+    pub fn unlock_golden_bank(&mut self) -> Result<(), Error> {
+        let golden_bank = match self.golden_bank {
+            Some(region) => self.options.sectors(region),
+            None => return Ok(()),
+        };
+        let unlocked = golden_bank & !self.options.sectors(self.bootloader);
+        let current_sectors = self.options.write_protected_sectors();
+        if current_sectors & unlocked == 0 {
+            return Ok(());
+        }
+        let level = self.options.readout_protection();
+        self.options.program(current_sectors & !unlocked, level)
+    }
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+pub mod doubles {
+    use super::*;
+
+    /// Simulated option bytes for a flash of uniform 16KB sectors.
+    pub struct FakeOptions {
+        pub sectors: u32,
+        pub readout_protection: ReadoutProtection,
+        pub times_programmed: usize,
+    }
+
+    pub const SECTOR_SIZE: usize = 16 * 1024;
+
+    impl FakeOptions {
+        pub fn new() -> Self {
+            Self {
+                sectors: 0,
+                readout_protection: ReadoutProtection::Disabled,
+                times_programmed: 0,
+            }
+        }
+    }
+
+    impl ProtectionOptions for FakeOptions {
+        fn sectors(&self, region: Region) -> u32 {
+            let first = region.start / SECTOR_SIZE;
+            let last = (region.start + region.size - 1) / SECTOR_SIZE;
+            (first..=last).fold(0, |mask, sector| mask | (1 << sector))
+        }
+
+        fn write_protected_sectors(&self) -> u32 { self.sectors }
+
+        fn readout_protection(&self) -> ReadoutProtection { self.readout_protection }
+
+        fn program(&mut self, sectors: u32, level: ReadoutProtection) -> Result<(), Error> {
+            if self.readout_protection == ReadoutProtection::Level2 {
+                return Err(Error::DeviceError("Option bytes are locked"));
+            }
+            self.sectors = sectors;
+            self.readout_protection = level;
+            self.times_programmed += 1;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{doubles::*, *};
+
+    const BOOTLOADER: Region = Region { start: 0, size: 2 * SECTOR_SIZE };
+    const GOLDEN_BANK: Region = Region { start: 4 * SECTOR_SIZE, size: 2 * SECTOR_SIZE };
+
+    #[test]
+    fn option_bytes_are_only_programmed_when_they_differ() {
+        let mut protection = FlashProtection::new(
+            FakeOptions::new(),
+            BOOTLOADER,
+            Some(GOLDEN_BANK),
+            ReadoutProtection::Level1,
+        );
+
+        assert_eq!(Ok(true), protection.enforce());
+        assert_eq!(Ok(false), protection.enforce());
+        assert_eq!(protection.options.sectors, 0b11_0011);
+        assert_eq!(protection.options.readout_protection, ReadoutProtection::Level1);
+        assert_eq!(protection.options.times_programmed, 1);
+    }
+
+    #[test]
+    fn readout_protection_is_never_lowered() {
+        let mut options = FakeOptions::new();
+        options.readout_protection = ReadoutProtection::Level1;
+        let mut protection =
+            FlashProtection::new(options, BOOTLOADER, None, ReadoutProtection::Disabled);
+
+        assert_eq!(Ok(true), protection.enforce());
+        assert_eq!(protection.options.readout_protection, ReadoutProtection::Level1);
+    }
+
+    #[test]
+    fn golden_bank_can_be_unlocked_until_protection_is_enforced_again() {
+        let mut protection = FlashProtection::new(
+            FakeOptions::new(),
+            BOOTLOADER,
+            Some(GOLDEN_BANK),
+            ReadoutProtection::Disabled,
+        );
+        protection.enforce().unwrap();
+
+        protection.unlock_golden_bank().unwrap();
+        assert_eq!(protection.options.sectors, 0b11);
+        assert_eq!(Ok(true), protection.enforce());
+        assert_eq!(protection.options.sectors, 0b11_0011);
+    }
+}
@@ -8,10 +8,13 @@ pub mod image_crc;
 #[cfg(feature = "ecdsa-verify")]
 pub mod image_ecdsa;
 
+pub mod write_session;
+
 #[cfg(not(feature = "ecdsa-verify"))]
 pub use image_crc::CrcImageReader;
 #[cfg(feature = "ecdsa-verify")]
 pub use image_ecdsa::EcdsaImageReader;
+pub use write_session::WriteSession;
 
 #[cfg(feature = "ecdsa-verify")]
 use ecdsa::elliptic_curve::generic_array::typenum::Unsigned;
@@ -80,6 +83,44 @@ impl<A: Address> Bank<A> {
     }
 }
 
+/// Fails the build unless a port's bank tables hold the same invariants as
+/// [`Bootloader::verify_bank_correctness`](crate::devices::bootloader::Bootloader::verify_bank_correctness):
+/// at most one golden bank, exactly one bootable bank (in MCU flash), indices in
+/// sequence from 1 across both tables, and no external banks without external flash.
+///
+/// Both tables must be constants, so ports declare them as such and initialize their
+/// bank statics from them. A broken layout is reported by the compiler as an overflow
+/// evaluating the assertion.
+#[macro_export]
+macro_rules! assert_bank_layout {
+    ($mcu_banks:expr, $external_banks:expr, $external_flash:expr) => {
+        static_assertions::const_assert!({
+            let (mcu, external) = (&$mcu_banks, &$external_banks);
+            let (mut golden, mut bootable, mut in_sequence) = (0, 0, true);
+            let mut external_bootable = false;
+            let mut i = 0;
+            while i < mcu.len() {
+                golden += mcu[i].is_golden as usize;
+                bootable += mcu[i].bootable as usize;
+                in_sequence &= mcu[i].index as usize == i + 1;
+                i += 1;
+            }
+            let mut i = 0;
+            while i < external.len() {
+                golden += external[i].is_golden as usize;
+                external_bootable |= external[i].bootable;
+                in_sequence &= external[i].index as usize == mcu.len() + i + 1;
+                i += 1;
+            }
+            golden <= 1
+                && bootable == 1
+                && !external_bootable
+                && in_sequence
+                && ($external_flash || external.is_empty())
+        });
+    };
+}
+
 /// Image descriptor.
 ///
 /// An image descriptor can only be constructed by scanning the flash and finding
@@ -0,0 +1,149 @@
+//! Size-checked streaming writes of firmware images into banks.
+//!
+//! Images usually arrive in blocks (e.g. through XMODEM), so their final size
+//! is unknown until the transfer ends. A write session streams those blocks
+//! into a bank while enforcing its boundary, and only succeeds once the result
+//! has been verified as a valid image.
+
+use super::{Bank, Image, Reader};
+use crate::error::Error;
+use blue_hal::{hal::flash, utilities::memory::Address};
+
+/// Streaming write of a single firmware image into a bank.
+pub struct WriteSession<'a, A: Address, F: flash::ReadWrite<Address = A>> {
+    flash: &'a mut F,
+    bank: Bank<A>,
+    bytes_written: usize,
+}
+
+impl<'a, A, F> WriteSession<'a, A, F>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    Error: From<F::Error>,
+{
+    pub fn new(flash: &'a mut F, bank: Bank<A>) -> Self { Self { flash, bank, bytes_written: 0 } }
+
+    /// Rejects an image before anything is written, when its size is known
+    /// in advance (e.g. from a YMODEM header) and it doesn't fit in the bank.
+    pub fn reserve(&self, size: usize) -> Result<(), Error> {
+        if size > self.bank.size - self.bytes_written {
+            Err(Error::ImageTooBig)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Streams blocks into the bank, continuing where the last write left off.
+    /// The driver erases flash as it writes, so only the region spanned by the
+    /// image is erased.
+    ///
+    /// Fails with [`Error::ImageTooBig`] as soon as a block would cross the bank
+    /// boundary, and with [`Error::PartialWrite`] if the flash fails after part
+    /// of the image has been written.
+    pub fn write<I: Iterator<Item = [u8; N]>, const N: usize>(
+        &mut self,
+        blocks: I,
+    ) -> Result<(), Error> {
+        let mut bounded = Bounded {
+            blocks,
+            remaining: self.bank.size - self.bytes_written,
+            consumed: 0,
+            overflow: false,
+        };
+        let result =
+            self.flash.write_from_blocks(self.bank.location + self.bytes_written, &mut bounded);
+        self.bytes_written += bounded.consumed;
+
+        match result {
+            Err(e) if self.bytes_written == 0 => Err(e.into()),
+            Err(_) => Err(Error::PartialWrite),
+            Ok(()) if bounded.overflow => Err(Error::ImageTooBig),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Total bytes streamed into the bank so far.
+    pub fn bytes_written(&self) -> usize { self.bytes_written }
+
+    /// Verifies the written image with the configured image reader. A session
+    /// is only successful if this succeeds.
+    pub fn finish<R: Reader>(self) -> Result<Image<A>, Error> {
+        if self.bytes_written == 0 {
+            return Err(Error::BankEmpty);
+        }
+        R::image_at(self.flash, self.bank)
+    }
+}
+
+/// Block iterator adapter that stops before exceeding a number of bytes.
+struct Bounded<I> {
+    blocks: I,
+    remaining: usize,
+    consumed: usize,
+    overflow: bool,
+}
+
+impl<I: Iterator<Item = [u8; N]>, const N: usize> Iterator for Bounded<I> {
+    type Item = [u8; N];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.overflow {
+            return None;
+        }
+        let block = self.blocks.next()?;
+        if N > self.remaining {
+            self.overflow = true;
+            return None;
+        }
+        self.remaining -= N;
+        self.consumed += N;
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blue_hal::hal::{
+        doubles::flash::{Address, FakeFlash},
+        flash::ReadWrite,
+    };
+
+    const BANK: Bank<Address> =
+        Bank { index: 1, size: 512, location: Address(0), bootable: false, is_golden: false };
+
+    #[test]
+    fn blocks_that_fit_are_written_at_the_bank_location() {
+        let mut flash = FakeFlash::new(Address(0));
+        let mut session = WriteSession::new(&mut flash, BANK);
+        let blocks = [[0xAAu8; 128], [0xBBu8; 128]];
+
+        session.write(blocks.iter().cloned()).unwrap();
+        assert_eq!(session.bytes_written(), 256);
+
+        let mut written = [0u8; 256];
+        flash.read(BANK.location, &mut written).unwrap();
+        assert!(written[..128].iter().all(|b| *b == 0xAA));
+        assert!(written[128..].iter().all(|b| *b == 0xBB));
+    }
+
+    #[test]
+    fn blocks_beyond_the_bank_boundary_are_rejected() {
+        let mut flash = FakeFlash::new(Address(0));
+        let mut session = WriteSession::new(&mut flash, BANK);
+        let blocks = [[0xAAu8; 128]; 5];
+
+        assert_eq!(Err(Error::ImageTooBig), session.write(blocks.iter().cloned()));
+        assert_eq!(session.bytes_written(), BANK.size);
+    }
+
+    #[test]
+    fn declared_sizes_beyond_the_bank_boundary_are_rejected() {
+        let mut flash = FakeFlash::new(Address(0));
+        let session = WriteSession::new(&mut flash, BANK);
+
+        assert_eq!(Ok(()), session.reserve(BANK.size));
+        assert_eq!(Err(Error::ImageTooBig), session.reserve(BANK.size + 1));
+    }
+}
@@ -0,0 +1,869 @@
+//! Binary management protocol for host tools.
+//!
+//! The CLI is meant for humans, and recovery mode only speaks XMODEM, so
+//! automated rigs would otherwise have to scrape prompts. This module offers
+//! a compact request/response protocol over any serial that both the boot
+//! manager and Loadstone's recovery mode can serve.
+//!
+//! Every message travels in a frame: the payload followed by its CRC-16/XMODEM
+//! (little endian), COBS encoded and terminated by a `0x00` delimiter. Empty
+//! frames (a lone delimiter) are ignored, so hosts may send them to flush the
+//! line or to request the protocol from recovery mode.
+//!
+//! * Requests: `[id] [opcode] [arguments...]`
+//! * Responses: `[id] [status] [data...]`, where status `0x00` means success.
+//!
+//! All multi-byte integers are little endian. Frames that fail their CRC are
+//! dropped without a response, as their id can't be trusted.
+//!
+//! | Opcode | Request         | Arguments                          | Response data                        |
+//! |--------|-----------------|------------------------------------|--------------------------------------|
+//! | `0x00` | Ping            | -                                  | `[version] [role]`                   |
+//! | `0x01` | List banks      | -                                  | `([index] [flags] [location:4] [size:4])*` |
+//! | `0x02` | Image info      | `[bank]`                           | `[size:4] [golden]`                  |
+//! | `0x03` | Write bank      | `[bank] [offset:4] [data...]`      | -                                    |
+//! | `0x04` | Erase bank      | `[bank]`                           | -                                    |
+//! | `0x05` | Verify bank     | `[bank]`                           | -                                    |
+//! | `0x06` | Set update plan | `[kind] [bank] ([flags])`          | -                                    |
+//! | `0x07` | Reboot          | -                                  | -                                    |
+//!
+//! Bank flags are `0x01` for bootable, `0x02` for golden and `0x04` for banks
+//! in external flash. Writing a bank at offset zero starts a new image, so the
+//! bank is erased first. Golden banks can only be rewritten, never just erased.
+//! Images written to golden or bootable banks are staged once a verify request
+//! confirms them, and golden banks only accept golden images. Update plan kinds
+//! are `0x00` (none), `0x01` (any), `0x02` (a specific bank), `0x03` (restore
+//! golden), `0x04` (enter recovery) and `0x05` (rollback). The optional update
+//! plan flags are `0x01` for plans that only apply to the next boot, which
+//! golden restores and rollbacks must set.
+
+use crate::{
+    devices::{
+        bank_state::BankState,
+        cli::file_transfer::{crc16, Timeout},
+        image::{self, Bank},
+        traits::{EraseRegion, Flash},
+        update_signal::UpdatePlan,
+    },
+    error::Error,
+};
+use blue_hal::{
+    hal::{
+        flash,
+        serial::{TimeoutRead, Write},
+    },
+    utilities::{memory::Address, xmodem},
+};
+use core::convert::TryInto;
+
+/// Version reported in response to a ping.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Maximum amount of data carried by a single write request.
+pub const MAX_DATA_SIZE: usize = 256;
+
+/// Marks the end of every frame. COBS guarantees it appears nowhere else.
+pub const FRAME_DELIMITER: u8 = 0x00;
+
+const MAX_PAYLOAD_SIZE: usize = MAX_DATA_SIZE + 16;
+const MAX_ENCODED_SIZE: usize = MAX_PAYLOAD_SIZE + MAX_PAYLOAD_SIZE / 254 + 2;
+const CRC_SIZE: usize = 2;
+const BANK_DESCRIPTION_SIZE: usize = 10;
+
+pub const STATUS_OK: u8 = 0x00;
+pub const STATUS_MALFORMED: u8 = 0x01;
+pub const STATUS_UNKNOWN_COMMAND: u8 = 0x02;
+
+pub const FLAG_BOOTABLE: u8 = 0x01;
+pub const FLAG_GOLDEN: u8 = 0x02;
+pub const FLAG_EXTERNAL: u8 = 0x04;
+
+pub const PLAN_FLAG_ONE_SHOT: u8 = 0x01;
+
+/// Which side of Loadstone is serving the protocol.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Role {
+    BootManager = 0,
+    Recovery = 1,
+}
+
+/// A decoded management request.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Request<'a> {
+    Ping,
+    ListBanks,
+    ImageInfo { bank: u8 },
+    WriteBank { bank: u8, offset: usize, data: &'a [u8] },
+    EraseBank { bank: u8 },
+    Verify { bank: u8 },
+    SetUpdatePlan { plan: UpdatePlan, one_shot: bool },
+    Reboot,
+}
+
+/// Reasons a request with a valid frame can't be served.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RequestError {
+    Malformed,
+    UnknownCommand,
+}
+
+impl<'a> Request<'a> {
+    /// Decodes a request from its opcode and arguments.
+    pub fn parse(opcode: u8, arguments: &'a [u8]) -> Result<Self, RequestError> {
+        let bank = || match arguments {
+            [bank] => Ok(*bank),
+            _ => Err(RequestError::Malformed),
+        };
+        let none =
+            |request| if arguments.is_empty() { Ok(request) } else { Err(RequestError::Malformed) };
+
+        match opcode {
+            0x00 => none(Request::Ping),
+            0x01 => none(Request::ListBanks),
+            0x02 => Ok(Request::ImageInfo { bank: bank()? }),
+            0x03 => match arguments {
+                [bank, offset @ ..] if offset.len() >= 4 => {
+                    let (offset, data) = offset.split_at(4);
+                    if data.len() > MAX_DATA_SIZE {
+                        return Err(RequestError::Malformed);
+                    }
+                    let offset = u32::from_le_bytes(offset.try_into().unwrap()) as usize;
+                    Ok(Request::WriteBank { bank: *bank, offset, data })
+                }
+                _ => Err(RequestError::Malformed),
+            },
+            0x04 => Ok(Request::EraseBank { bank: bank()? }),
+            0x05 => Ok(Request::Verify { bank: bank()? }),
+            0x06 => {
+                let (kind, bank, flags) = match arguments {
+                    [kind, bank] => (*kind, *bank, 0),
+                    [kind, bank, flags] => (*kind, *bank, *flags),
+                    _ => return Err(RequestError::Malformed),
+                };
+                let plan = match kind {
+                    0x00 => UpdatePlan::None,
+                    0x01 => UpdatePlan::Any,
+                    0x02 => UpdatePlan::Index(bank),
+                    0x03 => UpdatePlan::RestoreGolden,
+                    0x04 => UpdatePlan::EnterRecovery,
+                    0x05 => UpdatePlan::Rollback,
+                    _ => return Err(RequestError::Malformed),
+                };
+                let one_shot = flags & PLAN_FLAG_ONE_SHOT != 0;
+                if plan.is_one_shot_only() && !one_shot {
+                    return Err(RequestError::Malformed);
+                }
+                Ok(Request::SetUpdatePlan { plan, one_shot })
+            }
+            0x07 => none(Request::Reboot),
+            _ => Err(RequestError::UnknownCommand),
+        }
+    }
+}
+
+/// Summary of a flash bank, as reported to the host.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BankInfo {
+    pub index: u8,
+    pub location: usize,
+    pub size: usize,
+    pub bootable: bool,
+    pub golden: bool,
+    pub external: bool,
+}
+
+impl BankInfo {
+    pub fn new<A: Address>(bank: &Bank<A>, external: bool) -> Self {
+        Self {
+            index: bank.index,
+            location: bank.location.into(),
+            size: bank.size,
+            bootable: bank.bootable,
+            golden: bank.is_golden,
+            external,
+        }
+    }
+
+    fn flags(&self) -> u8 {
+        (if self.bootable { FLAG_BOOTABLE } else { 0 })
+            | (if self.golden { FLAG_GOLDEN } else { 0 })
+            | (if self.external { FLAG_EXTERNAL } else { 0 })
+    }
+}
+
+/// Summary of a verified image, as reported to the host.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImageInfo {
+    pub size: usize,
+    pub golden: bool,
+}
+
+/// Operations a device must offer to be managed through this protocol.
+pub trait Target {
+    fn role(&self) -> Role;
+    /// Description of the bank at a given position, in ascending index order.
+    fn bank(&self, position: usize) -> Option<BankInfo>;
+    /// Verifies the image in a bank, returning a summary of it.
+    fn image_info(&mut self, bank: u8) -> Result<ImageInfo, Error>;
+    fn write_bank(&mut self, bank: u8, offset: usize, data: &[u8]) -> Result<(), Error>;
+    fn erase_bank(&mut self, bank: u8) -> Result<(), Error>;
+    /// Verifies the image in a bank, staging it if it was just written.
+    fn verify_bank(&mut self, bank: u8) -> Result<(), Error>;
+    fn set_update_plan(&mut self, plan: UpdatePlan, one_shot: bool) -> Result<(), Error>;
+    fn reboot(&mut self) -> !;
+}
+
+/// Description of the bank at a given position, MCU banks first.
+pub fn bank_at<M: Address, E: Address>(
+    mcu_banks: &[Bank<M>],
+    external_banks: &[Bank<E>],
+    position: usize,
+) -> Option<BankInfo> {
+    bank_infos(mcu_banks, external_banks).nth(position)
+}
+
+/// Description of the bank with a given index.
+pub fn find_bank<M: Address, E: Address>(
+    mcu_banks: &[Bank<M>],
+    external_banks: &[Bank<E>],
+    index: u8,
+) -> Option<BankInfo> {
+    bank_infos(mcu_banks, external_banks).find(|b| b.index == index)
+}
+
+fn bank_infos<'a, M: Address, E: Address>(
+    mcu_banks: &'a [Bank<M>],
+    external_banks: &'a [Bank<E>],
+) -> impl Iterator<Item = BankInfo> + 'a {
+    mcu_banks
+        .iter()
+        .map(|b| BankInfo::new(b, false))
+        .chain(external_banks.iter().map(|b| BankInfo::new(b, true)))
+}
+
+/// State a bank is left in after writing a chunk to it, if it changes. Golden and
+/// bootable banks only hold a staged image once it verifies, so they remain empty
+/// from the start of a new image until then.
+pub fn state_after_write(bank: &BankInfo, offset: usize) -> Option<BankState> {
+    match (bank.golden || bank.bootable, offset) {
+        (false, _) => Some(BankState::Staged),
+        (true, 0) => Some(BankState::Empty),
+        (true, _) => None,
+    }
+}
+
+/// Checks an image verified in response to a verify request, which must be golden
+/// if the bank is, and returns the state the bank is left in, if it changes. Banks
+/// emptied by a write are staged once their new image verifies.
+pub fn check_verified(
+    bank: &BankInfo,
+    current: Option<BankState>,
+    result: Result<ImageInfo, Error>,
+) -> (Result<(), Error>, Option<BankState>) {
+    let result = match result {
+        Ok(image) if bank.golden && !image.golden => Err(Error::ImageIsNotGolden),
+        result => result.map(|_| ()),
+    };
+    let state = match (&result, current) {
+        (Ok(()), Some(BankState::Empty)) => Some(BankState::Staged),
+        (Ok(()), _) => None,
+        (Err(_), _) => BankState::after_verification(&result),
+    };
+    (result, state)
+}
+
+/// Flash chips and banks behind a [`Target`], so the boot manager and recovery
+/// mode read, write and erase banks in the same way.
+pub struct Storage<'a, M: Flash, E: Flash> {
+    pub mcu_flash: &'a mut M,
+    pub mcu_banks: &'a [Bank<M::Address>],
+    pub external_flash: Option<&'a mut E>,
+    pub external_banks: &'a [Bank<E::Address>],
+}
+
+impl<'a, M: Flash, E: Flash> Storage<'a, M, E> {
+    /// Verifies the image in a bank, returning a summary of it.
+    pub fn image_info<R: image::Reader>(&mut self, index: u8) -> Result<ImageInfo, Error> {
+        let image = if let Some(bank) = self.mcu_banks.iter().find(|b| b.index == index) {
+            R::image_at(&mut *self.mcu_flash, *bank).map(|i| (i.size(), i.is_golden()))
+        } else if let Some(bank) = self.external_banks.iter().find(|b| b.index == index) {
+            let external_flash = self.external_flash.as_mut().ok_or(Error::NoExternalFlash)?;
+            R::image_at(&mut **external_flash, *bank).map(|i| (i.size(), i.is_golden()))
+        } else {
+            Err(Error::BankInvalid)
+        };
+        image.map(|(size, golden)| ImageInfo { size, golden })
+    }
+
+    /// Writes a chunk of data at an offset within a bank. A chunk at offset zero
+    /// starts a new image, so the bank is erased before it is programmed.
+    pub fn write(&mut self, index: u8, offset: usize, data: &[u8]) -> Result<(), Error> {
+        if offset == 0 {
+            self.clear(index)?;
+        }
+        if let Some(bank) = self.mcu_banks.iter().find(|b| b.index == index) {
+            write_chunk(&mut *self.mcu_flash, *bank, offset, data)
+        } else if let Some(bank) = self.external_banks.iter().find(|b| b.index == index) {
+            let external_flash = self.external_flash.as_mut().ok_or(Error::NoExternalFlash)?;
+            write_chunk(&mut **external_flash, *bank, offset, data)
+        } else {
+            Err(Error::BankInvalid)
+        }
+    }
+
+    /// Erases a bank. Golden banks are refused, as they must never be left without
+    /// an image other than to be rewritten.
+    pub fn erase(&mut self, index: u8) -> Result<(), Error> {
+        match find_bank(self.mcu_banks, self.external_banks, index) {
+            Some(bank) if bank.golden => return Err(Error::BankInvalid),
+            _ => {}
+        }
+        self.clear(index)
+    }
+
+    fn clear(&mut self, index: u8) -> Result<(), Error> {
+        if let Some(bank) = self.mcu_banks.iter().find(|b| b.index == index) {
+            erase_bank(&mut *self.mcu_flash, *bank)
+        } else if let Some(bank) = self.external_banks.iter().find(|b| b.index == index) {
+            let external_flash = self.external_flash.as_mut().ok_or(Error::NoExternalFlash)?;
+            erase_bank(&mut **external_flash, *bank)
+        } else {
+            Err(Error::BankInvalid)
+        }
+    }
+}
+
+/// Writes a chunk of data at an offset within a bank, refusing to cross its boundary.
+pub fn write_chunk<A, F>(
+    flash: &mut F,
+    bank: Bank<A>,
+    offset: usize,
+    data: &[u8],
+) -> Result<(), Error>
+where
+    A: Address,
+    F: flash::ReadWrite<Address = A>,
+    Error: From<F::Error>,
+{
+    if offset.checked_add(data.len()).map_or(true, |end| end > bank.size) {
+        return Err(Error::ImageTooBig);
+    }
+    nb::block!(flash.write(bank.location + offset, data))?;
+    Ok(())
+}
+
+/// Erases a full bank, leaving every other bank in the same flash chip untouched.
+pub fn erase_bank<A, F>(flash: &mut F, bank: Bank<A>) -> Result<(), Error>
+where
+    A: Address,
+    F: EraseRegion<Address = A>,
+    Error: From<F::Error>,
+{
+    nb::block!(flash.erase_region(bank.location, bank.size))?;
+    Ok(())
+}
+
+/// Serves management requests from a serial until the target reboots. If a `timeout`
+/// is supplied, gives up with [`Error::TransferTimedOut`] once the host has been
+/// silent for longer than it.
+pub fn serve<S, T>(serial: &mut S, target: &mut T, timeout: Option<Timeout>) -> Result<(), Error>
+where
+    S: TimeoutRead + Write + ?Sized,
+    T: Target,
+{
+    let mut encoded = [0u8; MAX_ENCODED_SIZE];
+    let mut length = 0;
+    let mut overflow = false;
+    let now = || timeout.as_ref().map_or(0, |t| (t.clock)().0);
+    let mut last_byte = now();
+
+    loop {
+        let byte = match serial.read(xmodem::DEFAULT_TIMEOUT) {
+            Ok(byte) => byte,
+            Err(_) => {
+                if timeout.as_ref().map_or(false, |t| now().wrapping_sub(last_byte) > t.limit.0) {
+                    return Err(Error::TransferTimedOut);
+                }
+                continue;
+            }
+        };
+        last_byte = now();
+
+        if byte != FRAME_DELIMITER {
+            if length < encoded.len() {
+                encoded[length] = byte;
+                length += 1;
+            } else {
+                overflow = true;
+            }
+            continue;
+        }
+
+        if length > 0 && !overflow {
+            handle_frame(serial, target, &encoded[..length]);
+        }
+        length = 0;
+        overflow = false;
+    }
+}
+
+fn handle_frame<S, T>(serial: &mut S, target: &mut T, encoded: &[u8])
+where
+    S: TimeoutRead + Write + ?Sized,
+    T: Target,
+{
+    let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+    let length = match cobs_decode(encoded, &mut payload) {
+        Some(length) if length >= 2 + CRC_SIZE => length - CRC_SIZE,
+        _ => return,
+    };
+    let (payload, crc) = payload[..length + CRC_SIZE].split_at(length);
+    if crc16(payload) != u16::from_le_bytes([crc[0], crc[1]]) {
+        return;
+    }
+
+    let (id, opcode, arguments) = (payload[0], payload[1], &payload[2..]);
+    let mut response = Response::new(id);
+    let request = match Request::parse(opcode, arguments) {
+        Ok(request) => request,
+        Err(RequestError::Malformed) => return response.status(STATUS_MALFORMED).send(serial),
+        Err(RequestError::UnknownCommand) => {
+            return response.status(STATUS_UNKNOWN_COMMAND).send(serial)
+        }
+    };
+
+    let result = match request {
+        Request::Ping => {
+            response.push(PROTOCOL_VERSION).push(target.role() as u8);
+            Ok(())
+        }
+        Request::ListBanks => {
+            let fits = (MAX_PAYLOAD_SIZE - 2 - CRC_SIZE) / BANK_DESCRIPTION_SIZE;
+            let banks = (0..fits).map(|position| target.bank(position));
+            for bank in banks.take_while(Option::is_some).flatten() {
+                response
+                    .push(bank.index)
+                    .push(bank.flags())
+                    .extend(&(bank.location as u32).to_le_bytes())
+                    .extend(&(bank.size as u32).to_le_bytes());
+            }
+            Ok(())
+        }
+        Request::ImageInfo { bank } => target.image_info(bank).map(|image| {
+            response.extend(&(image.size as u32).to_le_bytes()).push(image.golden as u8);
+        }),
+        Request::WriteBank { bank, offset, data } => target.write_bank(bank, offset, data),
+        Request::EraseBank { bank } => target.erase_bank(bank),
+        Request::Verify { bank } => target.verify_bank(bank),
+        Request::SetUpdatePlan { plan, one_shot } => target.set_update_plan(plan, one_shot),
+        Request::Reboot => {
+            response.send(serial);
+            target.reboot();
+        }
+    };
+
+    match result {
+        Ok(()) => response.send(serial),
+        Err(e) => response.clear().status(error_code(&e)).send(serial),
+    }
+}
+
+/// Response status for application errors. Codes are part of the protocol,
+/// so existing values must never change.
+pub fn error_code(error: &Error) -> u8 {
+    match error {
+        Error::DriverError(_) => 0x10,
+        Error::ConfigurationError(_) => 0x11,
+        Error::DeviceError(_) => 0x12,
+        Error::BankInvalid => 0x13,
+        Error::BankEmpty => 0x14,
+        Error::ImageTooBig => 0x15,
+        Error::ImageIsNotGolden => 0x16,
+        Error::NoGoldenBankSupport => 0x17,
+        Error::FlashCorrupted => 0x18,
+        Error::NoExternalFlash => 0x19,
+        Error::NoImageToRestoreFrom => 0x1A,
+        Error::NoRecoverySupport => 0x1B,
+        Error::SignatureInvalid => 0x1C,
+        Error::CrcInvalid => 0x1D,
+        Error::TransferTimedOut => 0x1E,
+        Error::TransferCancelled => 0x1F,
+        Error::PartialWrite => 0x20,
+    }
+}
+
+/// Response payload under construction.
+struct Response {
+    buffer: [u8; MAX_PAYLOAD_SIZE],
+    length: usize,
+}
+
+impl Response {
+    fn new(id: u8) -> Self {
+        let mut buffer = [0u8; MAX_PAYLOAD_SIZE];
+        buffer[0] = id;
+        buffer[1] = STATUS_OK;
+        Self { buffer, length: 2 }
+    }
+
+    fn status(&mut self, status: u8) -> &mut Self {
+        self.buffer[1] = status;
+        self
+    }
+
+    fn clear(&mut self) -> &mut Self {
+        self.length = 2;
+        self
+    }
+
+    fn push(&mut self, byte: u8) -> &mut Self { self.extend(&[byte]) }
+
+    fn extend(&mut self, bytes: &[u8]) -> &mut Self {
+        // Callers never exceed the payload size, but a truncated response is
+        // preferable to a panic if they ever do.
+        let available = MAX_PAYLOAD_SIZE - CRC_SIZE - self.length;
+        let bytes = &bytes[..bytes.len().min(available)];
+        self.buffer[self.length..self.length + bytes.len()].copy_from_slice(bytes);
+        self.length += bytes.len();
+        self
+    }
+
+    fn send<S: Write + ?Sized>(&mut self, serial: &mut S) {
+        let crc = crc16(&self.buffer[..self.length]).to_le_bytes();
+        self.buffer[self.length..self.length + CRC_SIZE].copy_from_slice(&crc);
+        let mut encoded = [0u8; MAX_ENCODED_SIZE];
+        if let Some(length) = cobs_encode(&self.buffer[..self.length + CRC_SIZE], &mut encoded) {
+            for byte in encoded[..length].iter().chain(Some(&FRAME_DELIMITER)) {
+                let _ = serial.write_char(*byte as char);
+            }
+        }
+    }
+}
+
+/// Consistent Overhead Byte Stuffing. Returns the encoded length, which never contains zeroes.
+pub fn cobs_encode(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let mut code_index = 0;
+    let mut write = 1;
+    let mut code = 1u8;
+    for &byte in input {
+        if byte == 0 {
+            *output.get_mut(code_index)? = code;
+            code_index = write;
+            write += 1;
+            code = 1;
+        } else {
+            *output.get_mut(write)? = byte;
+            write += 1;
+            code += 1;
+            if code == 0xFF {
+                *output.get_mut(code_index)? = code;
+                code_index = write;
+                write += 1;
+                code = 1;
+            }
+        }
+    }
+    *output.get_mut(code_index)? = code;
+    Some(write)
+}
+
+/// Reverses [`cobs_encode`]. Returns the decoded length, or `None` if the input is malformed
+/// or doesn't fit the output.
+pub fn cobs_decode(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < input.len() {
+        let code = input[read];
+        if code == 0 {
+            return None;
+        }
+        read += 1;
+        for _ in 1..code {
+            let byte = *input.get(read)?;
+            if byte == 0 {
+                return None;
+            }
+            *output.get_mut(write)? = byte;
+            write += 1;
+            read += 1;
+        }
+        if code != 0xFF && read < input.len() {
+            *output.get_mut(write)? = 0;
+            write += 1;
+        }
+    }
+    Some(write)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use blue_hal::hal::time::Milliseconds;
+    use core::cell::Cell;
+    use std::{collections::VecDeque, vec::Vec};
+
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    struct NoData;
+
+    /// Serial double that plays back host bytes and records device output.
+    #[derive(Default)]
+    struct FakeHost {
+        incoming: VecDeque<u8>,
+        outgoing: Vec<u8>,
+    }
+
+    impl FakeHost {
+        fn send(&mut self, payload: &[u8]) {
+            let mut frame = payload.to_vec();
+            frame.extend_from_slice(&crc16(payload).to_le_bytes());
+            let mut encoded = [0u8; MAX_ENCODED_SIZE];
+            let length = cobs_encode(&frame, &mut encoded).unwrap();
+            self.incoming.extend(&encoded[..length]);
+            self.incoming.push_back(FRAME_DELIMITER);
+        }
+
+        fn responses(&self) -> Vec<Vec<u8>> {
+            self.outgoing
+                .split(|b| *b == FRAME_DELIMITER)
+                .filter(|frame| !frame.is_empty())
+                .map(|frame| {
+                    let mut decoded = [0u8; MAX_PAYLOAD_SIZE];
+                    let length = cobs_decode(frame, &mut decoded).unwrap();
+                    let (payload, crc) = decoded[..length].split_at(length - CRC_SIZE);
+                    assert_eq!(crc16(payload).to_le_bytes(), crc);
+                    payload.to_vec()
+                })
+                .collect()
+        }
+    }
+
+    impl Write for FakeHost {
+        type Error = NoData;
+
+        fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+            self.outgoing.extend(s.bytes());
+            Ok(())
+        }
+
+        fn write_char(&mut self, c: char) -> Result<(), Self::Error> {
+            self.outgoing.push(c as u8);
+            Ok(())
+        }
+    }
+
+    impl TimeoutRead for FakeHost {
+        type Error = NoData;
+
+        fn read<T: Copy + Into<Milliseconds>>(&mut self, _: T) -> Result<u8, Self::Error> {
+            self.incoming.pop_front().ok_or(NoData)
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeTarget {
+        memory: Vec<u8>,
+        plan: Option<(UpdatePlan, bool)>,
+    }
+
+    impl Target for FakeTarget {
+        fn role(&self) -> Role { Role::BootManager }
+
+        fn bank(&self, position: usize) -> Option<BankInfo> {
+            [
+                BankInfo {
+                    index: 1,
+                    location: 0x0800_0000,
+                    size: 64,
+                    bootable: true,
+                    golden: false,
+                    external: false,
+                },
+                BankInfo {
+                    index: 2,
+                    location: 0x0000_0000,
+                    size: 64,
+                    bootable: false,
+                    golden: true,
+                    external: true,
+                },
+            ]
+            .get(position)
+            .cloned()
+        }
+
+        fn image_info(&mut self, bank: u8) -> Result<ImageInfo, Error> {
+            match bank {
+                1 => Ok(ImageInfo { size: 0x1234, golden: false }),
+                _ => Err(Error::BankEmpty),
+            }
+        }
+
+        fn write_bank(&mut self, bank: u8, offset: usize, data: &[u8]) -> Result<(), Error> {
+            if bank != 2 {
+                return Err(Error::BankInvalid);
+            }
+            self.memory.resize(offset, 0xFF);
+            self.memory.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn erase_bank(&mut self, _bank: u8) -> Result<(), Error> {
+            self.memory.clear();
+            Ok(())
+        }
+
+        fn verify_bank(&mut self, bank: u8) -> Result<(), Error> {
+            self.image_info(bank).map(|_| ())
+        }
+
+        fn set_update_plan(&mut self, plan: UpdatePlan, one_shot: bool) -> Result<(), Error> {
+            self.plan = Some((plan, one_shot));
+            Ok(())
+        }
+
+        fn reboot(&mut self) -> ! { panic!("Rebooted") }
+    }
+
+    /// Serves until the host runs out of bytes, as the clock ticks on every reading
+    /// and any silence exceeds a zero timeout.
+    fn serve_until_silent(host: &mut FakeHost, target: &mut FakeTarget) -> Result<(), Error> {
+        let now = Cell::new(0u32);
+        let clock = || {
+            now.set(now.get() + 1);
+            Milliseconds(now.get())
+        };
+        serve(host, target, Some(Timeout { clock: &clock, limit: Milliseconds(0) }))
+    }
+
+    fn exchange(requests: &[&[u8]], target: &mut FakeTarget) -> Vec<Vec<u8>> {
+        let mut host = FakeHost::default();
+        requests.iter().for_each(|r| host.send(r));
+        assert_eq!(Err(Error::TransferTimedOut), serve_until_silent(&mut host, target));
+        host.responses()
+    }
+
+    #[test]
+    fn cobs_round_trip_removes_all_zeroes() {
+        let inputs: [&[u8]; 4] = [&[], &[0], &[1, 0, 0, 2], &[0x55; 300]];
+        for input in inputs.iter() {
+            let mut encoded = [0u8; 512];
+            let mut decoded = [0u8; 512];
+            let encoded_length = cobs_encode(input, &mut encoded).unwrap();
+            assert!(encoded[..encoded_length].iter().all(|b| *b != 0));
+            let decoded_length = cobs_decode(&encoded[..encoded_length], &mut decoded).unwrap();
+            assert_eq!(*input, &decoded[..decoded_length]);
+        }
+    }
+
+    #[test]
+    fn requests_are_parsed_from_arguments() {
+        assert_eq!(Ok(Request::Ping), Request::parse(0x00, &[]));
+        assert_eq!(Ok(Request::Verify { bank: 3 }), Request::parse(0x05, &[3]));
+        assert_eq!(
+            Ok(Request::WriteBank { bank: 2, offset: 0x100, data: &[0xAA, 0xBB] }),
+            Request::parse(0x03, &[2, 0x00, 0x01, 0x00, 0x00, 0xAA, 0xBB])
+        );
+        assert_eq!(
+            Ok(Request::SetUpdatePlan { plan: UpdatePlan::Index(4), one_shot: false }),
+            Request::parse(0x06, &[0x02, 4])
+        );
+        assert_eq!(
+            Ok(Request::SetUpdatePlan { plan: UpdatePlan::Rollback, one_shot: true }),
+            Request::parse(0x06, &[0x05, 0, PLAN_FLAG_ONE_SHOT])
+        );
+        assert_eq!(Err(RequestError::Malformed), Request::parse(0x06, &[0x05, 0]));
+        assert_eq!(Err(RequestError::Malformed), Request::parse(0x06, &[0x03, 0, 0]));
+        assert_eq!(Err(RequestError::Malformed), Request::parse(0x02, &[]));
+        assert_eq!(Err(RequestError::Malformed), Request::parse(0x03, &[2, 0x00]));
+        assert_eq!(Err(RequestError::UnknownCommand), Request::parse(0x42, &[]));
+    }
+
+    #[test]
+    fn ping_and_bank_listing_are_answered_with_matching_ids() {
+        let mut target = FakeTarget::default();
+        let responses = exchange(&[&[7, 0x00], &[8, 0x01]], &mut target);
+
+        assert_eq!(responses[0], [7, STATUS_OK, PROTOCOL_VERSION, Role::BootManager as u8]);
+        let bootable_mcu_bank = [1, FLAG_BOOTABLE, 0x00, 0x00, 0x00, 0x08, 64, 0, 0, 0];
+        let golden_external_bank = [2, FLAG_GOLDEN | FLAG_EXTERNAL, 0, 0, 0, 0, 64, 0, 0, 0];
+        assert_eq!(
+            responses[1],
+            [&[8, STATUS_OK][..], &bootable_mcu_bank, &golden_external_bank].concat()
+        );
+    }
+
+    #[test]
+    fn application_errors_are_reported_as_status_codes() {
+        let mut target = FakeTarget::default();
+        let responses =
+            exchange(&[&[1, 0x02, 1], &[2, 0x05, 2], &[3, 0x03, 1, 0, 0, 0, 0, 0xAA]], &mut target);
+
+        assert_eq!(responses[0], [1, STATUS_OK, 0x34, 0x12, 0x00, 0x00, 0]);
+        assert_eq!(responses[1], [2, error_code(&Error::BankEmpty)]);
+        assert_eq!(responses[2], [3, error_code(&Error::BankInvalid)]);
+    }
+
+    #[test]
+    fn writes_and_update_plans_reach_the_target() {
+        let mut target = FakeTarget::default();
+        let responses = exchange(
+            &[&[1, 0x03, 2, 0x02, 0, 0, 0, 0xAA, 0xBB], &[2, 0x06, 0x01, 0x00]],
+            &mut target,
+        );
+
+        assert_eq!(responses, [[1, STATUS_OK], [2, STATUS_OK]]);
+        assert_eq!(target.memory, [0xFF, 0xFF, 0xAA, 0xBB]);
+        assert_eq!(target.plan, Some((UpdatePlan::Any, false)));
+    }
+
+    #[test]
+    fn golden_and_bootable_banks_are_only_staged_once_verified() {
+        let regular = BankInfo {
+            index: 1,
+            location: 0,
+            size: 64,
+            bootable: false,
+            golden: false,
+            external: true,
+        };
+        let golden = BankInfo { golden: true, ..regular };
+        let bootable = BankInfo { bootable: true, external: false, ..regular };
+        let image = |golden| Ok(ImageInfo { size: 16, golden });
+
+        assert_eq!(Some(BankState::Staged), state_after_write(&regular, 0));
+        assert_eq!(Some(BankState::Staged), state_after_write(&regular, 32));
+        assert_eq!(Some(BankState::Empty), state_after_write(&golden, 0));
+        assert_eq!(None, state_after_write(&bootable, 32));
+
+        let empty = Some(BankState::Empty);
+        assert_eq!(
+            (Ok(()), Some(BankState::Staged)),
+            check_verified(&bootable, empty, image(false))
+        );
+        assert_eq!((Ok(()), Some(BankState::Staged)), check_verified(&golden, empty, image(true)));
+        assert_eq!(
+            (Err(Error::ImageIsNotGolden), None),
+            check_verified(&golden, empty, image(false))
+        );
+        assert_eq!(
+            (Err(Error::CrcInvalid), Some(BankState::Bad)),
+            check_verified(&bootable, empty, Err(Error::CrcInvalid))
+        );
+        let confirmed = Some(BankState::Confirmed);
+        assert_eq!((Ok(()), None), check_verified(&bootable, confirmed, image(false)));
+    }
+
+    #[test]
+    fn corrupted_frames_are_dropped_and_malformed_requests_rejected() {
+        let mut host = FakeHost::default();
+        host.send(&[1, 0x00]);
+        // Flip a payload byte after encoding, invalidating the CRC.
+        host.incoming[1] ^= 0x40;
+        host.send(&[2, 0x01, 0xFF]);
+        host.send(&[3, 0x99]);
+
+        let mut target = FakeTarget::default();
+        assert_eq!(Err(Error::TransferTimedOut), serve_until_silent(&mut host, &mut target));
+        assert_eq!(host.responses(), [[2, STATUS_MALFORMED], [3, STATUS_UNKNOWN_COMMAND]]);
+    }
+}
@@ -3,23 +3,66 @@
 //! generic, while board specifics (pins, board config) are
 //! handled in the `ports` module.
 
+pub mod bank_state;
 pub mod boot_manager;
 pub mod boot_metrics;
 pub mod bootloader;
 pub mod cli;
+pub mod flash_protection;
 pub mod image;
+pub mod management;
+pub mod recovery_pin;
+pub mod sfdp_flash;
+pub mod shared_flash;
 pub mod update_signal;
 
 /// General purpose traits that summarize requirements on devices.
 pub mod traits {
     use crate::error;
-    use blue_hal::hal::{flash, serial};
+    use blue_hal::hal::{flash, null::NullFlash, serial};
+    use core::iter::repeat;
     use marker_blanket::marker_blanket;
 
-    /// A supported flash must be able to read, write, and report errors
-    /// to the bootloader or boot manager.
+    /// A supported flash must be able to read, write, erase parts of itself,
+    /// and report errors to the bootloader or boot manager.
     #[marker_blanket]
-    pub trait Flash: flash::ReadWrite<Error: error::Convertible> {}
+    pub trait Flash: flash::ReadWrite<Error: error::Convertible> + EraseRegion {}
+
+    /// Flash able to erase a region without disturbing the rest of the chip, and
+    /// to program erased memory without erasing it again.
+    pub trait EraseRegion: flash::ReadWrite {
+        /// Erases every sector touched by the `size` bytes at `location`, each exactly
+        /// once. Drivers without a sector erase of their own keep this default, which
+        /// writes erased bytes over the region and leaves the erasing to `write`.
+        fn erase_region(
+            &mut self,
+            location: Self::Address,
+            size: usize,
+        ) -> nb::Result<(), Self::Error> {
+            const ERASED_BLOCK: [u8; 256] = [0xFF; 256];
+            let blocks = repeat(ERASED_BLOCK).take(size / ERASED_BLOCK.len());
+            self.write_from_blocks(location, blocks)?;
+            let remainder = size % ERASED_BLOCK.len();
+            if remainder > 0 {
+                let offset = size - remainder;
+                nb::block!(self.write(location + offset, &ERASED_BLOCK[..remainder]))?;
+            }
+            Ok(())
+        }
+
+        /// Programs `bytes` over erased memory at `address`, never erasing anything,
+        /// so records can be appended to a region without wearing it. Drivers keep
+        /// this default, which defers to `write`, only if their writes don't erase
+        /// when they merely clear bits.
+        fn program(&mut self, address: Self::Address, bytes: &[u8]) -> nb::Result<(), Self::Error> {
+            self.write(address, bytes)
+        }
+    }
+
+    impl EraseRegion for NullFlash {}
+
+    #[cfg(test)]
+    impl EraseRegion for blue_hal::hal::doubles::flash::FakeFlash {}
 
     /// A supported serial must be able to read, write, read with a timeout,
     /// and report errors to the bootloader or boot manager.
@@ -0,0 +1,60 @@
+//! Hardware strap to force Loadstone into recovery mode.
+//!
+//! A recovery pin is sampled once at the start of the boot process. If it is
+//! asserted, Loadstone skips booting and enters serial recovery mode (or the
+//! recovery menu), even if a valid image is available. This gives a way out of
+//! images that boot correctly but are otherwise unusable.
+use blue_hal::hal::gpio::InputPin;
+
+/// A source of a "force recovery" request at boot.
+pub trait RecoveryPin {
+    /// Whether the pin currently requests recovery mode.
+    fn is_asserted(&self) -> bool;
+}
+
+/// Recovery pin for ports or configurations that don't provide one. Never asserted.
+pub struct NullRecoveryPin;
+
+impl RecoveryPin for NullRecoveryPin {
+    fn is_asserted(&self) -> bool { false }
+}
+
+/// Recovery pin backed by a digital input, with configurable polarity.
+pub struct GpioRecoveryPin<P: InputPin> {
+    pin: P,
+    active_high: bool,
+}
+
+impl<P: InputPin> GpioRecoveryPin<P> {
+    pub fn new(pin: P, active_high: bool) -> Self { Self { pin, active_high } }
+}
+
+impl<P: InputPin> RecoveryPin for GpioRecoveryPin<P> {
+    fn is_asserted(&self) -> bool {
+        if self.active_high {
+            self.pin.is_high()
+        } else {
+            self.pin.is_low()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakePin(bool);
+
+    impl InputPin for FakePin {
+        fn is_high(&self) -> bool { self.0 }
+        fn is_low(&self) -> bool { !self.0 }
+    }
+
+    #[test]
+    fn gpio_recovery_pin_respects_polarity() {
+        assert!(GpioRecoveryPin::new(FakePin(true), true).is_asserted());
+        assert!(!GpioRecoveryPin::new(FakePin(false), true).is_asserted());
+        assert!(GpioRecoveryPin::new(FakePin(false), false).is_asserted());
+        assert!(!GpioRecoveryPin::new(FakePin(true), false).is_asserted());
+    }
+}
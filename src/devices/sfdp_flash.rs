@@ -0,0 +1,534 @@
+//! Generic driver for JEDEC compliant NOR flash chips over QSPI.
+//!
+//! Rather than hardcoding the geometry of a particular part, the driver reads
+//! the chip's Serial Flash Discoverable Parameters (SFDP, JESD216) on startup,
+//! and learns its size, smallest erase granularity, page size and addressing
+//! mode from the basic flash parameter table. Only the core JEDEC instruction
+//! set (single line read, page program, sector erase, status polling) is used,
+//! which every SFDP capable chip from Winbond, Macronix, ISSI and others
+//! implements.
+//!
+//! Addressing is always three bytes wide. Chips larger than 16MB that can also
+//! be addressed with three bytes are limited to their first 16MB; chips that
+//! can only be addressed with four bytes are rejected.
+use crate::{
+    devices::traits::EraseRegion,
+    error::{self, Error as LoadstoneError},
+};
+use blue_hal::hal::{
+    flash, qspi,
+    time::{self, Milliseconds},
+};
+use core::{
+    cmp::min,
+    marker::PhantomData,
+    ops::{Add, Sub},
+};
+use nb::block;
+
+/// Largest erase granularity supported, as the driver buffers one erase
+/// sector at a time to preserve its contents around a write.
+pub const MAX_SECTOR_SIZE: usize = 4096;
+/// Largest memory reachable with three byte addresses.
+const MAX_THREE_BYTE_SIZE: usize = 1 << 24;
+/// Page size assumed when the parameter table doesn't specify it.
+const DEFAULT_PAGE_SIZE: usize = 256;
+/// Every SFDP read requires eight dummy cycles.
+const SFDP_DUMMY_CYCLES: u8 = 8;
+/// Parameter ID of the JEDEC basic flash parameter table.
+const BASIC_TABLE_ID: u8 = 0x00;
+/// Dwords in the original (JESD216) basic flash parameter table. Later
+/// revisions only append to it.
+const MIN_BASIC_TABLE_DWORDS: usize = 9;
+/// Dwords of the basic flash parameter table the driver has any use for.
+const MAX_BASIC_TABLE_DWORDS: usize = 11;
+/// Chip erase takes far longer than any other operation.
+const CHIP_ERASE_TIMEOUT: Milliseconds = Milliseconds(300_000);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    TimeOut,
+    QspiError,
+    /// The chip answers the JEDEC ID command with an empty ID.
+    NotResponding,
+    /// The chip has no readable SFDP table.
+    NoSfdpTable,
+    /// The chip only supports four byte addressing.
+    UnsupportedAddressing,
+    /// The chip's smallest erase sector is larger than [`MAX_SECTOR_SIZE`].
+    UnsupportedGeometry,
+    AddressOutOfRange,
+}
+
+impl error::Convertible for Error {
+    fn into(self) -> LoadstoneError {
+        match self {
+            Error::TimeOut => LoadstoneError::DriverError("[External Flash] Operation timed out"),
+            Error::QspiError => LoadstoneError::DriverError("[External Flash] Qspi error"),
+            Error::NotResponding => {
+                LoadstoneError::DriverError("[External Flash] Chip not responding")
+            }
+            Error::NoSfdpTable => LoadstoneError::DriverError("[External Flash] No SFDP table"),
+            Error::UnsupportedAddressing => {
+                LoadstoneError::DriverError("[External Flash] Four byte addressing not supported")
+            }
+            Error::UnsupportedGeometry => {
+                LoadstoneError::DriverError("[External Flash] Erase sector too large")
+            }
+            Error::AddressOutOfRange => {
+                LoadstoneError::DriverError("[External Flash] Address out of range")
+            }
+        }
+    }
+}
+
+/// Address in external flash, counted from the start of the chip.
+#[derive(Default, Copy, Clone, Debug, PartialOrd, PartialEq, Eq, Ord)]
+pub struct Address(pub u32);
+
+impl Add<usize> for Address {
+    type Output = Self;
+    fn add(self, rhs: usize) -> Address { Address(self.0 + rhs as u32) }
+}
+
+impl Sub<usize> for Address {
+    type Output = Self;
+    fn sub(self, rhs: usize) -> Address { Address(self.0.saturating_sub(rhs as u32)) }
+}
+
+impl Sub<Address> for Address {
+    type Output = usize;
+    fn sub(self, rhs: Address) -> usize { self.0.saturating_sub(rhs.0) as usize }
+}
+
+impl From<Address> for usize {
+    fn from(address: Address) -> usize { address.0 as usize }
+}
+
+/// Address widths a chip accepts, as reported in its basic flash parameter table.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Addressing {
+    ThreeByte,
+    ThreeOrFourByte,
+    FourByte,
+}
+
+/// Chip layout, as learned from the SFDP table.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Geometry {
+    /// Total chip size in bytes.
+    pub size: usize,
+    /// Size in bytes of the smallest erasable sector.
+    pub sector_size: usize,
+    /// Instruction that erases a sector of `sector_size` bytes.
+    pub sector_erase: u8,
+    /// Size in bytes of a program page.
+    pub page_size: usize,
+    pub addressing: Addressing,
+}
+
+impl Geometry {
+    /// Reads the location and length in dwords of the basic flash parameter table
+    /// from the SFDP header and first parameter header, which the standard
+    /// requires to describe the basic table.
+    pub fn basic_table_location(header: &[u8; 16]) -> Result<(u32, usize), Error> {
+        if &header[0..4] != b"SFDP" || header[8] != BASIC_TABLE_ID {
+            return Err(Error::NoSfdpTable);
+        }
+        let length = header[11] as usize;
+        let pointer = u32::from_le_bytes([header[12], header[13], header[14], 0]);
+        if length < MIN_BASIC_TABLE_DWORDS {
+            return Err(Error::NoSfdpTable);
+        }
+        Ok((pointer, min(length, MAX_BASIC_TABLE_DWORDS)))
+    }
+
+    /// Decodes the basic flash parameter table, given as little endian dwords.
+    pub fn from_basic_table(table: &[u32]) -> Result<Self, Error> {
+        if table.len() < MIN_BASIC_TABLE_DWORDS {
+            return Err(Error::NoSfdpTable);
+        }
+
+        let addressing = match (table[0] >> 17) & 0b11 {
+            0b00 => Addressing::ThreeByte,
+            0b01 => Addressing::ThreeOrFourByte,
+            0b10 => Addressing::FourByte,
+            _ => return Err(Error::NoSfdpTable),
+        };
+
+        // Density is given in bits, either as N - 1 or, past 2 gigabits, as 2^N.
+        let density = table[1];
+        let size_bits = if density & (1 << 31) == 0 {
+            density as u64 + 1
+        } else {
+            1u64.checked_shl(density & 0x7FFF_FFFF).unwrap_or(u64::MAX)
+        };
+        let size = min(size_bits / 8, usize::MAX as u64) as usize;
+
+        // Erase types 1 to 4 are packed as (size exponent, instruction) pairs in
+        // dwords 8 and 9, with a zero exponent marking an unused type. Older
+        // tables may only declare the 4KB erase instruction in dword 1.
+        let erase_types = [table[7], table[7] >> 16, table[8], table[8] >> 16]
+            .iter()
+            .map(|pair| (pair & 0xFF, ((pair >> 8) & 0xFF) as u8))
+            .filter(|(exponent, _)| *exponent != 0)
+            .min_by_key(|(exponent, _)| *exponent);
+        let (sector_size, sector_erase) = match erase_types {
+            Some((exponent, instruction)) if exponent < 32 => (1usize << exponent, instruction),
+            None if table[0] & 0b11 == 0b01 => (4096, ((table[0] >> 8) & 0xFF) as u8),
+            _ => return Err(Error::UnsupportedGeometry),
+        };
+        if sector_size > MAX_SECTOR_SIZE {
+            return Err(Error::UnsupportedGeometry);
+        }
+
+        let page_size = match table.get(10) {
+            Some(dword) => 1usize << ((dword >> 4) & 0xF),
+            None => DEFAULT_PAGE_SIZE,
+        };
+
+        Ok(Self { size, sector_size, sector_erase, page_size, addressing })
+    }
+
+    /// Bytes the driver can reach with three byte addresses.
+    pub fn usable_size(&self) -> Result<usize, Error> {
+        match self.addressing {
+            Addressing::FourByte => Err(Error::UnsupportedAddressing),
+            _ => Ok(min(self.size, MAX_THREE_BYTE_SIZE)),
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug)]
+enum Command {
+    ReadId = 0x9F,
+    ReadSfdp = 0x5A,
+    ReadStatus = 0x05,
+    WriteEnable = 0x06,
+    Read = 0x03,
+    PageProgram = 0x02,
+    ChipErase = 0xC7,
+}
+
+const STATUS_WRITE_IN_PROGRESS: u8 = 0b1;
+
+/// JEDEC NOR flash chip, configured from its own SFDP table.
+pub struct SfdpFlash<QSPI, NOW>
+where
+    QSPI: qspi::Indirect,
+    NOW: time::Now,
+{
+    qspi: QSPI,
+    timeout: Milliseconds,
+    geometry: Geometry,
+    size: usize,
+    _marker: PhantomData<NOW>,
+}
+
+impl<QSPI, NOW> SfdpFlash<QSPI, NOW>
+where
+    QSPI: qspi::Indirect,
+    NOW: time::Now,
+{
+    /// Probes the chip and reads its geometry. Any single operation that takes
+    /// longer than `timeout` fails, with the exception of a full chip erase.
+    pub fn with_timeout(qspi: QSPI, timeout: Milliseconds) -> Result<Self, Error> {
+        let mut flash = Self {
+            qspi,
+            timeout,
+            geometry: Geometry {
+                size: 0,
+                sector_size: 0,
+                sector_erase: 0,
+                page_size: DEFAULT_PAGE_SIZE,
+                addressing: Addressing::ThreeByte,
+            },
+            size: 0,
+            _marker: PhantomData,
+        };
+        flash.verify_id()?;
+        flash.geometry = flash.read_geometry()?;
+        flash.size = flash.geometry.usable_size()?;
+        Ok(flash)
+    }
+
+    /// Chip layout as reported by the chip.
+    pub fn geometry(&self) -> Geometry { self.geometry }
+
+    fn verify_id(&mut self) -> Result<(), Error> {
+        let mut id = [0u8; 3];
+        block!(self.qspi.read(Some(Command::ReadId as u8), None, &mut id, 0))
+            .map_err(|_| Error::QspiError)?;
+        if id.iter().all(|b| *b == 0x00) || id.iter().all(|b| *b == 0xFF) {
+            return Err(Error::NotResponding);
+        }
+        Ok(())
+    }
+
+    fn read_sfdp(&mut self, address: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        block!(self.qspi.read(
+            Some(Command::ReadSfdp as u8),
+            Some(address),
+            bytes,
+            SFDP_DUMMY_CYCLES
+        ))
+        .map_err(|_| Error::QspiError)
+    }
+
+    fn read_geometry(&mut self) -> Result<Geometry, Error> {
+        let mut header = [0u8; 16];
+        self.read_sfdp(0, &mut header)?;
+        let (pointer, length) = Geometry::basic_table_location(&header)?;
+
+        let mut bytes = [0u8; 4 * MAX_BASIC_TABLE_DWORDS];
+        self.read_sfdp(pointer, &mut bytes[..4 * length])?;
+        let mut table = [0u32; MAX_BASIC_TABLE_DWORDS];
+        for (dword, chunk) in table.iter_mut().zip(bytes.chunks_exact(4)) {
+            *dword = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        Geometry::from_basic_table(&table[..length])
+    }
+
+    fn is_busy(&mut self) -> Result<bool, Error> {
+        let mut status = [0u8; 1];
+        block!(self.qspi.read(Some(Command::ReadStatus as u8), None, &mut status, 0))
+            .map_err(|_| Error::QspiError)?;
+        Ok(status[0] & STATUS_WRITE_IN_PROGRESS != 0)
+    }
+
+    fn wait_until_ready(&mut self, timeout: Milliseconds) -> Result<(), Error> {
+        let start = NOW::now();
+        while self.is_busy()? {
+            if NOW::now() - start > timeout {
+                return Err(Error::TimeOut);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends an instruction that modifies the chip, and waits for it to complete.
+    fn modify(
+        &mut self,
+        instruction: u8,
+        address: Option<u32>,
+        data: Option<&[u8]>,
+        timeout: Milliseconds,
+    ) -> Result<(), Error> {
+        block!(self.qspi.write(Some(Command::WriteEnable as u8), None, None, 0))
+            .map_err(|_| Error::QspiError)?;
+        block!(self.qspi.write(Some(instruction), address, data, 0))
+            .map_err(|_| Error::QspiError)?;
+        self.wait_until_ready(timeout)
+    }
+
+    /// Programs bytes over erased (or compatible) memory, one page at a time.
+    fn program(&mut self, address: usize, bytes: &[u8]) -> Result<(), Error> {
+        let page_size = self.geometry.page_size;
+        let mut address = address;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let length = min(bytes.len(), page_size - address % page_size);
+            let (page, rest) = bytes.split_at(length);
+            if page.iter().any(|b| *b != 0xFF) {
+                self.modify(
+                    Command::PageProgram as u8,
+                    Some(address as u32),
+                    Some(page),
+                    self.timeout,
+                )?;
+            }
+            address += length;
+            bytes = rest;
+        }
+        Ok(())
+    }
+
+    /// Writes bytes that fall within a single erase sector. The sector is only
+    /// erased if the new bytes can't be programmed over the current ones.
+    fn write_to_sector(&mut self, sector: usize, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        let sector_size = self.geometry.sector_size;
+        let mut buffer = [0u8; MAX_SECTOR_SIZE];
+        let buffer = &mut buffer[..sector_size];
+        self.wait_until_ready(self.timeout)?;
+        block!(self.qspi.read(Some(Command::Read as u8), Some(sector as u32), buffer, 0))
+            .map_err(|_| Error::QspiError)?;
+
+        let current = &buffer[offset..offset + bytes.len()];
+        if current == bytes {
+            return Ok(());
+        }
+        if current.iter().zip(bytes).all(|(old, new)| old & new == *new) {
+            return self.program(sector + offset, bytes);
+        }
+
+        buffer[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.modify(self.geometry.sector_erase, Some(sector as u32), None, self.timeout)?;
+        self.program(sector, buffer)
+    }
+}
+
+impl<QSPI, NOW> flash::ReadWrite for SfdpFlash<QSPI, NOW>
+where
+    QSPI: qspi::Indirect,
+    NOW: time::Now,
+{
+    type Error = Error;
+    type Address = Address;
+
+    fn label() -> &'static str { "External Flash (SFDP)" }
+
+    fn read(&mut self, address: Address, bytes: &mut [u8]) -> nb::Result<(), Self::Error> {
+        if address.0 as usize + bytes.len() > self.size {
+            return Err(nb::Error::Other(Error::AddressOutOfRange));
+        }
+        self.wait_until_ready(self.timeout)?;
+        block!(self.qspi.read(Some(Command::Read as u8), Some(address.0), bytes, 0))
+            .map_err(|_| nb::Error::Other(Error::QspiError))
+    }
+
+    fn write(&mut self, address: Address, bytes: &[u8]) -> nb::Result<(), Self::Error> {
+        if address.0 as usize + bytes.len() > self.size {
+            return Err(nb::Error::Other(Error::AddressOutOfRange));
+        }
+        let sector_size = self.geometry.sector_size;
+        let mut address = address.0 as usize;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let offset = address % sector_size;
+            let length = min(bytes.len(), sector_size - offset);
+            let (chunk, rest) = bytes.split_at(length);
+            self.write_to_sector(address - offset, offset, chunk)?;
+            address += length;
+            bytes = rest;
+        }
+        Ok(())
+    }
+
+    fn range(&self) -> (Address, Address) { (Address(0), Address(self.size as u32)) }
+
+    fn erase(&mut self) -> nb::Result<(), Self::Error> {
+        self.modify(Command::ChipErase as u8, None, None, CHIP_ERASE_TIMEOUT)?;
+        Ok(())
+    }
+
+    fn write_from_blocks<I: Iterator<Item = [u8; N]>, const N: usize>(
+        &mut self,
+        address: Address,
+        blocks: I,
+    ) -> Result<(), Self::Error> {
+        let mut address = address;
+        for block in blocks {
+            block!(self.write(address, &block))?;
+            address = address + N;
+        }
+        Ok(())
+    }
+}
+
+impl<QSPI, NOW> EraseRegion for SfdpFlash<QSPI, NOW>
+where
+    QSPI: qspi::Indirect,
+    NOW: time::Now,
+{
+    fn erase_region(&mut self, location: Address, size: usize) -> nb::Result<(), Self::Error> {
+        let start = location.0 as usize;
+        if start + size > self.size {
+            return Err(nb::Error::Other(Error::AddressOutOfRange));
+        }
+        let sector_size = self.geometry.sector_size;
+        let first_sector = start - start % sector_size;
+        for sector in (first_sector..start + size).step_by(sector_size) {
+            self.modify(self.geometry.sector_erase, Some(sector as u32), None, self.timeout)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// SFDP header and first parameter header of a Winbond W25Q128JV.
+    const W25Q128JV_HEADER: [u8; 16] = [
+        0x53, 0x46, 0x44, 0x50, 0x05, 0x01, 0x00, 0xFF, 0x00, 0x05, 0x01, 0x10, 0x80, 0x00, 0x00,
+        0xFF,
+    ];
+
+    /// Basic flash parameter table of a Winbond W25Q128JV.
+    const W25Q128JV_TABLE: [u32; 11] = [
+        0xFFF9_20E5,
+        0x07FF_FFFF,
+        0x6B08_EB44,
+        0xBB42_3B08,
+        0xFFFF_FFFE,
+        0xFF00_FFFF,
+        0xEB40_FFFF,
+        0x520F_200C,
+        0xFF00_D810,
+        0x0066_3600,
+        0x2300_0081,
+    ];
+
+    #[test]
+    fn basic_table_location_is_read_from_the_first_parameter_header() {
+        assert_eq!(Ok((0x80, 11)), Geometry::basic_table_location(&W25Q128JV_HEADER));
+    }
+
+    #[test]
+    fn missing_signature_means_no_sfdp_table() {
+        let mut header = W25Q128JV_HEADER;
+        header[0] = 0xFF;
+        assert_eq!(Err(Error::NoSfdpTable), Geometry::basic_table_location(&header));
+    }
+
+    #[test]
+    fn geometry_is_decoded_from_the_basic_table() {
+        let geometry = Geometry::from_basic_table(&W25Q128JV_TABLE).unwrap();
+        assert_eq!(
+            Geometry {
+                size: 16 * 1024 * 1024,
+                sector_size: 4096,
+                sector_erase: 0x20,
+                page_size: 256,
+                addressing: Addressing::ThreeByte,
+            },
+            geometry
+        );
+        assert_eq!(Ok(16 * 1024 * 1024), geometry.usable_size());
+    }
+
+    #[test]
+    fn original_tables_fall_back_to_the_legacy_erase_instruction() {
+        let mut table = W25Q128JV_TABLE;
+        table[7] = 0;
+        table[8] = 0;
+        let geometry = Geometry::from_basic_table(&table[..9]).unwrap();
+        assert_eq!(
+            (4096, 0x20, 256),
+            (geometry.sector_size, geometry.sector_erase, geometry.page_size)
+        );
+    }
+
+    #[test]
+    fn large_chips_are_limited_to_three_byte_addresses() {
+        let mut table = W25Q128JV_TABLE;
+        table[0] |= 0b01 << 17;
+        table[1] = 0x0FFF_FFFF;
+        let geometry = Geometry::from_basic_table(&table).unwrap();
+        assert_eq!(32 * 1024 * 1024, geometry.size);
+        assert_eq!(Ok(16 * 1024 * 1024), geometry.usable_size());
+
+        table[0] ^= 0b11 << 17;
+        let geometry = Geometry::from_basic_table(&table).unwrap();
+        assert_eq!(Err(Error::UnsupportedAddressing), geometry.usable_size());
+    }
+
+    #[test]
+    fn sectors_larger_than_the_buffer_are_rejected() {
+        let mut table = W25Q128JV_TABLE;
+        table[7] = 0x0000_D810;
+        table[8] = 0;
+        assert_eq!(Err(Error::UnsupportedGeometry), Geometry::from_basic_table(&table));
+    }
+}
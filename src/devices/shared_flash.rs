@@ -0,0 +1,64 @@
+//! Handles to a flash driver shared between several devices.
+//!
+//! The MCU flash holds the image banks, but may also hold a flash backed update
+//! signal, and its peripheral can only back a single driver. The driver is
+//! instead kept in a static cell, and every user gets a handle to it. Each
+//! operation borrows the driver only while it runs, so users take turns and
+//! never see each other mid operation.
+use crate::devices::traits::EraseRegion;
+use blue_hal::hal::flash;
+use core::cell::RefCell;
+
+/// Handle to a flash driver shared with other handles.
+pub struct SharedFlash<F: 'static>(&'static RefCell<F>);
+
+impl<F> SharedFlash<F> {
+    pub fn new(flash: &'static RefCell<F>) -> Self { Self(flash) }
+}
+
+impl<F> Clone for SharedFlash<F> {
+    fn clone(&self) -> Self { Self(self.0) }
+}
+
+impl<F> Copy for SharedFlash<F> {}
+
+impl<F: flash::ReadWrite> flash::ReadWrite for SharedFlash<F> {
+    type Error = F::Error;
+    type Address = F::Address;
+
+    fn label() -> &'static str { F::label() }
+
+    fn read(&mut self, address: Self::Address, bytes: &mut [u8]) -> nb::Result<(), Self::Error> {
+        self.0.borrow_mut().read(address, bytes)
+    }
+
+    fn write(&mut self, address: Self::Address, bytes: &[u8]) -> nb::Result<(), Self::Error> {
+        self.0.borrow_mut().write(address, bytes)
+    }
+
+    fn range(&self) -> (Self::Address, Self::Address) { self.0.borrow().range() }
+
+    fn erase(&mut self) -> nb::Result<(), Self::Error> { self.0.borrow_mut().erase() }
+
+    fn write_from_blocks<I: Iterator<Item = [u8; N]>, const N: usize>(
+        &mut self,
+        address: Self::Address,
+        blocks: I,
+    ) -> Result<(), Self::Error> {
+        self.0.borrow_mut().write_from_blocks(address, blocks)
+    }
+}
+
+impl<F: EraseRegion> EraseRegion for SharedFlash<F> {
+    fn erase_region(
+        &mut self,
+        location: Self::Address,
+        size: usize,
+    ) -> nb::Result<(), Self::Error> {
+        self.0.borrow_mut().erase_region(location, size)
+    }
+
+    fn program(&mut self, address: Self::Address, bytes: &[u8]) -> nb::Result<(), Self::Error> {
+        self.0.borrow_mut().program(address, bytes)
+    }
+}
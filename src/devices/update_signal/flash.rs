@@ -0,0 +1,317 @@
+//! Update signal kept in a reserved region of flash.
+//!
+//! Backup registers are lost on full power loss, and not every port has them,
+//! but every port has flash. Rather than rewriting the signal in place, every
+//! change appends a record to the region, so the region is only erased once it
+//! fills up and wear is spread evenly over it. The last record with a valid
+//! checksum holds the signal; a record torn by a reset halfway through a write
+//! fails its checksum and is ignored.
+//!
+//! The region must not overlap any bank, nor be shared with anything else, and
+//! should span whole sectors, as erasing it erases every sector it touches.
+//! Power loss while a full region is being erased loses the signal, which then
+//! reads as the default plan.
+use super::{register, ReadUpdateSignal, UpdatePlan, UpdateResult, WriteUpdateSignal};
+use crate::devices::traits::EraseRegion;
+use crc::crc32;
+use nb::block;
+
+/// Marks the start of a record.
+const RECORD_MAGIC: u32 = 0x5349_474E;
+/// Magic, plan, result and checksum, one word each.
+const RECORD_SIZE: usize = 16;
+
+/// Update plan and result, as encoded in backup registers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Record {
+    plan: u32,
+    result: u32,
+}
+
+impl Default for Record {
+    fn default() -> Self { Self { plan: register::encode(UpdatePlan::Any, false), result: 0 } }
+}
+
+impl Record {
+    fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0u8; RECORD_SIZE];
+        bytes[0..4].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.plan.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.result.to_le_bytes());
+        let checksum = crc32::checksum_ieee(&bytes[..12]);
+        bytes[12..16].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Option<Self> {
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        if word(0) != RECORD_MAGIC || word(12) != crc32::checksum_ieee(&bytes[..12]) {
+            return None;
+        }
+        Some(Self { plan: word(4), result: word(8) })
+    }
+}
+
+/// Update signal stored in a reserved flash region. Serves both Loadstone and the
+/// application, as long as each constructs its own over the same region.
+///
+/// Records are appended through [`EraseRegion::program`], so appending never
+/// erases the sectors they fall in.
+pub struct FlashUpdateSignal<F: EraseRegion> {
+    flash: F,
+    location: F::Address,
+    size: usize,
+    latest: Record,
+    /// Offset of the first erased record, or `None` if the region is full.
+    next: Option<usize>,
+}
+
+impl<F: EraseRegion> FlashUpdateSignal<F> {
+    /// Takes over the region of `size` bytes at `location`, and reads the last
+    /// signal stored there.
+    pub fn new(flash: F, location: F::Address, size: usize) -> Self {
+        let mut signal = Self { flash, location, size, latest: Record::default(), next: None };
+        signal.scan();
+        signal
+    }
+
+    fn scan(&mut self) {
+        for offset in (0..self.size / RECORD_SIZE).map(|slot| slot * RECORD_SIZE) {
+            let mut bytes = [0u8; RECORD_SIZE];
+            if block!(self.flash.read(self.location + offset, &mut bytes)).is_err() {
+                return;
+            }
+            if bytes.iter().all(|b| *b == 0xFF) {
+                self.next = Some(offset);
+                return;
+            }
+            if let Some(record) = Record::from_bytes(&bytes) {
+                self.latest = record;
+            }
+        }
+    }
+
+    /// Appends a record, erasing the region first if it's full. Unchanged
+    /// signals aren't written again, to avoid needless wear.
+    fn store(&mut self, record: Record) {
+        if record == self.latest {
+            return;
+        }
+        if self.next.is_none() && self.erase() {
+            self.next = Some(0);
+        }
+        let offset = match self.next {
+            Some(offset) => offset,
+            None => return,
+        };
+        if block!(self.flash.program(self.location + offset, &record.to_bytes())).is_ok() {
+            self.latest = record;
+        } else if self.is_erased(offset) {
+            // Nothing was programmed, so the record can be retried in the same slot.
+            return;
+        }
+        // A torn record can't be programmed over without an erase, so its slot is skipped.
+        let next = offset + RECORD_SIZE;
+        self.next = if next + RECORD_SIZE <= self.size { Some(next) } else { None };
+    }
+
+    /// Returns whether the whole region was erased.
+    fn erase(&mut self) -> bool {
+        block!(self.flash.erase_region(self.location, self.size)).is_ok()
+    }
+
+    fn is_erased(&mut self, offset: usize) -> bool {
+        let mut bytes = [0u8; RECORD_SIZE];
+        block!(self.flash.read(self.location + offset, &mut bytes)).is_ok()
+            && bytes.iter().all(|b| *b == 0xFF)
+    }
+}
+
+impl<F: EraseRegion> ReadUpdateSignal for FlashUpdateSignal<F> {
+    fn read_update_plan(&self) -> Option<UpdatePlan> {
+        register::decode(self.latest.plan).map(|(plan, _)| plan)
+    }
+
+    fn is_one_shot(&self) -> bool {
+        register::decode(self.latest.plan).map_or(false, |(_, one_shot)| one_shot)
+    }
+
+    fn clear_one_shot(&mut self) {
+        let plan = register::encode(UpdatePlan::Any, false);
+        self.store(Record { plan, ..self.latest });
+    }
+
+    fn write_update_result(&mut self, result: UpdateResult) {
+        let result = register::encode_result(result);
+        self.store(Record { result, ..self.latest });
+    }
+}
+
+impl<F: EraseRegion> WriteUpdateSignal for FlashUpdateSignal<F> {
+    fn write_update_plan(&mut self, plan: UpdatePlan, one_shot: bool) {
+        let plan = register::encode(plan, one_shot);
+        self.store(Record { plan, ..self.latest });
+    }
+
+    fn read_update_result(&self) -> Option<UpdateResult> {
+        register::decode_result(self.latest.result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use blue_hal::hal::{
+        doubles::flash::{Address, FakeFlash},
+        flash::{self, ReadWrite},
+    };
+
+    const SIZE: usize = 4 * RECORD_SIZE;
+
+    /// Fake flash that counts region erases. Like real flash, programming can only
+    /// clear bits, and fails if it would need an erase.
+    struct CountingFlash {
+        flash: FakeFlash,
+        erases: usize,
+        /// Bytes programmed before each program fails, if programming should fail.
+        failing_programs: Option<usize>,
+    }
+
+    impl flash::ReadWrite for CountingFlash {
+        type Error = ();
+        type Address = Address;
+
+        fn label() -> &'static str { "Counting fake flash" }
+        fn read(&mut self, address: Address, bytes: &mut [u8]) -> nb::Result<(), ()> {
+            self.flash.read(address, bytes).map_err(|e| e.map(|_| ()))
+        }
+        fn write(&mut self, address: Address, bytes: &[u8]) -> nb::Result<(), ()> {
+            self.flash.write(address, bytes).map_err(|e| e.map(|_| ()))
+        }
+        fn range(&self) -> (Address, Address) { self.flash.range() }
+        fn erase(&mut self) -> nb::Result<(), ()> { self.flash.erase().map_err(|e| e.map(|_| ())) }
+        fn write_from_blocks<I: Iterator<Item = [u8; N]>, const N: usize>(
+            &mut self,
+            address: Address,
+            blocks: I,
+        ) -> Result<(), ()> {
+            self.flash.write_from_blocks(address, blocks).map_err(|_| ())
+        }
+    }
+
+    impl EraseRegion for CountingFlash {
+        fn erase_region(&mut self, location: Address, size: usize) -> nb::Result<(), ()> {
+            self.erases += 1;
+            self.write(location, &[0xFF; SIZE][..size])
+        }
+
+        fn program(&mut self, address: Address, bytes: &[u8]) -> nb::Result<(), ()> {
+            let mut current = [0u8; SIZE];
+            let current = &mut current[..bytes.len()];
+            self.read(address, current)?;
+            if current.iter().zip(bytes).any(|(old, new)| old & new != *new) {
+                return Err(nb::Error::Other(()));
+            }
+            let programmed = self.failing_programs.unwrap_or(bytes.len());
+            self.write(address, &bytes[..programmed])?;
+            self.failing_programs.map_or(Ok(()), |_| Err(nb::Error::Other(())))
+        }
+    }
+
+    fn open() -> FlashUpdateSignal<CountingFlash> {
+        let flash =
+            CountingFlash { flash: FakeFlash::new(Address(0)), erases: 0, failing_programs: None };
+        FlashUpdateSignal::new(flash, Address(0), SIZE)
+    }
+
+    fn reopen(signal: FlashUpdateSignal<CountingFlash>) -> FlashUpdateSignal<CountingFlash> {
+        FlashUpdateSignal::new(signal.flash, Address(0), SIZE)
+    }
+
+    #[test]
+    fn signal_survives_reopening_the_region() {
+        let mut signal = open();
+        assert_eq!(Some(UpdatePlan::Any), signal.read_update_plan());
+        assert_eq!(None, signal.read_update_result());
+
+        signal.write_update_plan(UpdatePlan::Index(2), true);
+        signal.write_update_result(UpdateResult::AlreadyUpToDate);
+        let signal = reopen(signal);
+
+        assert_eq!(Some(UpdatePlan::Index(2)), signal.read_update_plan());
+        assert!(signal.is_one_shot());
+        assert_eq!(Some(UpdateResult::AlreadyUpToDate), signal.read_update_result());
+    }
+
+    #[test]
+    fn full_region_is_erased_before_appending() {
+        let mut signal = open();
+        for bank in 0..10 {
+            signal.write_update_plan(UpdatePlan::Index(bank), false);
+        }
+        let signal = reopen(signal);
+
+        assert_eq!(Some(UpdatePlan::Index(9)), signal.read_update_plan());
+    }
+
+    #[test]
+    fn region_is_erased_once_per_wrap() {
+        let mut signal = open();
+        for bank in 0..4 {
+            signal.write_update_plan(UpdatePlan::Index(bank), false);
+        }
+        assert_eq!(0, signal.flash.erases);
+
+        for bank in 4..12 {
+            signal.write_update_plan(UpdatePlan::Index(bank), false);
+        }
+        assert_eq!(2, signal.flash.erases);
+    }
+
+    #[test]
+    fn failed_programs_are_retried_in_the_same_slot() {
+        let mut signal = open();
+        signal.flash.failing_programs = Some(0);
+        signal.write_update_plan(UpdatePlan::Index(1), false);
+        assert_eq!(Some(UpdatePlan::Any), signal.read_update_plan());
+        assert_eq!(Some(0), signal.next);
+
+        signal.flash.failing_programs = None;
+        signal.write_update_plan(UpdatePlan::Index(1), false);
+        let signal = reopen(signal);
+
+        assert_eq!(Some(UpdatePlan::Index(1)), signal.read_update_plan());
+        assert_eq!(Some(RECORD_SIZE), signal.next);
+        assert_eq!(0, signal.flash.erases);
+    }
+
+    #[test]
+    fn slots_torn_by_a_failed_program_are_skipped() {
+        let mut signal = open();
+        signal.flash.failing_programs = Some(RECORD_SIZE / 2);
+        signal.write_update_plan(UpdatePlan::Index(1), false);
+        assert_eq!(Some(RECORD_SIZE), signal.next);
+
+        signal.flash.failing_programs = None;
+        signal.write_update_plan(UpdatePlan::Index(2), false);
+        let signal = reopen(signal);
+
+        assert_eq!(Some(UpdatePlan::Index(2)), signal.read_update_plan());
+        assert_eq!(0, signal.flash.erases);
+    }
+
+    #[test]
+    fn torn_records_are_ignored() {
+        let mut signal = open();
+        signal.write_update_plan(UpdatePlan::None, false);
+        let offset = signal.next.unwrap();
+        let mut torn =
+            Record { plan: register::encode(UpdatePlan::Rollback, false), result: 0 }.to_bytes();
+        torn[14] ^= 0xFF;
+        block!(flash::ReadWrite::write(&mut signal.flash, Address(0) + offset, &torn)).unwrap();
+        let signal = reopen(signal);
+
+        assert_eq!(Some(UpdatePlan::None), signal.read_update_plan());
+    }
+}
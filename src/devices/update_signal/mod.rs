@@ -0,0 +1,268 @@
+pub mod flash;
+
+/// Indicates the state of an update signal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UpdatePlan {
+    /// Do not update.
+    None,
+
+    /// Allow updates, if one is available.
+    Any,
+
+    /// Update from a specific image.
+    Index(u8),
+
+    /// Replace the current image with the golden image.
+    RestoreGolden,
+
+    /// Enter serial recovery mode instead of booting.
+    EnterRecovery,
+
+    /// Reject the current image, and go back to the newest image available
+    /// in any other bank (or the golden image). Requires bank state tracking.
+    Rollback,
+}
+
+impl UpdatePlan {
+    /// Whether the plan may only apply to the next boot. Restoring the golden image
+    /// or rolling back on every boot would discard each new image as soon as it was
+    /// installed.
+    pub fn is_one_shot_only(&self) -> bool {
+        matches!(self, UpdatePlan::RestoreGolden | UpdatePlan::Rollback)
+    }
+}
+
+/// Outcome of the update process during the last boot, reported back to the
+/// application through the update signal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UpdateResult {
+    /// The current image was replaced with the image in a bank.
+    UpdatedTo { bank: u8 },
+
+    /// The current image is the newest one available.
+    AlreadyUpToDate,
+
+    /// No newer image was found, or the update plan didn't allow updating.
+    NotUpdated,
+
+    /// The update plan couldn't be carried out.
+    UpdateError(UpdateError),
+}
+
+/// Reasons the update plan couldn't be carried out.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UpdateError {
+    /// There was no valid image to update, so one was restored instead.
+    NoCurrentImage,
+    /// The current image was marked as bad, so it was replaced by the restore process.
+    CurrentImageRejected,
+    /// Copying the new image to the bootable bank failed.
+    CopyFailed,
+    /// The plan requested the golden image, but none is available.
+    NoGoldenImage,
+    /// The plan requested recovery mode, but serial recovery is not supported.
+    NoRecoverySupport,
+    /// The plan requested a rollback, but bank states aren't tracked.
+    NoBankStates,
+}
+
+impl UpdateError {
+    pub fn description(&self) -> &'static str {
+        match self {
+            UpdateError::NoCurrentImage => "there was no current image",
+            UpdateError::CurrentImageRejected => "the current image was marked as bad",
+            UpdateError::CopyFailed => "the new image could not be copied",
+            UpdateError::NoGoldenImage => "there is no golden image",
+            UpdateError::NoRecoverySupport => "serial recovery is not supported",
+            UpdateError::NoBankStates => "bank states are not tracked",
+        }
+    }
+}
+
+/// Interface to the update signal from Loadstone's side.
+pub trait ReadUpdateSignal {
+    /// Plan held by the signal, or `None` if the signal doesn't hold a valid one
+    /// (e.g. because it was never written, or was corrupted).
+    fn read_update_plan(&self) -> Option<UpdatePlan>;
+    /// Whether the plan only applies to the next boot.
+    fn is_one_shot(&self) -> bool;
+    /// Reverts a one-shot plan to the default behaviour ([`UpdatePlan::Any`])
+    /// once Loadstone has consumed it.
+    fn clear_one_shot(&mut self);
+    /// Reports the outcome of the update process back to the application.
+    fn write_update_result(&mut self, result: UpdateResult);
+}
+
+/// Interface to the update signal from the application's side.
+pub trait WriteUpdateSignal {
+    fn write_update_plan(&mut self, plan: UpdatePlan, one_shot: bool);
+    /// Outcome of the update process during the last boot, if Loadstone reported one.
+    fn read_update_result(&self) -> Option<UpdateResult>;
+}
+
+/// Encoding of update signals (and their results) in 32 bit registers.
+///
+/// Registers are laid out, from most to least significant byte, as
+/// `[magic] [tag] [argument] [check]`, where the check byte is a CRC-8 of the
+/// other three. Registers that don't match this layout (such as leftover
+/// contents of a backup domain that lost power) hold no signal at all, so
+/// random values can't redirect an update.
+pub mod register {
+    use super::{UpdateError, UpdatePlan, UpdateResult};
+
+    const MAGIC: u8 = 0x5A;
+    /// Marks a plan that only applies to the next boot, in the upper half of the tag.
+    const FLAG_ONE_SHOT: u8 = 0x10;
+
+    pub fn encode(plan: UpdatePlan, one_shot: bool) -> u32 {
+        let (kind, argument) = match plan {
+            UpdatePlan::None => (0x00, 0),
+            UpdatePlan::Any => (0x01, 0),
+            UpdatePlan::Index(bank) => (0x02, bank),
+            UpdatePlan::RestoreGolden => (0x03, 0),
+            UpdatePlan::EnterRecovery => (0x04, 0),
+            UpdatePlan::Rollback => (0x05, 0),
+        };
+        let flags = if one_shot { FLAG_ONE_SHOT } else { 0 };
+        seal(flags | kind, argument)
+    }
+
+    /// Returns the plan, and whether it only applies to the next boot, or `None`
+    /// if the register holds no valid signal.
+    pub fn decode(bits: u32) -> Option<(UpdatePlan, bool)> {
+        let (tag, argument) = unseal(bits)?;
+        if tag & !(FLAG_ONE_SHOT | 0x0F) != 0 {
+            return None;
+        }
+        let plan = match (tag & 0x0F, argument) {
+            (0x00, 0) => UpdatePlan::None,
+            (0x01, 0) => UpdatePlan::Any,
+            (0x02, bank) => UpdatePlan::Index(bank),
+            (0x03, 0) => UpdatePlan::RestoreGolden,
+            (0x04, 0) => UpdatePlan::EnterRecovery,
+            (0x05, 0) => UpdatePlan::Rollback,
+            _ => return None,
+        };
+        Some((plan, tag & FLAG_ONE_SHOT != 0))
+    }
+
+    /// Encodes an update result, with the kind of result in the upper half of the tag,
+    /// the reason for an error in the lower half, and the bank updated from (if any)
+    /// as the argument.
+    pub fn encode_result(result: UpdateResult) -> u32 {
+        let (kind, reason, bank) = match result {
+            UpdateResult::UpdatedTo { bank } => (0x1, 0x0, bank),
+            UpdateResult::AlreadyUpToDate => (0x2, 0x0, 0),
+            UpdateResult::NotUpdated => (0x3, 0x0, 0),
+            UpdateResult::UpdateError(error) => (0x4, error_code(error), 0),
+        };
+        seal((kind << 4) | reason, bank)
+    }
+
+    pub fn decode_result(bits: u32) -> Option<UpdateResult> {
+        let (tag, bank) = unseal(bits)?;
+        match (tag >> 4, tag & 0x0F) {
+            (0x1, 0x0) => Some(UpdateResult::UpdatedTo { bank }),
+            (0x2, 0x0) => Some(UpdateResult::AlreadyUpToDate),
+            (0x3, 0x0) => Some(UpdateResult::NotUpdated),
+            (0x4, reason) => ERRORS.get(reason as usize).map(|e| UpdateResult::UpdateError(*e)),
+            _ => None,
+        }
+    }
+
+    const ERRORS: [UpdateError; 6] = [
+        UpdateError::NoCurrentImage,
+        UpdateError::CurrentImageRejected,
+        UpdateError::CopyFailed,
+        UpdateError::NoGoldenImage,
+        UpdateError::NoRecoverySupport,
+        UpdateError::NoBankStates,
+    ];
+
+    fn error_code(error: UpdateError) -> u8 {
+        ERRORS.iter().position(|e| *e == error).unwrap() as u8
+    }
+
+    fn seal(tag: u8, argument: u8) -> u32 {
+        let bytes = [MAGIC, tag, argument];
+        u32::from_be_bytes([MAGIC, tag, argument, crc8(&bytes)])
+    }
+
+    /// Returns the tag and argument of a register, if its magic and check byte are valid.
+    fn unseal(bits: u32) -> Option<(u8, u8)> {
+        let [magic, tag, argument, check] = bits.to_be_bytes();
+        if magic != MAGIC || check != crc8(&[magic, tag, argument]) {
+            return None;
+        }
+        Some((tag, argument))
+    }
+
+    /// CRC-8 (polynomial 0x07, zero initial value).
+    fn crc8(bytes: &[u8]) -> u8 {
+        bytes.iter().fold(0u8, |crc, byte| {
+            (0..8).fold(
+                crc ^ byte,
+                |crc, _| {
+                    if crc & 0x80 != 0 {
+                        (crc << 1) ^ 0x07
+                    } else {
+                        crc << 1
+                    }
+                },
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{register::*, *};
+
+    #[test]
+    fn plans_survive_the_register_encoding() {
+        let plans = [
+            UpdatePlan::None,
+            UpdatePlan::Any,
+            UpdatePlan::Index(0),
+            UpdatePlan::Index(7),
+            UpdatePlan::RestoreGolden,
+            UpdatePlan::EnterRecovery,
+            UpdatePlan::Rollback,
+        ];
+        for plan in plans.iter().cloned() {
+            assert_eq!(Some((plan, false)), decode(encode(plan, false)));
+            assert_eq!(Some((plan, true)), decode(encode(plan, true)));
+        }
+    }
+
+    #[test]
+    fn update_results_survive_the_register_encoding() {
+        let results = [
+            UpdateResult::UpdatedTo { bank: 3 },
+            UpdateResult::AlreadyUpToDate,
+            UpdateResult::NotUpdated,
+            UpdateResult::UpdateError(UpdateError::NoCurrentImage),
+            UpdateResult::UpdateError(UpdateError::NoBankStates),
+        ];
+        for result in results.iter().cloned() {
+            assert_eq!(Some(result), decode_result(encode_result(result)));
+        }
+        assert_eq!(None, decode_result(0));
+    }
+
+    #[test]
+    fn arbitrary_register_values_hold_no_signal() {
+        assert_eq!(None, decode(0x0000_0000));
+        assert_eq!(None, decode(0xFFFF_FFFF));
+        assert_eq!(None, decode(0x0000_0003));
+    }
+
+    #[test]
+    fn corrupted_registers_hold_no_signal() {
+        let bits = encode(UpdatePlan::Index(2), false);
+        for bit in 0..32 {
+            assert_eq!(None, decode(bits ^ (1 << bit)));
+        }
+        assert_eq!(None, decode_result(encode_result(UpdateResult::NotUpdated) ^ 0x0100));
+    }
+}
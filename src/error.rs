@@ -26,6 +26,9 @@ pub enum Error {
     NoRecoverySupport,
     SignatureInvalid,
     CrcInvalid,
+    TransferTimedOut,
+    TransferCancelled,
+    PartialWrite,
 }
 
 pub trait Convertible {
@@ -113,6 +116,15 @@ impl Error {
             Error::CrcInvalid => {
                 uwriteln!(serial, "[Logic Error] -> Image CRC is invalid")
             }
+            Error::TransferTimedOut => {
+                uwriteln!(serial, "[Transfer Error] -> Timed out waiting for the sender")
+            }
+            Error::TransferCancelled => {
+                uwriteln!(serial, "[Transfer Error] -> Transfer was cancelled")
+            }
+            Error::PartialWrite => {
+                uwriteln!(serial, "[Logic Error] -> Flash failed partway through writing the image")
+            }
         }
         .ok()
         .unwrap();
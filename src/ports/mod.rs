@@ -6,7 +6,44 @@
 use blue_hal::port;
 
 #[cfg(feature = "stm32f412")]
-port!(stm32f412: [bootloader, boot_manager, autogenerated, update_signal,]);
+port!(stm32f412: [bootloader, boot_manager, autogenerated, update_signal, flash_protection, bank_state,]);
+
+// The remaining stm32f4 parts share the stm32f412 sources, each with its
+// own autogenerated module. Option bytes are only laid out for the single
+// bank parts, so the dual bank ones go without flash protection.
+#[cfg(feature = "stm32f407")]
+#[path = "stm32f412"]
+pub mod stm32f407 {
+    pub mod bootloader;
+    pub mod boot_manager;
+    pub mod update_signal;
+    pub mod flash_protection;
+    pub mod bank_state;
+    #[path = "../stm32f407/autogenerated/mod.rs"]
+    pub mod autogenerated;
+}
+
+#[cfg(feature = "stm32f429")]
+#[path = "stm32f412"]
+pub mod stm32f429 {
+    pub mod bootloader;
+    pub mod boot_manager;
+    pub mod update_signal;
+    pub mod bank_state;
+    #[path = "../stm32f429/autogenerated/mod.rs"]
+    pub mod autogenerated;
+}
+
+#[cfg(feature = "stm32f469")]
+#[path = "stm32f412"]
+pub mod stm32f469 {
+    pub mod bootloader;
+    pub mod boot_manager;
+    pub mod update_signal;
+    pub mod bank_state;
+    #[path = "../stm32f469/autogenerated/mod.rs"]
+    pub mod autogenerated;
+}
 
 #[cfg(feature = "wgm160p")]
-port!(wgm160p: [bootloader, autogenerated, update_signal,]);
+port!(wgm160p: [bootloader, boot_manager, autogenerated, update_signal, serial, systick,]);
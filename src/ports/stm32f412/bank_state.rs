@@ -0,0 +1,49 @@
+//! Bank state table kept in the RTC backup registers, next to the update signal.
+//! Like the update signal, it survives resets, and power loss as long as the
+//! backup domain is powered.
+use crate::devices::bank_state::{self, BankState, BankStateStore, TABLE_WORDS};
+use blue_hal::stm32pac::{rtc, RTC};
+
+/// First backup register of the table. Register 0 holds the update signal.
+const FIRST_REGISTER: usize = 1;
+/// Backup register holding the seal of the table. Register 3 holds the update result.
+const SEAL_REGISTER: usize = 4;
+
+pub struct BankStateTable;
+
+impl BankStateTable {
+    pub fn new() -> Self { Self }
+
+    fn registers() -> &'static [rtc::BKPR] {
+        // NOTE(Safety): The table only touches its own backup registers, which
+        // nothing else in Loadstone or the boot manager accesses.
+        let rtc = unsafe { &*RTC::ptr() };
+        &rtc.bkpr
+    }
+
+    fn read() -> ([u32; TABLE_WORDS], u32) {
+        let registers = Self::registers();
+        let mut table = [0u32; TABLE_WORDS];
+        for (i, word) in table.iter_mut().enumerate() {
+            *word = registers[FIRST_REGISTER + i].read().bits();
+        }
+        (table, registers[SEAL_REGISTER].read().bits())
+    }
+}
+
+impl BankStateStore for BankStateTable {
+    fn bank_state(&self, bank: u8) -> Option<BankState> {
+        let (table, seal) = Self::read();
+        bank_state::read(&table, seal, bank)
+    }
+
+    fn set_bank_state(&mut self, bank: u8, state: BankState) {
+        let (mut table, seal) = Self::read();
+        let seal = bank_state::write(&mut table, seal, bank, state);
+        let registers = Self::registers();
+        for (i, word) in table.iter().enumerate() {
+            registers[FIRST_REGISTER + i].write(|w| unsafe { w.bits(*word) });
+        }
+        registers[SEAL_REGISTER].write(|w| unsafe { w.bits(seal) });
+    }
+}
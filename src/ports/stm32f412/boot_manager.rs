@@ -1,28 +1,34 @@
 //! Concrete boot manager construction and flash bank layout
-//! for stm32f412
-use crate::devices::{boot_manager::BootManager, cli::Cli};
+//! for the stm32f4 parts
+use crate::devices::{boot_manager::BootManager, cli::Cli, shared_flash::SharedFlash};
 use blue_hal::{drivers::stm32f4::{flash, rcc::Clocks, systick::SysTick}, hal::time, stm32pac};
 
-use super::autogenerated::{self, devices, memory_map::{EXTERNAL_BANKS, MCU_BANKS}, pin_configuration::{self, *}, UPDATE_SIGNAL_ENABLED};
+use super::autogenerated::{self, devices, memory_map::{EXTERNAL_BANKS, MCU_BANKS}, pin_configuration::{self, *}};
 #[cfg(feature="ecdsa-verify")]
 use crate::devices::image::EcdsaImageReader as ImageReader;
 #[cfg(not(feature="ecdsa-verify"))]
 use crate::devices::image::CrcImageReader as ImageReader;
-use super::update_signal::{UpdateSignalWriter, initialize_rtc_backup_domain};
+use super::update_signal::initialize_rtc_backup_domain;
+use super::bank_state::BankStateTable;
+use core::cell::RefCell;
 
-impl Default for BootManager<flash::McuFlash, ExternalFlash, Serial, ImageReader, UpdateSignalWriter> {
+impl Default for BootManager<SharedFlash<flash::McuFlash>, ExternalFlash, Serial, ImageReader, devices::UpdateSignalWriter, BankStateTable> {
     fn default() -> Self { Self::new() }
 }
 
-impl BootManager<flash::McuFlash, ExternalFlash, Serial, ImageReader, UpdateSignalWriter> {
+impl BootManager<SharedFlash<flash::McuFlash>, ExternalFlash, Serial, ImageReader, devices::UpdateSignalWriter, BankStateTable> {
     pub fn new() -> Self {
         let mut peripherals = stm32pac::Peripherals::take().unwrap();
         let cortex_peripherals = cortex_m::Peripherals::take().unwrap();
         let mcu_flash = flash::McuFlash::new(peripherals.FLASH).unwrap();
+        // The update signal may live in MCU flash as well, so it shares the driver.
+        let mcu_flash = SharedFlash::new(
+            cortex_m::singleton!(: RefCell<flash::McuFlash> = RefCell::new(mcu_flash)).unwrap(),
+        );
 
         initialize_rtc_backup_domain(&mut peripherals.RCC, &mut peripherals.PWR);
 
-        let (serial_pins, qspi_pins) = pin_configuration::pins(
+        let (serial_pins, qspi_pins, _) = pin_configuration::pins(
                 peripherals.GPIOA,
                 peripherals.GPIOB,
                 peripherals.GPIOC,
@@ -45,14 +51,13 @@ impl BootManager<flash::McuFlash, ExternalFlash, Serial, ImageReader, UpdateSign
             peripherals.USART6)
             .expect("Demo app can't function without serial!");
         let cli = Cli::new(serial).unwrap();
+        // Only some parts have a QSPI peripheral to drive external flash with.
+        #[cfg(any(feature = "stm32f412", feature = "stm32f469"))]
         let external_flash = devices::construct_flash(qspi_pins, peripherals.QUADSPI);
+        #[cfg(not(any(feature = "stm32f412", feature = "stm32f469")))]
+        let external_flash = devices::construct_flash(qspi_pins);
 
-        let update_signal = if UPDATE_SIGNAL_ENABLED {
-            let rtc = peripherals.RTC;
-            Some(UpdateSignalWriter::new(rtc))
-        } else {
-            None
-        };
+        let update_signal = devices::construct_update_signal_writer(peripherals.RTC, mcu_flash);
 
         BootManager {
             external_flash,
@@ -64,6 +69,7 @@ impl BootManager<flash::McuFlash, ExternalFlash, Serial, ImageReader, UpdateSign
             greeting: Some(autogenerated::DEMO_APP_GREETING),
             _marker: Default::default(),
             update_signal,
+            bank_states: Some(BankStateTable::new()),
         }
     }
 }
@@ -1,16 +1,16 @@
-//! Concrete bootloader construction and flash bank layout for stm32f412
-use crate::{devices::bootloader::Bootloader, error};
+//! Concrete bootloader construction and flash bank layout for the stm32f4 parts
+//! (stm32f407, stm32f412, stm32f429 and stm32f469)
+use crate::{devices::{bootloader::{Bootloader, RecoveryPolicy}, shared_flash::SharedFlash, traits::EraseRegion}, error};
 use crate::error::Error;
 use blue_hal::hal::null::NullError;
 use blue_hal::hal::time::Now;
 use blue_hal::{drivers::{micron::n25q128a_flash,
-    stm32f4::{flash, rcc::Clocks, serial, systick::SysTick}}, hal::time, stm32pac
+    stm32f4::{flash, rcc::Clocks, serial, systick::SysTick}}, hal::{qspi, time}, stm32pac
 };
 use super::autogenerated::{
     self,
     BOOT_TIME_METRICS_ENABLED,
-    UPDATE_SIGNAL_ENABLED,
-    RECOVERY_ENABLED, devices,
+    RECOVERY_ENABLED, RECOVERY_MENU_ENABLED, RECOVERY_TIMEOUT_MS, RECOVERY_MAX_ATTEMPTS, devices,
     memory_map::{EXTERNAL_BANKS, MCU_BANKS},
     pin_configuration::{self, *},
 };
@@ -18,21 +18,28 @@ use super::autogenerated::{
 use crate::devices::image::EcdsaImageReader as ImageReader;
 #[cfg(not(feature="ecdsa-verify"))]
 use crate::devices::image::CrcImageReader as ImageReader;
-use super::update_signal::{UpdateSignal, initialize_rtc_backup_domain};
+use super::update_signal::initialize_rtc_backup_domain;
+use super::bank_state::BankStateTable;
+use core::cell::RefCell;
 
-impl Default for Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, ImageReader, UpdateSignal> {
+impl Default for Bootloader<ExternalFlash, SharedFlash<flash::McuFlash>, Serial, SysTick, ImageReader, devices::UpdateSignal, devices::RecoveryPin, devices::ProtectionOptions, BankStateTable> {
     fn default() -> Self { Self::new() }
 }
 
-impl Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, ImageReader, UpdateSignal> {
+impl Bootloader<ExternalFlash, SharedFlash<flash::McuFlash>, Serial, SysTick, ImageReader, devices::UpdateSignal, devices::RecoveryPin, devices::ProtectionOptions, BankStateTable> {
     pub fn new() -> Self {
+        autogenerated::descriptor::retain_layout_descriptor();
         let mut peripherals = stm32pac::Peripherals::take().unwrap();
         let cortex_peripherals = cortex_m::Peripherals::take().unwrap();
         let mcu_flash = flash::McuFlash::new(peripherals.FLASH).unwrap();
+        // The update signal may live in MCU flash as well, so it shares the driver.
+        let mcu_flash = SharedFlash::new(
+            cortex_m::singleton!(: RefCell<flash::McuFlash> = RefCell::new(mcu_flash)).unwrap(),
+        );
 
         initialize_rtc_backup_domain(&mut peripherals.RCC, &mut peripherals.PWR);
 
-        let (serial_pins, qspi_pins) = pin_configuration::pins(
+        let (serial_pins, qspi_pins, recovery_pin) = pin_configuration::pins(
                 peripherals.GPIOA,
                 peripherals.GPIOB,
                 peripherals.GPIOC,
@@ -46,8 +53,14 @@ impl Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, ImageReader, Up
         let clocks = Clocks::hardcoded(peripherals.RCC);
         SysTick::init(cortex_peripherals.SYST, clocks);
         SysTick::wait(time::Seconds(1)); // Gives time for the flash chip to stabilize after powerup
+        // Only some parts have a QSPI peripheral to drive external flash with.
+        #[cfg(any(feature = "stm32f412", feature = "stm32f469"))]
         let optional_external_flash = devices::construct_flash(qspi_pins, peripherals.QUADSPI);
+        #[cfg(not(any(feature = "stm32f412", feature = "stm32f469")))]
+        let optional_external_flash = devices::construct_flash(qspi_pins);
         let optional_serial = devices::construct_serial(serial_pins, clocks, peripherals.USART1, peripherals.USART2, peripherals.USART6);
+        let recovery_pin = devices::construct_recovery_pin(recovery_pin);
+        let flash_protection = devices::construct_flash_protection();
 
         let start_time = if BOOT_TIME_METRICS_ENABLED {
             Some(SysTick::now())
@@ -55,12 +68,7 @@ impl Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, ImageReader, Up
             None
         };
 
-        let update_signal = if UPDATE_SIGNAL_ENABLED {
-            let rtc = peripherals.RTC;
-            Some(UpdateSignal::new(rtc))
-        } else {
-            None
-        };
+        let update_signal = devices::construct_update_signal(peripherals.RTC, mcu_flash);
 
         Bootloader {
             mcu_flash,
@@ -71,9 +79,17 @@ impl Bootloader<ExternalFlash, flash::McuFlash, Serial, SysTick, ImageReader, Up
             boot_metrics: Default::default(),
             start_time,
             recovery_enabled: RECOVERY_ENABLED,
+            recovery_policy: RecoveryPolicy {
+                timeout: RECOVERY_TIMEOUT_MS.map(time::Milliseconds),
+                max_attempts: RECOVERY_MAX_ATTEMPTS,
+            },
             greeting: autogenerated::LOADSTONE_GREETING,
             _marker: Default::default(),
             update_signal,
+            recovery_pin,
+            recovery_menu: RECOVERY_MENU_ENABLED,
+            flash_protection,
+            bank_states: Some(BankStateTable::new()),
         }
     }
 }
@@ -99,6 +115,9 @@ impl error::Convertible for n25q128a_flash::Error {
     }
 }
 
+/// The driver offers no subsector erase of its own, but erases subsectors as it writes over them.
+impl<QSPI: qspi::Indirect, NOW: time::Now> EraseRegion for n25q128a_flash::MicronN25q128a<QSPI, NOW> {}
+
 impl error::Convertible for NullError {
     fn into(self) -> Error { panic!("This error should never happen!") }
 }
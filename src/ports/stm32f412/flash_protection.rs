@@ -0,0 +1,118 @@
+//! Flash protection through the stm32f4 option bytes. Only the single bank, 1MB
+//! parts (stm32f407 and stm32f412) are supported, as the second bank of the 2MB
+//! parts has its own option bytes.
+use crate::{
+    devices::flash_protection::{ProtectionOptions, ReadoutProtection, Region},
+    error::Error,
+};
+use super::update_signal::SECTOR_SIZES;
+use blue_hal::stm32pac::FLASH;
+
+const FLASH_BASE: usize = 0x0800_0000;
+
+const OPTION_KEYS: [u32; 2] = [0x0819_2A3B, 0x4C5D_6E7F];
+const OPTCR_OPTLOCK: u32 = 1 << 0;
+const OPTCR_OPTSTRT: u32 = 1 << 1;
+const OPTCR_RDP_SHIFT: u32 = 8;
+const OPTCR_RDP_MASK: u32 = 0xFF << OPTCR_RDP_SHIFT;
+const OPTCR_NWRP_SHIFT: u32 = 16;
+const OPTCR_NWRP_MASK: u32 = 0xFFF << OPTCR_NWRP_SHIFT;
+const SR_BSY: u32 = 1 << 16;
+const SR_ERRORS: u32 = 0b1_1111_0010;
+
+const RDP_LEVEL_0: u32 = 0xAA;
+const RDP_LEVEL_1: u32 = 0x55;
+const RDP_LEVEL_2: u32 = 0xCC;
+
+/// Option byte access. Must not be used while the MCU flash driver is
+/// performing an operation, as both share the flash interface.
+pub struct OptionBytes {
+    _private: (),
+}
+
+impl OptionBytes {
+    pub fn new() -> Self { Self { _private: () } }
+
+    fn flash(&self) -> &'static blue_hal::stm32pac::flash::RegisterBlock {
+        // NOTE(Safety): The option control and status registers are not used by the
+        // MCU flash driver, and option bytes are never programmed while it is busy.
+        unsafe { &*FLASH::ptr() }
+    }
+
+    fn wait_until_idle(&self) -> Result<(), Error> {
+        while self.flash().sr.read().bits() & SR_BSY != 0 {}
+        let status = self.flash().sr.read().bits();
+        if status & SR_ERRORS != 0 {
+            // Error flags are cleared by writing them back.
+            self.flash().sr.write(|w| unsafe { w.bits(status & SR_ERRORS) });
+            Err(Error::DriverError("[MCU Flash] Failed to program option bytes"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl ProtectionOptions for OptionBytes {
+    fn sectors(&self, region: Region) -> u32 {
+        let region_end = region.start + region.size;
+        let mut sector_start = FLASH_BASE;
+        let mut mask = 0;
+        for (sector, size) in SECTOR_SIZES.iter().enumerate() {
+            let sector_end = sector_start + size;
+            if region.size > 0 && region.start < sector_end && sector_start < region_end {
+                mask |= 1 << sector;
+            }
+            sector_start = sector_end;
+        }
+        mask
+    }
+
+    fn write_protected_sectors(&self) -> u32 {
+        // A cleared nWRP bit means the sector is protected. Bits outside the nWRP
+        // field (such as SPRMOD) are not sectors, and must be ignored.
+        let optcr = self.flash().optcr.read().bits();
+        (!optcr & OPTCR_NWRP_MASK) >> OPTCR_NWRP_SHIFT
+    }
+
+    fn readout_protection(&self) -> ReadoutProtection {
+        match (self.flash().optcr.read().bits() & OPTCR_RDP_MASK) >> OPTCR_RDP_SHIFT {
+            RDP_LEVEL_0 => ReadoutProtection::Disabled,
+            RDP_LEVEL_2 => ReadoutProtection::Level2,
+            _ => ReadoutProtection::Level1,
+        }
+    }
+
+    fn program(&mut self, sectors: u32, readout_protection: ReadoutProtection) -> Result<(), Error> {
+        self.wait_until_idle()?;
+        let flash = self.flash();
+        if flash.optcr.read().bits() & OPTCR_OPTLOCK != 0 {
+            for key in OPTION_KEYS.iter() {
+                flash.optkeyr.write(|w| unsafe { w.bits(*key) });
+            }
+        }
+
+        let rdp = match readout_protection {
+            ReadoutProtection::Disabled => RDP_LEVEL_0,
+            ReadoutProtection::Level1 => RDP_LEVEL_1,
+            ReadoutProtection::Level2 => RDP_LEVEL_2,
+        };
+        let sectors = sectors & (OPTCR_NWRP_MASK >> OPTCR_NWRP_SHIFT);
+        let nwrp = !sectors & (OPTCR_NWRP_MASK >> OPTCR_NWRP_SHIFT);
+        let optcr = (flash.optcr.read().bits() & !(OPTCR_NWRP_MASK | OPTCR_RDP_MASK))
+            | (nwrp << OPTCR_NWRP_SHIFT)
+            | (rdp << OPTCR_RDP_SHIFT);
+        flash.optcr.write(|w| unsafe { w.bits(optcr) });
+        flash.optcr.write(|w| unsafe { w.bits(optcr | OPTCR_OPTSTRT) });
+        let result = self.wait_until_idle();
+        flash.optcr.modify(|r, w| unsafe { w.bits(r.bits() | OPTCR_OPTLOCK) });
+        result?;
+
+        let applied = self.write_protected_sectors() == sectors
+            && self.readout_protection() == readout_protection;
+        if applied {
+            Ok(())
+        } else {
+            Err(Error::DriverError("[MCU Flash] Option bytes did not take effect"))
+        }
+    }
+}
@@ -1,5 +1,35 @@
-use crate::devices::update_signal::{self, UpdatePlan};
-use blue_hal::stm32pac::RTC;
+use crate::devices::{traits::EraseRegion, update_signal::{self, register, UpdatePlan, UpdateResult}};
+use blue_hal::{
+    drivers::stm32f4::flash::{Address, Error, McuFlash},
+    stm32pac::{flash, FLASH, RTC},
+};
+
+const KB: usize = 1024;
+
+/// Sizes of the sectors in each flash bank, which every part lays out alike.
+pub(super) const SECTOR_SIZES: [usize; 12] = [
+    16 * KB, 16 * KB, 16 * KB, 16 * KB,
+    64 * KB,
+    128 * KB, 128 * KB, 128 * KB, 128 * KB, 128 * KB, 128 * KB, 128 * KB,
+];
+
+/// Start address and first encoded sector number of each flash bank. Only the
+/// 2MB parts have a second bank, and its sectors are encoded from 16 even
+/// though they are numbered from 12.
+#[cfg(any(feature = "stm32f429", feature = "stm32f469"))]
+const BANKS: [(usize, u32); 2] = [(0x0800_0000, 0), (0x0810_0000, 0x10)];
+#[cfg(not(any(feature = "stm32f429", feature = "stm32f469")))]
+const BANKS: [(usize, u32); 1] = [(0x0800_0000, 0)];
+
+const KEYS: [u32; 2] = [0x4567_0123, 0xCDEF_89AB];
+const CR_PG: u32 = 1 << 0;
+const CR_SER: u32 = 1 << 1;
+const CR_SNB_SHIFT: u32 = 3;
+const CR_PSIZE_X32: u32 = 0b10 << 8;
+const CR_STRT: u32 = 1 << 16;
+const CR_LOCK: u32 = 1 << 31;
+const SR_BSY: u32 = 1 << 16;
+const SR_ERRORS: u32 = 0b1_1111_0010;
 
 pub struct UpdateSignal {
     rtc: RTC,
@@ -12,13 +42,119 @@ impl UpdateSignal {
 }
 
 impl update_signal::ReadUpdateSignal for UpdateSignal {
-    fn read_update_plan(&self) -> UpdatePlan {
-        match self.rtc.bkpr[0].read().bits() {
-            0x00000000 => UpdatePlan::None,
-            0xFFFFFFFF => UpdatePlan::Any,
-            x => UpdatePlan::Index(x as u8),
+    fn read_update_plan(&self) -> Option<UpdatePlan> {
+        register::decode(self.rtc.bkpr[0].read().bits()).map(|(plan, _)| plan)
+    }
+
+    fn is_one_shot(&self) -> bool {
+        register::decode(self.rtc.bkpr[0].read().bits()).map_or(false, |(_, one_shot)| one_shot)
+    }
+
+    fn clear_one_shot(&mut self) {
+        let bits = register::encode(UpdatePlan::Any, false);
+        self.rtc.bkpr[0].write(|w| unsafe { w.bits(bits) });
+    }
+
+    fn write_update_result(&mut self, result: UpdateResult) {
+        let bits = register::encode_result(result);
+        self.rtc.bkpr[3].write(|w| unsafe { w.bits(bits) });
+    }
+}
+
+/// Returns the encoded sector number and the bounds of every sector touched
+/// by the region.
+fn sectors(start: usize, size: usize) -> impl Iterator<Item = (u32, usize, usize)> {
+    let end = start + size;
+    BANKS
+        .iter()
+        .flat_map(|&(base, first_sector)| {
+            SECTOR_SIZES.iter().enumerate().scan(base, move |sector_start, (sector, sector_size)| {
+                let sector_end = *sector_start + sector_size;
+                let bounds = (*sector_start, sector_end);
+                *sector_start = sector_end;
+                Some((first_sector + sector as u32, bounds.0, bounds.1))
+            })
+        })
+        .filter(move |&(_, sector_start, sector_end)| {
+            size > 0 && start < sector_end && sector_start < end
+        })
+}
+
+/// Returns whether the region lies entirely in flash.
+fn reachable(start: usize, size: usize) -> bool {
+    let sectors_end = sectors(start, size).map(|(_, _, end)| end).max();
+    start >= BANKS[0].0 && sectors_end.map_or(false, |end| end >= start + size)
+}
+
+/// Runs a flash operation with the control register unlocked, locking it again
+/// afterwards if it was found locked.
+fn unlocked<F>(operation: F) -> nb::Result<(), Error>
+where
+    F: FnOnce(&flash::RegisterBlock) -> nb::Result<(), Error>,
+{
+    // NOTE(Safety): The control register is only touched between operations
+    // of the MCU flash driver, and left locked if it was found locked.
+    let flash = unsafe { &*FLASH::ptr() };
+    if flash.sr.read().bits() & SR_BSY != 0 {
+        return Err(nb::Error::WouldBlock);
+    }
+    let was_locked = flash.cr.read().bits() & CR_LOCK != 0;
+    if was_locked {
+        for key in KEYS.iter() {
+            flash.keyr.write(|w| unsafe { w.bits(*key) });
         }
     }
+    let result = operation(flash);
+    flash.cr.write(|w| unsafe { w.bits(if was_locked { CR_LOCK } else { 0 }) });
+    result
+}
+
+/// Waits for the ongoing flash operation to finish, reporting its errors.
+fn finish(flash: &flash::RegisterBlock) -> nb::Result<(), Error> {
+    while flash.sr.read().bits() & SR_BSY != 0 {}
+    let status = flash.sr.read().bits();
+    if status & SR_ERRORS != 0 {
+        // Error flags are cleared by writing them back.
+        flash.sr.write(|w| unsafe { w.bits(status & SR_ERRORS) });
+        return Err(nb::Error::Other(Error::MemoryNotReachable));
+    }
+    Ok(())
+}
+
+impl EraseRegion for McuFlash {
+    fn erase_region(&mut self, location: Address, size: usize) -> nb::Result<(), Error> {
+        let start = location.0 as usize;
+        if !reachable(start, size) {
+            return Err(nb::Error::Other(Error::MemoryNotReachable));
+        }
+        unlocked(|flash| {
+            sectors(start, size).try_for_each(|(sector, _, _)| {
+                let cr = CR_SER | CR_PSIZE_X32 | (sector << CR_SNB_SHIFT);
+                flash.cr.write(|w| unsafe { w.bits(cr) });
+                flash.cr.write(|w| unsafe { w.bits(cr | CR_STRT) });
+                finish(flash)
+            })
+        })
+    }
+
+    /// The driver's `write` erases every sector it touches, so bytes are
+    /// programmed one at a time instead.
+    fn program(&mut self, address: Address, bytes: &[u8]) -> nb::Result<(), Error> {
+        let start = address.0 as usize;
+        if !reachable(start, bytes.len()) {
+            return Err(nb::Error::Other(Error::MemoryNotReachable));
+        }
+        unlocked(|flash| {
+            // Byte parallelism, as PSIZE is left clear.
+            flash.cr.write(|w| unsafe { w.bits(CR_PG) });
+            bytes.iter().enumerate().try_for_each(|(offset, byte)| {
+                // NOTE(Safety): The address was checked to lie in flash, which
+                // is only being programmed.
+                unsafe { core::ptr::write_volatile((start + offset) as *mut u8, *byte) };
+                finish(flash)
+            })
+        })
+    }
 }
 
 pub struct UpdateSignalWriter {
@@ -32,14 +168,14 @@ impl UpdateSignalWriter {
 }
 
 impl update_signal::WriteUpdateSignal for UpdateSignalWriter {
-    fn write_update_plan(&mut self, plan: UpdatePlan) {
-        let bits = match plan {
-            UpdatePlan::None => 0x00000000,
-            UpdatePlan::Any => 0xFFFFFFFF,
-            UpdatePlan::Index(x) => x as u32,
-        };
+    fn write_update_plan(&mut self, plan: UpdatePlan, one_shot: bool) {
+        let bits = register::encode(plan, one_shot);
         self.rtc.bkpr[0].write(|w| unsafe { w.bits(bits) });
     }
+
+    fn read_update_result(&self) -> Option<UpdateResult> {
+        register::decode_result(self.rtc.bkpr[3].read().bits())
+    }
 }
 
 /// Initializes the backup domain registers of the realtime clock, required for the update signal
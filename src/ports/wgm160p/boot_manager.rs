@@ -0,0 +1,50 @@
+//! Concrete boot manager construction and flash bank layout
+//! for the wgm160p
+use crate::devices::{bank_state::NullBankStateStore, boot_manager::BootManager, cli::Cli, shared_flash::SharedFlash};
+use blue_hal::{drivers::efm32gg11b::{clocks, flash::{self, Flash}}, efm32pac, hal::null::NullFlash};
+
+use super::autogenerated::{self, devices, memory_map::{EXTERNAL_BANKS, MCU_BANKS}, pin_configuration::{self, Serial}};
+#[cfg(feature="ecdsa-verify")]
+use crate::devices::image::EcdsaImageReader as ImageReader;
+#[cfg(not(feature="ecdsa-verify"))]
+use crate::devices::image::CrcImageReader as ImageReader;
+use super::systick::SysTick;
+use core::cell::RefCell;
+
+impl Default for BootManager<SharedFlash<Flash>, NullFlash, Serial, ImageReader, devices::UpdateSignalWriter, NullBankStateStore> {
+    fn default() -> Self { Self::new() }
+}
+
+impl BootManager<SharedFlash<Flash>, NullFlash, Serial, ImageReader, devices::UpdateSignalWriter, NullBankStateStore> {
+    pub fn new() -> Self {
+        let mut peripherals = efm32pac::Peripherals::take().unwrap();
+        let cortex_peripherals = cortex_m::Peripherals::take().unwrap();
+        let clocks = clocks::Clocks::new(peripherals.CMU, &mut peripherals.MSC);
+        let mcu_flash = flash::Flash::new(peripherals.MSC, &clocks);
+        // The update signal may live in MCU flash as well, so it shares the driver.
+        let mcu_flash = SharedFlash::new(
+            cortex_m::singleton!(: RefCell<Flash> = RefCell::new(mcu_flash)).unwrap(),
+        );
+        SysTick::init(cortex_peripherals.SYST, &clocks);
+
+        let serial_pins = pin_configuration::pins(&mut peripherals.GPIO);
+        let serial = devices::construct_serial(serial_pins, &clocks, peripherals.USART0, peripherals.USART1, peripherals.USART2)
+            .expect("Demo app can't function without serial!");
+        let cli = Cli::new(serial).unwrap();
+
+        let update_signal = devices::construct_update_signal_writer(mcu_flash);
+
+        BootManager {
+            external_flash: None,
+            mcu_flash,
+            external_banks: &EXTERNAL_BANKS,
+            mcu_banks: &MCU_BANKS,
+            cli: Some(cli),
+            boot_metrics: None,
+            greeting: Some(autogenerated::DEMO_APP_GREETING),
+            _marker: Default::default(),
+            update_signal,
+            bank_states: None,
+        }
+    }
+}
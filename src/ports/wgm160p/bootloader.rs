@@ -1,33 +1,69 @@
 //! Concrete bootloader construction and flash bank layout for the wgm160p
 
-use blue_hal::{drivers::efm32gg11b::{clocks, flash::{self, Flash}}, efm32pac, hal::null::{NullError, NullFlash, NullSerial, NullSystick}};
-use crate::{devices::{bootloader::Bootloader}, error::{self, Error}};
-use super::autogenerated;
-use super::autogenerated::memory_map::{EXTERNAL_BANKS, MCU_BANKS};
+use blue_hal::{drivers::efm32gg11b::{clocks, flash::{self, Flash}}, efm32pac, hal::{null::{NullError, NullFlash}, time::{self, Now}}};
+use crate::{devices::{bank_state::NullBankStateStore, bootloader::{Bootloader, RecoveryPolicy}, flash_protection::NullProtectionOptions, recovery_pin::NullRecoveryPin, shared_flash::SharedFlash}, error::{self, Error}};
+use super::autogenerated::{
+    self,
+    BOOT_TIME_METRICS_ENABLED,
+    RECOVERY_ENABLED, RECOVERY_MENU_ENABLED, RECOVERY_TIMEOUT_MS, RECOVERY_MAX_ATTEMPTS, devices,
+    memory_map::{EXTERNAL_BANKS, MCU_BANKS},
+    pin_configuration::{self, Serial},
+};
+use super::systick::SysTick;
+use core::cell::RefCell;
 
 #[cfg(feature="ecdsa-verify")]
 use crate::devices::image::EcdsaImageReader as ImageReader;
 #[cfg(not(feature="ecdsa-verify"))]
 use crate::devices::image::CrcImageReader as ImageReader;
-use super::update_signal::NullUpdateSignal;
 
-impl Bootloader<NullFlash, Flash, NullSerial, NullSystick, ImageReader, NullUpdateSignal> {
+impl Default for Bootloader<NullFlash, SharedFlash<Flash>, Serial, SysTick, ImageReader, devices::UpdateSignal, NullRecoveryPin, NullProtectionOptions, NullBankStateStore> {
+    fn default() -> Self { Self::new() }
+}
+
+impl Bootloader<NullFlash, SharedFlash<Flash>, Serial, SysTick, ImageReader, devices::UpdateSignal, NullRecoveryPin, NullProtectionOptions, NullBankStateStore> {
     pub fn new() -> Self {
+        autogenerated::descriptor::retain_layout_descriptor();
         let mut peripherals = efm32pac::Peripherals::take().unwrap();
+        let cortex_peripherals = cortex_m::Peripherals::take().unwrap();
         let clocks = clocks::Clocks::new(peripherals.CMU, &mut peripherals.MSC);
         let mcu_flash = flash::Flash::new(peripherals.MSC, &clocks);
+        // The update signal may live in MCU flash as well, so it shares the driver.
+        let mcu_flash = SharedFlash::new(
+            cortex_m::singleton!(: RefCell<Flash> = RefCell::new(mcu_flash)).unwrap(),
+        );
+        SysTick::init(cortex_peripherals.SYST, &clocks);
+
+        let serial_pins = pin_configuration::pins(&mut peripherals.GPIO);
+        let optional_serial = devices::construct_serial(serial_pins, &clocks, peripherals.USART0, peripherals.USART1, peripherals.USART2);
+
+        let start_time = if BOOT_TIME_METRICS_ENABLED {
+            Some(SysTick::now())
+        } else {
+            None
+        };
+
+        let update_signal = devices::construct_update_signal(mcu_flash);
         Bootloader {
             mcu_flash,
             external_banks: &EXTERNAL_BANKS,
             mcu_banks: &MCU_BANKS,
             external_flash: None,
-            serial: None,
+            serial: optional_serial,
             boot_metrics: Default::default(),
-            start_time: None,
-            recovery_enabled: false,
+            start_time,
+            recovery_enabled: RECOVERY_ENABLED,
+            recovery_policy: RecoveryPolicy {
+                timeout: RECOVERY_TIMEOUT_MS.map(time::Milliseconds),
+                max_attempts: RECOVERY_MAX_ATTEMPTS,
+            },
             greeting: autogenerated::LOADSTONE_GREETING,
             _marker: Default::default(),
-            update_signal: None,
+            update_signal,
+            recovery_pin: None,
+            recovery_menu: RECOVERY_MENU_ENABLED,
+            flash_protection: None,
+            bank_states: None,
         }
     }
 }
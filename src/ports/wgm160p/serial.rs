@@ -0,0 +1,188 @@
+//! Polling USART driver for the wgm160p, in asynchronous mode with eight data bits.
+use super::systick::SysTick;
+use blue_hal::{drivers::efm32gg11b::clocks::Clocks, efm32pac::{self, cmu, usart0}, hal::{serial, time::{Milliseconds, Now}}};
+use core::ops::Deref;
+use crate::error::{self, Error as LoadstoneError};
+
+/// USART peripherals that can back the serial.
+pub trait Instance: Deref<Target = usart0::RegisterBlock> {
+    /// Enables the peripheral's clock in CMU_HFPERCLKEN0.
+    fn enable_clock(cmu: &cmu::RegisterBlock);
+}
+
+impl Instance for efm32pac::USART0 {
+    fn enable_clock(cmu: &cmu::RegisterBlock) { cmu.hfperclken0.modify(|_, w| w.usart0().set_bit()); }
+}
+
+impl Instance for efm32pac::USART1 {
+    fn enable_clock(cmu: &cmu::RegisterBlock) { cmu.hfperclken0.modify(|_, w| w.usart1().set_bit()); }
+}
+
+impl Instance for efm32pac::USART2 {
+    fn enable_clock(cmu: &cmu::RegisterBlock) { cmu.hfperclken0.modify(|_, w| w.usart2().set_bit()); }
+}
+
+/// Route locations of the transmit and receive pins, as defined in the datasheet.
+/// The pins themselves are configured beforehand by the generated pin configuration.
+#[derive(Copy, Clone, Debug)]
+pub struct UsartPins {
+    pub tx_location: u8,
+    pub rx_location: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    Framing,
+    Parity,
+    Overrun,
+    Timeout,
+}
+
+pub struct Usart {
+    registers: &'static usart0::RegisterBlock,
+}
+
+/// Oversampling used in asynchronous mode.
+const OVERSAMPLING: u32 = 16;
+
+impl Usart {
+    pub fn new<U: Instance>(
+        usart: U,
+        pins: UsartPins,
+        clocks: &Clocks,
+        baud_rate: u32,
+        parity: Parity,
+        stop_bits: StopBits,
+    ) -> Self {
+        // NOTE(Safety): The peripheral is consumed here, so the register block is ours
+        // for as long as the driver lives. The clock enable register is shared, but the
+        // read-modify-write only touches this USART's bit.
+        let registers = unsafe { &*(usart.deref() as *const usart0::RegisterBlock) };
+        U::enable_clock(unsafe { &*efm32pac::CMU::ptr() });
+
+        // Fractional divider, per the reference manual: 256 * (fHFPERCLK / (OVS * baud) - 1).
+        let peripheral_clock_hz = clocks.hfperclk().0;
+        let divider = (32 * peripheral_clock_hz + (OVERSAMPLING * baud_rate) / 2) / (OVERSAMPLING * baud_rate);
+        let divider = (divider - 32) * 8;
+        registers.clkdiv.write(|w| unsafe { w.bits(divider & 0x007F_FFF8) });
+        registers.frame.write(|w| {
+            let w = w.databits().eight();
+            let w = match parity {
+                Parity::None => w.parity().none(),
+                Parity::Even => w.parity().even(),
+                Parity::Odd => w.parity().odd(),
+            };
+            match stop_bits {
+                StopBits::One => w.stopbits().one(),
+                StopBits::Two => w.stopbits().two(),
+            }
+        });
+        registers.routeloc0.write(|w| unsafe { w.txloc().bits(pins.tx_location).rxloc().bits(pins.rx_location) });
+        registers.routepen.write(|w| w.txpen().set_bit().rxpen().set_bit());
+        registers.cmd.write(|w| w.clearrx().set_bit().cleartx().set_bit());
+        registers.cmd.write(|w| w.rxen().set_bit().txen().set_bit());
+        Self { registers }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        while self.registers.status.read().txbl().bit_is_clear() {}
+        self.registers.txdata.write(|w| unsafe { w.txdata().bits(byte) });
+    }
+}
+
+/// Returns the next received byte, if any, reporting and clearing reception errors.
+fn try_read(registers: &usart0::RegisterBlock) -> nb::Result<u8, Error> {
+    let flags = registers.if_.read();
+    let error = if flags.rxof().bit_is_set() {
+        Some(Error::Overrun)
+    } else if flags.ferr().bit_is_set() {
+        Some(Error::Framing)
+    } else if flags.perr().bit_is_set() {
+        Some(Error::Parity)
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        registers.ifc.write(|w| w.rxof().set_bit().ferr().set_bit().perr().set_bit());
+        return Err(nb::Error::Other(error));
+    }
+    if registers.status.read().rxdatav().bit_is_set() {
+        Ok(registers.rxdata.read().rxdata().bits())
+    } else {
+        Err(nb::Error::WouldBlock)
+    }
+}
+
+/// Blocking iterator over received bytes.
+pub struct ReadIterator {
+    registers: &'static usart0::RegisterBlock,
+}
+
+impl Iterator for ReadIterator {
+    type Item = Result<u8, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> { Some(nb::block!(try_read(self.registers))) }
+}
+
+impl serial::Read for Usart {
+    type Error = Error;
+    type ReadIterator = ReadIterator;
+
+    fn bytes(&mut self) -> Self::ReadIterator { ReadIterator { registers: self.registers } }
+}
+
+impl serial::TimeoutRead for Usart {
+    type Error = Error;
+
+    fn read<T: Copy + Into<Milliseconds>>(&mut self, timeout: T) -> Result<u8, Self::Error> {
+        let start = SysTick::now();
+        loop {
+            match try_read(self.registers) {
+                Err(nb::Error::WouldBlock) if SysTick::now() - start > timeout.into() => {
+                    return Err(Error::Timeout)
+                }
+                Err(nb::Error::WouldBlock) => continue,
+                Err(nb::Error::Other(error)) => return Err(error),
+                Ok(byte) => return Ok(byte),
+            }
+        }
+    }
+}
+
+impl serial::Write for Usart {
+    type Error = Error;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        s.bytes().for_each(|b| self.write_byte(b));
+        Ok(())
+    }
+
+    fn write_char(&mut self, c: char) -> Result<(), Self::Error> {
+        let mut buffer = [0u8; 4];
+        self.write_str(c.encode_utf8(&mut buffer))
+    }
+}
+
+impl error::Convertible for Error {
+    fn into(self) -> LoadstoneError {
+        match self {
+            Error::Framing => LoadstoneError::DriverError("[Serial] Framing error"),
+            Error::Parity => LoadstoneError::DriverError("[Serial] Parity error"),
+            Error::Overrun => LoadstoneError::DriverError("[Serial] Overrun error"),
+            Error::Timeout => LoadstoneError::DriverError("[Serial] Timeout error"),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Millisecond time source for the wgm160p, driven by the Cortex-M SysTick timer.
+use blue_hal::{drivers::efm32gg11b::clocks::Clocks, hal::time::{self, Milliseconds}};
+use core::{ops::{Add, Sub}, sync::atomic::{AtomicU32, Ordering}};
+use cortex_m::peripheral::{syst::SystClkSource, SYST};
+
+static TICKS_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Milliseconds elapsed since the timer was started.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct Instant(u32);
+
+impl Sub for Instant {
+    type Output = Milliseconds;
+    fn sub(self, rhs: Self) -> Milliseconds { Milliseconds(self.0.wrapping_sub(rhs.0)) }
+}
+
+impl<T: Into<Milliseconds>> Add<T> for Instant {
+    type Output = Self;
+    fn add(self, rhs: T) -> Self { Instant(self.0.wrapping_add(rhs.into().0)) }
+}
+
+impl time::Instant for Instant {}
+
+pub struct SysTick;
+
+impl SysTick {
+    /// Starts counting milliseconds, ticking from the core clock.
+    pub fn init(mut syst: SYST, clocks: &Clocks) {
+        syst.set_clock_source(SystClkSource::Core);
+        syst.set_reload(clocks.hfclk().0 / 1000 - 1);
+        syst.clear_current();
+        syst.enable_interrupt();
+        syst.enable_counter();
+    }
+
+    /// Busy waits for the given time.
+    pub fn wait<T: Into<Milliseconds>>(t: T) {
+        let start = Self::now();
+        let period = t.into();
+        while Self::now() - start < period {}
+    }
+}
+
+impl time::Now for SysTick {
+    type I = Instant;
+    fn now() -> Self::I { Instant(TICKS_MS.load(Ordering::Relaxed)) }
+}
+
+/// Kept apart so the handler doesn't clash with the timer's name.
+mod handler {
+    use super::TICKS_MS;
+    use core::sync::atomic::Ordering;
+    use cortex_m_rt::exception;
+
+    #[exception]
+    fn SysTick() { TICKS_MS.fetch_add(1, Ordering::Relaxed); }
+}
@@ -1,8 +1,102 @@
-use crate::devices::update_signal::{ReadUpdateSignal, UpdatePlan};
+use crate::devices::{traits::EraseRegion, update_signal::{ReadUpdateSignal, UpdatePlan, UpdateResult, WriteUpdateSignal}};
+use blue_hal::{drivers::efm32gg11b::flash::{Address, Error, Flash}, efm32pac::{msc, MSC}};
+
+const PAGE_SIZE: usize = 4 * 1024;
+const WORD_SIZE: usize = 4;
+const LOCK_KEY: u32 = 0x1B71;
+const WRITECTRL_WREN: u32 = 1 << 0;
+const WRITECMD_LADDRIM: u32 = 1 << 0;
+const WRITECMD_ERASEPAGE: u32 = 1 << 1;
+const WRITECMD_WRITEONCE: u32 = 1 << 3;
+const STATUS_BUSY: u32 = 1 << 0;
+const STATUS_LOCKED: u32 = 1 << 1;
+const STATUS_INVADDR: u32 = 1 << 2;
+const STATUS_WDATAREADY: u32 = 1 << 3;
 
 #[derive(Default)]
 pub struct NullUpdateSignal;
 
 impl ReadUpdateSignal for NullUpdateSignal {
-    fn read_update_plan(&self) -> UpdatePlan { UpdatePlan::Any }
+    fn read_update_plan(&self) -> Option<UpdatePlan> { Some(UpdatePlan::Any) }
+    fn is_one_shot(&self) -> bool { false }
+    fn clear_one_shot(&mut self) {}
+    fn write_update_result(&mut self, _result: UpdateResult) {}
+}
+
+impl WriteUpdateSignal for NullUpdateSignal {
+    fn write_update_plan(&mut self, _plan: UpdatePlan, _one_shot: bool) {}
+    fn read_update_result(&self) -> Option<UpdateResult> { None }
+}
+
+/// Runs a flash operation with the memory system controller unlocked and
+/// writes enabled, locking it again afterwards.
+fn unlocked<F>(operation: F) -> nb::Result<(), Error>
+where
+    F: FnOnce(&msc::RegisterBlock) -> nb::Result<(), Error>,
+{
+    // NOTE(Safety): The memory system controller is only touched between
+    // operations of the flash driver, and left locked.
+    let msc = unsafe { &*MSC::ptr() };
+    if msc.status.read().bits() & STATUS_BUSY != 0 {
+        return Err(nb::Error::WouldBlock);
+    }
+    msc.lock.write(|w| unsafe { w.bits(LOCK_KEY) });
+    msc.writectrl.write(|w| unsafe { w.bits(WRITECTRL_WREN) });
+    let result = operation(msc);
+    msc.writectrl.write(|w| unsafe { w.bits(0) });
+    msc.lock.write(|w| unsafe { w.bits(0) });
+    result
+}
+
+/// Loads the address the next erase or write command applies to.
+fn load_address(msc: &msc::RegisterBlock, address: usize) -> nb::Result<(), Error> {
+    msc.addrb.write(|w| unsafe { w.bits(address as u32) });
+    msc.writecmd.write(|w| unsafe { w.bits(WRITECMD_LADDRIM) });
+    let status = msc.status.read().bits();
+    if status & STATUS_INVADDR != 0 {
+        Err(nb::Error::Other(Error::InvalidAddress))
+    } else if status & STATUS_LOCKED != 0 {
+        Err(nb::Error::Other(Error::MemoryIsLocked))
+    } else {
+        Ok(())
+    }
+}
+
+impl EraseRegion for Flash {
+    fn erase_region(&mut self, location: Address, size: usize) -> nb::Result<(), Error> {
+        let first_page = location.0 as usize / PAGE_SIZE;
+        let last_page = (location.0 as usize + size + PAGE_SIZE - 1) / PAGE_SIZE;
+        unlocked(|msc| {
+            (first_page..last_page).try_for_each(|page| {
+                load_address(msc, page * PAGE_SIZE)?;
+                msc.writecmd.write(|w| unsafe { w.bits(WRITECMD_ERASEPAGE) });
+                while msc.status.read().bits() & STATUS_BUSY != 0 {}
+                Ok(())
+            })
+        })
+    }
+
+    /// Programs word by word rather than through the driver's `write`, which
+    /// may erase the pages it touches. The bytes around `bytes` are padded with
+    /// ones, which leave the memory untouched.
+    fn program(&mut self, address: Address, bytes: &[u8]) -> nb::Result<(), Error> {
+        let start = address.0 as usize;
+        let end = start + bytes.len();
+        unlocked(|msc| {
+            (start - start % WORD_SIZE..end).step_by(WORD_SIZE).try_for_each(|word_start| {
+                let mut word = [0xFF; WORD_SIZE];
+                for (address, byte) in (word_start..).zip(word.iter_mut()) {
+                    if (start..end).contains(&address) {
+                        *byte = bytes[address - start];
+                    }
+                }
+                load_address(msc, word_start)?;
+                while msc.status.read().bits() & STATUS_WDATAREADY == 0 {}
+                msc.wdata.write(|w| unsafe { w.bits(u32::from_le_bytes(word)) });
+                msc.writecmd.write(|w| unsafe { w.bits(WRITECMD_WRITEONCE) });
+                while msc.status.read().bits() & STATUS_BUSY != 0 {}
+                Ok(())
+            })
+        })
+    }
 }
@@ -0,0 +1,249 @@
+//! Creation of a configuration from command line options, as an alternative
+//! to the GUI for scripts and CI. Only the most common options are offered;
+//! anything else can be edited in the resulting .ron file.
+use crate::error::Error;
+use loadstone_config::{
+    features::{BootMetrics, Serial, SerialLink, UpdateSignal},
+    memory::{Bank, FlashChip, InternalMemoryMap},
+    pins,
+    port::Port,
+    security::SecurityMode,
+    Configuration, KB,
+};
+
+/// Everything a new configuration is built from.
+pub struct Options {
+    pub port: Port,
+    pub bootloader_kb: u32,
+    /// Number of banks in MCU flash. The first one is bootable.
+    pub banks: usize,
+    /// Size of every bank. By default, banks share the available space evenly.
+    pub bank_kb: Option<u32>,
+    /// Name of the external flash chip, which must be available to the port.
+    pub external_flash: Option<String>,
+    pub external_banks: usize,
+    /// Index of the golden bank, counting MCU banks first.
+    pub golden_index: Option<usize>,
+    /// Names of the serial transmission and reception pins, e.g. `PA9`.
+    pub serial_pins: Option<(String, String)>,
+    pub serial_recovery: bool,
+    pub boot_metrics: bool,
+    pub update_signal: bool,
+    /// PEM contents of the verifying key. CRC mode is used without one.
+    pub verifying_key: Option<String>,
+}
+
+/// Builds a configuration from command line options, refusing to produce an invalid one.
+pub fn configuration(options: &Options) -> Result<Configuration, Error> {
+    let mut configuration = Configuration { port: options.port, ..Default::default() };
+    let internal_flash = configuration.internal_flash();
+
+    let update_signal = update_signal(options, &internal_flash);
+    let banks_end = match update_signal.flash_region() {
+        Some(region) => region.start_address,
+        None => internal_flash.end,
+    };
+    configuration.memory_configuration.internal_memory_map =
+        internal_memory_map(options, &internal_flash, banks_end)?;
+    configuration.feature_configuration.update_signal = update_signal;
+
+    if let Some(name) = &options.external_flash {
+        let chip = configuration
+            .external_flash()
+            .into_iter()
+            .find(|chip| chip.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::InvalidArgument(name.clone()))?;
+        // External flash chips are described by their last address.
+        configuration.memory_configuration.external_memory_map.banks =
+            banks(chip.start, chip.end + 1, options.external_banks, None, chip.region_size)?;
+        configuration.memory_configuration.external_flash = Some(chip);
+    }
+    configuration.memory_configuration.golden_index = options.golden_index;
+
+    if let Some((tx, rx)) = &options.serial_pins {
+        configuration.feature_configuration.serial =
+            serial(&options.port, tx, rx, options.serial_recovery)?;
+    }
+    if options.boot_metrics {
+        configuration.feature_configuration.boot_metrics =
+            BootMetrics::Enabled { timing: BootMetrics::timing_supported(&options.port) };
+    }
+
+    let security = &mut configuration.security_configuration;
+    match &options.verifying_key {
+        Some(key) => {
+            security.security_mode = SecurityMode::P256ECDSA;
+            security.verifying_key_raw = key.clone();
+        }
+        None => security.security_mode = SecurityMode::Crc,
+    }
+
+    configuration.cleanup();
+    let problems = configuration.validate();
+    if problems.is_empty() {
+        Ok(configuration)
+    } else {
+        Err(Error::InvalidConfiguration(problems))
+    }
+}
+
+/// Update signal in backup registers where the port has them, and in the last
+/// sector of MCU flash otherwise.
+fn update_signal(options: &Options, internal_flash: &FlashChip) -> UpdateSignal {
+    if !options.update_signal {
+        UpdateSignal::Disabled
+    } else if UpdateSignal::registers_supported(&options.port) {
+        UpdateSignal::Enabled
+    } else {
+        UpdateSignal::Flash {
+            start_address: internal_flash.end - internal_flash.region_size,
+            size_kb: internal_flash.region_size / KB!(1),
+        }
+    }
+}
+
+/// Loadstone at the start of MCU flash, followed by the banks from the next
+/// sector boundary up to `banks_end`.
+fn internal_memory_map(
+    options: &Options,
+    internal_flash: &FlashChip,
+    banks_end: u32,
+) -> Result<InternalMemoryMap, Error> {
+    let bootloader_end = KB!(options.bootloader_kb);
+    let region_size = internal_flash.region_size;
+    let first_bank =
+        internal_flash.start + (bootloader_end + region_size - 1) / region_size * region_size;
+    Ok(InternalMemoryMap {
+        bootloader_location: internal_flash.start,
+        bootloader_length_kb: options.bootloader_kb,
+        banks: banks(first_bank, banks_end, options.banks, options.bank_kb, region_size)?,
+        bootable_index: Some(0),
+    })
+}
+
+/// Lays `count` contiguous banks from `start`, either of the given size or
+/// sharing the space up to `end` in whole sectors.
+fn banks(
+    start: u32,
+    end: u32,
+    count: usize,
+    size_kb: Option<u32>,
+    region_size: u32,
+) -> Result<Vec<Bank>, Error> {
+    if count == 0 {
+        return Ok(vec![]);
+    }
+    let size = match size_kb {
+        Some(size_kb) => KB!(size_kb),
+        None => end.saturating_sub(start) / count as u32 / region_size * region_size,
+    };
+    if size == 0 {
+        return Err(Error::InvalidArgument(format!("{} banks", count)));
+    }
+    Ok((0..count as u32)
+        .map(|i| Bank { start_address: start + i * size, size_kb: size / KB!(1) })
+        .collect())
+}
+
+/// Serial enabled on the named pins, preferring a reception pin on the same
+/// peripheral as the transmission one.
+fn serial(port: &Port, tx: &str, rx: &str, recovery_enabled: bool) -> Result<Serial, Error> {
+    let tx_pin = pins::serial_tx(port)
+        .find(|pin| pin.to_string().eq_ignore_ascii_case(tx))
+        .ok_or_else(|| Error::InvalidArgument(tx.to_owned()))?;
+    let candidates: Vec<_> =
+        pins::serial_rx(port).filter(|pin| pin.to_string().eq_ignore_ascii_case(rx)).collect();
+    let rx_pin = candidates
+        .iter()
+        .find(|pin| pin.peripheral == tx_pin.peripheral)
+        .or_else(|| candidates.first())
+        .cloned()
+        .ok_or_else(|| Error::InvalidArgument(rx.to_owned()))?;
+    Ok(Serial::Enabled { recovery_enabled, tx_pin, rx_pin, link: SerialLink::default() })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options(port: Port) -> Options {
+        Options {
+            port,
+            bootloader_kb: 64,
+            banks: 2,
+            bank_kb: None,
+            external_flash: None,
+            external_banks: 0,
+            golden_index: None,
+            serial_pins: None,
+            serial_recovery: false,
+            boot_metrics: false,
+            update_signal: false,
+            verifying_key: None,
+        }
+    }
+
+    #[test]
+    fn banks_share_mcu_flash_after_loadstone() {
+        let configuration = configuration(&options(Port::Stm32F412)).unwrap();
+        let map = &configuration.memory_configuration.internal_memory_map;
+        assert_eq!(map.banks[0].start_address, 0x0801_0000);
+        assert_eq!(map.banks[0].size_kb, map.banks[1].size_kb);
+        assert_eq!(map.banks[1].end_address(), 0x0810_0000);
+        assert_eq!(map.bootable_index, Some(0));
+    }
+
+    #[test]
+    fn banks_leave_room_for_a_flash_update_signal() {
+        let configuration =
+            configuration(&Options { update_signal: true, ..options(Port::Wgm160P) }).unwrap();
+        let region = configuration.feature_configuration.update_signal.flash_region().unwrap();
+        let map = &configuration.memory_configuration.internal_memory_map;
+        assert!(map.banks.iter().all(|bank| !bank.overlaps(&region)));
+    }
+
+    #[test]
+    fn external_banks_span_the_named_chip() {
+        let configuration = configuration(&Options {
+            external_flash: Some("winbond w25q128jv".to_owned()),
+            external_banks: 2,
+            golden_index: Some(3),
+            ..options(Port::Stm32F469)
+        })
+        .unwrap();
+        let memory = &configuration.memory_configuration;
+        assert_eq!(memory.external_memory_map.banks[1].end_address(), 16 * 1024 * 1024);
+        assert_eq!(memory.golden_index, Some(3));
+    }
+
+    #[test]
+    fn serial_pins_are_found_by_name() {
+        let configuration = configuration(&Options {
+            serial_pins: Some(("PA9".to_owned(), "pa10".to_owned())),
+            ..options(Port::Stm32F412)
+        })
+        .unwrap();
+        match configuration.feature_configuration.serial {
+            Serial::Enabled { tx_pin, rx_pin, .. } => {
+                assert_eq!((tx_pin.index, rx_pin.index), (9, 10));
+                assert_eq!(tx_pin.peripheral, rx_pin.peripheral);
+            }
+            Serial::Disabled => panic!("Serial should be enabled"),
+        }
+    }
+
+    #[test]
+    fn invalid_options_are_refused() {
+        assert!(matches!(
+            configuration(&Options { golden_index: Some(5), ..options(Port::Stm32F412) }),
+            Err(Error::InvalidConfiguration(_))
+        ));
+        assert!(matches!(
+            configuration(&Options {
+                serial_pins: Some(("PZ1".to_owned(), "PA10".to_owned())),
+                ..options(Port::Stm32F412)
+            }),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
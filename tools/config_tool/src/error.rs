@@ -0,0 +1,29 @@
+use loadstone_config::{schema::LoadError, validation::Problem};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug)]
+pub enum Error {
+    FileReadFailed(String),
+    FileWriteFailed(String),
+    InvalidArgument(String),
+    LoadFailed(LoadError),
+    InvalidConfiguration(Vec<Problem>),
+    GenerationFailed(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        use Error::*;
+        match self {
+            FileReadFailed(path) => write!(f, "Failed to read `{}`.", path),
+            FileWriteFailed(path) => write!(f, "Failed to write `{}`.", path),
+            InvalidArgument(argument) => write!(f, "Invalid argument `{}`.", argument),
+            LoadFailed(error) => write!(f, "{}.", error),
+            InvalidConfiguration(problems) => {
+                write!(f, "The configuration is invalid:")?;
+                problems.iter().try_for_each(|p| write!(f, "\n* {}", p))
+            }
+            GenerationFailed(reason) => write!(f, "Code generation failed ({}).", reason),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+mod create;
+mod error;
+
+use crate::{create::Options, error::Error};
+use clap::{clap_app, ArgMatches};
+use enum_iterator::IntoEnumIterator;
+use loadstone_config::{
+    codegen::{generate_application_linker_scripts, generate_modules},
+    port::Port,
+    report, Configuration,
+};
+use ron::ser::PrettyConfig;
+use std::{fs, process, str::FromStr};
+
+/// Reads a configuration file, upgrading it from an older schema if necessary.
+fn load(path: &str) -> Result<Configuration, Error> {
+    let source = fs::read_to_string(path).map_err(|_| Error::FileReadFailed(path.to_owned()))?;
+    Configuration::from_ron(&source).map_err(Error::LoadFailed)
+}
+
+/// Reads a configuration file, refusing it unless it can produce a Loadstone binary.
+fn load_valid(path: &str) -> Result<Configuration, Error> {
+    let configuration = load(path)?;
+    let problems = configuration.validate();
+    if problems.is_empty() {
+        Ok(configuration)
+    } else {
+        Err(Error::InvalidConfiguration(problems))
+    }
+}
+
+fn parse<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<Option<T>, Error> {
+    matches
+        .value_of(name)
+        .map(|value| value.parse().map_err(|_| Error::InvalidArgument(value.to_owned())))
+        .transpose()
+}
+
+fn options(matches: &ArgMatches) -> Result<Options, Error> {
+    let port = matches.value_of("port").unwrap();
+    let serial_pins = match (matches.value_of("serial_tx"), matches.value_of("serial_rx")) {
+        (Some(tx), Some(rx)) => Some((tx.to_owned(), rx.to_owned())),
+        _ => None,
+    };
+    let verifying_key = matches
+        .value_of("key")
+        .map(|path| fs::read_to_string(path).map_err(|_| Error::FileReadFailed(path.to_owned())))
+        .transpose()?;
+    Ok(Options {
+        port: Port::into_enum_iter()
+            .find(|p| p.to_string().eq_ignore_ascii_case(port))
+            .ok_or_else(|| Error::InvalidArgument(port.to_owned()))?,
+        bootloader_kb: parse(matches, "bootloader")?.unwrap(),
+        banks: parse(matches, "banks")?.unwrap(),
+        bank_kb: parse(matches, "bank_size")?,
+        external_flash: matches.value_of("external_flash").map(str::to_owned),
+        external_banks: parse(matches, "external_banks")?.unwrap(),
+        golden_index: parse(matches, "golden")?,
+        serial_pins,
+        serial_recovery: matches.is_present("serial_recovery"),
+        boot_metrics: matches.is_present("boot_metrics"),
+        update_signal: matches.is_present("update_signal"),
+        verifying_key,
+    })
+}
+
+fn run_command(name: &str, matches: &ArgMatches) -> Result<(), Error> {
+    match name {
+        "new" => {
+            let configuration = create::configuration(&options(matches)?)?;
+            let ron = ron::ser::to_string_pretty(&configuration, PrettyConfig::default())
+                .map_err(|e| Error::GenerationFailed(e.to_string()))?;
+            match matches.value_of("output") {
+                Some(path) => {
+                    fs::write(path, ron).map_err(|_| Error::FileWriteFailed(path.to_owned()))?
+                }
+                None => println!("{}", ron),
+            }
+        }
+        "validate" => {
+            load_valid(matches.value_of("config").unwrap())?;
+            println!("The configuration is valid.");
+        }
+        "memory_map" => {
+            let configuration = load(matches.value_of("config").unwrap())?;
+            match matches.value_of("format").unwrap() {
+                "markdown" => print!("{}", report::markdown(&configuration)),
+                "svg" => print!("{}", report::svg(&configuration)),
+                _ => print!("{}", report::diagram(&configuration)),
+            }
+        }
+        "generate" => {
+            let configuration = load_valid(matches.value_of("config").unwrap())?;
+            let loadstone = matches.value_of("loadstone").unwrap();
+            generate_modules(loadstone, &configuration)
+                .map_err(|e| Error::GenerationFailed(e.to_string()))?;
+            println!("Generated the {} port under `{}`.", configuration.port, loadstone);
+        }
+        "linker_script" => {
+            let configuration = load_valid(matches.value_of("config").unwrap())?;
+            let bank = match parse(matches, "bank")? {
+                Some(bank) => bank,
+                None => configuration
+                    .memory_configuration
+                    .internal_memory_map
+                    .bootable_index
+                    .ok_or_else(|| Error::InvalidArgument("--bank".to_owned()))?,
+            };
+            let output = matches.value_of("output").unwrap();
+            generate_application_linker_scripts(output, &configuration, bank)
+                .map_err(|e| Error::GenerationFailed(e.to_string()))?;
+            println!("Wrote the linker scripts for Bank {} under `{}`.", bank + 1, output);
+        }
+        "cargo" => {
+            let path = matches.value_of("config").unwrap();
+            let configuration = load_valid(path)?;
+            let mut features: Vec<_> = configuration.required_feature_flags().collect();
+            let binary = if matches.is_present("demo_app") {
+                features.push("relocate-to-bootable-bank");
+                "demo_app"
+            } else {
+                "loadstone"
+            };
+            println!(
+                "LOADSTONE_CONFIG=\"$(cat {})\" cargo b {} --features {}",
+                path,
+                binary,
+                features.join(",")
+            );
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn main() {
+    let matches = clap_app!(app =>
+        (name: "loadstone-config")
+        (version: env!("CARGO_PKG_VERSION"))
+        (about: env!("CARGO_PKG_DESCRIPTION"))
+        (@setting SubcommandRequiredElseHelp)
+        (@subcommand new =>
+            (about: "Creates a configuration file from the options below.")
+            (@arg port: +required "Target port, e.g. `stm32f412`.")
+            (@arg bootloader: --bootloader +takes_value default_value("64")
+                "Kilobytes reserved for Loadstone at the start of MCU flash.")
+            (@arg banks: --banks +takes_value default_value("2")
+                "Number of banks in MCU flash. The first one is bootable.")
+            (@arg bank_size: --("bank-size") +takes_value
+                "Kilobytes in every MCU bank. By default, banks fill MCU flash.")
+            (@arg external_flash: --("external-flash") +takes_value
+                "Name of the external flash chip, e.g. `Winbond W25Q128JV`.")
+            (@arg external_banks: --("external-banks") +takes_value default_value("1")
+                "Number of banks filling the external flash chip.")
+            (@arg golden: --golden +takes_value
+                "Index of the golden bank, counting MCU banks first from zero.")
+            (@arg serial_tx: --("serial-tx") +takes_value requires[serial_rx]
+                "Serial transmission pin, e.g. `PA9`.")
+            (@arg serial_rx: --("serial-rx") +takes_value requires[serial_tx]
+                "Serial reception pin, e.g. `PA10`.")
+            (@arg serial_recovery: --("serial-recovery") "Offers recovery over serial.")
+            (@arg boot_metrics: --("boot-metrics") "Relays boot metrics to the application.")
+            (@arg update_signal: --("update-signal")
+                "Lets the application control when updates happen.")
+            (@arg key: --key +takes_value
+                "PEM file with the P256 ECDSA verifying key. CRC32 is used without one.")
+            (@arg output: -o --output +takes_value "File to write, instead of printing it."))
+        (@subcommand validate =>
+            (about: "Lists every problem that prevents building Loadstone from a configuration.")
+            (@arg config: +required "The .ron configuration file."))
+        (@subcommand memory_map =>
+            (about: "Shows the memory areas described by a configuration.")
+            (@arg config: +required "The .ron configuration file.")
+            (@arg format: -f --format +takes_value default_value("text")
+                possible_value[text markdown svg] "Format of the report."))
+        (@subcommand generate =>
+            (about: "Generates the sources of the configured port.")
+            (@arg config: +required "The .ron configuration file.")
+            (@arg loadstone: -l --loadstone +takes_value default_value(".")
+                "Root of the Loadstone repository to generate into."))
+        (@subcommand linker_script =>
+            (about: "Generates the linker scripts of an application booted from an MCU bank.")
+            (@arg config: +required "The .ron configuration file.")
+            (@arg bank: -b --bank +takes_value
+                "Index of the MCU bank, counting from zero. Defaults to the bootable bank.")
+            (@arg output: -o --output +takes_value default_value(".")
+                "Folder to write `memory.x` and `application.x` to."))
+        (@subcommand cargo =>
+            (about: "Prints the cargo command that builds Loadstone from a configuration.")
+            (@arg config: +required "The .ron configuration file.")
+            (@arg demo_app: --("demo-app") "Builds the demo application instead."))
+    )
+    .get_matches();
+
+    let (name, subcommand) = matches.subcommand();
+    if let Err(error) = run_command(name, subcommand.unwrap()) {
+        // Returning the error from `main` would print its debug form instead.
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,281 @@
+//! Boot manager CLI driver.
+//!
+//! Commands are sent as text lines, and their output is collected until the
+//! CLI prints its next prompt.
+
+use crate::{
+    error::Error,
+    port::{drain, read_byte},
+    xmodem,
+};
+use std::{
+    io::{Read, Write},
+    time::{Duration, Instant},
+};
+
+/// Printed by the CLI when it's ready for the next command.
+const PROMPT: &str = "\n> ";
+/// Printed by the `flash` command right before it starts receiving.
+const TRANSFER_BANNER: &str = "Starting XMODEM mode!";
+/// Printed by the `flash` command once the image is stored and verified.
+const TRANSFER_COMPLETE: &str = "Image transfer complete!";
+/// Prefix of every error reported by the CLI.
+const ERROR_PREFIX: &str = "[CLI Error]";
+/// Reported by the CLI for commands it doesn't know.
+const UNKNOWN_COMMAND: &str = "Unknown command";
+
+/// Time the line must be quiet before the CLI is considered settled.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+pub struct Device<P: Read + Write> {
+    port: P,
+    timeout: Duration,
+}
+
+impl<P: Read + Write> Device<P> {
+    pub fn new(port: P, timeout: Duration) -> Self { Self { port, timeout } }
+
+    /// Wakes up the CLI and waits until it's ready for a command. Any greeting
+    /// or stale prompt is discarded.
+    pub fn connect(&mut self) -> Result<(), Error> {
+        self.write(b"\n")?;
+        self.read_until(&[PROMPT], "a prompt")?;
+        drain(&mut self.port, SETTLE_TIME)
+    }
+
+    /// Runs a command, returning the non-empty lines of its output.
+    pub fn command(&mut self, command: &str) -> Result<Vec<String>, Error> {
+        self.write(format!("{}\n", command).as_bytes())?;
+        let (output, _) = self.read_until(&[PROMPT], "the command to finish")?;
+        check(lines(&output))
+    }
+
+    /// Stores an image in a bank through the `flash` command.
+    pub fn flash(&mut self, bank: u8, name: &str, image: &[u8]) -> Result<Vec<String>, Error> {
+        self.write(format!("flash bank={}\n", bank).as_bytes())?;
+        let (output, found) =
+            self.read_until(&[TRANSFER_BANNER, PROMPT], "the transfer to start")?;
+        if found == PROMPT {
+            // The command finished without starting a transfer.
+            check(lines(&output))?;
+            return Err(Error::UnexpectedOutput(output));
+        }
+        self.read_until(&["\n"], "the transfer to start")?;
+
+        // If the device cancelled the transfer, it explains why before the next prompt.
+        match xmodem::send(&mut self.port, name, image, self.timeout) {
+            Ok(()) | Err(Error::TransferCancelled) => (),
+            Err(e) => return Err(e),
+        }
+        let (output, _) = self.read_until(&[PROMPT], "the image to be stored")?;
+        let output = check(lines(&output))?;
+        if output.iter().any(|l| l == TRANSFER_COMPLETE) {
+            Ok(output)
+        } else {
+            Err(Error::UnexpectedOutput(output.join("\n")))
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.port
+            .write_all(bytes)
+            .and_then(|_| self.port.flush())
+            .map_err(|e| Error::PortFailed(e.to_string()))
+    }
+
+    /// Collects output until one of the given patterns appears, returning
+    /// the text before it and the pattern found.
+    fn read_until<'p>(
+        &mut self,
+        patterns: &[&'p str],
+        waiting_for: &'static str,
+    ) -> Result<(String, &'p str), Error> {
+        let deadline = Instant::now() + self.timeout;
+        let mut output = Vec::new();
+        loop {
+            match read_byte(&mut self.port, deadline)? {
+                Some(b'\r') => continue,
+                Some(byte) => output.push(byte),
+                None => return Err(Error::TimedOut(waiting_for)),
+            }
+            if let Some(pattern) = patterns.iter().find(|p| output.ends_with(p.as_bytes())) {
+                output.truncate(output.len() - pattern.len());
+                return Ok((String::from_utf8_lossy(&output).into_owned(), pattern));
+            }
+        }
+    }
+}
+
+fn lines(output: &str) -> Vec<String> {
+    output.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_owned).collect()
+}
+
+/// Turns CLI error reports into errors, passing any other output through.
+fn check(lines: Vec<String>) -> Result<Vec<String>, Error> {
+    match lines.iter().position(|l| l.starts_with(ERROR_PREFIX) || l == UNKNOWN_COMMAND) {
+        Some(position) => Err(Error::CommandFailed(lines[position..].join("\n"))),
+        None => Ok(lines),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        io::{self, ErrorKind},
+        sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        thread,
+    };
+
+    const SOH: u8 = 0x01;
+    const STX: u8 = 0x02;
+    const EOT: u8 = 0x04;
+    const ACK: u8 = 0x06;
+
+    /// One end of an in-memory serial line.
+    struct Line {
+        tx: Sender<u8>,
+        rx: Receiver<u8>,
+    }
+
+    fn line() -> (Line, Line) {
+        let (host_tx, device_rx) = channel();
+        let (device_tx, host_rx) = channel();
+        (Line { tx: host_tx, rx: host_rx }, Line { tx: device_tx, rx: device_rx })
+    }
+
+    impl Read for Line {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            match self.rx.recv_timeout(Duration::from_millis(50)) {
+                Ok(byte) => {
+                    buffer[0] = byte;
+                    Ok(1)
+                }
+                Err(RecvTimeoutError::Timeout) => Err(ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => Err(ErrorKind::BrokenPipe.into()),
+            }
+        }
+    }
+
+    impl Write for Line {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            for byte in buffer {
+                self.tx.send(*byte).map_err(|_| io::Error::from(ErrorKind::BrokenPipe))?;
+            }
+            Ok(buffer.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    impl Line {
+        fn byte(&mut self) -> u8 { self.rx.recv_timeout(Duration::from_secs(5)).unwrap() }
+
+        fn read_line(&mut self) -> String {
+            let mut line = Vec::new();
+            loop {
+                match self.byte() {
+                    b'\n' => return String::from_utf8(line).unwrap(),
+                    byte => line.push(byte),
+                }
+            }
+        }
+
+        /// Minimal XMODEM-1K/CRC receiver, returning the file trimmed to its declared size.
+        fn receive(&mut self) -> Vec<u8> {
+            let mut size = None;
+            let mut data = Vec::new();
+            let mut ended = false;
+            self.write_all(b"C").unwrap();
+            loop {
+                let length = match self.byte() {
+                    SOH => 128,
+                    STX => 1024,
+                    EOT => {
+                        ended = true;
+                        self.write_all(&[ACK, b'C']).unwrap();
+                        continue;
+                    }
+                    other => panic!("Unexpected byte {:#x}", other),
+                };
+                let number = self.byte();
+                assert_eq!(!number, self.byte());
+                let payload: Vec<u8> = (0..length).map(|_| self.byte()).collect();
+                let crc = u16::from_be_bytes([self.byte(), self.byte()]);
+                assert_eq!(crc, xmodem::crc16(&payload));
+                self.write_all(&[ACK]).unwrap();
+
+                match (number, ended) {
+                    (0, true) => break,
+                    (0, false) => {
+                        let header = String::from_utf8_lossy(&payload).into_owned();
+                        size = header.split('\0').nth(1).and_then(|s| s.parse().ok());
+                        self.write_all(b"C").unwrap();
+                    }
+                    _ => data.extend_from_slice(&payload),
+                }
+            }
+            data.truncate(size.unwrap());
+            data
+        }
+    }
+
+    /// Plays the boot manager side of a session, returning any flashed image.
+    fn simulated_boot_manager(mut line: Line) -> thread::JoinHandle<Option<Vec<u8>>> {
+        thread::spawn(move || {
+            let mut image = None;
+            line.write_all(b"\r\n--=Loadstone demo app CLI + Boot Manager=--\r\n\n> ").unwrap();
+            loop {
+                let command = line.read_line();
+                let response = match command.as_str() {
+                    "" => "",
+                    "banks" => "[MCU] Banks:\r\n   - [1] Bootable - Size: 1024b\r\n",
+                    "flash bank=2" => {
+                        line.write_all(b"Starting XMODEM mode! Send file.\r\n").unwrap();
+                        image = Some(line.receive());
+                        "Image transfer complete!\r\n"
+                    }
+                    "flash bank=1" => {
+                        "[CLI Error] Internal boot manager error: \r\n[Logic Error] -> Bank invalid\r\n"
+                    }
+                    "quit" => return image,
+                    _ => "Unknown command\r\n",
+                };
+                line.write_all(response.as_bytes()).unwrap();
+                line.write_all(PROMPT.as_bytes()).unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn commands_return_their_output_up_to_the_next_prompt() {
+        let (host, device) = line();
+        let simulation = simulated_boot_manager(device);
+        let mut device = Device::new(host, Duration::from_secs(5));
+
+        device.connect().unwrap();
+        assert_eq!(device.command("banks").unwrap(), vec![
+            "[MCU] Banks:".to_owned(),
+            "- [1] Bootable - Size: 1024b".to_owned(),
+        ]);
+        assert!(matches!(device.command("bogus"), Err(Error::CommandFailed(_))));
+
+        device.write(b"quit\n").unwrap();
+        simulation.join().unwrap();
+    }
+
+    #[test]
+    fn images_are_flashed_through_xmodem() {
+        let (host, device) = line();
+        let simulation = simulated_boot_manager(device);
+        let mut device = Device::new(host, Duration::from_secs(5));
+        let image: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+
+        device.connect().unwrap();
+        device.flash(2, "image.bin", &image).unwrap();
+        assert!(matches!(device.flash(1, "image.bin", &image), Err(Error::CommandFailed(_))));
+
+        device.write(b"quit\n").unwrap();
+        assert_eq!(simulation.join().unwrap(), Some(image));
+    }
+}
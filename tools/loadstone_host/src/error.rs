@@ -0,0 +1,31 @@
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug)]
+pub enum Error {
+    PortOpenFailed(String),
+    PortFailed(String),
+    ImageReadFailed,
+    InvalidArgument(String),
+    TimedOut(&'static str),
+    TransferCancelled,
+    TransferFailed,
+    CommandFailed(String),
+    UnexpectedOutput(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        use Error::*;
+        match self {
+            PortOpenFailed(reason) => write!(f, "Failed to open serial port ({}).", reason),
+            PortFailed(reason) => write!(f, "Serial port failed ({}).", reason),
+            ImageReadFailed => write!(f, "Failed to read image file."),
+            InvalidArgument(argument) => write!(f, "Invalid argument `{}`.", argument),
+            TimedOut(waiting_for) => write!(f, "Timed out waiting for {}.", waiting_for),
+            TransferCancelled => write!(f, "The device cancelled the file transfer."),
+            TransferFailed => write!(f, "The device repeatedly rejected the file transfer."),
+            CommandFailed(output) => write!(f, "The device reported an error:\n{}", output),
+            UnexpectedOutput(output) => write!(f, "Unexpected device output:\n{}", output),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+mod device;
+mod error;
+mod parse;
+mod port;
+mod xmodem;
+
+use crate::{device::Device, error::Error};
+use clap::{clap_app, ArgMatches};
+use std::{fs, path::Path, time::Duration};
+
+fn bank_argument(matches: &ArgMatches) -> Result<u8, Error> {
+    let bank = matches.value_of("bank").unwrap();
+    bank.parse().map_err(|_| Error::InvalidArgument(bank.to_owned()))
+}
+
+/// Suffix requesting a plan that only applies to the next boot, if asked for.
+fn once_argument(matches: &ArgMatches) -> &'static str {
+    if matches.is_present("once") {
+        " once"
+    } else {
+        ""
+    }
+}
+
+fn run_command<P>(device: &mut Device<P>, name: &str, matches: &ArgMatches) -> Result<(), Error>
+where
+    P: std::io::Read + std::io::Write,
+{
+    match name {
+        "banks" => {
+            let lines = device.command("banks")?;
+            parse::banks(&lines)?.iter().for_each(|b| println!("{}", b));
+        }
+        "images" => {
+            let lines = device.command("images")?;
+            parse::images(&lines)?.iter().for_each(|i| println!("{}", i));
+        }
+        "metrics" => match parse::metrics(&device.command("metrics")?)? {
+            Some(metrics) => println!("{}", metrics),
+            None => println!("No boot metrics available."),
+        },
+        "flash" => {
+            let bank = bank_argument(matches)?;
+            let path = matches.value_of("image").unwrap();
+            let image = fs::read(path).map_err(|_| Error::ImageReadFailed)?;
+            let name = Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or("image");
+            device.flash(bank, name, &image)?;
+            println!("Stored {} bytes in bank {}.", image.len(), bank);
+        }
+        "update_signal_bank" => {
+            let bank = bank_argument(matches)?;
+            device.command(&format!(
+                "update_signal_bank bank={}{}",
+                bank,
+                once_argument(matches)
+            ))?;
+        }
+        "update_signal_any" | "update_signal_none" | "update_signal_recovery" => {
+            device.command(&format!("{}{}", name, once_argument(matches)))?;
+        }
+        // Golden restores and rollbacks always apply to the next boot only.
+        "update_signal_golden" | "update_signal_rollback" => {
+            device.command(name)?;
+        }
+        "command" => {
+            let command = matches.value_of("text").unwrap();
+            device.command(command)?.iter().for_each(|l| println!("{}", l));
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), String> {
+    let matches = clap_app!(app =>
+        (name: env!("CARGO_PKG_NAME"))
+        (version: env!("CARGO_PKG_VERSION"))
+        (about: env!("CARGO_PKG_DESCRIPTION"))
+        (@setting SubcommandRequiredElseHelp)
+        (@arg port: -p --port +takes_value +required
+            "Serial device connected to the boot manager (pseudo-terminals are supported).")
+        (@arg baud: -b --baud +takes_value default_value("115200") "Baud rate of the serial device.")
+        (@arg timeout: -t --timeout +takes_value default_value("10")
+            "Seconds to wait for the device to respond.")
+        (@subcommand banks => (about: "Lists the flash banks."))
+        (@subcommand images => (about: "Lists the valid images in each bank (slow)."))
+        (@subcommand metrics => (about: "Shows the boot metrics relayed by Loadstone."))
+        (@subcommand flash =>
+            (about: "Stores a firmware image in a non-bootable bank.")
+            (@arg bank: +required "Bank index.")
+            (@arg image: +required "The (signed) firmware image file."))
+        (@subcommand update_signal_bank =>
+            (about: "Only allows Loadstone to update from a specific bank.")
+            (@arg bank: +required "Updatable bank index.")
+            (@arg once: --once "Only applies to the next boot."))
+        (@subcommand update_signal_any =>
+            (about: "Allows Loadstone to update from any bank.")
+            (@arg once: --once "Only applies to the next boot."))
+        (@subcommand update_signal_none =>
+            (about: "Disallows Loadstone from updating.")
+            (@arg once: --once "Only applies to the next boot."))
+        (@subcommand update_signal_golden =>
+            (about: "Makes Loadstone replace the current image with the golden image on the next boot."))
+        (@subcommand update_signal_recovery =>
+            (about: "Makes Loadstone enter serial recovery mode instead of booting.")
+            (@arg once: --once "Only applies to the next boot."))
+        (@subcommand update_signal_rollback =>
+            (about: "Makes Loadstone reject the current image and go back to a previous one on the next boot."))
+        (@subcommand command =>
+            (about: "Runs an arbitrary boot manager command, printing its output.")
+            (@arg text: +required "The command line, e.g. `help`."))
+    )
+    .get_matches();
+
+    let path = matches.value_of("port").unwrap();
+    let baud_rate = matches.value_of("baud").unwrap().parse().map_err(|_| "Invalid baud rate.")?;
+    let timeout = matches.value_of("timeout").unwrap().parse().map_err(|_| "Invalid timeout.")?;
+    let (name, subcommand) = matches.subcommand();
+    let subcommand = subcommand.unwrap();
+
+    let port = port::open(path, baud_rate).map_err(|e| e.to_string())?;
+    let mut device = Device::new(port, Duration::from_secs(timeout));
+    device
+        .connect()
+        .and_then(|_| run_command(&mut device, name, subcommand))
+        .map_err(|e| e.to_string())
+}
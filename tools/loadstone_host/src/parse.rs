@@ -0,0 +1,235 @@
+//! Interpretation of boot manager command output.
+
+use crate::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bank {
+    /// Label of the flash chip the bank resides in.
+    pub flash: String,
+    pub index: u8,
+    pub bootable: bool,
+    pub golden: bool,
+    pub size: usize,
+    /// State recorded by Loadstone, on ports that keep track of bank states.
+    pub state: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub bank: u8,
+    pub size: usize,
+    pub golden: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BootPath {
+    Direct,
+    Restored { bank: u8 },
+    Updated { bank: u8 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metrics {
+    pub boot_path: BootPath,
+    pub boot_time_ms: Option<u32>,
+    /// Whether Loadstone ignored the update signal, as it held no valid plan.
+    pub no_update_signal: bool,
+}
+
+/// Parses the output of the `banks` command.
+pub fn banks(lines: &[String]) -> Result<Vec<Bank>, Error> {
+    let mut flash = None;
+    let mut banks = Vec::new();
+    for line in lines {
+        if let Some(label) = line.strip_suffix("] Banks:").and_then(|l| l.strip_prefix('[')) {
+            flash = Some(label.to_owned());
+        } else if let Some(description) = line.strip_prefix("- [") {
+            let bank = || -> Option<Bank> {
+                let (index, rest) = description.split_once(']')?;
+                let mut fields = rest.split(" - ").map(str::trim);
+                let bootable = fields.next()? == "Bootable";
+                let size = number(fields.next()?.strip_prefix("Size: ")?.strip_suffix('b')?)?;
+                let (mut golden, mut state) = (false, None);
+                for field in fields {
+                    golden |= field == "GOLDEN";
+                    state = field.strip_prefix("State: ").map(str::to_owned).or(state);
+                }
+                let index = number(index)?;
+                Some(Bank { flash: flash.clone()?, index, bootable, golden, size, state })
+            };
+            banks.push(bank().ok_or_else(|| unexpected(line))?);
+        } else {
+            return Err(unexpected(line));
+        }
+    }
+    Ok(banks)
+}
+
+/// Parses the output of the `images` command. Banks without a valid image are omitted.
+pub fn images(lines: &[String]) -> Result<Vec<Image>, Error> {
+    lines
+        .iter()
+        .filter(|l| !l.ends_with("] Images:"))
+        .map(|line| {
+            let image = || -> Option<Image> {
+                // The golden marker is optional, but the separator before it isn't.
+                let line = line.trim_end_matches(|c: char| c == '-' || c.is_whitespace());
+                let mut fields = line.split(" - ").map(str::trim);
+                let bank = number(fields.next()?.strip_prefix("Bank ")?)?;
+                if fields.next()? != "[IMAGE]" {
+                    return None;
+                }
+                let size = number(fields.next()?.strip_prefix("Size: ")?.strip_suffix('b')?)?;
+                let golden = fields.any(|f| f == "GOLDEN");
+                Some(Image { bank, size, golden })
+            };
+            image().ok_or_else(|| unexpected(line))
+        })
+        .collect()
+}
+
+/// Parses the output of the `metrics` command. Returns `None` if Loadstone didn't relay any.
+pub fn metrics(lines: &[String]) -> Result<Option<Metrics>, Error> {
+    if lines.iter().any(|l| l.starts_with("Loadstone did not relay any boot metrics")) {
+        return Ok(None);
+    }
+
+    let mut boot_path = None;
+    let mut boot_time_ms = None;
+    let mut no_update_signal = false;
+    for line in lines.iter().filter(|l| l.starts_with('*')) {
+        if line.contains("booted directly") {
+            boot_path = Some(BootPath::Direct);
+        } else if let Some(bank) = bank_after(line, "restored from bank ") {
+            boot_path = Some(BootPath::Restored { bank });
+        } else if let Some(bank) = bank_after(line, "updated from bank ") {
+            boot_path = Some(BootPath::Updated { bank });
+        } else if let Some(time) = line.strip_prefix("* Boot process took ") {
+            boot_time_ms = time.strip_suffix(" milliseconds.").and_then(number);
+        } else if line.starts_with("* Update signal held no valid plan") {
+            no_update_signal = true;
+        }
+    }
+
+    let boot_path = boot_path.ok_or_else(|| Error::UnexpectedOutput(lines.join("\n")))?;
+    Ok(Some(Metrics { boot_path, boot_time_ms, no_update_signal }))
+}
+
+impl Display for Bank {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "bank={} flash=\"{}\" size={} bootable={} golden={}",
+            self.index, self.flash, self.size, self.bootable, self.golden
+        )?;
+        if let Some(state) = &self.state {
+            write!(f, " state={}", state)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Image {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "bank={} size={} golden={}", self.bank, self.size, self.golden)
+    }
+}
+
+impl Display for Metrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self.boot_path {
+            BootPath::Direct => write!(f, "boot_path=direct")?,
+            BootPath::Restored { bank } => write!(f, "boot_path=restored bank={}", bank)?,
+            BootPath::Updated { bank } => write!(f, "boot_path=updated bank={}", bank)?,
+        }
+        if let Some(time) = self.boot_time_ms {
+            write!(f, " boot_time_ms={}", time)?;
+        }
+        if self.no_update_signal {
+            write!(f, " update_signal=none")?;
+        }
+        Ok(())
+    }
+}
+
+fn number<T: std::str::FromStr>(text: &str) -> Option<T> { text.trim().parse().ok() }
+
+fn bank_after(line: &str, prefix: &str) -> Option<u8> {
+    let (_, rest) = line.split_once(prefix)?;
+    number(&rest[..rest.find(|c: char| !c.is_ascii_digit())?])
+}
+
+fn unexpected(line: &str) -> Error { Error::UnexpectedOutput(line.to_owned()) }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> { text.lines().map(|l| l.trim().to_owned()).collect() }
+
+    #[test]
+    fn banks_are_parsed_across_flash_chips() {
+        let output = lines(
+            "[Stm32F412 MCU Flash] Banks:
+               - [1] Bootable - Size: 16384b
+               - [2] Non-Bootable - Size: 870400b - GOLDEN
+             [n25q128a] Banks:
+               - [3] Non-Bootable - Size: 7680000b - State: Bad",
+        );
+        let banks = banks(&output).unwrap();
+        assert_eq!(banks.len(), 3);
+        assert_eq!(banks[0], Bank {
+            flash: "Stm32F412 MCU Flash".to_owned(),
+            index: 1,
+            bootable: true,
+            golden: false,
+            size: 16384,
+            state: None,
+        });
+        assert!(banks[1].golden && !banks[1].bootable);
+        assert_eq!(banks[2].flash, "n25q128a");
+        assert_eq!(banks[2].state.as_deref(), Some("Bad"));
+    }
+
+    #[test]
+    fn images_are_parsed_and_flagged_as_golden() {
+        let output = lines(
+            "[Stm32F412 MCU Flash] Images:
+             Bank 1 - [IMAGE] - Size: 4000b - 
+             Bank 2 - [IMAGE] - Size: 5000b -  - GOLDEN",
+        );
+        assert_eq!(images(&output).unwrap(), vec![
+            Image { bank: 1, size: 4000, golden: false },
+            Image { bank: 2, size: 5000, golden: true },
+        ]);
+    }
+
+    #[test]
+    fn metrics_are_parsed_when_available() {
+        let output = lines(
+            "[Boot Metrics]
+             * Application was first updated from bank 3, ([n25q128a]), then booted.
+             * Boot process took 512 milliseconds.
+             * Update signal held no valid plan, so it was ignored.",
+        );
+        assert_eq!(
+            metrics(&output).unwrap(),
+            Some(Metrics {
+                boot_path: BootPath::Updated { bank: 3 },
+                boot_time_ms: Some(512),
+                no_update_signal: true,
+            })
+        );
+
+        let output =
+            lines("Loadstone did not relay any boot metrics, or the boot metrics were corrupted.");
+        assert_eq!(metrics(&output).unwrap(), None);
+    }
+
+    #[test]
+    fn unexpected_output_is_rejected() {
+        assert!(banks(&lines("Something else entirely")).is_err());
+        assert!(images(&lines("Bank one - [IMAGE] - Size: 12b")).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+//! Serial port access.
+//!
+//! Ports are polled with a short timeout, so callers can enforce their own
+//! deadlines. Pseudo-terminals work as well as real serial devices, which
+//! lets CI stand in a simulated device for real hardware.
+
+use crate::error::Error;
+use serialport::SerialPort;
+use std::{
+    io::{ErrorKind, Read},
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub fn open(path: &str, baud_rate: u32) -> Result<Box<dyn SerialPort>, Error> {
+    serialport::new(path, baud_rate)
+        .timeout(POLL_INTERVAL)
+        .open()
+        .map_err(|e| Error::PortOpenFailed(e.to_string()))
+}
+
+/// Reads a single byte, returning `None` if none arrives before the deadline.
+pub fn read_byte<P: Read + ?Sized>(port: &mut P, deadline: Instant) -> Result<Option<u8>, Error> {
+    let mut byte = [0u8];
+    while Instant::now() < deadline {
+        match port.read(&mut byte) {
+            Ok(1) => return Ok(Some(byte[0])),
+            Ok(_) => continue,
+            Err(e) if is_transient(e.kind()) => continue,
+            Err(e) => return Err(Error::PortFailed(e.to_string())),
+        }
+    }
+    Ok(None)
+}
+
+/// Discards incoming bytes until the line has been quiet for a while.
+pub fn drain<P: Read + ?Sized>(port: &mut P, quiet: Duration) -> Result<(), Error> {
+    while read_byte(port, Instant::now() + quiet)?.is_some() {}
+    Ok(())
+}
+
+fn is_transient(kind: ErrorKind) -> bool {
+    matches!(kind, ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted)
+}
@@ -0,0 +1,158 @@
+//! XMODEM file transfer, sender side.
+//!
+//! Mirrors the receiver in Loadstone's `cli::file_transfer` module: files are
+//! sent as a single file YMODEM batch of XMODEM-1K packets with CRC-16, so the
+//! device knows the image size before writing anything. Receivers that request
+//! the classic additive checksum are also supported.
+
+use crate::{
+    error::Error,
+    port::{drain, read_byte},
+};
+use std::{
+    io::{Read, Write},
+    time::{Duration, Instant},
+};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC_REQUEST: u8 = b'C';
+
+/// Filler for the unused end of the last packet.
+const PADDING: u8 = 0x1A;
+const PAYLOAD_SIZE: usize = 128;
+const LONG_PAYLOAD_SIZE: usize = 1024;
+
+/// Times a packet is resent before giving up on the transfer.
+const MAX_RETRIES: u32 = 10;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Checksum {
+    Additive,
+    Crc16,
+}
+
+/// Sends a file over an established connection to an XMODEM receiver.
+pub fn send<P: Read + Write + ?Sized>(
+    port: &mut P,
+    name: &str,
+    data: &[u8],
+    timeout: Duration,
+) -> Result<(), Error> {
+    let mut sender = Sender { port, timeout, checksum: Checksum::Crc16 };
+
+    sender.negotiate()?;
+    sender.send_packet(0, &header(name, data.len()))?;
+    sender.negotiate()?;
+    for (index, chunk) in data.chunks(LONG_PAYLOAD_SIZE).enumerate() {
+        sender.send_packet((index + 1) as u8, chunk)?;
+    }
+    sender.end_transmission()?;
+
+    // An empty header closes the batch.
+    sender.negotiate()?;
+    sender.send_packet(0, &[0u8; PAYLOAD_SIZE])
+}
+
+/// CRC-16/XMODEM (polynomial 0x1021, no reflection, zero initial value).
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// YMODEM header payload: the file name and its decimal size, NUL separated.
+fn header(name: &str, size: usize) -> Vec<u8> {
+    let mut header = format!("{}\0{}", name, size).into_bytes();
+    header.truncate(PAYLOAD_SIZE - 1);
+    header.resize(PAYLOAD_SIZE, 0);
+    header
+}
+
+struct Sender<'a, P: ?Sized> {
+    port: &'a mut P,
+    timeout: Duration,
+    checksum: Checksum,
+}
+
+impl<'a, P: Read + Write + ?Sized> Sender<'a, P> {
+    /// Waits for the receiver to request a transfer, adopting the checksum it asks for.
+    fn negotiate(&mut self) -> Result<(), Error> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match read_byte(self.port, deadline)? {
+                Some(CRC_REQUEST) => self.checksum = Checksum::Crc16,
+                Some(NAK) => self.checksum = Checksum::Additive,
+                Some(CAN) => return Err(Error::TransferCancelled),
+                Some(_) => continue,
+                None => return Err(Error::TimedOut("the device to start the transfer")),
+            }
+            return Ok(());
+        }
+    }
+
+    fn send_packet(&mut self, number: u8, payload: &[u8]) -> Result<(), Error> {
+        let (start, length) = if payload.len() <= PAYLOAD_SIZE {
+            (SOH, PAYLOAD_SIZE)
+        } else {
+            (STX, LONG_PAYLOAD_SIZE)
+        };
+        let mut packet = vec![start, number, !number];
+        packet.extend_from_slice(payload);
+        packet.resize(3 + length, PADDING);
+        let payload = &packet[3..];
+        let checksum = match self.checksum {
+            Checksum::Crc16 => crc16(payload).to_be_bytes().to_vec(),
+            Checksum::Additive => vec![payload.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))],
+        };
+        packet.extend_from_slice(&checksum);
+
+        for _ in 0..MAX_RETRIES {
+            self.write(&packet)?;
+            if self.acknowledged()? {
+                return Ok(());
+            }
+            // Let the receiver finish discarding the rejected packet.
+            drain(self.port, Duration::from_millis(100))?;
+        }
+        Err(Error::TransferFailed)
+    }
+
+    fn end_transmission(&mut self) -> Result<(), Error> {
+        for _ in 0..MAX_RETRIES {
+            self.write(&[EOT])?;
+            if self.acknowledged()? {
+                return Ok(());
+            }
+        }
+        Err(Error::TransferFailed)
+    }
+
+    /// Waits for the receiver's verdict on the last thing sent. Anything other
+    /// than an acknowledgement (or silence) means it must be sent again.
+    fn acknowledged(&mut self) -> Result<bool, Error> {
+        let deadline = Instant::now() + self.timeout;
+        match read_byte(self.port, deadline)? {
+            Some(ACK) => Ok(true),
+            Some(CAN) => Err(Error::TransferCancelled),
+            _ => Ok(false),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.port
+            .write_all(bytes)
+            .and_then(|_| self.port.flush())
+            .map_err(|e| Error::PortFailed(e.to_string()))
+    }
+}